@@ -0,0 +1,1659 @@
+use crate::event::{level_rank, Event, FieldValue, LEVEL_KEYS};
+use crate::timespec::{bucket_label_format, bucket_starts};
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use indexmap::IndexMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::hash::{Hash, Hasher};
+use std::io::{self, Write};
+
+/// Counts events per distinct combination of values of some keys (`--count-by host,level`).
+/// The counts come out as one event per combination, with the values and a `count` field,
+/// most frequent first. A missing key counts as null
+#[derive(Debug)]
+pub struct CountBy {
+    keys: Vec<String>,
+    /// Combinations in order of first appearance, keyed by their JSON form
+    counts: IndexMap<String, (Vec<Option<FieldValue>>, usize)>,
+}
+
+impl CountBy {
+    pub fn new(keys: Vec<String>) -> Self {
+        Self {
+            keys,
+            counts: IndexMap::new(),
+        }
+    }
+
+    pub fn add(&mut self, event: &Event) {
+        let values: Vec<Option<FieldValue>> =
+            self.keys.iter().map(|key| event.get_value(key)).collect();
+        let key = serde_json::to_string(&values).unwrap_or_default();
+        self.counts.entry(key).or_insert((values, 0)).1 += 1;
+    }
+
+    /// The combinations with their counts; ties keep the order they were first seen in
+    pub fn finish(&mut self) -> Vec<Event> {
+        let mut counts: Vec<(Vec<Option<FieldValue>>, usize)> =
+            std::mem::take(&mut self.counts).into_values().collect();
+        counts.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+        counts
+            .into_iter()
+            .map(|(values, count)| {
+                let mut event = Event::new();
+                for (key, value) in self.keys.iter().zip(values) {
+                    event.set_field(key.clone(), value.unwrap_or(FieldValue::Null));
+                }
+                event.set_field("count".to_string(), FieldValue::Number(count as f64));
+                event.extract_core_fields();
+                event
+            })
+            .collect()
+    }
+}
+
+/// Tracks the most frequent values of a field in bounded memory with the Space-Saving
+/// algorithm (Metwally et al., 2005): once `capacity` values are tracked, a new value takes
+/// over the counter of the least frequent one. Counts are exact until then, and afterwards
+/// may overestimate values that took over a counter
+#[derive(Debug)]
+struct SpaceSaving {
+    capacity: usize,
+    /// Counter per value in its text form, with the value as first seen
+    counters: HashMap<String, (FieldValue, usize)>,
+    /// Counters ordered by count, to find the least frequent
+    by_count: BTreeSet<(usize, String)>,
+}
+
+impl SpaceSaving {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            counters: HashMap::new(),
+            by_count: BTreeSet::new(),
+        }
+    }
+
+    fn add(&mut self, value: FieldValue) {
+        let key = value.to_string();
+        if let Some((_, count)) = self.counters.get_mut(&key) {
+            self.by_count.remove(&(*count, key.clone()));
+            *count += 1;
+            self.by_count.insert((*count, key));
+            return;
+        }
+        let mut count = 1;
+        if self.counters.len() >= self.capacity {
+            if let Some((min, evicted)) = self.by_count.pop_first() {
+                self.counters.remove(&evicted);
+                count = min + 1;
+            }
+        }
+        self.counters.insert(key.clone(), (value, count));
+        self.by_count.insert((count, key));
+    }
+
+    /// The `k` most frequent values, most frequent first
+    fn top(&self, k: usize) -> Vec<(FieldValue, usize)> {
+        self.by_count
+            .iter()
+            .rev()
+            .take(k)
+            .map(|(count, key)| (self.counters[key].0.clone(), *count))
+            .collect()
+    }
+}
+
+/// The K most frequent values of some fields (`--top 10 status,path`), with counts and their
+/// share of all events, as one event per field and value. Memory is bounded per field, so
+/// counts are exact unless a field has more than `100 × K` (at least 1000) distinct values
+#[derive(Debug)]
+pub struct TopValues {
+    k: usize,
+    fields: Vec<(String, SpaceSaving)>,
+    total: usize,
+}
+
+impl TopValues {
+    pub fn new(k: usize, keys: Vec<String>) -> Self {
+        let capacity = k.saturating_mul(100).max(1000);
+        Self {
+            k,
+            fields: keys
+                .into_iter()
+                .map(|key| (key, SpaceSaving::new(capacity)))
+                .collect(),
+            total: 0,
+        }
+    }
+
+    pub fn add(&mut self, event: &Event) {
+        self.total += 1;
+        for (key, values) in &mut self.fields {
+            if let Some(value) = event.get_value(key) {
+                values.add(value);
+            }
+        }
+    }
+
+    /// For each field in turn, its top values with `count` and `percent` of all events
+    pub fn finish(&self) -> Vec<Event> {
+        let mut rows = Vec::new();
+        for (key, values) in &self.fields {
+            for (value, count) in values.top(self.k) {
+                let percent = count as f64 * 100.0 / self.total as f64;
+                let mut event = Event::new();
+                event.set_field("field".to_string(), FieldValue::String(key.clone()));
+                event.set_field("value".to_string(), value);
+                event.set_field("count".to_string(), FieldValue::Number(count as f64));
+                event.set_field(
+                    "percent".to_string(),
+                    FieldValue::Number((percent * 10.0).round() / 10.0),
+                );
+                rows.push(event);
+            }
+        }
+        rows
+    }
+}
+
+/// Registers of `HyperLogLog` as a power of two; 2^14 give a standard error of about 0.8%
+const HLL_PRECISION: u32 = 14;
+
+/// Estimates the number of distinct values in a fixed 16 KiB (Flajolet et al., 2007): each
+/// value's hash picks a register, which keeps the longest run of leading zeros seen in the
+/// rest of the hash. Small counts are corrected by linear counting and are nearly exact
+#[derive(Debug)]
+struct HyperLogLog {
+    registers: Vec<u8>,
+}
+
+impl HyperLogLog {
+    fn new() -> Self {
+        Self {
+            registers: vec![0; 1 << HLL_PRECISION],
+        }
+    }
+
+    fn add(&mut self, value: &str) {
+        // Fixed keys, so estimates are the same from run to run
+        let mut hasher = std::hash::DefaultHasher::new();
+        value.hash(&mut hasher);
+        let hash = hasher.finish();
+        let index = (hash >> (64 - HLL_PRECISION)) as usize;
+        let rest = (hash << HLL_PRECISION) | (1 << (HLL_PRECISION - 1));
+        let rank = rest.leading_zeros() as u8 + 1;
+        self.registers[index] = self.registers[index].max(rank);
+    }
+
+    fn estimate(&self) -> f64 {
+        let m = self.registers.len() as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum: f64 = self
+            .registers
+            .iter()
+            .map(|&rank| 2f64.powi(-i32::from(rank)))
+            .sum();
+        let estimate = alpha * m * m / sum;
+        let zeros = self.registers.iter().filter(|&&rank| rank == 0).count();
+        if estimate <= 2.5 * m && zeros > 0 {
+            m * (m / zeros as f64).ln()
+        } else {
+            estimate
+        }
+    }
+}
+
+/// The number of distinct values of some fields (`--cardinality user_id,request_id`), in
+/// fixed memory per field however many there are, within about 1%. Comes out as one event
+/// per field with `distinct` and the number of `events` that had the field
+#[derive(Debug)]
+pub struct Cardinality {
+    fields: Vec<(String, HyperLogLog, usize)>,
+}
+
+impl Cardinality {
+    pub fn new(keys: Vec<String>) -> Self {
+        Self {
+            fields: keys
+                .into_iter()
+                .map(|key| (key, HyperLogLog::new(), 0))
+                .collect(),
+        }
+    }
+
+    pub fn add(&mut self, event: &Event) {
+        for (key, distinct, events) in &mut self.fields {
+            if let Some(value) = event.get_value(key) {
+                distinct.add(&value.to_string());
+                *events += 1;
+            }
+        }
+    }
+
+    pub fn finish(&self) -> Vec<Event> {
+        self.fields
+            .iter()
+            .map(|(key, distinct, events)| {
+                let mut event = Event::new();
+                event.set_field("field".to_string(), FieldValue::String(key.clone()));
+                event.set_field(
+                    "distinct".to_string(),
+                    FieldValue::Number(distinct.estimate().round()),
+                );
+                event.set_field("events".to_string(), FieldValue::Number(*events as f64));
+                event
+            })
+            .collect()
+    }
+}
+
+/// Example values `--schema` shows per key
+const SCHEMA_EXAMPLES: usize = 3;
+
+/// Characters of an example value of `--schema` before it is cut short
+const SCHEMA_EXAMPLE_WIDTH: usize = 40;
+
+/// What one key of `--schema` holds
+#[derive(Debug, Default)]
+struct KeySchema {
+    events: usize,
+    /// Value types in the order they were first seen
+    types: Vec<&'static str>,
+    examples: Vec<String>,
+}
+
+/// Which keys the input has (`--schema`): one event per key, in the order they first
+/// appeared, with the `events` that had it and their `percent` of all events, the value
+/// `types` seen and a few distinct `examples`
+#[derive(Debug, Default)]
+pub struct Schema {
+    total: usize,
+    keys: IndexMap<String, KeySchema>,
+}
+
+impl Schema {
+    pub fn add(&mut self, event: &Event) {
+        self.total += 1;
+        for (key, value) in &event.fields {
+            let schema = self.keys.entry(key.clone()).or_default();
+            schema.events += 1;
+            let value_type = match value {
+                FieldValue::String(_) => "string",
+                FieldValue::Number(_) => "number",
+                FieldValue::Boolean(_) => "bool",
+                FieldValue::Null => "null",
+            };
+            if !schema.types.contains(&value_type) {
+                schema.types.push(value_type);
+            }
+            if schema.examples.len() < SCHEMA_EXAMPLES && *value != FieldValue::Null {
+                let mut example = value.to_string();
+                if let Some((cut, _)) = example.char_indices().nth(SCHEMA_EXAMPLE_WIDTH) {
+                    example.truncate(cut);
+                    example.push('…');
+                }
+                if !schema.examples.contains(&example) {
+                    schema.examples.push(example);
+                }
+            }
+        }
+    }
+
+    pub fn finish(&self) -> Vec<Event> {
+        self.keys
+            .iter()
+            .map(|(key, schema)| {
+                let percent = schema.events as f64 * 100.0 / self.total as f64;
+                let mut event = Event::new();
+                event.set_field("field".to_string(), FieldValue::String(key.clone()));
+                event.set_field(
+                    "events".to_string(),
+                    FieldValue::Number(schema.events as f64),
+                );
+                event.set_field(
+                    "percent".to_string(),
+                    FieldValue::Number((percent * 10.0).round() / 10.0),
+                );
+                event.set_field(
+                    "types".to_string(),
+                    FieldValue::String(schema.types.join(",")),
+                );
+                event.set_field(
+                    "examples".to_string(),
+                    FieldValue::String(schema.examples.join(", ")),
+                );
+                event
+            })
+            .collect()
+    }
+}
+
+/// Relative accuracy of the percentiles of `--agg`
+const RELATIVE_ACCURACY: f64 = 0.01;
+
+/// Up to this many values per field, `--agg` keeps them and its percentiles are exact
+const EXACT_VALUES: usize = 1000;
+
+/// Approximate quantiles in bounded memory, in the manner of DDSketch (Masson et al., 2019):
+/// values are counted in buckets whose bounds grow geometrically, so a quantile is within 1%
+/// of the value of that rank. Zero and negative values are counted apart
+#[derive(Debug, Default)]
+struct QuantileSketch {
+    positive: BTreeMap<i32, usize>,
+    negative: BTreeMap<i32, usize>,
+    zeros: usize,
+}
+
+impl QuantileSketch {
+    fn gamma() -> f64 {
+        (1.0 + RELATIVE_ACCURACY) / (1.0 - RELATIVE_ACCURACY)
+    }
+
+    fn bucket(magnitude: f64) -> i32 {
+        (magnitude.ln() / Self::gamma().ln()).ceil() as i32
+    }
+
+    /// The value a bucket stands for, within the relative accuracy of all values in it
+    fn bucket_value(index: i32) -> f64 {
+        let gamma = Self::gamma();
+        2.0 * gamma.powi(index) / (gamma + 1.0)
+    }
+
+    fn add(&mut self, value: f64) {
+        if value > 0.0 {
+            *self.positive.entry(Self::bucket(value)).or_insert(0) += 1;
+        } else if value < 0.0 {
+            *self.negative.entry(Self::bucket(-value)).or_insert(0) += 1;
+        } else {
+            self.zeros += 1;
+        }
+    }
+
+    /// Bucket values with their counts, smallest first
+    fn buckets(&self) -> impl Iterator<Item = (f64, usize)> + '_ {
+        self.negative
+            .iter()
+            .rev()
+            .map(|(&index, &n)| (-Self::bucket_value(index), n))
+            .chain((self.zeros > 0).then_some((0.0, self.zeros)))
+            .chain(
+                self.positive
+                    .iter()
+                    .map(|(&index, &n)| (Self::bucket_value(index), n)),
+            )
+    }
+
+    /// The `q` quantile (0 to 1) of `count` values
+    fn quantile(&self, q: f64, count: usize) -> Option<f64> {
+        if count == 0 {
+            return None;
+        }
+        let rank = (q * (count - 1) as f64).round() as usize;
+        let mut seen = 0;
+        for (value, n) in self.buckets() {
+            seen += n;
+            if seen > rank {
+                return Some(value);
+            }
+        }
+        None
+    }
+}
+
+/// A statistic of `--agg`
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Statistic {
+    Count,
+    Min,
+    Max,
+    Mean,
+    Sum,
+    /// Percentile, 0 to 100
+    Percentile(f64),
+}
+
+impl Statistic {
+    const DEFAULT: [Statistic; 7] = [
+        Statistic::Count,
+        Statistic::Min,
+        Statistic::Mean,
+        Statistic::Percentile(50.0),
+        Statistic::Percentile(95.0),
+        Statistic::Percentile(99.0),
+        Statistic::Max,
+    ];
+
+    fn parse(name: &str) -> Result<Self, String> {
+        let name = name.trim().to_lowercase();
+        Ok(match name.as_str() {
+            "count" => Statistic::Count,
+            "min" => Statistic::Min,
+            "max" => Statistic::Max,
+            "mean" | "avg" => Statistic::Mean,
+            "sum" => Statistic::Sum,
+            "median" => Statistic::Percentile(50.0),
+            _ => match name.strip_prefix('p').and_then(|p| p.parse::<f64>().ok()) {
+                Some(p) if (0.0..=100.0).contains(&p) => Statistic::Percentile(p),
+                _ => {
+                    return Err(format!(
+                        "unknown statistic '{}' (use count, min, max, mean, sum or a percentile like p95)",
+                        name
+                    ))
+                }
+            },
+        })
+    }
+
+    fn name(self) -> String {
+        match self {
+            Statistic::Count => "count".to_string(),
+            Statistic::Min => "min".to_string(),
+            Statistic::Max => "max".to_string(),
+            Statistic::Mean => "mean".to_string(),
+            Statistic::Sum => "sum".to_string(),
+            Statistic::Percentile(p) => format!("p{}", p),
+        }
+    }
+}
+
+/// Streaming statistics of one numeric field
+#[derive(Debug)]
+pub(crate) struct FieldAggregate {
+    key: String,
+    statistics: Vec<Statistic>,
+    count: usize,
+    sum: f64,
+    min: f64,
+    max: f64,
+    /// All values so far, until there are more than `EXACT_VALUES`
+    exact: Option<Vec<f64>>,
+    sketch: QuantileSketch,
+}
+
+impl FieldAggregate {
+    fn new(key: String, statistics: Vec<Statistic>) -> Self {
+        Self {
+            key,
+            statistics,
+            count: 0,
+            sum: 0.0,
+            min: 0.0,
+            max: 0.0,
+            exact: Some(Vec::new()),
+            sketch: QuantileSketch::default(),
+        }
+    }
+
+    /// Count, min, mean, p50, p95, p99 and max of values named `key`
+    pub(crate) fn with_default_statistics(key: String) -> Self {
+        Self::new(key, Statistic::DEFAULT.to_vec())
+    }
+
+    pub(crate) fn add(&mut self, value: f64) {
+        if self.count == 0 {
+            self.min = value;
+            self.max = value;
+        }
+        self.count += 1;
+        self.sum += value;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.sketch.add(value);
+        if let Some(values) = &mut self.exact {
+            values.push(value);
+            if values.len() > EXACT_VALUES {
+                self.exact = None;
+            }
+        }
+    }
+
+    /// The values with how often each occurs; approximated by the buckets of the sketch once
+    /// there are too many to keep
+    fn distribution(&self) -> Vec<(f64, usize)> {
+        match &self.exact {
+            Some(values) => values.iter().map(|&value| (value, 1)).collect(),
+            None => self
+                .sketch
+                .buckets()
+                .map(|(value, count)| (value.clamp(self.min, self.max), count))
+                .collect(),
+        }
+    }
+
+    fn percentile(&self, p: f64) -> f64 {
+        let q = p / 100.0;
+        if let Some(values) = &self.exact {
+            let mut values = values.clone();
+            values.sort_by(f64::total_cmp);
+            return values[(q * (values.len() - 1) as f64).round() as usize];
+        }
+        self.sketch
+            .quantile(q, self.count)
+            .map(|value| round_significant(value.clamp(self.min, self.max), 3))
+            .unwrap_or(f64::NAN)
+    }
+
+    fn value(&self, statistic: Statistic) -> f64 {
+        match statistic {
+            Statistic::Count => self.count as f64,
+            Statistic::Min => self.min,
+            Statistic::Max => self.max,
+            Statistic::Mean => self.sum / self.count as f64,
+            Statistic::Sum => self.sum,
+            Statistic::Percentile(p) => self.percentile(p),
+        }
+    }
+
+    pub(crate) fn summary(&self) -> String {
+        if self.count == 0 {
+            return format!("{}: no numeric values", self.key);
+        }
+        let values: Vec<String> = self
+            .statistics
+            .iter()
+            .map(|&statistic| {
+                format!(
+                    "{}={}",
+                    statistic.name(),
+                    format_number(self.value(statistic))
+                )
+            })
+            .collect();
+        format!("{}: {}", self.key, values.join(" "))
+    }
+}
+
+/// A field as a finite number, also from text that parses as one
+fn numeric_value(event: &Event, key: &str) -> Option<f64> {
+    let value = event.get_value(key)?.as_number()?;
+    value.is_finite().then_some(value)
+}
+
+/// Round to a number of significant digits, so approximate values don't look exact
+fn round_significant(value: f64, digits: i32) -> f64 {
+    if value == 0.0 || !value.is_finite() {
+        return value;
+    }
+    let scale = 10f64.powi(digits - 1 - value.abs().log10().floor() as i32);
+    (value * scale).round() / scale
+}
+
+/// Whole numbers without decimals, others with at most three
+pub fn format_number(value: f64) -> String {
+    if value.fract() == 0.0 && value.abs() < 1e15 {
+        format!("{:.0}", value)
+    } else {
+        let text = format!("{:.3}", value);
+        text.trim_end_matches('0').trim_end_matches('.').to_string()
+    }
+}
+
+/// Statistics of numeric fields over the shown events (`--agg latency_ms:p50,p95,max`),
+/// computed in one pass and bounded memory: count, sum, mean, min and max are exact, and so
+/// are percentiles over up to 1000 values; beyond that they are within 1%. Values that aren't
+/// numbers, or text that doesn't parse as one, are skipped
+#[derive(Debug, Default)]
+pub struct NumericAggregates {
+    fields: Vec<FieldAggregate>,
+}
+
+impl NumericAggregates {
+    /// Parse `KEY[:STATS]` specs, where STATS is a comma-separated list; without it count,
+    /// min, mean, p50, p95, p99 and max
+    pub fn parse(specs: &[String]) -> Result<Self, String> {
+        let fields = specs
+            .iter()
+            .map(|spec| {
+                let (key, statistics) = match spec.split_once(':') {
+                    Some((key, names)) => (
+                        key,
+                        names
+                            .split(',')
+                            .filter(|name| !name.trim().is_empty())
+                            .map(Statistic::parse)
+                            .collect::<Result<Vec<_>, String>>()?,
+                    ),
+                    None => (spec.as_str(), Statistic::DEFAULT.to_vec()),
+                };
+                if key.trim().is_empty() || statistics.is_empty() {
+                    return Err(format!("expected KEY[:STATS], got '{}'", spec));
+                }
+                Ok(FieldAggregate::new(key.trim().to_string(), statistics))
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Self { fields })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    pub fn add(&mut self, event: &Event) {
+        for field in &mut self.fields {
+            if let Some(value) = numeric_value(event, &field.key) {
+                field.add(value);
+            }
+        }
+    }
+
+    /// A line per field, e.g. `latency_ms: p50=35 p95=120 max=512`
+    pub fn summaries(&self) -> Vec<String> {
+        self.fields.iter().map(FieldAggregate::summary).collect()
+    }
+
+    /// Each statistic of the fields with values, named like `latency_ms p95`
+    pub fn values(&self) -> Vec<(String, f64)> {
+        self.fields
+            .iter()
+            .filter(|field| field.count > 0)
+            .flat_map(|field| {
+                field.statistics.iter().map(|&statistic| {
+                    (
+                        format!("{} {}", field.key, statistic.name()),
+                        field.value(statistic),
+                    )
+                })
+            })
+            .collect()
+    }
+}
+
+/// Statistics of the durations in the stats (`-s`): fields declared as durations with
+/// `--types`, and with `--normalize-units` every numeric field ending in `_ms`, which is
+/// where it puts durations. All in milliseconds
+#[derive(Debug, Default)]
+pub struct DurationStats {
+    keys: Vec<String>,
+    normalized: bool,
+    fields: IndexMap<String, FieldAggregate>,
+}
+
+impl DurationStats {
+    const STATISTICS: [Statistic; 4] = [
+        Statistic::Min,
+        Statistic::Mean,
+        Statistic::Percentile(95.0),
+        Statistic::Max,
+    ];
+
+    pub fn new(keys: Vec<String>, normalized: bool) -> Self {
+        Self {
+            keys,
+            normalized,
+            fields: IndexMap::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty() && !self.normalized
+    }
+
+    pub fn add(&mut self, event: &Event) {
+        for (key, value) in &event.fields {
+            let FieldValue::Number(millis) = value else {
+                continue;
+            };
+            let duration = self.keys.contains(key) || (self.normalized && key.ends_with("_ms"));
+            if duration && millis.is_finite() {
+                self.fields
+                    .entry(key.clone())
+                    .or_insert_with(|| {
+                        FieldAggregate::new(format!("{} (ms)", key), Self::STATISTICS.to_vec())
+                    })
+                    .add(*millis);
+            }
+        }
+    }
+
+    /// A line per field seen, e.g. `latency_ms (ms): min=2 mean=41.7 p95=152 max=4020`
+    pub fn summaries(&self) -> Vec<String> {
+        self.fields.values().map(FieldAggregate::summary).collect()
+    }
+}
+
+/// Lengths of the input lines in bytes for the stats, where pathological mega-lines show
+/// in the maximum
+#[derive(Debug)]
+pub struct LineLengths {
+    lengths: FieldAggregate,
+}
+
+impl Default for LineLengths {
+    fn default() -> Self {
+        Self {
+            lengths: FieldAggregate::new(
+                "Line length".to_string(),
+                vec![
+                    Statistic::Percentile(50.0),
+                    Statistic::Percentile(95.0),
+                    Statistic::Percentile(99.0),
+                    Statistic::Max,
+                ],
+            ),
+        }
+    }
+}
+
+impl LineLengths {
+    pub fn add(&mut self, length: usize) {
+        self.lengths.add(length as f64);
+    }
+
+    /// E.g. `Line length: p50=120 p95=310 p99=880 max=65536`, once there are lines
+    pub fn summary(&self) -> Option<String> {
+        (self.lengths.count > 0).then(|| self.lengths.summary())
+    }
+}
+
+/// Width of the longest bar of `--hist` and `--timeline`
+const BAR_WIDTH: usize = 40;
+
+/// A bar for `count` scaled to the largest count, after two spaces; any count above zero
+/// gets at least a mark, and zero no bar at all
+fn bar(count: usize, most: usize) -> String {
+    if count == 0 {
+        return String::new();
+    }
+    format!(
+        "  {}",
+        "#".repeat((count * BAR_WIDTH).div_ceil(most.max(1)))
+    )
+}
+
+/// The distribution of a numeric field as an ASCII histogram (`--hist latency_ms`), with
+/// buckets of equal width between the smallest and largest value, or of equal ratio with a
+/// log scale, which suits long-tailed values like latencies. Memory is bounded as for `--agg`,
+/// with values beyond the first 1000 counted to within 1% of their bucket bounds
+#[derive(Debug)]
+pub struct Histogram {
+    values: FieldAggregate,
+    buckets: usize,
+    log_scale: bool,
+}
+
+impl Histogram {
+    pub fn new(key: String, buckets: usize, log_scale: bool) -> Self {
+        Self {
+            values: FieldAggregate::new(key, Vec::new()),
+            buckets: buckets.max(1),
+            log_scale,
+        }
+    }
+
+    pub fn add(&mut self, event: &Event) {
+        if let Some(value) = numeric_value(event, &self.values.key) {
+            self.add_value(value);
+        }
+    }
+
+    pub(crate) fn add_value(&mut self, value: f64) {
+        // Nothing to place on a log scale below zero
+        if !self.log_scale || value > 0.0 {
+            self.values.add(value);
+        }
+    }
+
+    /// Bucket bounds and counts
+    fn counts(&self) -> Vec<(f64, f64, usize)> {
+        let (min, max) = (self.values.min, self.values.max);
+        let buckets = if min < max { self.buckets } else { 1 };
+        let scale = |value: f64| if self.log_scale { value.ln() } else { value };
+        let unscale = |value: f64| if self.log_scale { value.exp() } else { value };
+        let (low, high) = (scale(min), scale(max));
+        let width = (high - low) / buckets as f64;
+
+        let mut counts = vec![0; buckets];
+        for (value, count) in self.values.distribution() {
+            let index = if width > 0.0 {
+                ((scale(value) - low) / width) as usize
+            } else {
+                0
+            };
+            counts[index.min(buckets - 1)] += count;
+        }
+        counts
+            .into_iter()
+            .enumerate()
+            .map(|(i, count)| {
+                let lower = unscale(low + width * i as f64);
+                let upper = if i + 1 == buckets {
+                    max
+                } else {
+                    unscale(low + width * (i + 1) as f64)
+                };
+                (lower, upper, count)
+            })
+            .collect()
+    }
+
+    pub fn write_report(&self, out: &mut dyn Write) -> io::Result<()> {
+        let key = &self.values.key;
+        if self.values.count == 0 {
+            let positive = if self.log_scale { "positive " } else { "" };
+            return writeln!(out, "{}: no {}numeric values", key, positive);
+        }
+        writeln!(
+            out,
+            "{}: {} values, min {}, max {}{}",
+            key,
+            self.values.count,
+            format_number(self.values.min),
+            format_number(self.values.max),
+            if self.log_scale { ", log scale" } else { "" }
+        )?;
+        let counts = self.counts();
+        let most = counts.iter().map(|(_, _, count)| *count).max().unwrap_or(0);
+        for (lower, upper, count) in counts {
+            writeln!(
+                out,
+                "{:>10} .. {:<10}{:>8}{}",
+                format_number(round_significant(lower, 3)),
+                format_number(round_significant(upper, 3)),
+                count,
+                bar(count, most)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Events per time bucket (`--timeline 1m`), optionally split by the values of a key such as
+/// the level (`--crosstab level@1h`), as a table with a bar per bucket or as CSV. Buckets
+/// without events are shown too, unless there are very many in a row, and events without a
+/// timestamp are left out
+#[derive(Debug)]
+pub struct EventTimeline {
+    bucket_ms: i64,
+    split_by: Option<String>,
+    /// Start of each bucket (in milliseconds) to the events per value of the key in it
+    /// (lowercased for levels), or under "" when not split or without the key
+    counts: BTreeMap<i64, BTreeMap<String, usize>>,
+}
+
+impl EventTimeline {
+    pub fn new(bucket: Duration, split_by: Option<String>) -> Result<Self, String> {
+        let bucket_ms = bucket.num_milliseconds();
+        if bucket_ms <= 0 {
+            return Err("bucket size must be positive".to_string());
+        }
+        Ok(Self {
+            bucket_ms,
+            split_by,
+            counts: BTreeMap::new(),
+        })
+    }
+
+    pub fn add(&mut self, event: &Event) {
+        let Some(timestamp) = event.timestamp else {
+            return;
+        };
+        let start = timestamp
+            .timestamp_millis()
+            .div_euclid(self.bucket_ms)
+            .saturating_mul(self.bucket_ms);
+        let value = match &self.split_by {
+            Some(key) => match event.get_value(key) {
+                Some(value) if self.by_level() => value.to_string().to_lowercase(),
+                Some(value) => value.to_string(),
+                None => String::new(),
+            },
+            None => String::new(),
+        };
+        *self
+            .counts
+            .entry(start)
+            .or_default()
+            .entry(value)
+            .or_default() += 1;
+    }
+
+    fn by_level(&self) -> bool {
+        self.split_by
+            .as_deref()
+            .is_some_and(|key| LEVEL_KEYS.contains(&key))
+    }
+
+    /// Values seen, levels most severe first and others in alphabetical order; events
+    /// without the key come last, as "none"
+    fn levels(&self) -> Vec<String> {
+        let levels: BTreeSet<&String> = self
+            .counts
+            .values()
+            .flat_map(|counts| counts.keys())
+            .collect();
+        let mut levels: Vec<String> = levels.into_iter().cloned().collect();
+        let by_level = self.by_level();
+        // Stable, so levels of the same rank stay in alphabetical order
+        levels.sort_by_key(|level| match level.as_str() {
+            "" => (1, 0),
+            _ if !by_level => (0, 0),
+            level => (0, u8::MAX - level_rank(level).unwrap_or(0)),
+        });
+        levels
+    }
+
+    /// Per bucket start its events per value (in the order of `levels`) and in total, from
+    /// the first to the last event
+    fn rows(&self, levels: &[String]) -> Vec<(DateTime<Utc>, Vec<usize>, usize)> {
+        bucket_starts(self.counts.keys().copied(), self.bucket_ms)
+            .into_iter()
+            .filter_map(|start| {
+                let counts = self.counts.get(&start);
+                let by_level: Vec<usize> = levels
+                    .iter()
+                    .map(|level| {
+                        counts
+                            .and_then(|counts| counts.get(level))
+                            .copied()
+                            .unwrap_or(0)
+                    })
+                    .collect();
+                let total = by_level.iter().sum();
+                Some((DateTime::from_timestamp_millis(start)?, by_level, total))
+            })
+            .collect()
+    }
+
+    pub fn write_report(&self, csv: bool, out: &mut dyn Write) -> io::Result<()> {
+        let levels = self.levels();
+        let rows = self.rows(&levels);
+        let names: Vec<&str> = levels
+            .iter()
+            .map(|level| if level.is_empty() { "none" } else { level })
+            .collect();
+        // Split columns only say something with values to split by
+        let split = self.split_by.is_some() && !names.is_empty();
+
+        if csv {
+            let precision = if self.bucket_ms % 1000 == 0 {
+                SecondsFormat::Secs
+            } else {
+                SecondsFormat::Millis
+            };
+            let mut header = vec!["time"];
+            if split {
+                header.extend(&names);
+            }
+            header.push("count");
+            writeln!(out, "{}", header.join(","))?;
+            for (start, by_level, total) in rows {
+                let mut row = vec![start.to_rfc3339_opts(precision, true)];
+                if split {
+                    row.extend(by_level.iter().map(usize::to_string));
+                }
+                row.push(total.to_string());
+                writeln!(out, "{}", row.join(","))?;
+            }
+            return Ok(());
+        }
+
+        let layout = match (rows.first(), rows.last()) {
+            (Some((first, _, _)), Some((last, _, _))) => {
+                bucket_label_format(*first, *last, self.bucket_ms)
+            }
+            _ => return Ok(()),
+        };
+        let labels: Vec<String> = rows
+            .iter()
+            .map(|(start, _, _)| start.format(layout).to_string())
+            .collect();
+        let label_width = labels.iter().map(String::len).max().unwrap_or(0);
+        let most = rows.iter().map(|(_, _, total)| *total).max().unwrap_or(0);
+
+        write!(out, "{:<width$}", "time", width = label_width)?;
+        if split {
+            for name in &names {
+                write!(out, "  {:>5}", name)?;
+            }
+        }
+        writeln!(out, "  {:>7}", "count")?;
+        for (label, (_, by_level, total)) in labels.iter().zip(rows) {
+            write!(out, "{}", label)?;
+            if split {
+                for (name, count) in names.iter().zip(by_level) {
+                    write!(out, "  {:>width$}", count, width = name.len().max(5))?;
+                }
+            }
+            writeln!(out, "  {:>7}{}", total, bar(total, most))?;
+        }
+        Ok(())
+    }
+}
+
+/// Most time buckets `Activity` keeps before halving its resolution
+const ACTIVITY_BUCKETS: usize = 4096;
+
+/// Sparkline marks from low to high
+const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// Event and error volume over time, for the sparklines of the stats. Memory is bounded:
+/// events are counted per time bucket, starting at a millisecond, and buckets double in size
+/// whenever there get to be too many
+#[derive(Debug)]
+pub struct Activity {
+    resolution_ms: i64,
+    /// Start of each bucket (in milliseconds) to its events and errors
+    counts: BTreeMap<i64, (usize, usize)>,
+}
+
+impl Default for Activity {
+    fn default() -> Self {
+        Self {
+            resolution_ms: 1,
+            counts: BTreeMap::new(),
+        }
+    }
+}
+
+/// Sparklines of event and error volume, and the time each mark stands for
+#[derive(Debug, PartialEq)]
+pub struct Sparklines {
+    pub events: String,
+    pub errors: Option<String>,
+    pub peak_events: usize,
+    pub peak_errors: usize,
+    pub per_mark: Duration,
+}
+
+impl Activity {
+    /// Count an event; errors are events of level error or more severe
+    pub fn add(&mut self, event: &Event) {
+        let Some(timestamp) = event.timestamp else {
+            return;
+        };
+        let error = event.level_rank() >= level_rank("error");
+        let start = timestamp
+            .timestamp_millis()
+            .div_euclid(self.resolution_ms)
+            .saturating_mul(self.resolution_ms);
+        let counts = self.counts.entry(start).or_default();
+        counts.0 += 1;
+        counts.1 += usize::from(error);
+        if self.counts.len() > ACTIVITY_BUCKETS {
+            self.coarsen();
+        }
+    }
+
+    fn coarsen(&mut self) {
+        self.resolution_ms = self.resolution_ms.saturating_mul(2);
+        let mut coarse: BTreeMap<i64, (usize, usize)> = BTreeMap::new();
+        for (start, (events, errors)) in std::mem::take(&mut self.counts) {
+            let counts = coarse
+                .entry(start.div_euclid(self.resolution_ms) * self.resolution_ms)
+                .or_default();
+            counts.0 += events;
+            counts.1 += errors;
+        }
+        self.counts = coarse;
+    }
+
+    /// Sparklines of up to `width` marks over the time from the first to the last event;
+    /// None unless they are apart. Errors only get one if there are any
+    pub fn sparklines(&self, width: usize) -> Option<Sparklines> {
+        let first = *self.counts.keys().next()?;
+        let span = *self.counts.keys().next_back()? + self.resolution_ms - first;
+        let marks = width.min((span / self.resolution_ms) as usize);
+        if marks < 2 {
+            return None;
+        }
+        let per_mark = (span + marks as i64 - 1) / marks as i64;
+        let mut bins = vec![(0, 0); marks];
+        for (start, (events, errors)) in &self.counts {
+            let bin = &mut bins[(((start - first) / per_mark) as usize).min(marks - 1)];
+            bin.0 += events;
+            bin.1 += errors;
+        }
+
+        let peak_events = bins.iter().map(|bin| bin.0).max().unwrap_or(0);
+        let peak_errors = bins.iter().map(|bin| bin.1).max().unwrap_or(0);
+        let line = |counts: Vec<usize>, peak: usize| -> String {
+            counts
+                .into_iter()
+                .map(|count| match count {
+                    0 => ' ',
+                    count => SPARKS[((count * SPARKS.len()).div_ceil(peak) - 1).min(7)],
+                })
+                .collect()
+        };
+        Some(Sparklines {
+            events: line(bins.iter().map(|bin| bin.0).collect(), peak_events),
+            errors: (peak_errors > 0)
+                .then(|| line(bins.iter().map(|bin| bin.1).collect(), peak_errors)),
+            peak_events,
+            peak_errors,
+            per_mark: Duration::milliseconds(per_mark),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(host: Option<&str>, level: &str) -> Event {
+        let mut event = Event::new();
+        if let Some(host) = host {
+            event.set_field("host".to_string(), FieldValue::String(host.to_string()));
+        }
+        event.set_field("level".to_string(), FieldValue::String(level.to_string()));
+        event.extract_core_fields();
+        event
+    }
+
+    #[test]
+    fn test_count_by() {
+        let mut count_by = CountBy::new(vec!["host".to_string(), "level".to_string()]);
+        for event in [
+            event(Some("a"), "info"),
+            event(Some("b"), "error"),
+            event(Some("b"), "error"),
+            event(None, "info"),
+            event(Some("a"), "info"),
+            event(Some("b"), "error"),
+        ] {
+            count_by.add(&event);
+        }
+        let rows: Vec<(FieldValue, Option<String>, FieldValue)> = count_by
+            .finish()
+            .into_iter()
+            .map(|row| {
+                (
+                    row.fields["host"].clone(),
+                    row.level.clone(),
+                    row.fields["count"].clone(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                (
+                    FieldValue::String("b".to_string()),
+                    Some("error".to_string()),
+                    FieldValue::Number(3.0)
+                ),
+                (
+                    FieldValue::String("a".to_string()),
+                    Some("info".to_string()),
+                    FieldValue::Number(2.0)
+                ),
+                (
+                    FieldValue::Null,
+                    Some("info".to_string()),
+                    FieldValue::Number(1.0)
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_space_saving() {
+        let mut values = SpaceSaving::new(3);
+        for value in ["a", "b", "a", "c", "a", "b", "d", "a"] {
+            values.add(FieldValue::String(value.to_string()));
+        }
+        // "d" took over the counter of "c", the least frequent
+        let top: Vec<(String, usize)> = values
+            .top(3)
+            .into_iter()
+            .map(|(value, count)| (value.to_string(), count))
+            .collect();
+        assert_eq!(
+            top,
+            [
+                ("a".to_string(), 4),
+                ("d".to_string(), 2),
+                ("b".to_string(), 2)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_top_values() {
+        let mut top = TopValues::new(2, vec!["status".to_string(), "level".to_string()]);
+        for status in [200.0, 500.0, 200.0, 404.0, 200.0] {
+            let mut event = Event::new();
+            event.set_field("status".to_string(), FieldValue::Number(status));
+            top.add(&event);
+        }
+        let rows: Vec<(FieldValue, FieldValue, FieldValue)> = top
+            .finish()
+            .into_iter()
+            .map(|row| {
+                (
+                    row.fields["value"].clone(),
+                    row.fields["count"].clone(),
+                    row.fields["percent"].clone(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                (
+                    FieldValue::Number(200.0),
+                    FieldValue::Number(3.0),
+                    FieldValue::Number(60.0)
+                ),
+                (
+                    FieldValue::Number(500.0),
+                    FieldValue::Number(1.0),
+                    FieldValue::Number(20.0)
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_quantile_sketch() {
+        let mut sketch = QuantileSketch::default();
+        for value in 1..=1000 {
+            sketch.add(value as f64);
+        }
+        for (q, expected) in [(0.5, 500.0), (0.95, 950.0), (0.99, 990.0), (1.0, 1000.0)] {
+            let value = sketch.quantile(q, 1000).unwrap();
+            assert!(
+                (value - expected).abs() <= expected * RELATIVE_ACCURACY,
+                "q{}: {}",
+                q,
+                value
+            );
+        }
+
+        let mut sketch = QuantileSketch::default();
+        for value in [-5.0, 0.0, 0.0, 3.0] {
+            sketch.add(value);
+        }
+        assert!((sketch.quantile(0.0, 4).unwrap() + 5.0).abs() < 0.05);
+        assert_eq!(sketch.quantile(0.5, 4), Some(0.0));
+        assert_eq!(QuantileSketch::default().quantile(0.5, 0), None);
+    }
+
+    #[test]
+    fn test_numeric_aggregates() {
+        let mut aggregates = NumericAggregates::parse(&[
+            "latency_ms:count,min,mean,p50,p99,max,sum".to_string(),
+            "size".to_string(),
+        ])
+        .unwrap();
+        for latency in ["12", "30", "oops", "18", "240"] {
+            let mut event = Event::new();
+            event.set_field(
+                "latency_ms".to_string(),
+                FieldValue::String(latency.to_string()),
+            );
+            aggregates.add(&event);
+        }
+        assert_eq!(
+            aggregates.summaries(),
+            [
+                "latency_ms: count=4 min=12 mean=75 p50=30 p99=240 max=240 sum=300",
+                "size: no numeric values",
+            ]
+        );
+        assert_eq!(
+            aggregates.values()[..2],
+            [
+                ("latency_ms count".to_string(), 4.0),
+                ("latency_ms min".to_string(), 12.0)
+            ]
+        );
+        assert_eq!(aggregates.values().len(), 7);
+
+        assert_eq!(Statistic::parse("P99.9"), Ok(Statistic::Percentile(99.9)));
+        assert_eq!(Statistic::parse("median"), Ok(Statistic::Percentile(50.0)));
+        assert!(Statistic::parse("p101").is_err());
+        assert!(NumericAggregates::parse(&["latency_ms:stddev".to_string()]).is_err());
+        assert!(NumericAggregates::parse(&[":max".to_string()]).is_err());
+        assert_eq!(format_number(round_significant(1234.5678, 3)), "1230");
+        assert_eq!(format_number(0.0123456), "0.012");
+    }
+
+    #[test]
+    fn test_aggregate_percentiles() {
+        let mut exact = FieldAggregate::new("n".to_string(), vec![Statistic::Percentile(50.0)]);
+        let mut approximate = FieldAggregate::new("n".to_string(), vec![]);
+        for value in 1..=EXACT_VALUES {
+            exact.add(value as f64);
+        }
+        for value in 1..=10 * EXACT_VALUES {
+            approximate.add(value as f64);
+        }
+        assert!(exact.exact.is_some());
+        assert_eq!(exact.percentile(50.0), 501.0);
+        assert!(approximate.exact.is_none());
+        let p95 = approximate.percentile(95.0);
+        assert!(
+            (p95 - 9500.0).abs() <= 9500.0 * RELATIVE_ACCURACY,
+            "{}",
+            p95
+        );
+        assert_eq!(approximate.percentile(100.0), 10000.0);
+    }
+
+    fn histogram(values: &[&str], buckets: usize, log_scale: bool) -> String {
+        let mut histogram = Histogram::new("latency_ms".to_string(), buckets, log_scale);
+        for value in values {
+            let mut event = Event::new();
+            event.set_field(
+                "latency_ms".to_string(),
+                FieldValue::String(value.to_string()),
+            );
+            histogram.add(&event);
+        }
+        let mut out = Vec::new();
+        histogram.write_report(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_histogram() {
+        let values = ["1", "2", "2", "3", "4", "4", "4", "4", "10", "oops"];
+        assert_eq!(
+            histogram(&values, 3, false),
+            "latency_ms: 9 values, min 1, max 10\n\
+            \x20        1 .. 4                4  ########################################\n\
+            \x20        4 .. 7                4  ########################################\n\
+            \x20        7 .. 10               1  ##########\n"
+        );
+        assert_eq!(
+            histogram(&["0", "1", "10", "100", "1000"], 3, true),
+            "latency_ms: 4 values, min 1, max 1000, log scale\n\
+            \x20        1 .. 10               1  ####################\n\
+            \x20       10 .. 100              1  ####################\n\
+            \x20      100 .. 1000             2  ########################################\n"
+        );
+        assert_eq!(
+            histogram(&["5", "5"], 10, false),
+            "latency_ms: 2 values, min 5, max 5\n\
+            \x20        5 .. 5                2  ########################################\n"
+        );
+        assert_eq!(
+            histogram(&["x"], 10, true),
+            "latency_ms: no positive numeric values\n"
+        );
+
+        // Beyond the values kept, buckets are filled from the sketch
+        let mut histogram = Histogram::new("n".to_string(), 4, false);
+        for value in 1..=10 * EXACT_VALUES {
+            let mut event = Event::new();
+            event.set_field("n".to_string(), FieldValue::Number(value as f64));
+            histogram.add(&event);
+        }
+        for (_, _, count) in histogram.counts() {
+            assert!(count.abs_diff(2500) <= 50, "{}", count);
+        }
+    }
+
+    #[test]
+    fn test_event_timeline() {
+        let mut split =
+            EventTimeline::new(Duration::seconds(30), Some("level".to_string())).unwrap();
+        let mut total = EventTimeline::new(Duration::minutes(1), None).unwrap();
+        for (time, level) in [
+            ("2024-03-14T10:00:05Z", Some("info")),
+            ("2024-03-14T10:00:40Z", Some("ERROR")),
+            ("2024-03-14T10:00:41Z", Some("info")),
+            ("2024-03-14T10:02:10Z", None),
+        ] {
+            let mut event = Event::new();
+            event.timestamp = Some(time.parse().unwrap());
+            event.level = level.map(str::to_string);
+            split.add(&event);
+            total.add(&event);
+        }
+        // Without a timestamp
+        split.add(&Event::new());
+
+        let report = |timeline: &EventTimeline, csv: bool| {
+            let mut out = Vec::new();
+            timeline.write_report(csv, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(
+            report(&split, false),
+            "time      error   info   none    count\n\
+            10:00:00      0      1      0        1  ####################\n\
+            10:00:30      1      1      0        2  ########################################\n\
+            10:01:00      0      0      0        0\n\
+            10:01:30      0      0      0        0\n\
+            10:02:00      0      0      1        1  ####################\n"
+        );
+        assert_eq!(
+            report(&total, true),
+            "time,count\n\
+            2024-03-14T10:00:00Z,3\n\
+            2024-03-14T10:01:00Z,0\n\
+            2024-03-14T10:02:00Z,1\n"
+        );
+        assert!(EventTimeline::new(Duration::zero(), None).is_err());
+
+        let mut crosstab =
+            EventTimeline::new(Duration::hours(1), Some("status".to_string())).unwrap();
+        for (time, status) in [
+            ("2024-03-14T10:10:00Z", "500"),
+            ("2024-03-14T10:20:00Z", "200"),
+            ("2024-03-14T11:05:00Z", "200"),
+        ] {
+            let mut event = Event::new();
+            event.timestamp = Some(time.parse().unwrap());
+            event.set_field("status".to_string(), FieldValue::String(status.to_string()));
+            crosstab.add(&event);
+        }
+        assert_eq!(
+            report(&crosstab, true),
+            "time,200,500,count\n\
+            2024-03-14T10:00:00Z,1,1,2\n\
+            2024-03-14T11:00:00Z,1,0,1\n"
+        );
+
+        // An outlier decades earlier doesn't fill in every second since
+        let mut outlier = EventTimeline::new(Duration::seconds(1), None).unwrap();
+        for time in ["1971-01-01T00:00:00Z", "2024-03-14T10:00:00Z"] {
+            let mut event = Event::new();
+            event.timestamp = Some(time.parse().unwrap());
+            outlier.add(&event);
+        }
+        assert_eq!(
+            report(&outlier, true),
+            "time,count\n1971-01-01T00:00:00Z,1\n2024-03-14T10:00:00Z,1\n"
+        );
+    }
+
+    #[test]
+    fn test_activity_sparklines() {
+        let mut activity = Activity::default();
+        let start: DateTime<Utc> = "2024-03-14T10:00:00Z".parse().unwrap();
+        let mut add = |second: i64, level: &str| {
+            let mut event = Event::new();
+            event.timestamp = Some(start + Duration::seconds(second));
+            event.level = Some(level.to_string());
+            activity.add(&event);
+        };
+        for _ in 0..8 {
+            add(0, "info");
+        }
+        add(1, "info");
+        for _ in 0..4 {
+            add(3, "ERROR");
+        }
+        add(4, "warn");
+
+        let sparklines = activity.sparklines(5).unwrap();
+        assert_eq!(sparklines.events, "█▁ ▄▁");
+        assert_eq!(sparklines.errors.as_deref(), Some("   █ "));
+        assert_eq!(sparklines.peak_events, 8);
+        assert_eq!(sparklines.peak_errors, 4);
+        assert_eq!(sparklines.per_mark, Duration::milliseconds(801));
+
+        // Coarser buckets keep the counts
+        for _ in 0..12 {
+            activity.coarsen();
+        }
+        assert_eq!(activity.resolution_ms, 4096);
+        assert_eq!(
+            activity
+                .counts
+                .values()
+                .map(|counts| counts.0)
+                .sum::<usize>(),
+            14
+        );
+        assert!(activity.counts.len() <= 2);
+
+        let mut activity = Activity::default();
+        for second in 0..3 {
+            let mut event = Event::new();
+            event.timestamp = Some(start + Duration::seconds(second));
+            activity.add(&event);
+        }
+        let sparklines = activity.sparklines(2).unwrap();
+        assert_eq!(sparklines.events, "█▄");
+        assert_eq!(sparklines.errors, None);
+        assert_eq!(Activity::default().sparklines(40), None);
+    }
+
+    #[test]
+    fn test_hyperloglog() {
+        for n in [0, 1, 10, 1000, 100_000] {
+            let mut distinct = HyperLogLog::new();
+            // Repeats don't count
+            for _ in 0..2 {
+                for i in 0..n {
+                    distinct.add(&format!("user{}", i));
+                }
+            }
+            let estimate = distinct.estimate();
+            assert!(
+                (estimate - n as f64).abs() <= n as f64 * 0.02,
+                "{}: {}",
+                n,
+                estimate
+            );
+        }
+    }
+
+    #[test]
+    fn test_cardinality() {
+        let mut cardinality = Cardinality::new(vec!["user".to_string(), "path".to_string()]);
+        for (user, path) in [("alice", "/a"), ("bob", "/a"), ("alice", "/b")] {
+            let mut event = Event::new();
+            event.set_field("user".to_string(), FieldValue::String(user.to_string()));
+            if path == "/a" {
+                event.set_field("path".to_string(), FieldValue::String(path.to_string()));
+            }
+            cardinality.add(&event);
+        }
+        let rows: Vec<(FieldValue, FieldValue, FieldValue)> = cardinality
+            .finish()
+            .into_iter()
+            .map(|row| {
+                (
+                    row.fields["field"].clone(),
+                    row.fields["distinct"].clone(),
+                    row.fields["events"].clone(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                (
+                    FieldValue::String("user".to_string()),
+                    FieldValue::Number(2.0),
+                    FieldValue::Number(3.0)
+                ),
+                (
+                    FieldValue::String("path".to_string()),
+                    FieldValue::Number(1.0),
+                    FieldValue::Number(2.0)
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_duration_stats() {
+        let mut durations = DurationStats::new(vec!["took".to_string()], true);
+        for (took, latency_ms, status) in [(100.0, 2.0, 200.0), (300.0, 40.0, 500.0)] {
+            let mut event = Event::new();
+            event.set_field("took".to_string(), FieldValue::Number(took));
+            event.set_field("latency_ms".to_string(), FieldValue::Number(latency_ms));
+            event.set_field("status".to_string(), FieldValue::Number(status));
+            event.set_field(
+                "total_ms".to_string(),
+                FieldValue::String("n/a".to_string()),
+            );
+            durations.add(&event);
+        }
+        assert_eq!(
+            durations.summaries(),
+            [
+                "took (ms): min=100 mean=200 p95=300 max=300",
+                "latency_ms (ms): min=2 mean=21 p95=40 max=40",
+            ]
+        );
+
+        let mut declared = DurationStats::new(Vec::new(), false);
+        let mut event = Event::new();
+        event.set_field("latency_ms".to_string(), FieldValue::Number(5.0));
+        declared.add(&event);
+        assert!(declared.summaries().is_empty());
+    }
+
+    #[test]
+    fn test_schema() {
+        let mut schema = Schema::default();
+        for (status, user) in [
+            (FieldValue::Number(200.0), Some("alice")),
+            (FieldValue::String("n/a".to_string()), None),
+            (FieldValue::Number(200.0), Some(&*"x".repeat(50))),
+            (FieldValue::Number(500.0), Some("bob")),
+        ] {
+            let mut event = Event::new();
+            event.set_field("status".to_string(), status);
+            if let Some(user) = user {
+                event.set_field("user".to_string(), FieldValue::String(user.to_string()));
+            }
+            schema.add(&event);
+        }
+        let rows: Vec<Vec<FieldValue>> = schema
+            .finish()
+            .into_iter()
+            .map(|row| row.fields.into_values().collect())
+            .collect();
+        let text = |value: &str| FieldValue::String(value.to_string());
+        assert_eq!(
+            rows,
+            [
+                vec![
+                    text("status"),
+                    FieldValue::Number(4.0),
+                    FieldValue::Number(100.0),
+                    text("number,string"),
+                    text("200, n/a, 500"),
+                ],
+                vec![
+                    text("user"),
+                    FieldValue::Number(3.0),
+                    FieldValue::Number(75.0),
+                    text("string"),
+                    text(&format!("alice, {}…, bob", "x".repeat(40))),
+                ],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_line_lengths() {
+        let mut lengths = LineLengths::default();
+        assert_eq!(lengths.summary(), None);
+        for length in (1..=100).chain([65536]) {
+            lengths.add(length);
+        }
+        assert_eq!(
+            lengths.summary().as_deref(),
+            Some("Line length: p50=51 p95=96 p99=100 max=65536")
+        );
+    }
+}
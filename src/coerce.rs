@@ -0,0 +1,196 @@
+use crate::event::{Event, FieldValue};
+use crate::normalize::parse_duration_ms;
+
+/// Declared type of a field for `--types`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldType {
+    Int,
+    Float,
+    Bool,
+    String,
+    /// A duration such as `1.5s` or `230ms` as milliseconds; plain numbers are taken as
+    /// milliseconds already
+    Duration,
+}
+
+impl FieldType {
+    fn parse(name: &str) -> Result<Self, String> {
+        match name.trim().to_lowercase().as_str() {
+            "int" | "integer" => Ok(FieldType::Int),
+            "float" | "number" => Ok(FieldType::Float),
+            "bool" | "boolean" => Ok(FieldType::Bool),
+            "string" | "str" => Ok(FieldType::String),
+            "duration" => Ok(FieldType::Duration),
+            other => Err(format!(
+                "unknown type '{}' (use int, float, bool, string or duration)",
+                other
+            )),
+        }
+    }
+
+    /// The value as this type, or None when it can't be converted
+    fn coerce(self, value: &FieldValue) -> Option<FieldValue> {
+        match (self, value) {
+            (_, FieldValue::Null) => Some(FieldValue::Null),
+            (FieldType::String, value) => Some(FieldValue::String(value.to_string())),
+            (FieldType::Float, FieldValue::Number(n)) => Some(FieldValue::Number(*n)),
+            (FieldType::Float, FieldValue::String(s)) => {
+                s.trim().parse().ok().map(FieldValue::Number)
+            }
+            (FieldType::Int, FieldValue::Number(n)) => {
+                (n.fract() == 0.0).then_some(FieldValue::Number(*n))
+            }
+            (FieldType::Int, FieldValue::String(s)) => {
+                let n: f64 = s.trim().parse().ok()?;
+                (n.fract() == 0.0).then_some(FieldValue::Number(n))
+            }
+            (FieldType::Bool, FieldValue::Boolean(b)) => Some(FieldValue::Boolean(*b)),
+            (FieldType::Bool, FieldValue::Number(n)) if *n == 0.0 || *n == 1.0 => {
+                Some(FieldValue::Boolean(*n == 1.0))
+            }
+            (FieldType::Bool, FieldValue::String(s)) => match s.trim().to_lowercase().as_str() {
+                "true" | "yes" | "on" | "1" => Some(FieldValue::Boolean(true)),
+                "false" | "no" | "off" | "0" => Some(FieldValue::Boolean(false)),
+                _ => None,
+            },
+            (FieldType::Duration, FieldValue::Number(n)) => Some(FieldValue::Number(*n)),
+            (FieldType::Duration, FieldValue::String(s)) => s
+                .trim()
+                .parse()
+                .ok()
+                .or_else(|| parse_duration_ms(s))
+                .map(FieldValue::Number),
+            _ => None,
+        }
+    }
+}
+
+/// Forces fields into declared types (`--types status:int,zip:string`), overriding what was
+/// guessed from the text. Values that don't convert are left as they are and counted
+#[derive(Debug, Clone, Default)]
+pub struct TypeCoercion {
+    types: Vec<(String, FieldType)>,
+}
+
+impl TypeCoercion {
+    /// Parse `KEY:TYPE` specs
+    pub fn parse(specs: &[String]) -> Result<Self, String> {
+        let types = specs
+            .iter()
+            .map(|spec| {
+                let (key, name) = spec
+                    .rsplit_once(':')
+                    .filter(|(key, _)| !key.trim().is_empty())
+                    .ok_or_else(|| format!("expected KEY:TYPE, got '{}'", spec))?;
+                Ok((key.trim().to_string(), FieldType::parse(name)?))
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Self { types })
+    }
+
+    /// Keys declared as strings, whose text parsers shouldn't turn into numbers in the
+    /// first place (so ZIP codes keep their leading zeros)
+    pub fn text_keys(&self) -> Vec<String> {
+        self.types
+            .iter()
+            .filter(|(_, field_type)| *field_type == FieldType::String)
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+
+    /// Keys declared as durations, in milliseconds once converted
+    pub fn duration_keys(&self) -> Vec<String> {
+        self.types
+            .iter()
+            .filter(|(_, field_type)| *field_type == FieldType::Duration)
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+
+    /// Convert the fields, returning how many couldn't be
+    pub fn apply(&self, event: &mut Event) -> usize {
+        let mut failures = 0;
+        for (key, field_type) in &self.types {
+            let Some(value) = event.fields.get(key) else {
+                continue;
+            };
+            match field_type.coerce(value) {
+                Some(coerced) => {
+                    if coerced != *value {
+                        event.assign_field(key, coerced);
+                    }
+                }
+                None => failures += 1,
+            }
+        }
+        failures
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_coerce_types() {
+        let types = TypeCoercion::parse(&[
+            "status:int".to_string(),
+            "latency:float".to_string(),
+            "success:bool".to_string(),
+            "version:string".to_string(),
+            "retries:int".to_string(),
+        ])
+        .unwrap();
+        assert_eq!(types.text_keys(), ["version"]);
+
+        let mut event = Event::new();
+        for (key, value) in [
+            ("status", FieldValue::String("503".to_string())),
+            ("latency", FieldValue::String(" 12.5 ".to_string())),
+            ("success", FieldValue::String("no".to_string())),
+            ("version", FieldValue::Number(1.1)),
+            ("retries", FieldValue::String("many".to_string())),
+        ] {
+            event.set_field(key.to_string(), value);
+        }
+        assert_eq!(types.apply(&mut event), 1);
+        assert_eq!(event.fields["status"], FieldValue::Number(503.0));
+        assert_eq!(event.fields["latency"], FieldValue::Number(12.5));
+        assert_eq!(event.fields["success"], FieldValue::Boolean(false));
+        assert_eq!(
+            event.fields["version"],
+            FieldValue::String("1.1".to_string())
+        );
+        assert_eq!(
+            event.fields["retries"],
+            FieldValue::String("many".to_string())
+        );
+
+        assert_eq!(FieldType::Int.coerce(&FieldValue::Number(2.5)), None);
+        assert_eq!(
+            FieldType::Bool.coerce(&FieldValue::Number(1.0)),
+            Some(FieldValue::Boolean(true))
+        );
+        assert!(TypeCoercion::parse(&["status".to_string()]).is_err());
+        assert!(TypeCoercion::parse(&["status:date".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_coerce_durations() {
+        let types =
+            TypeCoercion::parse(&["took:duration".to_string(), "status:int".to_string()]).unwrap();
+        assert_eq!(types.duration_keys(), ["took"]);
+
+        let mut event = Event::new();
+        for (took, expected) in [
+            ("1m30s", FieldValue::Number(90000.0)),
+            (" 230ms", FieldValue::Number(230.0)),
+            ("12.5", FieldValue::Number(12.5)),
+            ("soon", FieldValue::String("soon".to_string())),
+        ] {
+            event.set_field("took".to_string(), FieldValue::String(took.to_string()));
+            types.apply(&mut event);
+            assert_eq!(event.fields["took"], expected);
+        }
+    }
+}
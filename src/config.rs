@@ -0,0 +1,207 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Deserializer};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Settings read from the config file (`--config`, `$KELORA_CONFIG`, or
+/// `~/.config/kelora/config.toml`)
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Named filter sets for `--apply`
+    #[serde(default)]
+    pub filters: BTreeMap<String, FilterSet>,
+    /// Canonical key names and their aliases for `--normalize-keys`, e.g.
+    /// `level = ["severity", "lvl"]`
+    #[serde(default, deserialize_with = "alias_map")]
+    pub aliases: BTreeMap<String, Vec<String>>,
+}
+
+/// A reusable combination of filters and key selections, e.g.
+///
+/// ```toml
+/// [filters.prod-errors]
+/// level = "error"
+/// where = ["status>=500", "env=prod"]
+/// ```
+///
+/// Every list also accepts a single string, which for levels, sources and keys may be
+/// comma-separated as on the command line
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct FilterSet {
+    #[serde(deserialize_with = "comma_separated")]
+    pub level: Vec<String>,
+    pub min_level: Option<String>,
+    #[serde(rename = "where", deserialize_with = "one_or_many")]
+    pub where_filters: Vec<String>,
+    #[serde(deserialize_with = "one_or_many")]
+    pub where_not: Vec<String>,
+    pub grep: Option<String>,
+    #[serde(deserialize_with = "one_or_many")]
+    pub grep_field: Vec<String>,
+    #[serde(deserialize_with = "one_or_many")]
+    pub grep_v: Vec<String>,
+    #[serde(deserialize_with = "one_or_many")]
+    pub filter: Vec<String>,
+    #[serde(deserialize_with = "comma_separated")]
+    pub source: Vec<String>,
+    pub since: Option<String>,
+    pub until: Option<String>,
+    #[serde(deserialize_with = "comma_separated")]
+    pub keys: Vec<String>,
+    #[serde(deserialize_with = "comma_separated")]
+    pub exclude_keys: Vec<String>,
+}
+
+impl Config {
+    pub fn parse(text: &str) -> Result<Self> {
+        Ok(toml::from_str(text)?)
+    }
+
+    /// Load the config file given, or else the default one
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => default_path().context("No config file: set --config or KELORA_CONFIG")?,
+        };
+        let text = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        Self::parse(&text).with_context(|| format!("Invalid config file {}", path.display()))
+    }
+
+    /// Like [`Config::load`], but without a config file given or at the default place, the
+    /// config is empty
+    pub fn load_if_present(path: Option<&Path>) -> Result<Self> {
+        match path {
+            Some(path) => Self::load(Some(path)),
+            None => match default_path() {
+                Some(path) if path.exists() => Self::load(Some(&path)),
+                _ => Ok(Self::default()),
+            },
+        }
+    }
+
+    pub fn filter_set(&self, name: &str) -> Result<&FilterSet> {
+        self.filters.get(name).with_context(|| {
+            let known: Vec<&str> = self.filters.keys().map(String::as_str).collect();
+            if known.is_empty() {
+                format!("Unknown filter set '{}' (none are defined)", name)
+            } else {
+                format!(
+                    "Unknown filter set '{}' (defined: {})",
+                    name,
+                    known.join(", ")
+                )
+            }
+        })
+    }
+}
+
+fn default_path() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os("KELORA_CONFIG") {
+        return Some(PathBuf::from(path));
+    }
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_dir.join("kelora").join("config.toml"))
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl OneOrMany {
+    fn into_vec(self) -> Vec<String> {
+        match self {
+            OneOrMany::One(value) => vec![value],
+            OneOrMany::Many(values) => values,
+        }
+    }
+}
+
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    Ok(OneOrMany::deserialize(deserializer)?.into_vec())
+}
+
+fn split_commas(values: Vec<String>) -> Vec<String> {
+    values
+        .iter()
+        .flat_map(|value| value.split(','))
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+        .collect()
+}
+
+fn comma_separated<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    Ok(split_commas(one_or_many(deserializer)?))
+}
+
+fn alias_map<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<BTreeMap<String, Vec<String>>, D::Error> {
+    Ok(BTreeMap::<String, OneOrMany>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(canonical, aliases)| (canonical, split_commas(aliases.into_vec())))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_filter_sets() {
+        let config = Config::parse(
+            r#"
+[filters.prod-errors]
+level = "error"
+where = ["status>=500", "env=prod"]
+keys = "ts,level,msg"
+
+[filters.recent]
+since = "1h"
+grep-v = "healthz"
+"#,
+        )
+        .unwrap();
+
+        let errors = config.filter_set("prod-errors").unwrap();
+        assert_eq!(errors.level, vec!["error"]);
+        assert_eq!(errors.where_filters, vec!["status>=500", "env=prod"]);
+        assert_eq!(errors.keys, vec!["ts", "level", "msg"]);
+        assert!(errors.since.is_none());
+
+        let recent = config.filter_set("recent").unwrap();
+        assert_eq!(recent.since.as_deref(), Some("1h"));
+        assert_eq!(recent.grep_v, vec!["healthz"]);
+
+        let unknown = config.filter_set("missing").unwrap_err().to_string();
+        assert!(unknown.contains("prod-errors, recent"));
+    }
+
+    #[test]
+    fn test_parse_aliases() {
+        let config = Config::parse(
+            r#"
+[aliases]
+level = ["severity", "lvl"]
+msg = "text,body"
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.aliases["level"], vec!["severity", "lvl"]);
+        assert_eq!(config.aliases["msg"], vec!["text", "body"]);
+        assert!(config.filters.is_empty());
+    }
+
+    #[test]
+    fn test_rejects_unknown_settings() {
+        assert!(Config::parse("[filters.typo]\nlevels = \"error\"\n").is_err());
+        assert!(Config::parse("[filters.bad]\nwhere = 500\n").is_err());
+    }
+}
@@ -0,0 +1,210 @@
+use crate::event::{Event, FieldValue};
+use crate::parsers::{JsonlParser, LogParser, LogfmtParser};
+use base64::alphabet;
+use base64::engine::general_purpose::{GeneralPurpose, GeneralPurposeConfig};
+use base64::engine::DecodePaddingMode;
+use base64::Engine;
+
+/// Base64 with or without padding, in the standard or the URL-safe alphabet
+const BASE64_ENGINES: [GeneralPurpose; 2] = [
+    GeneralPurpose::new(
+        &alphabet::STANDARD,
+        GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+    ),
+    GeneralPurpose::new(
+        &alphabet::URL_SAFE,
+        GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+    ),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    Base64,
+    Hex,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Reparse {
+    Json,
+    Logfmt,
+}
+
+/// Decodes a field's base64 or hex value in place (`--decode FIELD:ENCODING[:FORMAT]`). With
+/// a FORMAT of `json` or `logfmt`, the decoded text is parsed too and its keys replace the
+/// field as `<field>.<key>`. Values that don't decode to UTF-8 text, or don't parse, are left
+/// as they are
+pub struct FieldDecoder {
+    field: String,
+    encoding: Encoding,
+    reparse: Option<(Reparse, Box<dyn LogParser>)>,
+}
+
+impl std::fmt::Debug for FieldDecoder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FieldDecoder")
+            .field("field", &self.field)
+            .field("encoding", &self.encoding)
+            .field("reparse", &self.reparse.as_ref().map(|(format, _)| format))
+            .finish()
+    }
+}
+
+impl FieldDecoder {
+    /// Parse `FIELD:ENCODING[:FORMAT]`, e.g. `payload:base64` or `data:hex:json`
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut parts = spec.splitn(3, ':');
+        let field = parts.next().unwrap_or_default().trim();
+        let encoding = parts.next().map(|name| name.trim().to_lowercase());
+        if field.is_empty() {
+            return Err(format!("expected FIELD:ENCODING[:FORMAT], got '{}'", spec));
+        }
+        let encoding = match encoding.as_deref() {
+            Some("base64") => Encoding::Base64,
+            Some("hex") => Encoding::Hex,
+            Some(name) => return Err(format!("unknown encoding '{}' (use base64 or hex)", name)),
+            None => return Err(format!("expected FIELD:ENCODING[:FORMAT], got '{}'", spec)),
+        };
+        let reparse: Option<(Reparse, Box<dyn LogParser>)> = match parts
+            .next()
+            .map(|name| name.trim().to_lowercase())
+            .as_deref()
+        {
+            None => None,
+            Some("json") => Some((Reparse::Json, Box::new(JsonlParser::new()))),
+            Some("logfmt") => Some((Reparse::Logfmt, Box::new(LogfmtParser::new()))),
+            Some(name) => return Err(format!("unknown format '{}' (use json or logfmt)", name)),
+        };
+        Ok(Self {
+            field: field.to_string(),
+            encoding,
+            reparse,
+        })
+    }
+
+    pub fn apply(&self, event: &mut Event) {
+        // Hex digits alone may have been taken for a number
+        let encoded = match event.fields.get(&self.field) {
+            Some(FieldValue::String(text)) => text.trim().to_string(),
+            Some(number @ FieldValue::Number(_)) => number.to_string(),
+            _ => return,
+        };
+        let Some(decoded) = self.decode(&encoded) else {
+            return;
+        };
+
+        let parsed = self.reparse.as_ref().and_then(|(_, parser)| {
+            parser
+                .parse(&decoded)
+                .ok()
+                .filter(|parsed| !parsed.fields.is_empty())
+        });
+        match parsed {
+            Some(parsed) => {
+                let Some(index) = event.fields.get_index_of(&self.field) else {
+                    return;
+                };
+                event.fields.shift_remove(&self.field);
+                for (offset, (key, value)) in parsed.fields.into_iter().enumerate() {
+                    event.fields.shift_insert(
+                        index + offset,
+                        format!("{}.{}", self.field, key),
+                        value,
+                    );
+                }
+            }
+            None => event.assign_field(&self.field, FieldValue::String(decoded)),
+        }
+    }
+
+    fn decode(&self, encoded: &str) -> Option<String> {
+        let bytes = match self.encoding {
+            Encoding::Base64 => BASE64_ENGINES
+                .iter()
+                .find_map(|engine| engine.decode(encoded).ok())?,
+            Encoding::Hex => hex::decode(encoded).ok()?,
+        };
+        String::from_utf8(bytes).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(key: &str, value: &str) -> Event {
+        let mut event = Event::new();
+        event.set_field("id".to_string(), FieldValue::Number(1.0));
+        event.set_field(key.to_string(), FieldValue::String(value.to_string()));
+        event.set_field("host".to_string(), FieldValue::String("a".to_string()));
+        event
+    }
+
+    fn text(value: &str) -> FieldValue {
+        FieldValue::String(value.to_string())
+    }
+
+    #[test]
+    fn test_decode() {
+        // "user=alice action=login"
+        let mut padded = event("payload", "dXNlcj1hbGljZSBhY3Rpb249bG9naW4=");
+        FieldDecoder::parse("payload:base64")
+            .unwrap()
+            .apply(&mut padded);
+        assert_eq!(padded.fields["payload"], text("user=alice action=login"));
+
+        // "???" in the URL-safe alphabet
+        let mut url_safe = event("payload", "Pz8_");
+        FieldDecoder::parse("payload:BASE64")
+            .unwrap()
+            .apply(&mut url_safe);
+        assert_eq!(url_safe.fields["payload"], text("???"));
+
+        let mut hex = event("data", "68656c6c6f");
+        FieldDecoder::parse("data:hex").unwrap().apply(&mut hex);
+        assert_eq!(hex.fields["data"], text("hello"));
+
+        let mut digits = event("data", "");
+        digits.set_field("data".to_string(), FieldValue::Number(6869.0));
+        FieldDecoder::parse("data:hex").unwrap().apply(&mut digits);
+        assert_eq!(digits.fields["data"], text("hi"));
+
+        let mut invalid = event("data", "not hex");
+        FieldDecoder::parse("data:hex").unwrap().apply(&mut invalid);
+        assert_eq!(invalid.fields["data"], text("not hex"));
+
+        // Binary data stays encoded
+        let mut binary = event("data", "fffe00");
+        FieldDecoder::parse("data:hex").unwrap().apply(&mut binary);
+        assert_eq!(binary.fields["data"], text("fffe00"));
+
+        assert!(FieldDecoder::parse("payload").is_err());
+        assert!(FieldDecoder::parse("payload:rot13").is_err());
+        assert!(FieldDecoder::parse("payload:hex:xml").is_err());
+        assert!(FieldDecoder::parse(":hex").is_err());
+    }
+
+    #[test]
+    fn test_decode_and_reparse() {
+        // {"user":"alice","status":403}
+        let mut json = event("payload", "eyJ1c2VyIjoiYWxpY2UiLCJzdGF0dXMiOjQwM30=");
+        FieldDecoder::parse("payload:base64:json")
+            .unwrap()
+            .apply(&mut json);
+        let keys: Vec<&String> = json.fields.keys().collect();
+        assert_eq!(keys, ["id", "payload.user", "payload.status", "host"]);
+        assert_eq!(json.fields["payload.status"], FieldValue::Number(403.0));
+
+        let mut logfmt = event("payload", "dXNlcj1hbGljZSBhY3Rpb249bG9naW4=");
+        FieldDecoder::parse("payload:base64:logfmt")
+            .unwrap()
+            .apply(&mut logfmt);
+        assert_eq!(logfmt.fields["payload.action"], text("login"));
+
+        // Text that doesn't parse is still decoded: "plain text"
+        let mut plain = event("payload", "706c61696e2074657874");
+        FieldDecoder::parse("payload:hex:json")
+            .unwrap()
+            .apply(&mut plain);
+        assert_eq!(plain.fields["payload"], text("plain text"));
+    }
+}
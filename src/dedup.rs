@@ -0,0 +1,480 @@
+use crate::drain::Drain;
+use crate::event::{Event, FieldValue, TIMESTAMP_KEYS};
+use chrono::{DateTime, Utc};
+use indexmap::IndexMap;
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+
+/// Collapses runs of consecutive duplicate events into their first event. A merged event gets
+/// `repeat_count`, plus `first_seen`/`last_seen` when the events have timestamps.
+///
+/// Events are duplicates when everything but the timestamp is equal, so a retry storm logging
+/// the same line every few milliseconds becomes a single event, or, with selected keys, when
+/// just those values are equal, even if counters or request IDs differ.
+#[derive(Default)]
+pub struct Deduplicator {
+    keys: Vec<String>,
+    pending: Option<Run>,
+    suppressed: usize,
+}
+
+struct Run {
+    event: Event,
+    key: String,
+    count: usize,
+    last_seen: Option<DateTime<Utc>>,
+}
+
+impl Deduplicator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Compare only these keys (core field aliases work)
+    pub fn with_keys(mut self, keys: Vec<String>) -> Self {
+        self.keys = keys;
+        self
+    }
+
+    /// Add an event; returns the previous run once an event that differs from it arrives
+    pub fn push(&mut self, event: Event) -> Option<Event> {
+        let key = self.dedup_key(&event);
+        if let Some(run) = &mut self.pending {
+            if run.key == key {
+                run.count += 1;
+                run.last_seen = event.timestamp.or(run.last_seen);
+                self.suppressed += 1;
+                return None;
+            }
+        }
+        let previous = self.flush();
+        self.pending = Some(Run {
+            last_seen: event.timestamp,
+            event,
+            key,
+            count: 1,
+        });
+        previous
+    }
+
+    /// Take the run in progress, e.g. at the end of the input
+    pub fn flush(&mut self) -> Option<Event> {
+        let run = self.pending.take()?;
+        let mut event = run.event;
+        if run.count > 1 {
+            event.set_field(
+                "repeat_count".to_string(),
+                FieldValue::Number(run.count as f64),
+            );
+            let format = |ts: DateTime<Utc>| {
+                FieldValue::String(ts.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string())
+            };
+            if let (Some(first), Some(last)) = (event.timestamp, run.last_seen) {
+                event.set_field("first_seen".to_string(), format(first));
+                event.set_field("last_seen".to_string(), format(last));
+            }
+        }
+        Some(event)
+    }
+
+    /// Number of events merged into an earlier one
+    pub fn suppressed(&self) -> usize {
+        self.suppressed
+    }
+
+    /// The compared values, in a form that is equal for duplicates
+    fn dedup_key(&self, event: &Event) -> String {
+        if !self.keys.is_empty() {
+            let values: Vec<Option<FieldValue>> =
+                self.keys.iter().map(|key| event.get_value(key)).collect();
+            return serde_json::to_string(&values).unwrap_or_default();
+        }
+        let fields: Vec<(&String, &FieldValue)> = event
+            .fields
+            .iter()
+            .filter(|(key, _)| !TIMESTAMP_KEYS.contains(&key.as_str()))
+            .collect();
+        serde_json::to_string(&(&event.level, &event.message, fields)).unwrap_or_default()
+    }
+}
+
+/// Which event to keep per distinct value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Occurrence {
+    First,
+    Last,
+}
+
+/// Keeps one event per distinct value of a key (`--first-of`/`--last-of`). Events without the
+/// key share a single group. First occurrences stream through as they are seen; last
+/// occurrences are only known at the end, where they come out in order of first appearance.
+/// With a Drain miner, events are grouped by the template their message is mined into instead.
+pub struct DistinctFilter {
+    key: String,
+    keep: Occurrence,
+    templates: Option<Drain>,
+    seen: HashSet<String>,
+    latest: IndexMap<String, Event>,
+    /// Last occurrences handed out by `finish`
+    released: usize,
+    total: usize,
+}
+
+impl DistinctFilter {
+    pub fn new(key: &str, keep: Occurrence) -> Self {
+        Self {
+            key: key.to_string(),
+            keep,
+            templates: None,
+            seen: HashSet::new(),
+            latest: IndexMap::new(),
+            released: 0,
+            total: 0,
+        }
+    }
+
+    /// Group by Drain template instead of by the key's value
+    pub fn with_templates(mut self, drain: Drain) -> Self {
+        self.templates = Some(drain);
+        self
+    }
+
+    /// Add an event; returns it if it should be output right away
+    pub fn push(&mut self, event: Event) -> Option<Event> {
+        self.total += 1;
+        let value = match &mut self.templates {
+            // A cluster keeps its index as its template is generalized
+            Some(drain) => drain
+                .add_event(&event)
+                .map(|index| index.to_string())
+                .unwrap_or_default(),
+            None => serde_json::to_string(&event.get_value(&self.key)).unwrap_or_default(),
+        };
+        match self.keep {
+            Occurrence::First => self.seen.insert(value).then_some(event),
+            Occurrence::Last => {
+                self.latest.insert(value, event);
+                None
+            }
+        }
+    }
+
+    /// The held-back last occurrences, at the end of the input
+    pub fn finish(&mut self) -> Vec<Event> {
+        self.released += self.latest.len();
+        std::mem::take(&mut self.latest).into_values().collect()
+    }
+
+    /// Number of events left out so far
+    pub fn suppressed(&self) -> usize {
+        self.total - self.seen.len() - self.latest.len() - self.released
+    }
+}
+
+/// Distinct values `--distinct` tells apart exactly, before it turns approximate
+const DISTINCT_EXACT: usize = 1_000_000;
+
+/// Bits of the Bloom filter `--distinct` uses beyond that (8 MiB)
+const BLOOM_BITS: u64 = 1 << 26;
+
+/// Bits set per value in the Bloom filter
+const BLOOM_HASHES: u64 = 7;
+
+/// Streams each value of a key the first time it is seen (`--distinct path`), as an event
+/// with just that key and the timestamp of the event it came in. Values are held as 64-bit
+/// hashes, so the first million are told apart exactly in about 20 MiB; beyond that they go
+/// into a fixed-size Bloom filter, where a new value is taken for a seen one about once in
+/// 500 at 5 million values and once in 20 at 10 million
+pub struct DistinctValues {
+    key: String,
+    limit: usize,
+    seen: Seen,
+}
+
+enum Seen {
+    Exact(HashSet<u64>),
+    Approximate(Vec<u64>),
+}
+
+impl DistinctValues {
+    pub fn new(key: &str) -> Self {
+        Self::with_limit(key, DISTINCT_EXACT)
+    }
+
+    fn with_limit(key: &str, limit: usize) -> Self {
+        Self {
+            key: key.to_string(),
+            limit,
+            seen: Seen::Exact(HashSet::new()),
+        }
+    }
+
+    /// The event to output when the event has a value of the key not seen before
+    pub fn add(&mut self, event: &Event) -> Option<Event> {
+        let value = event.get_value(&self.key)?;
+        let mut hasher = std::hash::DefaultHasher::new();
+        value.to_string().hash(&mut hasher);
+        let hash = hasher.finish();
+
+        let new = match &mut self.seen {
+            Seen::Exact(hashes) => {
+                let new = hashes.insert(hash);
+                if hashes.len() > self.limit {
+                    let mut bits = vec![0; (BLOOM_BITS / 64) as usize];
+                    for &hash in hashes.iter() {
+                        bloom_insert(&mut bits, hash);
+                    }
+                    self.seen = Seen::Approximate(bits);
+                }
+                new
+            }
+            Seen::Approximate(bits) => bloom_insert(bits, hash),
+        };
+        new.then(|| {
+            let mut distinct = Event::new();
+            distinct.timestamp = event.timestamp;
+            distinct.set_field(self.key.clone(), value);
+            distinct
+        })
+    }
+
+    /// Whether values are past the exact limit, so some new ones may have been missed
+    pub fn is_approximate(&self) -> bool {
+        matches!(self.seen, Seen::Approximate(_))
+    }
+}
+
+/// Set the bits of a hash, telling whether any of them wasn't set yet
+fn bloom_insert(bits: &mut [u64], hash: u64) -> bool {
+    let (first, step) = (hash & 0xffff_ffff, (hash >> 32) | 1);
+    let mut new = false;
+    for i in 0..BLOOM_HASHES {
+        let bit = first.wrapping_add(i.wrapping_mul(step)) % BLOOM_BITS;
+        let (word, mask) = ((bit / 64) as usize, 1 << (bit % 64));
+        new |= bits[word] & mask == 0;
+        bits[word] |= mask;
+    }
+    new
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(message: &str, ts: &str) -> Event {
+        let mut event = Event::new();
+        event.timestamp = Some(ts.parse().unwrap());
+        event.message = Some(message.to_string());
+        event.set_field("ts".to_string(), FieldValue::String(ts.to_string()));
+        event
+    }
+
+    #[test]
+    fn test_consecutive_duplicates_collapse() {
+        let mut dedup = Deduplicator::new();
+        let mut out = Vec::new();
+        for (message, ts) in [
+            ("retrying", "2024-03-14T10:00:00Z"),
+            ("retrying", "2024-03-14T10:00:01Z"),
+            ("retrying", "2024-03-14T10:00:02Z"),
+            ("connected", "2024-03-14T10:00:03Z"),
+            ("retrying", "2024-03-14T10:00:04Z"),
+        ] {
+            out.extend(dedup.push(event(message, ts)));
+        }
+        out.extend(dedup.flush());
+
+        let summary: Vec<(String, Option<FieldValue>)> = out
+            .iter()
+            .map(|e| {
+                (
+                    e.message.clone().unwrap(),
+                    e.fields.get("repeat_count").cloned(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("retrying".to_string(), Some(FieldValue::Number(3.0))),
+                ("connected".to_string(), None),
+                ("retrying".to_string(), None),
+            ]
+        );
+        assert_eq!(dedup.suppressed(), 2);
+        assert_eq!(
+            out[0].timestamp,
+            Some("2024-03-14T10:00:00Z".parse().unwrap()),
+            "The first event of a run is kept"
+        );
+    }
+
+    #[test]
+    fn test_differing_fields_are_not_duplicates() {
+        let mut dedup = Deduplicator::new();
+        let mut first = event("retrying", "2024-03-14T10:00:00Z");
+        first.set_field("attempt".to_string(), FieldValue::Number(1.0));
+        let mut second = event("retrying", "2024-03-14T10:00:01Z");
+        second.set_field("attempt".to_string(), FieldValue::Number(2.0));
+
+        assert!(dedup.push(first).is_none());
+        assert!(dedup.push(second).is_some());
+        assert_eq!(dedup.suppressed(), 0);
+    }
+
+    #[test]
+    fn test_dedup_by_keys() {
+        let mut dedup = Deduplicator::new().with_keys(vec!["msg".to_string(), "host".to_string()]);
+        let mut out = Vec::new();
+        for (attempt, ts) in [
+            (1.0, "2024-03-14T10:00:00Z"),
+            (2.0, "2024-03-14T10:00:05Z"),
+            (3.0, "2024-03-14T10:00:09Z"),
+        ] {
+            let mut event = event("retrying", ts);
+            event.set_field("attempt".to_string(), FieldValue::Number(attempt));
+            event.set_field("host".to_string(), FieldValue::String("web01".to_string()));
+            out.extend(dedup.push(event));
+        }
+        out.extend(dedup.flush());
+
+        assert_eq!(out.len(), 1);
+        let merged = &out[0];
+        assert_eq!(merged.fields.get("attempt"), Some(&FieldValue::Number(1.0)));
+        assert_eq!(
+            merged.fields.get("repeat_count"),
+            Some(&FieldValue::Number(3.0))
+        );
+        assert_eq!(
+            merged.fields.get("first_seen"),
+            Some(&FieldValue::String("2024-03-14T10:00:00.000Z".to_string()))
+        );
+        assert_eq!(
+            merged.fields.get("last_seen"),
+            Some(&FieldValue::String("2024-03-14T10:00:09.000Z".to_string()))
+        );
+    }
+
+    fn errors() -> Vec<Event> {
+        [
+            ("timeout", 1.0),
+            ("refused", 2.0),
+            ("timeout", 3.0),
+            ("", 4.0),
+        ]
+        .into_iter()
+        .map(|(error, n)| {
+            let mut event = Event::new();
+            if !error.is_empty() {
+                event.set_field("error".to_string(), FieldValue::String(error.to_string()));
+            }
+            event.set_field("n".to_string(), FieldValue::Number(n));
+            event
+        })
+        .collect()
+    }
+
+    #[test]
+    fn test_first_of() {
+        let mut filter = DistinctFilter::new("error", Occurrence::First);
+        let kept: Vec<Event> = errors()
+            .into_iter()
+            .filter_map(|event| filter.push(event))
+            .collect();
+        let numbers: Vec<_> = kept.iter().map(|e| e.fields["n"].clone()).collect();
+        assert_eq!(
+            numbers,
+            vec![
+                FieldValue::Number(1.0),
+                FieldValue::Number(2.0),
+                FieldValue::Number(4.0)
+            ]
+        );
+        assert!(filter.finish().is_empty());
+        assert_eq!(filter.suppressed(), 1);
+    }
+
+    #[test]
+    fn test_last_of() {
+        let mut filter = DistinctFilter::new("error", Occurrence::Last);
+        for event in errors() {
+            assert!(filter.push(event).is_none());
+        }
+        assert_eq!(filter.suppressed(), 1);
+        let numbers: Vec<_> = filter
+            .finish()
+            .iter()
+            .map(|e| e.fields["n"].clone())
+            .collect();
+        assert_eq!(
+            numbers,
+            vec![
+                FieldValue::Number(3.0),
+                FieldValue::Number(2.0),
+                FieldValue::Number(4.0)
+            ]
+        );
+        assert_eq!(filter.suppressed(), 1);
+    }
+
+    #[test]
+    fn test_first_of_template() {
+        let drain = Drain::new(crate::drain::DrainConfig::default()).unwrap();
+        let mut filter = DistinctFilter::new("_template", Occurrence::First).with_templates(drain);
+        let kept: Vec<String> = [
+            "user alice logged in",
+            "disk full",
+            "user bob logged in",
+            "disk full",
+        ]
+        .into_iter()
+        .filter_map(|message| {
+            let mut event = Event::new();
+            event.message = Some(message.to_string());
+            filter.push(event)
+        })
+        .filter_map(|event| event.message)
+        .collect();
+        assert_eq!(kept, ["user alice logged in", "disk full"]);
+        assert_eq!(filter.suppressed(), 2);
+    }
+
+    #[test]
+    fn test_distinct_values() {
+        let mut distinct = DistinctValues::with_limit("path", 3);
+        let mut out = Vec::new();
+        for (path, ts) in [
+            ("/a", "2024-03-14T10:00:00Z"),
+            ("/b", "2024-03-14T10:00:01Z"),
+            ("/a", "2024-03-14T10:00:02Z"),
+            ("/c", "2024-03-14T10:00:03Z"),
+        ] {
+            let mut event = event("GET", ts);
+            event.set_field("path".to_string(), FieldValue::String(path.to_string()));
+            out.extend(distinct.add(&event));
+        }
+        // Without the key
+        assert!(distinct
+            .add(&event("GET", "2024-03-14T10:00:04Z"))
+            .is_none());
+
+        let paths: Vec<_> = out.iter().map(|e| e.fields["path"].to_string()).collect();
+        assert_eq!(paths, vec!["/a", "/b", "/c"]);
+        assert_eq!(out[2].fields.len(), 1);
+        assert_eq!(
+            out[2].timestamp,
+            Some("2024-03-14T10:00:03Z".parse().unwrap())
+        );
+        assert!(!distinct.is_approximate());
+
+        // Past the limit, seen values stay seen and new ones still come out
+        for path in ["/d", "/a", "/d", "/e"] {
+            let mut event = Event::new();
+            event.set_field("path".to_string(), FieldValue::String(path.to_string()));
+            out.extend(distinct.add(&event));
+        }
+        assert!(distinct.is_approximate());
+        let paths: Vec<_> = out.iter().map(|e| e.fields["path"].to_string()).collect();
+        assert_eq!(paths, vec!["/a", "/b", "/c", "/d", "/e"]);
+    }
+}
@@ -0,0 +1,1414 @@
+use crate::event::{key_matches, Event};
+use crate::formatters::escape_csv;
+use crate::timespec::{bucket_label_format, bucket_starts};
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Seek, SeekFrom, Write};
+
+/// Template token standing for any value
+pub const WILDCARD: &str = "<*>";
+
+/// Key standing for an event's template, e.g. `--first-of _template`
+pub const TEMPLATE_KEY: &str = "_template";
+
+/// Variable parts replaced by named placeholders before clustering, so e.g. all request IDs
+/// look the same. Order matters: earlier masks win
+const MASKS: &[(&str, &str)] = &[
+    (
+        r"\b[0-9a-fA-F]{8}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{12}\b",
+        "<uuid>",
+    ),
+    (r"\b\d{1,3}(?:\.\d{1,3}){3}(?::\d+)?\b", "<ip>"),
+    (r"\b0[xX][0-9a-fA-F]+\b", "<hex>"),
+    // Numbers, also with a unit such as 12ms or 2xx
+    (r"\b\d+(?:\.\d+)?(?:[a-zA-Z]+)?\b", "<num>"),
+];
+
+/// Tuning of the clustering (`--drain-sim-th`, `--drain-depth`, `--drain-max-children`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DrainConfig {
+    /// Share of tokens a message must have in common with a template to join it
+    pub sim_th: f64,
+    /// Depth of the parse tree: the root, one level for the token count, `depth - 3` levels
+    /// for the leading tokens, and the leaves with the templates
+    pub depth: usize,
+    /// Most children per tree node; further tokens share a wildcard child
+    pub max_children: usize,
+}
+
+impl Default for DrainConfig {
+    fn default() -> Self {
+        Self {
+            sim_th: 0.4,
+            depth: 4,
+            max_children: 100,
+        }
+    }
+}
+
+/// A mined template and the messages it stands for
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Cluster {
+    pub tokens: Vec<String>,
+    pub count: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first_seen: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_seen: Option<DateTime<Utc>>,
+    /// Events per lowercased level
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub levels: BTreeMap<String, usize>,
+    /// The first distinct messages, as logged, with `--drain-examples`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub examples: Vec<String>,
+}
+
+impl Cluster {
+    pub fn template(&self) -> String {
+        self.tokens.join(" ")
+    }
+
+    /// Short hash of the template, the same wherever and whenever the template is mined, so
+    /// it can be referred to across hosts and days. It changes when the template does
+    pub fn id(&self) -> String {
+        let digest = Sha256::digest(self.template().as_bytes());
+        hex::encode(&digest[..4])
+    }
+
+    /// Take in the events of a similar template mined elsewhere
+    fn absorb(&mut self, other: Cluster) {
+        for (template, token) in self.tokens.iter_mut().zip(&other.tokens) {
+            if template != token {
+                *template = WILDCARD.to_string();
+            }
+        }
+        self.count += other.count;
+        self.first_seen = match (self.first_seen, other.first_seen) {
+            (Some(first), Some(other)) => Some(first.min(other)),
+            (first, other) => first.or(other),
+        };
+        self.last_seen = self.last_seen.max(other.last_seen);
+        for (level, count) in other.levels {
+            *self.levels.entry(level).or_default() += count;
+        }
+        let max_examples = self.examples.len().max(other.examples.len());
+        for example in other.examples {
+            if self.examples.len() < max_examples && !self.examples.contains(&example) {
+                self.examples.push(example);
+            }
+        }
+    }
+
+    fn record(&mut self, event: &Event) {
+        if let Some(timestamp) = event.timestamp {
+            self.first_seen = Some(self.first_seen.map_or(timestamp, |t| t.min(timestamp)));
+            self.last_seen = Some(self.last_seen.map_or(timestamp, |t| t.max(timestamp)));
+        }
+        if let Some(level) = &event.level {
+            *self.levels.entry(level.to_lowercase()).or_default() += 1;
+        }
+    }
+
+    /// Events per minute between the first and the last one seen
+    pub fn rate(&self) -> Option<f64> {
+        let span = self.last_seen? - self.first_seen?;
+        let minutes = span.num_milliseconds() as f64 / 60_000.0;
+        (minutes > 0.0).then(|| self.count as f64 / minutes)
+    }
+
+    /// `first .. last, rate/min, level share` for the report, empty when events had neither
+    /// timestamps nor levels
+    fn summary(&self) -> String {
+        let mut parts = Vec::new();
+        if let (Some(first), Some(last)) = (self.first_seen, self.last_seen) {
+            let first = first.to_rfc3339_opts(SecondsFormat::Secs, true);
+            let last = last.to_rfc3339_opts(SecondsFormat::Secs, true);
+            if first == last {
+                parts.push(first);
+            } else {
+                parts.push(format!("{} .. {}", first, last));
+            }
+        }
+        if let Some(rate) = self.rate() {
+            parts.push(format!("{:.1}/min", rate));
+        }
+        let mut levels: Vec<(&String, &usize)> = self.levels.iter().collect();
+        levels.sort_by_key(|(_, count)| std::cmp::Reverse(**count));
+        for (level, count) in levels {
+            let share = *count as f64 * 100.0 / self.count as f64;
+            parts.push(format!("{} {:.0}%", level, share));
+        }
+        parts.join(", ")
+    }
+}
+
+/// What `--drain-state` keeps between runs. The tree is rebuilt from the templates
+#[derive(Debug, Serialize, Deserialize)]
+struct State {
+    clusters: Vec<Cluster>,
+}
+
+#[derive(Debug, Default)]
+struct Node {
+    children: HashMap<String, Node>,
+    clusters: Vec<usize>,
+}
+
+/// Mines message templates with the Drain algorithm (He et al., "Drain: An Online Log Parsing
+/// Approach with Fixed Depth Tree", 2017): messages are routed through a tree by token count
+/// and leading tokens to a few candidate templates, join the most similar one if it is
+/// similar enough, and turn the tokens where they differ into `<*>`
+#[derive(Debug)]
+pub struct Drain {
+    config: DrainConfig,
+    // User-defined masks first, then the built-in ones
+    masks: Vec<(Regex, String)>,
+    custom_masks: usize,
+    root: Node,
+    clusters: Vec<Cluster>,
+    // Templates in the report, and examples kept per template
+    top: Option<usize>,
+    examples: usize,
+}
+
+impl Drain {
+    pub fn new(config: DrainConfig) -> Result<Self, String> {
+        if !(0.0..=1.0).contains(&config.sim_th) {
+            return Err(format!(
+                "similarity threshold must be between 0 and 1, got {}",
+                config.sim_th
+            ));
+        }
+        if config.depth < 3 {
+            return Err(format!("depth must be at least 3, got {}", config.depth));
+        }
+        if config.max_children < 2 {
+            return Err(format!(
+                "max children must be at least 2, got {}",
+                config.max_children
+            ));
+        }
+        let masks = MASKS
+            .iter()
+            .map(|(pattern, placeholder)| (Regex::new(pattern).unwrap(), placeholder.to_string()))
+            .collect();
+        Ok(Self {
+            config,
+            masks,
+            custom_masks: 0,
+            root: Node::default(),
+            clusters: Vec::new(),
+            top: None,
+            examples: 0,
+        })
+    }
+
+    /// Report only the `top` most frequent templates
+    pub fn with_top(mut self, top: usize) -> Self {
+        self.top = Some(top);
+        self
+    }
+
+    /// Keep up to `examples` distinct messages of each template and show them in the report
+    pub fn with_examples(mut self, examples: usize) -> Self {
+        self.examples = examples;
+        self
+    }
+
+    /// Also replace text matching a `REGEX=PLACEHOLDER` rule before clustering, e.g.
+    /// `order-\d+=<order_id>`. These apply in the order given, before the built-in masks
+    pub fn with_mask(mut self, rule: &str) -> Result<Self, String> {
+        let (pattern, placeholder) = rule
+            .rsplit_once('=')
+            .map(|(pattern, placeholder)| (pattern.trim(), placeholder.trim()))
+            .filter(|(pattern, placeholder)| !pattern.is_empty() && !placeholder.is_empty())
+            .ok_or_else(|| format!("expected REGEX=PLACEHOLDER, got '{}'", rule))?;
+        let regex = Regex::new(pattern).map_err(|e| e.to_string())?;
+        let placeholder = if placeholder.starts_with('<') && placeholder.ends_with('>') {
+            placeholder.to_string()
+        } else {
+            format!("<{}>", placeholder)
+        };
+        self.masks.insert(self.custom_masks, (regex, placeholder));
+        self.custom_masks += 1;
+        Ok(self)
+    }
+
+    /// Cluster a message, returning the index of its cluster
+    pub fn add(&mut self, message: &str) -> usize {
+        let tokens = self.tokenize(message);
+        let index = match self.search(&tokens) {
+            Some(index) => {
+                let cluster = &mut self.clusters[index];
+                for (template, token) in cluster.tokens.iter_mut().zip(&tokens) {
+                    if template != token {
+                        *template = WILDCARD.to_string();
+                    }
+                }
+                cluster.count += 1;
+                index
+            }
+            None => {
+                let index = self.clusters.len();
+                self.insert(&tokens, index);
+                self.clusters.push(Cluster {
+                    tokens,
+                    count: 1,
+                    ..Default::default()
+                });
+                index
+            }
+        };
+        let examples = &mut self.clusters[index].examples;
+        if examples.len() < self.examples && !examples.iter().any(|example| example == message) {
+            examples.push(message.to_string());
+        }
+        index
+    }
+
+    /// Cluster an event's message and keep track of when and at which levels the template
+    /// occurs. Events without a message are skipped
+    pub fn add_event(&mut self, event: &Event) -> Option<usize> {
+        let index = self.add(event.message.as_deref()?);
+        self.clusters[index].record(event);
+        Some(index)
+    }
+
+    fn tokenize(&self, message: &str) -> Vec<String> {
+        let mut masked = message.to_string();
+        for (regex, placeholder) in &self.masks {
+            if regex.is_match(&masked) {
+                masked = regex
+                    .replace_all(&masked, placeholder.as_str())
+                    .into_owned();
+            }
+        }
+        masked.split_whitespace().map(str::to_string).collect()
+    }
+
+    /// A regex for the messages of a cluster's template, capturing the text that fills each
+    /// placeholder, and those placeholders in order
+    fn template_pattern(&self, index: usize) -> (Regex, Vec<String>) {
+        let mut placeholders: Vec<&str> = self
+            .masks
+            .iter()
+            .map(|(_, placeholder)| placeholder.as_str())
+            .chain([WILDCARD])
+            .collect();
+        placeholders.sort_by_key(|placeholder| std::cmp::Reverse(placeholder.len()));
+        placeholders.dedup();
+        let alternatives: Vec<String> = placeholders.iter().map(|p| regex::escape(p)).collect();
+        let finder = Regex::new(&alternatives.join("|")).unwrap();
+
+        let mut pattern = String::from(r"^\s*");
+        let mut names = Vec::new();
+        for (i, token) in self.clusters[index].tokens.iter().enumerate() {
+            if i > 0 {
+                pattern.push_str(r"\s+");
+            }
+            let mut literal_start = 0;
+            for placeholder in finder.find_iter(token) {
+                pattern.push_str(&regex::escape(&token[literal_start..placeholder.start()]));
+                pattern.push_str("(.+?)");
+                names.push(placeholder.as_str().to_string());
+                literal_start = placeholder.end();
+            }
+            pattern.push_str(&regex::escape(&token[literal_start..]));
+        }
+        pattern.push_str(r"\s*$");
+        (Regex::new(&pattern).unwrap(), names)
+    }
+
+    /// Levels of leading tokens in the tree
+    fn prefix_depth(&self) -> usize {
+        self.config.depth - 2
+    }
+
+    fn search(&self, tokens: &[String]) -> Option<usize> {
+        let mut node = self.root.children.get(&tokens.len().to_string())?;
+        for (depth, token) in tokens.iter().enumerate().map(|(i, token)| (i + 1, token)) {
+            if depth >= self.prefix_depth() || depth == tokens.len() {
+                break;
+            }
+            node = node
+                .children
+                .get(token)
+                .or_else(|| node.children.get(WILDCARD))?;
+        }
+        self.best_match(&node.clusters, tokens)
+    }
+
+    /// The most similar template with at least the threshold similarity; ties go to the one
+    /// with more wildcards
+    fn best_match(&self, candidates: &[usize], tokens: &[String]) -> Option<usize> {
+        let mut best: Option<(usize, f64, usize)> = None;
+        for &index in candidates {
+            let template = &self.clusters[index].tokens;
+            let mut same = 0;
+            let mut wildcards = 0;
+            for (template, token) in template.iter().zip(tokens) {
+                // A wildcard matches a wildcard of a template being merged
+                if template == token {
+                    same += 1;
+                } else if template == WILDCARD {
+                    wildcards += 1;
+                }
+            }
+            let similarity = if tokens.is_empty() {
+                1.0
+            } else {
+                same as f64 / tokens.len() as f64
+            };
+            if best.is_none_or(|(_, best_similarity, best_wildcards)| {
+                similarity > best_similarity
+                    || (similarity == best_similarity && wildcards > best_wildcards)
+            }) {
+                best = Some((index, similarity, wildcards));
+            }
+        }
+        best.filter(|(_, similarity, _)| *similarity >= self.config.sim_th)
+            .map(|(index, _, _)| index)
+    }
+
+    fn insert(&mut self, tokens: &[String], index: usize) {
+        let prefix_depth = self.prefix_depth();
+        let max_children = self.config.max_children;
+        let mut node = self
+            .root
+            .children
+            .entry(tokens.len().to_string())
+            .or_default();
+        for (depth, token) in (1..).zip(tokens) {
+            if depth >= prefix_depth || depth >= tokens.len() {
+                break;
+            }
+            let key = if node.children.contains_key(token) {
+                token.as_str()
+            } else if token.chars().any(|c| c.is_ascii_digit()) {
+                WILDCARD
+            } else if node.children.contains_key(WILDCARD) {
+                if node.children.len() < max_children {
+                    token.as_str()
+                } else {
+                    WILDCARD
+                }
+            } else if node.children.len() + 1 < max_children {
+                token.as_str()
+            } else {
+                WILDCARD
+            };
+            node = node.children.entry(key.to_string()).or_default();
+        }
+        node.clusters.push(index);
+    }
+
+    /// The mined templates as JSON, for [`Drain::restore`]
+    pub fn save(&self) -> String {
+        serde_json::to_string_pretty(&State {
+            clusters: self.clusters.clone(),
+        })
+        .unwrap_or_default()
+    }
+
+    pub fn cluster(&self, index: usize) -> &Cluster {
+        &self.clusters[index]
+    }
+
+    /// The clusters, most frequent first
+    pub fn most_frequent(&self) -> Vec<&Cluster> {
+        let mut clusters: Vec<&Cluster> = self.clusters.iter().collect();
+        clusters.sort_by_key(|cluster| std::cmp::Reverse(cluster.count));
+        clusters
+    }
+
+    /// Share of all mined events that belong to a cluster
+    pub fn share(&self, index: usize) -> f64 {
+        let total: usize = self.clusters.iter().map(|cluster| cluster.count).sum();
+        match total {
+            0 => 0.0,
+            total => self.clusters[index].count as f64 / total as f64,
+        }
+    }
+
+    /// Continue with the templates of an earlier [`Drain::save`]
+    pub fn restore(&mut self, json: &str) -> Result<(), String> {
+        let state: State = serde_json::from_str(json).map_err(|e| e.to_string())?;
+        for cluster in state.clusters {
+            let index = self.clusters.len();
+            self.insert(&cluster.tokens, index);
+            self.clusters.push(cluster);
+        }
+        Ok(())
+    }
+
+    /// Fold in the templates of another [`Drain::save`], e.g. from another host: each joins
+    /// the most similar template like a message would, with all its events, or is added
+    pub fn merge(&mut self, json: &str) -> Result<(), String> {
+        let state: State = serde_json::from_str(json).map_err(|e| e.to_string())?;
+        for cluster in state.clusters {
+            match self.search(&cluster.tokens) {
+                Some(index) => self.clusters[index].absorb(cluster),
+                None => {
+                    let index = self.clusters.len();
+                    self.insert(&cluster.tokens, index);
+                    self.clusters.push(cluster);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the templates, most frequent first, with their counts and IDs. Below each, when
+    /// known, the first and last time it was seen, its rate and the share of each level, and
+    /// example messages
+    pub fn write_report(&self, out: &mut dyn Write) -> io::Result<()> {
+        let mut clusters = self.most_frequent();
+        clusters.truncate(self.top.unwrap_or(clusters.len()));
+        for cluster in clusters {
+            writeln!(
+                out,
+                "{:>8}  {}  {}",
+                cluster.count,
+                cluster.id(),
+                cluster.template()
+            )?;
+            let summary = cluster.summary();
+            if !summary.is_empty() {
+                writeln!(out, "{:>8}  {}", "", summary)?;
+            }
+            for example in &cluster.examples {
+                writeln!(out, "{:>8}  | {}", "", example)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The templates whose events `--template-id` and `--template-match` output, checked against
+/// the template as mined by the time each event arrives
+#[derive(Debug, Default)]
+pub struct TemplateFilter {
+    ids: Vec<String>,
+    patterns: Vec<String>,
+}
+
+impl TemplateFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Select the template with this ID, or an ID starting with it
+    pub fn with_template_id(mut self, id: &str) -> Self {
+        self.ids.push(id.trim().to_lowercase());
+        self
+    }
+
+    /// Select templates matching a pattern, where `*` stands for any text, so a template
+    /// copied from the report matches itself
+    pub fn with_template_match(mut self, pattern: &str) -> Self {
+        self.patterns.push(pattern.to_string());
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty() && self.patterns.is_empty()
+    }
+
+    pub fn selects(&self, cluster: &Cluster) -> bool {
+        let id = cluster.id();
+        let template = cluster.template();
+        self.ids.iter().any(|prefix| id.starts_with(prefix))
+            || self
+                .patterns
+                .iter()
+                .any(|pattern| key_matches(pattern, &template))
+    }
+}
+
+/// Events held back for `--drain-anomalies` until all are mined, as whether a template is rare
+/// is only known then. They wait in a temporary file, so large inputs don't have to fit in
+/// memory
+#[derive(Debug)]
+pub struct HeldEvents {
+    rare_below: f64,
+    held: Option<BufWriter<File>>,
+}
+
+/// A held event as written to the temporary file, with its cluster and what serde skips
+#[derive(Serialize, Deserialize)]
+struct Held {
+    index: usize,
+    source: Option<String>,
+    event: Event,
+}
+
+impl HeldEvents {
+    /// Keep events of templates rarer than a percentage (`0.1%`) or fraction (`0.001`) of
+    /// all events
+    pub fn new(rare_below: &str) -> Result<Self, String> {
+        let below = rare_below.trim();
+        let share = match below.strip_suffix('%') {
+            Some(percent) => percent.trim().parse::<f64>().map(|p| p / 100.0),
+            None => below.parse::<f64>(),
+        }
+        .map_err(|_| format!("expected a percentage or fraction, got '{}'", below))?;
+        if !(0.0..=1.0).contains(&share) {
+            return Err(format!("'{}' is not between 0 and 100%", below));
+        }
+        Ok(Self {
+            rare_below: share,
+            held: None,
+        })
+    }
+
+    /// Keep an event of the cluster at `index` for later
+    pub fn hold(&mut self, index: usize, mut event: Event) -> io::Result<()> {
+        let held = match &mut self.held {
+            Some(held) => held,
+            None => self.held.insert(BufWriter::new(tempfile::tempfile()?)),
+        };
+        let source = event.source.take();
+        serde_json::to_writer(
+            &mut *held,
+            &Held {
+                index,
+                source,
+                event,
+            },
+        )?;
+        writeln!(held)
+    }
+
+    /// The held events, in input order, whose final template is rare
+    pub fn into_selected(
+        self,
+        drain: &Drain,
+    ) -> io::Result<impl Iterator<Item = io::Result<Event>>> {
+        let rare: Vec<bool> = (0..drain.clusters.len())
+            .map(|index| drain.share(index) < self.rare_below)
+            .collect();
+        let held = match self.held {
+            Some(held) => {
+                let mut file = held.into_inner().map_err(|e| e.into_error())?;
+                file.seek(SeekFrom::Start(0))?;
+                Some(BufReader::new(file))
+            }
+            None => None,
+        };
+        Ok(held
+            .into_iter()
+            .flat_map(BufRead::lines)
+            .filter_map(move |line| {
+                let held = line
+                    .and_then(|line| serde_json::from_str::<Held>(&line).map_err(io::Error::other));
+                match held {
+                    Ok(held) if !rare[held.index] => None,
+                    Ok(Held {
+                        source, mut event, ..
+                    }) => {
+                        event.source = source;
+                        Some(Ok(event))
+                    }
+                    Err(e) => Some(Err(e)),
+                }
+            }))
+    }
+}
+
+/// How often each template occurs in the two inputs of `kelora drain-diff`, which are mined
+/// together so their templates line up
+#[derive(Debug)]
+pub struct TemplateDiff {
+    old_source: String,
+    min_change: f64,
+    /// Events per cluster in the old and the new input
+    counts: Vec<[usize; 2]>,
+}
+
+impl TemplateDiff {
+    /// Templates whose share of events grows or shrinks by `min_change` times are reported
+    /// as changed
+    pub fn new(old_source: &str, min_change: f64) -> Result<Self, String> {
+        if min_change.is_nan() || min_change <= 1.0 {
+            return Err(format!("change factor must be above 1, got {}", min_change));
+        }
+        Ok(Self {
+            old_source: old_source.to_string(),
+            min_change,
+            counts: Vec::new(),
+        })
+    }
+
+    /// Count an event of the cluster at `index`; events not from the old input are new
+    pub fn add(&mut self, index: usize, event: &Event) {
+        let side = usize::from(event.source.as_deref() != Some(self.old_source.as_str()));
+        if self.counts.len() <= index {
+            self.counts.resize(index + 1, [0, 0]);
+        }
+        self.counts[index][side] += 1;
+    }
+
+    /// Templates only in the new input, then those gone from it, then those changed the most
+    fn rows(&self) -> Vec<(String, [usize; 2], usize)> {
+        let totals = self.counts.iter().fold([0, 0], |[old, new], counts| {
+            [old + counts[0], new + counts[1]]
+        });
+        let mut rows = Vec::new();
+        for (index, &[old, new]) in self.counts.iter().enumerate() {
+            let (rank, weight, label) = match (old, new) {
+                (0, 0) => continue,
+                (0, new) => (0, new as f64, "new".to_string()),
+                (old, 0) => (1, old as f64, "gone".to_string()),
+                (old, new) => {
+                    let ratio = (new as f64 / totals[1] as f64) / (old as f64 / totals[0] as f64);
+                    if ratio < self.min_change && ratio > 1.0 / self.min_change {
+                        continue;
+                    }
+                    (
+                        2,
+                        ratio.ln().abs(),
+                        format!("{:+.0}%", (ratio - 1.0) * 100.0),
+                    )
+                }
+            };
+            rows.push((rank, weight, label, [old, new], index));
+        }
+        rows.sort_by(|a, b| a.0.cmp(&b.0).then(b.1.total_cmp(&a.1)));
+        rows.into_iter()
+            .map(|(_, _, label, counts, index)| (label, counts, index))
+            .collect()
+    }
+
+    /// Write the new, gone and changed templates with their counts in both inputs
+    pub fn write_report(&self, drain: &Drain, out: &mut dyn Write) -> io::Result<()> {
+        for (label, [old, new], index) in self.rows() {
+            let cluster = drain.cluster(index);
+            writeln!(
+                out,
+                "{:<6} {:>8} {:>8}  {}  {}",
+                label,
+                old,
+                new,
+                cluster.id(),
+                cluster.template()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// The values filling the placeholders of the templates with an ID, for `--drain-params`.
+/// Which template a message ends up in is only known at the end, so the distinct messages of
+/// all templates are kept until then
+#[derive(Debug)]
+pub struct DrainParams {
+    id_prefix: String,
+    messages: Vec<HashMap<String, usize>>,
+}
+
+impl DrainParams {
+    /// Report on the templates whose ID is or starts with `id`
+    pub fn new(id: &str) -> Self {
+        Self {
+            id_prefix: id.trim().to_lowercase(),
+            messages: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, index: usize, message: &str) {
+        if self.messages.len() <= index {
+            self.messages.resize_with(index + 1, HashMap::new);
+        }
+        *self.messages[index].entry(message.to_string()).or_default() += 1;
+    }
+
+    /// Values of each placeholder with their counts, most frequent first
+    fn values(&self, drain: &Drain, index: usize) -> Vec<(String, Vec<(String, usize)>)> {
+        let (pattern, placeholders) = drain.template_pattern(index);
+        let mut counts: Vec<HashMap<&str, usize>> = vec![HashMap::new(); placeholders.len()];
+        for (message, count) in self.messages.get(index).into_iter().flatten() {
+            let Some(captures) = pattern.captures(message) else {
+                continue;
+            };
+            for (values, value) in counts.iter_mut().zip(captures.iter().skip(1)) {
+                *values.entry(value.map_or("", |m| m.as_str())).or_default() += count;
+            }
+        }
+        placeholders
+            .into_iter()
+            .zip(counts)
+            .map(|(placeholder, values)| {
+                let mut values: Vec<(String, usize)> = values
+                    .into_iter()
+                    .map(|(value, count)| (value.to_string(), count))
+                    .collect();
+                values.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+                (placeholder, values)
+            })
+            .collect()
+    }
+
+    /// Write each selected template followed by its placeholders, numbered by position, and
+    /// their values
+    pub fn write_report(&self, drain: &Drain, out: &mut dyn Write) -> io::Result<()> {
+        let mut selected: Vec<usize> = (0..drain.clusters.len())
+            .filter(|&index| drain.cluster(index).id().starts_with(&self.id_prefix))
+            .collect();
+        selected.sort_by_key(|&index| std::cmp::Reverse(drain.cluster(index).count));
+        for index in selected {
+            let cluster = drain.cluster(index);
+            writeln!(
+                out,
+                "{:>8}  {}  {}",
+                cluster.count,
+                cluster.id(),
+                cluster.template()
+            )?;
+            for (position, (placeholder, values)) in (1..).zip(self.values(drain, index)) {
+                writeln!(out, "{:>8}  {} {}", "", position, placeholder)?;
+                for (value, count) in values {
+                    writeln!(out, "{:>8}  {:>8}  {}", "", count, value)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Events per template and time bucket, for `--drain-timeline`. Events without a timestamp
+/// aren't counted
+#[derive(Debug)]
+pub struct DrainTimeline {
+    bucket_ms: i64,
+    /// Start of each bucket (in milliseconds) to the events per cluster in it
+    counts: BTreeMap<i64, HashMap<usize, usize>>,
+}
+
+impl DrainTimeline {
+    pub fn new(bucket: Duration) -> Result<Self, String> {
+        let bucket_ms = bucket.num_milliseconds();
+        if bucket_ms <= 0 {
+            return Err("bucket size must be positive".to_string());
+        }
+        Ok(Self {
+            bucket_ms,
+            counts: BTreeMap::new(),
+        })
+    }
+
+    pub fn add(&mut self, index: usize, event: &Event) {
+        let Some(timestamp) = event.timestamp else {
+            return;
+        };
+        let start = timestamp
+            .timestamp_millis()
+            .div_euclid(self.bucket_ms)
+            .saturating_mul(self.bucket_ms);
+        *self
+            .counts
+            .entry(start)
+            .or_default()
+            .entry(index)
+            .or_default() += 1;
+    }
+
+    /// All buckets from the first to the last event, including empty ones unless there are
+    /// very many in a row
+    fn buckets(&self) -> Vec<i64> {
+        bucket_starts(self.counts.keys().copied(), self.bucket_ms)
+    }
+
+    /// Write one row per template, most frequent first, with its events per bucket; as CSV
+    /// with RFC 3339 bucket starts, or as a table with short times
+    pub fn write_report(&self, drain: &Drain, csv: bool, out: &mut dyn Write) -> io::Result<()> {
+        let buckets = self.buckets();
+        let mut rows: Vec<(usize, Vec<usize>)> = (0..drain.clusters.len())
+            .map(|index| {
+                let counts = buckets
+                    .iter()
+                    .map(|start| {
+                        self.counts
+                            .get(start)
+                            .and_then(|counts| counts.get(&index))
+                            .copied()
+                            .unwrap_or(0)
+                    })
+                    .collect();
+                (index, counts)
+            })
+            .filter(|(_, counts): &(usize, Vec<usize>)| counts.iter().any(|&count| count > 0))
+            .collect();
+        rows.sort_by_key(|(_, counts)| std::cmp::Reverse(counts.iter().sum::<usize>()));
+        rows.truncate(drain.top.unwrap_or(rows.len()));
+
+        let starts: Vec<DateTime<Utc>> = buckets
+            .iter()
+            .filter_map(|&start| DateTime::from_timestamp_millis(start))
+            .collect();
+        if csv {
+            let mut header = vec!["template_id".to_string(), "template".to_string()];
+            header.extend(
+                starts
+                    .iter()
+                    .map(|start| start.to_rfc3339_opts(SecondsFormat::Secs, true)),
+            );
+            writeln!(out, "{}", header.join(","))?;
+            for (index, counts) in rows {
+                let cluster = drain.cluster(index);
+                let mut row = vec![cluster.id(), escape_csv(&cluster.template())];
+                row.extend(counts.iter().map(usize::to_string));
+                writeln!(out, "{}", row.join(","))?;
+            }
+            return Ok(());
+        }
+
+        let layout = match (starts.first(), starts.last()) {
+            (Some(&first), Some(&last)) => bucket_label_format(first, last, self.bucket_ms),
+            _ => "%H:%M",
+        };
+        let labels: Vec<String> = starts
+            .iter()
+            .map(|start| start.format(layout).to_string())
+            .collect();
+        write!(out, "{:<8}", "id")?;
+        for label in &labels {
+            write!(out, "  {:>5}", label)?;
+        }
+        writeln!(out, "  template")?;
+        for (index, counts) in rows {
+            let cluster = drain.cluster(index);
+            write!(out, "{}", cluster.id())?;
+            for (label, count) in labels.iter().zip(counts) {
+                write!(out, "  {:>width$}", count, width = label.len().max(5))?;
+            }
+            writeln!(out, "  {}", cluster.template())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn templates(drain: &Drain) -> Vec<(String, usize)> {
+        drain
+            .clusters
+            .iter()
+            .map(|cluster| (cluster.template(), cluster.count))
+            .collect()
+    }
+
+    #[test]
+    fn test_drain_clusters() {
+        let mut drain = Drain::new(DrainConfig::default()).unwrap();
+        for message in [
+            "connected to 10.0.0.1:5432 in 12ms",
+            "user alice logged in",
+            "connected to 10.0.0.7:5432 in 340ms",
+            "user bob logged in",
+            "cache miss for key 7f3a",
+            "user carol logged out",
+        ] {
+            drain.add(message);
+        }
+        assert_eq!(
+            templates(&drain),
+            [
+                ("connected to <ip> in <num>".to_string(), 2),
+                ("user <*> logged <*>".to_string(), 3),
+                ("cache miss for key 7f3a".to_string(), 1),
+            ]
+        );
+
+        let mut report = Vec::new();
+        drain.write_report(&mut report).unwrap();
+        assert_eq!(
+            String::from_utf8(report).unwrap(),
+            "       3  49f0680d  user <*> logged <*>\n       2  f55b5311  connected to <ip> in <num>\n       1  6dc7618b  cache miss for key 7f3a\n"
+        );
+    }
+
+    #[test]
+    fn test_drain_config() {
+        let messages = ["job a finished", "job b finished", "job c failed"];
+
+        // A strict threshold keeps similar messages apart
+        let mut strict = Drain::new(DrainConfig {
+            sim_th: 0.9,
+            ..DrainConfig::default()
+        })
+        .unwrap();
+        for message in messages {
+            strict.add(message);
+        }
+        assert_eq!(strict.clusters.len(), 3);
+
+        let mut loose = Drain::new(DrainConfig {
+            sim_th: 0.3,
+            ..DrainConfig::default()
+        })
+        .unwrap();
+        for message in messages {
+            loose.add(message);
+        }
+        assert_eq!(templates(&loose), [("job <*> <*>".to_string(), 3)]);
+
+        // With a deeper tree, messages are told apart by more leading tokens
+        let mut deep = Drain::new(DrainConfig {
+            sim_th: 0.3,
+            depth: 5,
+            ..DrainConfig::default()
+        })
+        .unwrap();
+        for message in messages {
+            deep.add(message);
+        }
+        assert_eq!(deep.clusters.len(), 3);
+
+        assert!(Drain::new(DrainConfig {
+            sim_th: 1.5,
+            ..DrainConfig::default()
+        })
+        .is_err());
+        assert!(Drain::new(DrainConfig {
+            depth: 2,
+            ..DrainConfig::default()
+        })
+        .is_err());
+        assert!(Drain::new(DrainConfig {
+            max_children: 1,
+            ..DrainConfig::default()
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn test_drain_max_children() {
+        let mut drain = Drain::new(DrainConfig {
+            max_children: 2,
+            ..DrainConfig::default()
+        })
+        .unwrap();
+        for message in ["alpha started ok", "beta started ok", "gamma started ok"] {
+            drain.add(message);
+        }
+        // Only one distinct first token fits next to the wildcard child, so the rest share it
+        assert_eq!(
+            templates(&drain),
+            [
+                ("alpha started ok".to_string(), 1),
+                ("<*> started ok".to_string(), 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_drain_masks() {
+        let messages = [
+            "shipped order-1001 to 10.1.2.3",
+            "shipped order-2002 to 10.1.2.4",
+            "shipped SKU-77 to 10.1.2.5",
+        ];
+        let mut drain = Drain::new(DrainConfig {
+            sim_th: 0.8,
+            ..DrainConfig::default()
+        })
+        .unwrap()
+        .with_mask(r"order-\d+=<order_id>")
+        .unwrap()
+        .with_mask(r"SKU-\d+=sku")
+        .unwrap();
+        for message in messages {
+            drain.add(message);
+        }
+        assert_eq!(
+            templates(&drain),
+            [
+                ("shipped <order_id> to <ip>".to_string(), 2),
+                ("shipped <sku> to <ip>".to_string(), 1),
+            ]
+        );
+
+        // Regexes may contain '=' themselves
+        assert!(Drain::new(DrainConfig::default())
+            .unwrap()
+            .with_mask("user=\\w+=<user>")
+            .is_ok());
+        assert!(Drain::new(DrainConfig::default())
+            .unwrap()
+            .with_mask("order-\\d+")
+            .is_err());
+        assert!(Drain::new(DrainConfig::default())
+            .unwrap()
+            .with_mask("(=<x>")
+            .is_err());
+    }
+
+    #[test]
+    fn test_drain_state() {
+        let mut first = Drain::new(DrainConfig::default()).unwrap();
+        for message in ["user alice logged in", "user bob logged in", "disk full"] {
+            first.add(message);
+        }
+        let state = first.save();
+
+        let mut second = Drain::new(DrainConfig::default()).unwrap();
+        second.restore(&state).unwrap();
+        second.add("user carol logged in");
+        second.add("disk full");
+        second.add("cache cleared");
+        assert_eq!(
+            templates(&second),
+            [
+                ("user <*> logged in".to_string(), 3),
+                ("disk full".to_string(), 2),
+                ("cache cleared".to_string(), 1),
+            ]
+        );
+
+        assert!(second.restore("{\"clusters\": 5}").is_err());
+    }
+
+    #[test]
+    fn test_cluster_id() {
+        let cluster = |template: &str, count| Cluster {
+            tokens: template.split(' ').map(str::to_string).collect(),
+            count,
+            ..Default::default()
+        };
+        let id = cluster("user <*> logged in", 3).id();
+        assert_eq!(id.len(), 8);
+        assert_eq!(id, cluster("user <*> logged in", 1).id());
+        assert_ne!(id, cluster("user <*> logged out", 3).id());
+    }
+
+    #[test]
+    fn test_drain_stats() {
+        let event = |timestamp: &str, level: &str, message: &str| {
+            let mut event = Event::new();
+            event.timestamp = Some(timestamp.parse().unwrap());
+            event.level = Some(level.to_string());
+            event.message = Some(message.to_string());
+            event
+        };
+        let mut drain = Drain::new(DrainConfig::default()).unwrap();
+        drain.add_event(&event("2024-03-14T10:02:00Z", "ERROR", "disk sda full"));
+        drain.add_event(&event("2024-03-14T10:00:00Z", "warn", "disk sdb full"));
+        drain.add_event(&event("2024-03-14T10:01:00Z", "error", "disk sdc full"));
+        drain.add_event(&event("2024-03-14T10:05:00Z", "info", "backup done"));
+        assert_eq!(drain.add_event(&Event::new()), None);
+
+        let disk = &drain.clusters[0];
+        assert_eq!(disk.rate(), Some(1.5));
+        assert_eq!(
+            disk.summary(),
+            "2024-03-14T10:00:00Z .. 2024-03-14T10:02:00Z, 1.5/min, error 67%, warn 33%"
+        );
+        assert_eq!(drain.clusters[1].rate(), None);
+        assert_eq!(
+            drain.clusters[1].summary(),
+            "2024-03-14T10:05:00Z, info 100%"
+        );
+
+        let mut restored = Drain::new(DrainConfig::default()).unwrap();
+        restored.restore(&drain.save()).unwrap();
+        assert_eq!(restored.clusters[0].summary(), disk.summary());
+    }
+
+    #[test]
+    fn test_rare_events() {
+        let mut drain = Drain::new(DrainConfig::default()).unwrap();
+        let mut rare = HeldEvents::new("10%").unwrap();
+        for i in 0..19 {
+            let mut event = Event::new();
+            event.message = Some(format!("request {} served", i));
+            let index = drain.add_event(&event).unwrap();
+            rare.hold(index, event).unwrap();
+        }
+        let mut odd = Event::new();
+        odd.message = Some("checksum mismatch on block".to_string());
+        odd.source = Some("app.log".to_string());
+        let index = drain.add_event(&odd).unwrap();
+        assert_eq!(drain.share(index), 0.05);
+        rare.hold(index, odd.clone()).unwrap();
+
+        let events: Vec<Event> = (rare.into_selected(&drain).unwrap())
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].message, odd.message);
+        assert_eq!(events[0].source, odd.source);
+
+        let rare_below = HeldEvents::new;
+        assert_eq!(rare_below("0.001").unwrap().rare_below, 0.001);
+        assert!(rare_below("0.1 %").is_ok());
+        assert!(rare_below("120%").is_err());
+        assert!(rare_below("rare").is_err());
+    }
+
+    #[test]
+    fn test_template_filters() {
+        let messages = [
+            "user alice logged in",
+            "disk sda full",
+            "user bob logged in",
+            "disk sdb full",
+        ];
+        let select = |filter: TemplateFilter| {
+            let mut drain = Drain::new(DrainConfig::default()).unwrap();
+            let mut selected = Vec::new();
+            for message in messages {
+                let mut event = Event::new();
+                event.message = Some(message.to_string());
+                let index = drain.add_event(&event).unwrap();
+                if filter.selects(drain.cluster(index)) {
+                    selected.push(message);
+                }
+            }
+            selected
+        };
+
+        let id = Cluster {
+            tokens: vec!["disk".into(), "<*>".into(), "full".into()],
+            ..Default::default()
+        }
+        .id();
+        // The first disk event still has a template of its own
+        assert_eq!(
+            select(TemplateFilter::new().with_template_id(&id[..4].to_uppercase())),
+            ["disk sdb full"]
+        );
+        assert_eq!(
+            select(TemplateFilter::new().with_template_match("user * logged in")),
+            ["user alice logged in", "user bob logged in"]
+        );
+        assert_eq!(
+            select(
+                TemplateFilter::new()
+                    .with_template_match("disk *")
+                    .with_template_id("ffffffff")
+            ),
+            ["disk sda full", "disk sdb full"]
+        );
+        assert!(select(TemplateFilter::new().with_template_match("user")).is_empty());
+    }
+
+    #[test]
+    fn test_template_diff() {
+        let mut drain = Drain::new(DrainConfig::default()).unwrap();
+        let mut diff = TemplateDiff::new("before.log", 2.0).unwrap();
+        let mut add = |source: &str, message: &str, times: usize| {
+            for _ in 0..times {
+                let mut event = Event::new();
+                event.source = Some(source.to_string());
+                event.message = Some(message.to_string());
+                let index = drain.add_event(&event).unwrap();
+                diff.add(index, &event);
+            }
+        };
+        add("before.log", "request served", 90);
+        add("before.log", "cache miss", 5);
+        add("before.log", "config reloaded", 5);
+        add("after.log", "request served", 80);
+        add("after.log", "cache miss", 15);
+        add("after.log", "connection reset by peer", 5);
+
+        let rows: Vec<(String, [usize; 2])> = diff
+            .rows()
+            .into_iter()
+            .map(|(label, counts, _)| (label, counts))
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                ("new".to_string(), [0, 5]),
+                ("gone".to_string(), [5, 0]),
+                ("+200%".to_string(), [5, 15]),
+            ]
+        );
+
+        assert!(TemplateDiff::new("before.log", 1.0).is_err());
+    }
+
+    #[test]
+    fn test_drain_top_and_examples() {
+        let mut drain = Drain::new(DrainConfig::default())
+            .unwrap()
+            .with_top(1)
+            .with_examples(2);
+        for message in [
+            "user alice logged in",
+            "disk full",
+            "user alice logged in",
+            "user bob logged in",
+            "user carol logged in",
+        ] {
+            drain.add(message);
+        }
+        assert_eq!(
+            drain.clusters[0].examples,
+            ["user alice logged in", "user bob logged in"]
+        );
+
+        let mut report = Vec::new();
+        drain.write_report(&mut report).unwrap();
+        assert_eq!(
+            String::from_utf8(report).unwrap(),
+            "       4  65231b8d  user <*> logged in\n          | user alice logged in\n          | user bob logged in\n"
+        );
+    }
+
+    #[test]
+    fn test_drain_merge() {
+        let event = |timestamp: &str, message: &str| {
+            let mut event = Event::new();
+            event.timestamp = Some(timestamp.parse().unwrap());
+            event.level = Some("info".to_string());
+            event.message = Some(message.to_string());
+            event
+        };
+        let mut host_a = Drain::new(DrainConfig::default()).unwrap();
+        host_a.add_event(&event("2024-03-14T10:00:00Z", "user alice logged in"));
+        host_a.add_event(&event("2024-03-14T10:05:00Z", "user bob logged in"));
+        host_a.add_event(&event("2024-03-14T10:06:00Z", "disk full"));
+        let mut host_b = Drain::new(DrainConfig::default()).unwrap();
+        host_b.add_event(&event("2024-03-14T09:00:00Z", "user carol logged in"));
+        host_b.add_event(&event("2024-03-14T09:30:00Z", "cache cleared"));
+
+        // Without timestamps
+        let mut host_c = Drain::new(DrainConfig::default()).unwrap();
+        host_c.add("user dave logged in");
+
+        let mut fleet = Drain::new(DrainConfig::default()).unwrap();
+        fleet.merge(&host_a.save()).unwrap();
+        fleet.merge(&host_b.save()).unwrap();
+        fleet.merge(&host_c.save()).unwrap();
+        assert_eq!(
+            templates(&fleet),
+            [
+                ("user <*> logged in".to_string(), 4),
+                ("disk full".to_string(), 1),
+                ("cache cleared".to_string(), 1),
+            ]
+        );
+        let users = &fleet.clusters[0];
+        assert_eq!(
+            users.first_seen,
+            Some("2024-03-14T09:00:00Z".parse().unwrap())
+        );
+        assert_eq!(
+            users.last_seen,
+            Some("2024-03-14T10:05:00Z".parse().unwrap())
+        );
+        assert_eq!(users.levels["info"], 3);
+
+        assert!(fleet.merge("[]").is_err());
+    }
+
+    #[test]
+    fn test_drain_params() {
+        let mut drain = Drain::new(DrainConfig::default())
+            .unwrap()
+            .with_mask(r"req-\d+=<request>")
+            .unwrap();
+        let mut params = DrainParams::new("");
+        for message in [
+            "GET /a from 10.0.0.1 took 12ms id=req-1",
+            "GET /b from 10.0.0.2 took 7ms id=req-2",
+            "GET /a from 10.0.0.1 took 12ms id=req-1",
+            "disk full",
+        ] {
+            let index = drain.add(message);
+            params.add(index, message);
+        }
+        assert_eq!(
+            drain.cluster(0).template(),
+            "GET <*> from <ip> took <num> id=<request>"
+        );
+        let strings = |values: &[(&str, usize)]| -> Vec<(String, usize)> {
+            values.iter().map(|(v, c)| (v.to_string(), *c)).collect()
+        };
+        assert_eq!(
+            params.values(&drain, 0),
+            [
+                ("<*>".to_string(), strings(&[("/a", 2), ("/b", 1)])),
+                (
+                    "<ip>".to_string(),
+                    strings(&[("10.0.0.1", 2), ("10.0.0.2", 1)])
+                ),
+                ("<num>".to_string(), strings(&[("12ms", 2), ("7ms", 1)])),
+                (
+                    "<request>".to_string(),
+                    strings(&[("req-1", 2), ("req-2", 1)])
+                ),
+            ]
+        );
+        assert!(params.values(&drain, 1).is_empty());
+    }
+
+    #[test]
+    fn test_drain_timeline() {
+        let mut drain = Drain::new(DrainConfig::default()).unwrap();
+        let mut timeline = DrainTimeline::new(Duration::minutes(5)).unwrap();
+        for (timestamp, message) in [
+            ("2024-03-14T10:01:00Z", "request served"),
+            ("2024-03-14T10:02:00Z", "request served"),
+            ("2024-03-14T10:12:00Z", "disk, full"),
+            ("2024-03-14T10:13:00Z", "request served"),
+            ("2024-03-14T10:14:59Z", "disk, full"),
+        ] {
+            let mut event = Event::new();
+            event.timestamp = Some(timestamp.parse().unwrap());
+            event.message = Some(message.to_string());
+            let index = drain.add_event(&event).unwrap();
+            timeline.add(index, &event);
+        }
+        let mut untimed = Event::new();
+        untimed.message = Some("request served".to_string());
+        timeline.add(drain.add_event(&untimed).unwrap(), &untimed);
+
+        let mut table = Vec::new();
+        timeline.write_report(&drain, false, &mut table).unwrap();
+        assert_eq!(
+            String::from_utf8(table).unwrap(),
+            "id        10:00  10:05  10:10  template
+87e7492b      2      0      1  request served
+81bc6888      0      0      2  disk, full
+"
+        );
+
+        let mut csv = Vec::new();
+        timeline.write_report(&drain, true, &mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "template_id,template,2024-03-14T10:00:00Z,2024-03-14T10:05:00Z,2024-03-14T10:10:00Z
+87e7492b,request served,2,0,1
+81bc6888,\"disk, full\",0,0,2
+"
+        );
+
+        // An outlier decades earlier doesn't fill in every second since
+        let mut outlier = DrainTimeline::new(Duration::seconds(1)).unwrap();
+        for timestamp in ["1971-01-01T00:00:00Z", "2024-03-14T10:00:00Z"] {
+            let mut event = Event::new();
+            event.timestamp = Some(timestamp.parse().unwrap());
+            event.message = Some("request served".to_string());
+            outlier.add(drain.add_event(&event).unwrap(), &event);
+        }
+        let mut csv = Vec::new();
+        outlier.write_report(&drain, true, &mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "template_id,template,1971-01-01T00:00:00Z,2024-03-14T10:00:00Z
+87e7492b,request served,1,1
+"
+        );
+
+        assert!(DrainTimeline::new(Duration::zero()).is_err());
+    }
+}
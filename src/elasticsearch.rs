@@ -0,0 +1,166 @@
+use crate::event::Event;
+use crate::formatters::{EsBulkFormatter, Formatter};
+use crate::sinks::Sink;
+use anyhow::{bail, Context, Result};
+use std::thread;
+use std::time::Duration;
+
+// Direct indexing through the Elasticsearch `_bulk` API
+pub struct EsBulkSink {
+    endpoint: String,
+    formatter: EsBulkFormatter,
+    body: String,
+    pending: usize,
+}
+
+/// Events per bulk request
+const ES_BATCH_SIZE: usize = 500;
+
+/// Attempts per bulk request before giving up
+const ES_MAX_ATTEMPTS: u32 = 5;
+
+/// Delay before the first retry, doubled after every failed attempt
+const ES_INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+
+impl EsBulkSink {
+    /// Index into the cluster at a base URL such as `http://localhost:9200`
+    pub fn new(url: &str, formatter: EsBulkFormatter) -> Self {
+        let url = url.trim_end_matches('/');
+        let endpoint = if url.ends_with("/_bulk") {
+            url.to_string()
+        } else {
+            format!("{}/_bulk", url)
+        };
+        Self {
+            endpoint,
+            formatter,
+            body: String::new(),
+            pending: 0,
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if self.pending == 0 {
+            return Ok(());
+        }
+
+        let mut backoff = ES_INITIAL_BACKOFF;
+        let mut attempt = 1;
+        let response = loop {
+            let result = ureq::post(&self.endpoint)
+                .header("Content-Type", "application/x-ndjson")
+                .send(self.body.as_bytes());
+            match result {
+                Ok(response) => break response,
+                Err(e) if attempt < ES_MAX_ATTEMPTS && is_retryable(&e) => {
+                    thread::sleep(backoff);
+                    backoff *= 2;
+                    attempt += 1;
+                }
+                Err(e) => {
+                    return Err(e).with_context(|| {
+                        format!(
+                            "Failed to index events at {} after {} attempt(s)",
+                            self.endpoint, attempt
+                        )
+                    })
+                }
+            }
+        };
+
+        // Bulk requests succeed as a whole even when individual documents are rejected
+        let text = response.into_body().read_to_string().unwrap_or_default();
+        if let Some(reason) = first_item_error(&text) {
+            bail!("Elasticsearch rejected documents: {}", reason);
+        }
+
+        self.body.clear();
+        self.pending = 0;
+        Ok(())
+    }
+}
+
+impl Sink for EsBulkSink {
+    fn write(&mut self, event: &Event) -> Result<()> {
+        self.body.push_str(&self.formatter.format(event));
+        self.body.push('\n');
+        self.pending += 1;
+
+        if self.pending >= ES_BATCH_SIZE {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.flush()
+    }
+}
+
+/// Connection problems, throttling and server errors are worth retrying; other client errors
+/// will fail the same way again
+fn is_retryable(error: &ureq::Error) -> bool {
+    match error {
+        ureq::Error::StatusCode(status) => *status == 429 || *status >= 500,
+        _ => true,
+    }
+}
+
+/// The first per-document error reported in a bulk response, if any
+fn first_item_error(response: &str) -> Option<String> {
+    let json: serde_json::Value = serde_json::from_str(response).ok()?;
+    if json["errors"] != serde_json::Value::Bool(true) {
+        return None;
+    }
+    let items = json["items"].as_array()?;
+    let error = items
+        .iter()
+        .filter_map(|item| item.as_object()?.values().next())
+        .find_map(|result| result.get("error"))?;
+    Some(
+        error["reason"]
+            .as_str()
+            .map(str::to_string)
+            .unwrap_or_else(|| error.to_string()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_retryable() {
+        assert!(is_retryable(&ureq::Error::StatusCode(429)));
+        assert!(is_retryable(&ureq::Error::StatusCode(503)));
+        assert!(!is_retryable(&ureq::Error::StatusCode(400)));
+        assert!(is_retryable(&ureq::Error::ConnectionFailed));
+    }
+
+    #[test]
+    fn test_first_item_error() {
+        let response = r#"{"took":3,"errors":true,"items":[
+            {"index":{"_index":"logs","status":201}},
+            {"index":{"_index":"logs","status":400,"error":{"type":"mapper_parsing_exception","reason":"failed to parse field [status]"}}}
+        ]}"#;
+        assert_eq!(
+            first_item_error(response).as_deref(),
+            Some("failed to parse field [status]")
+        );
+        assert_eq!(first_item_error(r#"{"errors":false,"items":[]}"#), None);
+        assert_eq!(first_item_error(""), None);
+    }
+
+    #[test]
+    fn test_endpoint_path() {
+        let formatter = || EsBulkFormatter::new("logs").unwrap();
+        assert_eq!(
+            EsBulkSink::new("http://localhost:9200/", formatter()).endpoint,
+            "http://localhost:9200/_bulk"
+        );
+        assert_eq!(
+            EsBulkSink::new("http://localhost:9200/_bulk", formatter()).endpoint,
+            "http://localhost:9200/_bulk"
+        );
+    }
+}
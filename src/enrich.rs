@@ -0,0 +1,471 @@
+use crate::event::{Event, FieldValue};
+use crate::filters::parse_ip;
+use maxminddb::Reader;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use woothee::parser::Parser;
+
+/// What the parser reports for parts it can't tell
+const UA_UNKNOWN: &str = woothee::woothee::VALUE_UNKNOWN;
+
+/// Expands a User-Agent field (`--parse-ua`) into `ua_browser`, `ua_version`, `ua_os` and
+/// `ua_device` (pc, smartphone, mobilephone, crawler, appliance or misc). Parts the parser
+/// can't tell are left out
+pub struct UserAgentParser {
+    field: String,
+    parser: Parser,
+}
+
+impl UserAgentParser {
+    pub fn new(field: &str) -> Self {
+        Self {
+            field: field.to_string(),
+            parser: Parser::new(),
+        }
+    }
+
+    pub fn apply(&self, event: &mut Event) {
+        let Some(FieldValue::String(agent)) = event.get_value(&self.field) else {
+            return;
+        };
+        let Some(ua) = self.parser.parse(&agent) else {
+            return;
+        };
+        for (key, value) in [
+            ("ua_browser", ua.name),
+            ("ua_version", ua.version),
+            ("ua_os", ua.os),
+            ("ua_device", ua.category),
+        ] {
+            if !value.is_empty() && value != UA_UNKNOWN {
+                event.set_field(key.to_string(), FieldValue::String(value.to_string()));
+            }
+        }
+    }
+}
+
+/// Annotates events with the location of an IP address field (`--geoip`), looked up in
+/// MaxMind databases: `geo_country` (ISO code) and `geo_city` from City or Country
+/// databases, `geo_asn` and `geo_as_org` from ASN databases. Several databases can be
+/// combined; what none of them knows is left out
+pub struct GeoIp {
+    field: String,
+    databases: Vec<Reader<Vec<u8>>>,
+}
+
+/// The parts of GeoIP2/GeoLite2 City, Country and ASN records that are used
+#[derive(Deserialize)]
+struct GeoRecord<'a> {
+    #[serde(borrow)]
+    country: Option<Place<'a>>,
+    #[serde(borrow)]
+    city: Option<Place<'a>>,
+    autonomous_system_number: Option<u32>,
+    autonomous_system_organization: Option<&'a str>,
+}
+
+#[derive(Deserialize)]
+struct Place<'a> {
+    iso_code: Option<&'a str>,
+    #[serde(borrow)]
+    names: Option<BTreeMap<&'a str, &'a str>>,
+}
+
+impl GeoIp {
+    pub fn open(field: &str, paths: &[impl AsRef<Path>]) -> Result<Self, String> {
+        let databases = paths
+            .iter()
+            .map(|path| {
+                Reader::open_readfile(path)
+                    .map_err(|e| format!("{}: {}", path.as_ref().display(), e))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self::new(field, databases))
+    }
+
+    pub fn new(field: &str, databases: Vec<Reader<Vec<u8>>>) -> Self {
+        Self {
+            field: field.to_string(),
+            databases,
+        }
+    }
+
+    pub fn apply(&self, event: &mut Event) {
+        let Some(ip) = event
+            .get_value(&self.field)
+            .and_then(|value| parse_ip(&value.to_string()))
+        else {
+            return;
+        };
+        for database in &self.databases {
+            let Ok(record) = database.lookup::<GeoRecord>(ip) else {
+                continue;
+            };
+            let mut set = |key: &str, value: FieldValue| {
+                event.set_field(key.to_string(), value);
+            };
+            if let Some(code) = record.country.as_ref().and_then(|c| c.iso_code) {
+                set("geo_country", FieldValue::String(code.to_string()));
+            }
+            if let Some(name) = record
+                .city
+                .as_ref()
+                .and_then(|city| city.names.as_ref()?.get("en").copied())
+            {
+                set("geo_city", FieldValue::String(name.to_string()));
+            }
+            if let Some(asn) = record.autonomous_system_number {
+                set("geo_asn", FieldValue::Number(asn as f64));
+            }
+            if let Some(org) = record.autonomous_system_organization {
+                set("geo_as_org", FieldValue::String(org.to_string()));
+            }
+        }
+    }
+}
+
+/// Most lookups left running after their timeout; beyond this, addresses go unresolved
+/// instead of piling up threads on an unresponsive resolver
+const RDNS_MAX_PENDING: usize = 16;
+
+/// Resolves IP address fields to host names (`--rdns`), writing them to `<field>_ptr`.
+/// Lookups that take longer than the timeout are left out, but keep running in the
+/// background, so later events with the same address can use their result; until then, the
+/// address is left out without starting another lookup. Answers, including failed lookups
+/// and addresses without a PTR record, are cached for the whole run
+pub struct ReverseDns {
+    fields: Vec<String>,
+    timeout: Duration,
+    resolver: fn(IpAddr) -> Option<String>,
+    cache: Arc<Mutex<HashMap<IpAddr, Lookup>>>,
+    pending: Arc<AtomicUsize>,
+}
+
+enum Lookup {
+    /// Still running after its timeout
+    Pending,
+    Done(Option<String>),
+}
+
+impl ReverseDns {
+    pub fn new(fields: Vec<String>, timeout: Duration) -> Self {
+        Self {
+            fields,
+            timeout,
+            resolver: |ip| dns_lookup::lookup_addr(&ip).ok(),
+            cache: Arc::default(),
+            pending: Arc::default(),
+        }
+    }
+
+    /// Look names up with this function instead of the system resolver
+    #[cfg(test)]
+    pub fn with_resolver(mut self, resolver: fn(IpAddr) -> Option<String>) -> Self {
+        self.resolver = resolver;
+        self
+    }
+
+    pub fn apply(&self, event: &mut Event) {
+        for field in &self.fields {
+            let Some(ip) = event
+                .get_value(field)
+                .and_then(|value| parse_ip(&value.to_string()))
+            else {
+                continue;
+            };
+            if let Some(host) = self.resolve(ip) {
+                event.set_field(format!("{}_ptr", field), FieldValue::String(host));
+            }
+        }
+    }
+
+    fn resolve(&self, ip: IpAddr) -> Option<String> {
+        let mut lookups = self.cache.lock().ok()?;
+        match lookups.get(&ip) {
+            Some(Lookup::Done(host)) => return host.clone(),
+            Some(Lookup::Pending) => return None,
+            None if self.pending.load(Ordering::SeqCst) >= RDNS_MAX_PENDING => return None,
+            None => {}
+        }
+        lookups.insert(ip, Lookup::Pending);
+        drop(lookups);
+
+        self.pending.fetch_add(1, Ordering::SeqCst);
+        let (sender, receiver) = mpsc::channel();
+        let (resolver, cache, pending) = (self.resolver, self.cache.clone(), self.pending.clone());
+        thread::spawn(move || {
+            // Without a PTR record, the resolver answers with the address itself
+            let host = resolver(ip).filter(|host| host.parse::<IpAddr>() != Ok(ip));
+            if let Ok(mut cache) = cache.lock() {
+                cache.insert(ip, Lookup::Done(host.clone()));
+            }
+            pending.fetch_sub(1, Ordering::SeqCst);
+            let _ = sender.send(host);
+        });
+        receiver.recv_timeout(self.timeout).ok().flatten()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_user_agent() {
+        let parser = UserAgentParser::new("user_agent");
+        let mut event = Event::new();
+        event.set_field(
+            "user_agent".to_string(),
+            FieldValue::String(
+                "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36"
+                    .to_string(),
+            ),
+        );
+        parser.apply(&mut event);
+        let field = |key: &str| event.fields.get(key).map(|value| value.to_string());
+        assert_eq!(field("ua_browser").as_deref(), Some("Chrome"));
+        assert_eq!(field("ua_version").as_deref(), Some("120.0.0.0"));
+        assert_eq!(field("ua_os").as_deref(), Some("Windows 10"));
+        assert_eq!(field("ua_device").as_deref(), Some("pc"));
+
+        let mut bot = Event::new();
+        bot.set_field(
+            "user_agent".to_string(),
+            FieldValue::String(
+                "Mozilla/5.0 (compatible; Googlebot/2.1; +http://www.google.com/bot.html)"
+                    .to_string(),
+            ),
+        );
+        parser.apply(&mut bot);
+        assert_eq!(
+            bot.fields.get("ua_device"),
+            Some(&FieldValue::String("crawler".to_string()))
+        );
+
+        let mut unknown = Event::new();
+        unknown.set_field(
+            "user_agent".to_string(),
+            FieldValue::String("curl-ish".to_string()),
+        );
+        parser.apply(&mut unknown);
+        assert_eq!(unknown.fields.len(), 1);
+    }
+
+    /// Encode a data field: the type and payload size in a control byte, extended types
+    /// (above 7) in a second byte, and sizes from 29 on in one more
+    fn encode(kind: u8, size: usize, payload: &[u8]) -> Vec<u8> {
+        let short_size = size.min(29) as u8;
+        let mut bytes = if kind > 7 {
+            vec![short_size, kind - 7]
+        } else {
+            vec![(kind << 5) | short_size]
+        };
+        if size >= 29 {
+            bytes.push((size - 29) as u8);
+        }
+        bytes.extend_from_slice(payload);
+        bytes
+    }
+
+    fn string(text: &str) -> Vec<u8> {
+        encode(2, text.len(), text.as_bytes())
+    }
+
+    fn uint(kind: u8, value: u64) -> Vec<u8> {
+        let bytes = value.to_be_bytes();
+        let start = bytes.iter().position(|b| *b != 0).unwrap_or(bytes.len());
+        encode(kind, bytes.len() - start, &bytes[start..])
+    }
+
+    fn map(entries: Vec<(&str, Vec<u8>)>) -> Vec<u8> {
+        let mut bytes = encode(7, entries.len(), &[]);
+        for (key, value) in entries {
+            bytes.extend(string(key));
+            bytes.extend(value);
+        }
+        bytes
+    }
+
+    /// A minimal IPv4 MaxMind database holding one record per network
+    fn database(records: Vec<([u8; 4], usize, Vec<u8>)>) -> Reader<Vec<u8>> {
+        const EMPTY: usize = usize::MAX;
+        let mut nodes: Vec<[usize; 2]> = vec![[EMPTY, EMPTY]];
+        let mut data = Vec::new();
+        let mut pointers = Vec::new();
+        for (network, prefix, record) in records {
+            let bits = u32::from_be_bytes(network);
+            let mut node = 0;
+            for i in 0..prefix - 1 {
+                let bit = (bits >> (31 - i) & 1) as usize;
+                if nodes[node][bit] == EMPTY {
+                    nodes.push([EMPTY, EMPTY]);
+                    nodes[node][bit] = nodes.len() - 1;
+                }
+                node = nodes[node][bit];
+            }
+            pointers.push((node, (bits >> (32 - prefix) & 1) as usize, data.len()));
+            data.extend(record);
+        }
+        let count = nodes.len();
+        let mut records: Vec<[usize; 2]> = nodes
+            .iter()
+            .map(|node| node.map(|next| if next == EMPTY { count } else { next }))
+            .collect();
+        for (node, bit, offset) in pointers {
+            records[node][bit] = count + 16 + offset;
+        }
+
+        let mut buf = Vec::new();
+        for record in records.iter().flatten() {
+            buf.extend_from_slice(&(*record as u32).to_be_bytes()[1..]);
+        }
+        buf.extend([0; 16]);
+        buf.extend(data);
+        buf.extend(b"\xAB\xCD\xEFMaxMind.com");
+        buf.extend(map(vec![
+            ("binary_format_major_version", uint(5, 2)),
+            ("binary_format_minor_version", uint(5, 0)),
+            ("build_epoch", uint(9, 0)),
+            ("database_type", string("Test")),
+            ("description", map(vec![])),
+            ("ip_version", uint(5, 4)),
+            ("languages", encode(11, 0, &[])),
+            ("node_count", uint(6, count as u64)),
+            ("record_size", uint(5, 24)),
+        ]));
+        Reader::from_source(buf).unwrap()
+    }
+
+    #[test]
+    fn test_geoip() {
+        let city = database(vec![(
+            [203, 0, 113, 0],
+            24,
+            map(vec![
+                (
+                    "city",
+                    map(vec![("names", map(vec![("en", string("Sydney"))]))]),
+                ),
+                ("country", map(vec![("iso_code", string("AU"))])),
+            ]),
+        )]);
+        let asn = database(vec![(
+            [203, 0, 0, 0],
+            16,
+            map(vec![
+                ("autonomous_system_number", uint(6, 64500)),
+                ("autonomous_system_organization", string("Example Net")),
+            ]),
+        )]);
+        let geoip = GeoIp::new("client_ip", vec![city, asn]);
+
+        let mut event = Event::new();
+        event.set_field(
+            "client_ip".to_string(),
+            FieldValue::String("203.0.113.9:443".to_string()),
+        );
+        geoip.apply(&mut event);
+        let field = |key: &str| event.fields.get(key).cloned();
+        assert_eq!(
+            field("geo_country"),
+            Some(FieldValue::String("AU".to_string()))
+        );
+        assert_eq!(
+            field("geo_city"),
+            Some(FieldValue::String("Sydney".to_string()))
+        );
+        assert_eq!(field("geo_asn"), Some(FieldValue::Number(64500.0)));
+        assert_eq!(
+            field("geo_as_org"),
+            Some(FieldValue::String("Example Net".to_string()))
+        );
+
+        // Only the ASN database knows this one
+        let mut event = Event::new();
+        event.set_field(
+            "client_ip".to_string(),
+            FieldValue::String("203.0.7.1".to_string()),
+        );
+        geoip.apply(&mut event);
+        let keys: Vec<&String> = event.fields.keys().collect();
+        assert_eq!(keys, ["client_ip", "geo_asn", "geo_as_org"]);
+
+        let mut event = Event::new();
+        event.set_field(
+            "client_ip".to_string(),
+            FieldValue::String("198.51.100.1".to_string()),
+        );
+        geoip.apply(&mut event);
+        assert_eq!(event.fields.len(), 1);
+
+        assert!(GeoIp::open("client_ip", &["/nonexistent.mmdb"]).is_err());
+    }
+
+    /// Held by the test while the slow lookup should hang
+    static SLOW_GATE: Mutex<()> = Mutex::new(());
+    static SLOW_LOOKUPS: AtomicUsize = AtomicUsize::new(0);
+
+    fn fake_resolver(ip: IpAddr) -> Option<String> {
+        match ip.to_string().as_str() {
+            "10.0.0.1" => Some("db01.internal".to_string()),
+            "10.0.0.2" => {
+                SLOW_LOOKUPS.fetch_add(1, Ordering::SeqCst);
+                let _gate = SLOW_GATE.lock();
+                Some("slow.internal".to_string())
+            }
+            "10.0.0.3" => Some("10.0.0.3".to_string()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_reverse_dns() {
+        let rdns = ReverseDns::new(
+            vec!["src_ip".to_string(), "dst_ip".to_string()],
+            Duration::from_millis(50),
+        )
+        .with_resolver(fake_resolver);
+        let event = |src: &str, dst: &str| {
+            let mut event = Event::new();
+            event.set_field("src_ip".to_string(), FieldValue::String(src.to_string()));
+            event.set_field("dst_ip".to_string(), FieldValue::String(dst.to_string()));
+            rdns.apply(&mut event);
+            (
+                event.fields.get("src_ip_ptr").map(|v| v.to_string()),
+                event.fields.get("dst_ip_ptr").map(|v| v.to_string()),
+            )
+        };
+
+        assert_eq!(
+            event("10.0.0.1", "192.0.2.1"),
+            (Some("db01.internal".to_string()), None)
+        );
+        // No PTR record
+        assert_eq!(event("10.0.0.3", "not an ip"), (None, None));
+
+        // Too slow at first, and not looked up again while that lookup runs
+        let gate = SLOW_GATE.lock().unwrap();
+        assert_eq!(event("10.0.0.2", "not an ip"), (None, None));
+        assert_eq!(event("10.0.0.2", "not an ip"), (None, None));
+        assert_eq!(SLOW_LOOKUPS.load(Ordering::SeqCst), 1);
+        // Then answered from the cache
+        drop(gate);
+        while rdns.pending.load(Ordering::SeqCst) > 0 {
+            thread::yield_now();
+        }
+        assert_eq!(
+            event("10.0.0.2", "10.0.0.1:5432"),
+            (
+                Some("slow.internal".to_string()),
+                Some("db01.internal".to_string())
+            )
+        );
+        assert_eq!(SLOW_LOOKUPS.load(Ordering::SeqCst), 1);
+    }
+}
@@ -1,16 +1,79 @@
 use chrono::{DateTime, Utc};
+use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+
+/// Field names recognized as the event timestamp
+pub const TIMESTAMP_KEYS: &[&str] = &["timestamp", "ts", "time", "at", "_t", "@t", "t"];
+
+/// Field names recognized as the event level
+pub const LEVEL_KEYS: &[&str] = &[
+    "level",
+    "log_level",
+    "loglevel",
+    "lvl",
+    "severity",
+    "sev",
+    "@l",
+];
+
+/// Field names recognized as the event message
+pub const MESSAGE_KEYS: &[&str] = &["message", "msg", "@m"];
+
+/// Field name for the input file an event was read from
+pub const SOURCE_KEY: &str = "_file";
+
+/// Level names for syslog severities 0 (emergency) to 7 (debug)
+pub const SYSLOG_LEVEL_NAMES: [&str; 8] = [
+    "EMERGENCY",
+    "ALERT",
+    "CRITICAL",
+    "ERROR",
+    "WARNING",
+    "NOTICE",
+    "INFO",
+    "DEBUG",
+];
+
+/// Syslog severity (0 = emergency ... 7 = debug) for a level name, alias, or numeric severity
+pub fn syslog_severity(level: &str) -> Option<u8> {
+    match level.trim().to_uppercase().as_str() {
+        "EMERGENCY" | "EMERG" | "PANIC" => Some(0),
+        "ALERT" => Some(1),
+        "CRITICAL" | "CRIT" | "FATAL" => Some(2),
+        "ERROR" | "ERR" => Some(3),
+        "WARNING" | "WARN" => Some(4),
+        "NOTICE" => Some(5),
+        "INFO" | "INFORMATIONAL" => Some(6),
+        "DEBUG" | "TRACE" => Some(7),
+        other => other.parse::<u8>().ok().filter(|severity| *severity <= 7),
+    }
+}
+
+/// Position of a level in the severity ordering, from trace (0) up to emergency (8), so that
+/// more severe levels compare greater
+pub fn level_rank(level: &str) -> Option<u8> {
+    if level.trim().eq_ignore_ascii_case("TRACE") {
+        return Some(0);
+    }
+    syslog_severity(level).map(|severity| 8 - severity)
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct Event {
     pub timestamp: Option<DateTime<Utc>>,
     pub level: Option<String>,
     pub message: Option<String>,
-    pub fields: HashMap<String, FieldValue>,
+    // Fields in input order
+    pub fields: IndexMap<String, FieldValue>,
+    // Input file the event was read from; not output, but available as `_file`
+    #[serde(skip)]
+    pub source: Option<String>,
+    // The timestamp as --ts-display renders it, output in its place
+    #[serde(skip)]
+    pub timestamp_display: Option<FieldValue>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum FieldValue {
     String(String),
@@ -30,67 +93,59 @@ impl Event {
 
     /// Filter to only show specified keys, keeping only fields that actually exist
     pub fn filter_keys(&mut self, keys: &[String]) {
-        let mut new_fields = HashMap::new();
-
-        // First, handle core fields - only keep them if they exist and are requested
-        let mut keep_timestamp = false;
-        let mut keep_level = false;
-        let mut keep_message = false;
-
-        for key in keys {
-            match key.as_str() {
-                "timestamp" | "ts" | "time" | "at" | "_t" | "@t" | "t" => {
-                    if self.timestamp.is_some() {
-                        keep_timestamp = true;
-                    }
-                }
-                "level" | "log_level" | "loglevel" | "lvl" | "severity" | "@l" => {
-                    if self.level.is_some() {
-                        keep_level = true;
-                    }
-                }
-                "message" | "msg" | "@m" => {
-                    if self.message.is_some() {
-                        keep_message = true;
-                    }
-                }
-                _ => {
-                    // For other fields, only include if they exist
-                    if let Some(value) = self.fields.get(key) {
-                        new_fields.insert(key.clone(), value.clone());
-                    }
-                }
-            }
+        // First, handle core fields - clear them if they weren't requested
+        let requested = |core: &str| {
+            keys.iter()
+                .any(|key| core_key_name(key) == Some(core) || key_matches(key, core))
+        };
+        if !requested("timestamp") {
+            self.timestamp = None;
         }
+        if !requested("level") {
+            self.level = None;
+        }
+        if !requested("message") {
+            self.message = None;
+        }
+
+        // For other fields, only keep the requested ones, preserving input order
+        self.fields.retain(|key, _| {
+            keys.iter().any(|pattern| key_matches(pattern, key)) && !is_core_key(key)
+        });
+    }
 
-        // Clear core fields if they weren't requested or don't exist
-        if !keep_timestamp {
+    /// Drop the given keys, keeping everything else. Naming a core field (or one of its
+    /// aliases) drops the core field together with any raw copies of it
+    pub fn exclude_keys(&mut self, keys: &[String]) {
+        let excluded_core: Vec<&str> = ["timestamp", "level", "message"]
+            .into_iter()
+            .filter(|core| {
+                keys.iter()
+                    .any(|key| core_key_name(key) == Some(*core) || key_matches(key, core))
+            })
+            .collect();
+        if excluded_core.contains(&"timestamp") {
             self.timestamp = None;
         }
-        if !keep_level {
+        if excluded_core.contains(&"level") {
             self.level = None;
         }
-        if !keep_message {
+        if excluded_core.contains(&"message") {
             self.message = None;
         }
 
-        self.fields = new_fields;
+        self.fields.retain(|key, _| {
+            !keys.iter().any(|pattern| key_matches(pattern, key))
+                && !core_key_name(key).is_some_and(|core| excluded_core.contains(&core))
+        });
     }
 
     /// Try to parse and extract core fields from the fields map
     pub fn extract_core_fields(&mut self) {
-        // Extract timestamp
-        for ts_key in &["timestamp", "ts", "time", "at", "_t", "@t", "t"] {
-            if let Some(FieldValue::String(ts_str)) = self.fields.get(*ts_key) {
-                if let Ok(ts) = parse_timestamp(ts_str) {
-                    self.timestamp = Some(ts);
-                    break;
-                }
-            }
-        }
+        self.extract_timestamp(&TimestampOptions::default());
 
         // Extract level
-        for level_key in &["level", "log_level", "loglevel", "lvl", "severity", "@l"] {
+        for level_key in LEVEL_KEYS {
             if let Some(value) = self.fields.get(*level_key) {
                 if let Some(level_str) = value.as_string() {
                     self.level = Some(level_str.clone());
@@ -100,7 +155,7 @@ impl Event {
         }
 
         // Extract message
-        for msg_key in &["message", "msg", "@m"] {
+        for msg_key in MESSAGE_KEYS {
             if let Some(value) = self.fields.get(*msg_key) {
                 if let Some(msg_str) = value.as_string() {
                     self.message = Some(msg_str.clone());
@@ -110,6 +165,138 @@ impl Event {
         }
     }
 
+    /// Set the timestamp from the first timestamp field that parses
+    pub fn extract_timestamp(&mut self, options: &TimestampOptions) {
+        if let Some(ts) = TIMESTAMP_KEYS.iter().find_map(|key| {
+            self.fields
+                .get(*key)
+                .and_then(|value| timestamp_from_value(value, options))
+        }) {
+            self.timestamp = Some(ts);
+        }
+    }
+
+    /// Rename a field in place, replacing any field already named `to`. A field renamed to a
+    /// core field alias (e.g. `text` to `message`) becomes that core field
+    pub fn rename_field(&mut self, from: &str, to: &str) {
+        if from == to || !self.fields.contains_key(from) {
+            return;
+        }
+        self.fields.shift_remove(to);
+        let Some((index, _, value)) = self.fields.shift_remove_full(from) else {
+            return;
+        };
+
+        self.update_core_field(to, &value);
+        self.fields.shift_insert(index, to.to_string(), value);
+    }
+
+    /// Set a field, replacing its value in place if it exists. Setting a core field alias
+    /// (e.g. `msg`) also sets that core field
+    pub fn assign_field(&mut self, key: &str, value: FieldValue) {
+        self.update_core_field(key, &value);
+        self.fields.insert(key.to_string(), value);
+    }
+
+    fn update_core_field(&mut self, key: &str, value: &FieldValue) {
+        if TIMESTAMP_KEYS.contains(&key) {
+            if let Some(ts) = timestamp_from_value(value, &TimestampOptions::default()) {
+                self.timestamp = Some(ts);
+            }
+            return;
+        }
+        let Some(text) = value.as_string() else {
+            return;
+        };
+        if LEVEL_KEYS.contains(&key) {
+            self.level = Some(text.clone());
+        } else if MESSAGE_KEYS.contains(&key) {
+            self.message = Some(text.clone());
+        }
+    }
+
+    /// Expand fields holding JSON objects or arrays (kept as JSON text, e.g. from JSON input)
+    /// into one field per leaf, with keys joined by `separator`: `{"http":{"status":200}}`
+    /// becomes `http.status=200`, `{"tags":["a","b"]}` becomes `tags.0` and `tags.1`. Levels
+    /// below `max_depth` stay JSON text
+    pub fn flatten(&mut self, separator: &str, max_depth: Option<usize>) {
+        self.flatten_where(separator, max_depth, |_, text| text.starts_with(['{', '[']));
+    }
+
+    /// Expand string fields holding stringified JSON objects, e.g. `data="{\"id\":5}"`, like
+    /// [`Event::flatten`] does, but only for keys matching one of `patterns` (wildcards allowed,
+    /// so `*` detects them in any field)
+    pub fn expand_json(&mut self, patterns: &[String], separator: &str) {
+        self.flatten_where(separator, None, |key, text| {
+            text.trim_start().starts_with('{')
+                && patterns.iter().any(|pattern| key_matches(pattern, key))
+        });
+    }
+
+    fn flatten_where(
+        &mut self,
+        separator: &str,
+        max_depth: Option<usize>,
+        is_nested: impl Fn(&str, &str) -> bool,
+    ) {
+        let nested = |key: &str, value: &FieldValue| matches!(value, FieldValue::String(text) if is_nested(key, text));
+        if !self.fields.iter().any(|(key, value)| nested(key, value)) {
+            return;
+        }
+
+        let mut flattened = IndexMap::with_capacity(self.fields.len());
+        for (key, value) in std::mem::take(&mut self.fields) {
+            let json = match &value {
+                FieldValue::String(text) if nested(&key, &value) => {
+                    serde_json::from_str::<serde_json::Value>(text).ok()
+                }
+                _ => None,
+            };
+            match json {
+                Some(json) => flatten_json(key, json, separator, max_depth, &mut flattened),
+                None => {
+                    flattened.insert(key, value);
+                }
+            }
+        }
+        self.fields = flattened;
+    }
+
+    /// Look up a value by key, falling back to the core fields for their well-known aliases
+    pub fn get_value(&self, key: &str) -> Option<FieldValue> {
+        if let Some(value) = self.fields.get(key) {
+            return Some(value.clone());
+        }
+
+        if TIMESTAMP_KEYS.contains(&key) {
+            self.timestamp
+                .map(|ts| FieldValue::String(ts.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string()))
+        } else if LEVEL_KEYS.contains(&key) {
+            self.level.clone().map(FieldValue::String)
+        } else if MESSAGE_KEYS.contains(&key) {
+            self.message.clone().map(FieldValue::String)
+        } else if key == SOURCE_KEY {
+            self.source.clone().map(FieldValue::String)
+        } else {
+            None
+        }
+    }
+
+    /// Severity rank of the event (see [`level_rank`]), also for numeric syslog severities,
+    /// which stay a field since they aren't level names
+    pub fn level_rank(&self) -> Option<u8> {
+        match &self.level {
+            Some(level) => level_rank(level),
+            None => LEVEL_KEYS
+                .iter()
+                .find_map(|key| match self.fields.get(*key) {
+                    Some(FieldValue::Number(n)) => Some(n.to_string()),
+                    _ => None,
+                })
+                .and_then(|severity| level_rank(&severity)),
+        }
+    }
+
     /// Check if the event has any content to display
     pub fn has_displayable_content(&self) -> bool {
         self.timestamp.is_some()
@@ -119,6 +306,113 @@ impl Event {
     }
 }
 
+fn flatten_json(
+    key: String,
+    value: serde_json::Value,
+    separator: &str,
+    depth: Option<usize>,
+    out: &mut IndexMap<String, FieldValue>,
+) {
+    let children: Vec<(String, serde_json::Value)> = match value {
+        serde_json::Value::Object(map) if !map.is_empty() && depth != Some(0) => {
+            map.into_iter().collect()
+        }
+        serde_json::Value::Array(items) if !items.is_empty() && depth != Some(0) => items
+            .into_iter()
+            .enumerate()
+            .map(|(i, item)| (i.to_string(), item))
+            .collect(),
+        serde_json::Value::String(s) => {
+            out.insert(key, FieldValue::String(s));
+            return;
+        }
+        serde_json::Value::Number(n) => {
+            out.insert(key, FieldValue::Number(n.as_f64().unwrap_or(0.0)));
+            return;
+        }
+        serde_json::Value::Bool(b) => {
+            out.insert(key, FieldValue::Boolean(b));
+            return;
+        }
+        serde_json::Value::Null => {
+            out.insert(key, FieldValue::Null);
+            return;
+        }
+        nested => {
+            out.insert(key, FieldValue::String(nested.to_string()));
+            return;
+        }
+    };
+    for (child_key, child) in children {
+        flatten_json(
+            format!("{}{}{}", key, separator, child_key),
+            child,
+            separator,
+            depth.map(|depth| depth - 1),
+            out,
+        );
+    }
+}
+
+/// Whether a key selector contains wildcards
+pub fn is_key_pattern(pattern: &str) -> bool {
+    pattern.contains(['*', '?'])
+}
+
+/// Match a key against a selector where `*` stands for any run of characters and `?` for
+/// exactly one, e.g. `http.*`, `*_id` or `k8s.pod.*`; selectors without wildcards must equal
+/// the key
+pub fn key_matches(pattern: &str, key: &str) -> bool {
+    if !is_key_pattern(pattern) {
+        return pattern == key;
+    }
+    let pattern: Vec<char> = pattern.chars().collect();
+    let key: Vec<char> = key.chars().collect();
+
+    // Iterative wildcard matching, backtracking to the most recent `*`
+    let (mut p, mut k) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while k < key.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, k));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == key[k] => {
+                p += 1;
+                k += 1;
+            }
+            _ => match star {
+                Some((star_p, star_k)) => {
+                    p = star_p + 1;
+                    k = star_k + 1;
+                    star = Some((star_p, star_k + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Whether a key is one of the well-known aliases of a core field
+pub fn is_core_key(key: &str) -> bool {
+    TIMESTAMP_KEYS.contains(&key) || LEVEL_KEYS.contains(&key) || MESSAGE_KEYS.contains(&key)
+}
+
+/// The canonical core field name (`timestamp`, `level`, `message`) for an alias, if any
+pub fn core_key_name(key: &str) -> Option<&'static str> {
+    if TIMESTAMP_KEYS.contains(&key) {
+        Some("timestamp")
+    } else if LEVEL_KEYS.contains(&key) {
+        Some("level")
+    } else if MESSAGE_KEYS.contains(&key) {
+        Some("message")
+    } else {
+        None
+    }
+}
+
 impl FieldValue {
     pub fn as_string(&self) -> Option<&String> {
         match self {
@@ -126,6 +420,14 @@ impl FieldValue {
             _ => None,
         }
     }
+    /// Numbers as-is, numeric strings coerced
+    pub fn as_number(&self) -> Option<f64> {
+        match self {
+            FieldValue::Number(n) => Some(*n),
+            FieldValue::String(s) => s.trim().parse().ok(),
+            _ => None,
+        }
+    }
 }
 
 impl std::fmt::Display for FieldValue {
@@ -139,7 +441,92 @@ impl std::fmt::Display for FieldValue {
     }
 }
 
-fn parse_timestamp(ts_str: &str) -> Result<DateTime<Utc>, chrono::ParseError> {
+/// Unit of numeric timestamps counting from the Unix epoch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EpochUnit {
+    Seconds,
+    Millis,
+    Micros,
+    Nanos,
+}
+
+impl EpochUnit {
+    /// Guess the unit from the magnitude of a timestamp between 1973 and 2286. Smaller
+    /// values are more likely counters or durations than times, so they get none
+    pub fn detect(value: f64) -> Option<Self> {
+        match value.abs() {
+            v if v < 1e8 => None,
+            v if v < 1e11 => Some(Self::Seconds),
+            v if v < 1e14 => Some(Self::Millis),
+            v if v < 1e17 => Some(Self::Micros),
+            _ => Some(Self::Nanos),
+        }
+    }
+
+    pub fn to_timestamp(self, value: f64) -> Option<DateTime<Utc>> {
+        // Nanoseconds per unit, and the step fractions are rounded to: floats near the current
+        // time in seconds only resolve microseconds
+        let (factor, step) = match self {
+            Self::Seconds => (1_000_000_000, 1_000),
+            Self::Millis => (1_000_000, 1),
+            Self::Micros => (1_000, 1),
+            Self::Nanos => (1, 1),
+        };
+        let whole = value.trunc();
+        if !whole.is_finite() || whole.abs() >= i64::MAX as f64 {
+            return None;
+        }
+        let fraction = ((value - whole) * (factor / step) as f64).round() as i64 * step;
+        let nanos = (whole as i64).checked_mul(factor)?.checked_add(fraction)?;
+        Some(DateTime::from_timestamp_nanos(nanos))
+    }
+}
+
+/// How to read timestamp fields beyond the built-in heuristics (`--ts-format`, `--epoch-unit`)
+#[derive(Debug, Clone, Default)]
+pub struct TimestampOptions {
+    /// strftime pattern tried before the known formats
+    pub format: Option<String>,
+    /// Unit of numeric timestamps, instead of guessing it from their magnitude
+    pub epoch_unit: Option<EpochUnit>,
+}
+
+/// Parse a timestamp field: text in one of the known formats, or a number of seconds,
+/// milliseconds, ... since the epoch
+pub fn timestamp_from_value(
+    value: &FieldValue,
+    options: &TimestampOptions,
+) -> Option<DateTime<Utc>> {
+    match value {
+        FieldValue::String(text) => options
+            .format
+            .as_deref()
+            .and_then(|format| parse_timestamp_with_format(text, format))
+            .or_else(|| parse_timestamp(text).ok()),
+        FieldValue::Number(n) => options
+            .epoch_unit
+            .or_else(|| EpochUnit::detect(*n))
+            .and_then(|unit| unit.to_timestamp(*n)),
+        _ => None,
+    }
+}
+
+/// Parse a timestamp in a strftime format. Times without an offset are taken as UTC, and
+/// dates without a time as midnight
+pub fn parse_timestamp_with_format(text: &str, format: &str) -> Option<DateTime<Utc>> {
+    if let Ok(dt) = DateTime::parse_from_str(text, format) {
+        return Some(dt.with_timezone(&Utc));
+    }
+    if let Ok(dt) = chrono::NaiveDateTime::parse_from_str(text, format) {
+        return Some(dt.and_utc());
+    }
+    chrono::NaiveDate::parse_from_str(text, format)
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(|dt| dt.and_utc())
+}
+
+pub fn parse_timestamp(ts_str: &str) -> Result<DateTime<Utc>, chrono::ParseError> {
     // Try common timestamp formats in order of likelihood
     let formats = [
         "%Y-%m-%dT%H:%M:%S%.fZ",   // ISO 8601 with subseconds
@@ -163,3 +550,231 @@ fn parse_timestamp(ts_str: &str) -> Result<DateTime<Utc>, chrono::ParseError> {
     // Return a proper chrono parse error
     chrono::NaiveDateTime::parse_from_str(ts_str, "%Y-%m-%dT%H:%M:%SZ").map(|dt| dt.and_utc())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_matches() {
+        assert!(key_matches("http.*", "http.status"));
+        assert!(!key_matches("http.*", "https"));
+        assert!(key_matches("*_id", "request_id"));
+        assert!(!key_matches("*_id", "request_ids"));
+        assert!(key_matches("k8s.*.name", "k8s.pod.name"));
+        assert!(key_matches("a*b*c", "aXbYbZc"));
+        assert!(key_matches("lvl?", "lvl1"));
+        assert!(key_matches("*", ""));
+        assert!(key_matches("status", "status"));
+        assert!(!key_matches("status", "status_code"));
+    }
+
+    #[test]
+    fn test_key_patterns_select_fields() {
+        let mut event = Event::new();
+        event.message = Some("hi".to_string());
+        for key in ["http.method", "http.status", "user_id", "trace_id", "pod"] {
+            event.set_field(key.to_string(), FieldValue::Null);
+        }
+
+        let mut selected = event.clone();
+        selected.filter_keys(&["http.*".to_string(), "pod".to_string()]);
+        let keys: Vec<&String> = selected.fields.keys().collect();
+        assert_eq!(keys, ["http.method", "http.status", "pod"]);
+        assert!(selected.message.is_none());
+
+        event.exclude_keys(&["*_id".to_string(), "mess*".to_string()]);
+        let keys: Vec<&String> = event.fields.keys().collect();
+        assert_eq!(keys, ["http.method", "http.status", "pod"]);
+        assert!(event.message.is_none());
+    }
+
+    #[test]
+    fn test_rename_field() {
+        let mut event = Event::new();
+        for (key, value) in [("a", "1"), ("text", "disk full"), ("b", "2"), ("c", "3")] {
+            event.set_field(key.to_string(), FieldValue::String(value.to_string()));
+        }
+
+        event.rename_field("a", "alpha");
+        event.rename_field("c", "b");
+        event.rename_field("missing", "x");
+        let keys: Vec<&String> = event.fields.keys().collect();
+        assert_eq!(keys, ["alpha", "text", "b"]);
+        assert_eq!(event.fields["b"], FieldValue::String("3".to_string()));
+
+        event.rename_field("text", "message");
+        assert_eq!(event.message.as_deref(), Some("disk full"));
+        assert!(event.fields.contains_key("message"));
+    }
+
+    #[test]
+    fn test_flatten() {
+        let mut event = Event::new();
+        event.set_field("before".to_string(), FieldValue::Number(1.0));
+        event.set_field(
+            "http".to_string(),
+            FieldValue::String(r#"{"status":503,"req":{"method":"GET","tls":true}}"#.to_string()),
+        );
+        event.set_field(
+            "tags".to_string(),
+            FieldValue::String(r#"["a","b"]"#.to_string()),
+        );
+        event.set_field("empty".to_string(), FieldValue::String("{}".to_string()));
+        event.set_field(
+            "text".to_string(),
+            FieldValue::String("[not json".to_string()),
+        );
+
+        let mut flat = event.clone();
+        flat.flatten(".", None);
+        let keys: Vec<&String> = flat.fields.keys().collect();
+        assert_eq!(
+            keys,
+            [
+                "before",
+                "http.status",
+                "http.req.method",
+                "http.req.tls",
+                "tags.0",
+                "tags.1",
+                "empty",
+                "text"
+            ]
+        );
+        assert_eq!(flat.fields["http.status"], FieldValue::Number(503.0));
+        assert_eq!(flat.fields["http.req.tls"], FieldValue::Boolean(true));
+
+        let mut shallow = event.clone();
+        shallow.flatten("_", Some(1));
+        assert_eq!(
+            shallow.fields["http_req"],
+            FieldValue::String(r#"{"method":"GET","tls":true}"#.to_string())
+        );
+        assert_eq!(
+            shallow.fields["tags_1"],
+            FieldValue::String("b".to_string())
+        );
+    }
+
+    #[test]
+    fn test_expand_json() {
+        let mut event = Event::new();
+        event.set_field(
+            "data".to_string(),
+            FieldValue::String(r#"{"user":"x","id":5,"geo":{"cc":"DE"}}"#.to_string()),
+        );
+        event.set_field(
+            "extra".to_string(),
+            FieldValue::String(r#"{"a":1}"#.to_string()),
+        );
+        event.set_field(
+            "tags".to_string(),
+            FieldValue::String(r#"["a","b"]"#.to_string()),
+        );
+
+        let mut expanded = event.clone();
+        expanded.expand_json(&["data".to_string()], ".");
+        let keys: Vec<&String> = expanded.fields.keys().collect();
+        assert_eq!(
+            keys,
+            ["data.user", "data.id", "data.geo.cc", "extra", "tags"]
+        );
+        assert_eq!(expanded.fields["data.id"], FieldValue::Number(5.0));
+
+        // Only objects are detected, arrays stay as they are
+        let mut detected = event.clone();
+        detected.expand_json(&["*".to_string()], "_");
+        let keys: Vec<&String> = detected.fields.keys().collect();
+        assert_eq!(
+            keys,
+            ["data_user", "data_id", "data_geo_cc", "extra_a", "tags"]
+        );
+    }
+
+    #[test]
+    fn test_epoch_timestamps() {
+        let expected: DateTime<Utc> = "2024-03-14T10:00:00.123Z".parse().unwrap();
+        for (key, value) in [
+            ("ts", 1710410400.123),
+            ("time", 1710410400123.0),
+            ("timestamp", 1710410400123000.0),
+            ("t", 1710410400123000000.0),
+        ] {
+            let mut event = Event::new();
+            event.set_field(key.to_string(), FieldValue::Number(value));
+            event.extract_core_fields();
+            // Floats can't hold nanoseconds since the epoch exactly
+            assert_eq!(
+                event.timestamp.map(|ts| ts.timestamp_micros()),
+                Some(expected.timestamp_micros()),
+                "{}={}",
+                key,
+                value
+            );
+        }
+
+        // Small numbers aren't taken for times unless the unit is given
+        let mut event = Event::new();
+        event.set_field("t".to_string(), FieldValue::Number(90.0));
+        event.extract_core_fields();
+        assert_eq!(event.timestamp, None);
+        event.extract_timestamp(&TimestampOptions {
+            epoch_unit: Some(EpochUnit::Seconds),
+            ..Default::default()
+        });
+        assert_eq!(
+            event.timestamp,
+            Some("1970-01-01T00:01:30Z".parse().unwrap())
+        );
+
+        let mut event = Event::new();
+        event.set_field("ts".to_string(), FieldValue::Number(1710410400.0));
+        event.extract_timestamp(&TimestampOptions {
+            epoch_unit: Some(EpochUnit::Millis),
+            ..Default::default()
+        });
+        assert_eq!(
+            event.timestamp,
+            Some("1970-01-20T19:06:50.400Z".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_custom_timestamp_format() {
+        let options = TimestampOptions {
+            format: Some("%d/%b/%Y:%H:%M:%S %z".to_string()),
+            ..Default::default()
+        };
+        let mut event = Event::new();
+        event.set_field(
+            "time".to_string(),
+            FieldValue::String("14/Mar/2024:11:00:00 +0100".to_string()),
+        );
+        event.extract_core_fields();
+        assert_eq!(event.timestamp, None);
+        event.extract_timestamp(&options);
+        assert_eq!(
+            event.timestamp,
+            Some("2024-03-14T10:00:00Z".parse().unwrap())
+        );
+
+        // The known formats still apply when the custom one doesn't fit
+        let mut event = Event::new();
+        event.set_field(
+            "ts".to_string(),
+            FieldValue::String("2024-03-14T10:00:00Z".to_string()),
+        );
+        event.extract_timestamp(&options);
+        assert!(event.timestamp.is_some());
+
+        assert_eq!(
+            parse_timestamp_with_format("20240314 100000", "%Y%m%d %H%M%S"),
+            Some("2024-03-14T10:00:00Z".parse().unwrap())
+        );
+        assert_eq!(
+            parse_timestamp_with_format("14.03.2024", "%d.%m.%Y"),
+            Some("2024-03-14T00:00:00Z".parse().unwrap())
+        );
+    }
+}
@@ -0,0 +1,769 @@
+use crate::event::{Event, FieldValue};
+use regex::Regex;
+use std::cmp::Ordering;
+
+/// A compiled expression such as `level == "ERROR" && (status >= 500 || msg =~ /timeout/)`.
+///
+/// Operators, from loosest to tightest binding:
+/// `||`/`or`, `&&`/`and`, `!`/`not`, comparisons (`== != > >= < <= =~ !~`),
+/// `+ -`, `* / %`, unary `-`. Operands are numbers, `"strings"` or `'strings'`,
+/// `/regexes/`, `true`, `false`, `null`, field names (dots allowed, e.g. `http.status`)
+/// and function calls: `has(field)`, `get("odd-key")`, `len(x)`, `lower(x)`, `upper(x)`,
+/// `contains(x, y)`, `starts_with(x, y)`, `ends_with(x, y)`, `num(x)`, `str(x)`.
+/// Missing fields evaluate to `null`.
+#[derive(Debug, Clone)]
+pub enum Expr {
+    Literal(FieldValue),
+    Field(String),
+    Not(Box<Expr>),
+    Neg(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+    Match {
+        expr: Box<Expr>,
+        regex: Regex,
+        negated: bool,
+    },
+    Call(Func, Vec<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinOp {
+    Eq,
+    Ne,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Func {
+    Has,
+    Get,
+    Len,
+    Lower,
+    Upper,
+    Contains,
+    StartsWith,
+    EndsWith,
+    Num,
+    Str,
+}
+
+impl Func {
+    fn from_name(name: &str) -> Option<(Self, usize)> {
+        Some(match name {
+            "has" => (Func::Has, 1),
+            "get" => (Func::Get, 1),
+            "len" => (Func::Len, 1),
+            "lower" => (Func::Lower, 1),
+            "upper" => (Func::Upper, 1),
+            "contains" => (Func::Contains, 2),
+            "starts_with" => (Func::StartsWith, 2),
+            "ends_with" => (Func::EndsWith, 2),
+            "num" => (Func::Num, 1),
+            "str" => (Func::Str, 1),
+            _ => return None,
+        })
+    }
+}
+
+impl Expr {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let tokens = tokenize(source)?;
+        let mut parser = ExprParser { tokens, pos: 0 };
+        let expr = parser.parse_or()?;
+        match parser.peek() {
+            None => Ok(expr),
+            Some(token) => Err(format!("unexpected {}", token.describe())),
+        }
+    }
+
+    pub fn evaluate(&self, event: &Event) -> FieldValue {
+        match self {
+            Expr::Literal(value) => value.clone(),
+            Expr::Field(name) => event.get_value(name).unwrap_or(FieldValue::Null),
+            Expr::Not(expr) => FieldValue::Boolean(!truthy(&expr.evaluate(event))),
+            Expr::Neg(expr) => match expr.evaluate(event).as_number() {
+                Some(n) => FieldValue::Number(-n),
+                None => FieldValue::Null,
+            },
+            Expr::And(left, right) => {
+                FieldValue::Boolean(left.matches(event) && right.matches(event))
+            }
+            Expr::Or(left, right) => {
+                FieldValue::Boolean(left.matches(event) || right.matches(event))
+            }
+            Expr::Binary(op, left, right) => {
+                binary(*op, &left.evaluate(event), &right.evaluate(event))
+            }
+            Expr::Match {
+                expr,
+                regex,
+                negated,
+            } => {
+                let found = match expr.evaluate(event) {
+                    FieldValue::Null => false,
+                    value => regex.is_match(&value.to_string()),
+                };
+                FieldValue::Boolean(found != *negated)
+            }
+            Expr::Call(func, args) => call(*func, args, event),
+        }
+    }
+
+    /// Evaluate as a condition: null, false, 0 and "" are false
+    pub fn matches(&self, event: &Event) -> bool {
+        truthy(&self.evaluate(event))
+    }
+
+    /// The regexes of `=~` matches that make the expression hold, for highlighting; those of
+    /// `!~` and under `!` are left out
+    pub fn match_patterns(&self) -> Vec<&str> {
+        match self {
+            Expr::Match {
+                expr,
+                regex,
+                negated,
+            } => {
+                let mut patterns = expr.match_patterns();
+                if !negated {
+                    patterns.push(regex.as_str());
+                }
+                patterns
+            }
+            Expr::Not(_) | Expr::Literal(_) | Expr::Field(_) => Vec::new(),
+            Expr::Neg(expr) => expr.match_patterns(),
+            Expr::And(left, right) | Expr::Or(left, right) | Expr::Binary(_, left, right) => {
+                let mut patterns = left.match_patterns();
+                patterns.extend(right.match_patterns());
+                patterns
+            }
+            Expr::Call(_, args) => args.iter().flat_map(Expr::match_patterns).collect(),
+        }
+    }
+}
+
+fn truthy(value: &FieldValue) -> bool {
+    match value {
+        FieldValue::Null => false,
+        FieldValue::Boolean(b) => *b,
+        FieldValue::Number(n) => *n != 0.0,
+        FieldValue::String(s) => !s.is_empty(),
+    }
+}
+
+/// Numeric comparison when both sides are numeric, string comparison otherwise
+fn compare(left: &FieldValue, right: &FieldValue) -> Option<Ordering> {
+    match (left, right) {
+        (FieldValue::Null, FieldValue::Null) => Some(Ordering::Equal),
+        (FieldValue::Null, _) | (_, FieldValue::Null) => None,
+        (FieldValue::Boolean(a), FieldValue::Boolean(b)) => Some(a.cmp(b)),
+        _ => match (left.as_number(), right.as_number()) {
+            (Some(a), Some(b)) => a.partial_cmp(&b),
+            _ => Some(left.to_string().cmp(&right.to_string())),
+        },
+    }
+}
+
+fn binary(op: BinOp, left: &FieldValue, right: &FieldValue) -> FieldValue {
+    let ordering = || compare(left, right);
+    let arithmetic = |f: fn(f64, f64) -> f64| match (left.as_number(), right.as_number()) {
+        (Some(a), Some(b)) => FieldValue::Number(f(a, b)),
+        _ => FieldValue::Null,
+    };
+    match op {
+        BinOp::Eq => FieldValue::Boolean(ordering() == Some(Ordering::Equal)),
+        BinOp::Ne => FieldValue::Boolean(ordering() != Some(Ordering::Equal)),
+        BinOp::Gt => FieldValue::Boolean(ordering() == Some(Ordering::Greater)),
+        BinOp::Ge => FieldValue::Boolean(matches!(
+            ordering(),
+            Some(Ordering::Greater | Ordering::Equal)
+        )),
+        BinOp::Lt => FieldValue::Boolean(ordering() == Some(Ordering::Less)),
+        BinOp::Le => {
+            FieldValue::Boolean(matches!(ordering(), Some(Ordering::Less | Ordering::Equal)))
+        }
+        // `+` concatenates when either side isn't numeric
+        BinOp::Add => match (left.as_number(), right.as_number()) {
+            (Some(a), Some(b)) => FieldValue::Number(a + b),
+            _ if matches!(left, FieldValue::Null) || matches!(right, FieldValue::Null) => {
+                FieldValue::Null
+            }
+            _ => FieldValue::String(format!("{}{}", left, right)),
+        },
+        BinOp::Sub => arithmetic(|a, b| a - b),
+        BinOp::Mul => arithmetic(|a, b| a * b),
+        BinOp::Div => match right.as_number() {
+            Some(b) if b != 0.0 => arithmetic(|a, b| a / b),
+            _ => FieldValue::Null,
+        },
+        BinOp::Rem => match right.as_number() {
+            Some(b) if b != 0.0 => arithmetic(|a, b| a % b),
+            _ => FieldValue::Null,
+        },
+    }
+}
+
+fn call(func: Func, args: &[Expr], event: &Event) -> FieldValue {
+    let text = |i: usize| match args[i].evaluate(event) {
+        FieldValue::Null => None,
+        value => Some(value.to_string()),
+    };
+    match func {
+        // has() and get() take a field name (bare or quoted), not a value
+        Func::Has | Func::Get => {
+            let name = match &args[0] {
+                Expr::Field(name) => name.clone(),
+                other => other.evaluate(event).to_string(),
+            };
+            let value = event.get_value(&name);
+            if func == Func::Has {
+                FieldValue::Boolean(value.is_some())
+            } else {
+                value.unwrap_or(FieldValue::Null)
+            }
+        }
+        Func::Len => text(0)
+            .map(|s| FieldValue::Number(s.chars().count() as f64))
+            .unwrap_or(FieldValue::Null),
+        Func::Lower => text(0)
+            .map(|s| FieldValue::String(s.to_lowercase()))
+            .unwrap_or(FieldValue::Null),
+        Func::Upper => text(0)
+            .map(|s| FieldValue::String(s.to_uppercase()))
+            .unwrap_or(FieldValue::Null),
+        Func::Contains | Func::StartsWith | Func::EndsWith => match (text(0), text(1)) {
+            (Some(haystack), Some(needle)) => FieldValue::Boolean(match func {
+                Func::Contains => haystack.contains(&needle),
+                Func::StartsWith => haystack.starts_with(&needle),
+                _ => haystack.ends_with(&needle),
+            }),
+            _ => FieldValue::Boolean(false),
+        },
+        Func::Num => args[0]
+            .evaluate(event)
+            .as_number()
+            .map(FieldValue::Number)
+            .unwrap_or(FieldValue::Null),
+        Func::Str => text(0).map(FieldValue::String).unwrap_or(FieldValue::Null),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Str(String),
+    Regex(String),
+    Ident(String),
+    Op(&'static str),
+    LParen,
+    RParen,
+    Comma,
+}
+
+impl Token {
+    fn describe(&self) -> String {
+        match self {
+            Token::Number(n) => format!("number {}", n),
+            Token::Str(s) => format!("string \"{}\"", s),
+            Token::Regex(r) => format!("regex /{}/", r),
+            Token::Ident(name) => format!("'{}'", name),
+            Token::Op(op) => format!("'{}'", op),
+            Token::LParen => "'('".to_string(),
+            Token::RParen => "')'".to_string(),
+            Token::Comma => "','".to_string(),
+        }
+    }
+
+    /// Whether a `/` after this token is division rather than the start of a regex
+    fn ends_operand(&self) -> bool {
+        matches!(
+            self,
+            Token::Number(_) | Token::Str(_) | Token::Regex(_) | Token::Ident(_) | Token::RParen
+        )
+    }
+}
+
+const OPERATORS: &[&str] = &[
+    "&&", "||", "==", "!=", ">=", "<=", "=~", "!~", ">", "<", "!", "+", "-", "*", "/", "%",
+];
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        let operand_expected = !tokens.last().is_some_and(Token::ends_operand);
+        if c == '/' && operand_expected {
+            let (body, next) = read_delimited(&chars, i, '/')?;
+            tokens.push(Token::Regex(body));
+            i = next;
+        } else if c == '"' || c == '\'' {
+            let (body, next) = read_delimited(&chars, i, c)?;
+            tokens.push(Token::Str(body));
+            i = next;
+        } else if c.is_ascii_digit() {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            let text: String = chars[start..i].iter().collect();
+            let number = text
+                .parse()
+                .map_err(|_| format!("invalid number '{}'", text))?;
+            tokens.push(Token::Number(number));
+        } else if c.is_alphabetic() || c == '_' || c == '@' {
+            let start = i;
+            while i < chars.len()
+                && (chars[i].is_alphanumeric() || matches!(chars[i], '_' | '.' | '@'))
+            {
+                i += 1;
+            }
+            tokens.push(Token::Ident(chars[start..i].iter().collect()));
+        } else if c == '(' {
+            tokens.push(Token::LParen);
+            i += 1;
+        } else if c == ')' {
+            tokens.push(Token::RParen);
+            i += 1;
+        } else if c == ',' {
+            tokens.push(Token::Comma);
+            i += 1;
+        } else {
+            let rest: String = chars[i..].iter().take(2).collect();
+            let op = OPERATORS
+                .iter()
+                .find(|op| rest.starts_with(**op))
+                .ok_or_else(|| format!("unexpected character '{}'", c))?;
+            tokens.push(Token::Op(op));
+            i += op.chars().count();
+        }
+    }
+    Ok(tokens)
+}
+
+/// Read a quoted string or regex body starting at the opening delimiter. A backslash escapes
+/// the delimiter; in strings it also escapes itself and `n`/`t`, while regex escapes are kept
+fn read_delimited(
+    chars: &[char],
+    start: usize,
+    delimiter: char,
+) -> Result<(String, usize), String> {
+    let mut body = String::new();
+    let mut i = start + 1;
+    while i < chars.len() {
+        match chars[i] {
+            c if c == delimiter => return Ok((body, i + 1)),
+            '\\' if i + 1 < chars.len() => {
+                let next = chars[i + 1];
+                match (delimiter, next) {
+                    (_, n) if n == delimiter => body.push(n),
+                    ('/', n) => {
+                        body.push('\\');
+                        body.push(n);
+                    }
+                    (_, 'n') => body.push('\n'),
+                    (_, 't') => body.push('\t'),
+                    (_, n) => body.push(n),
+                }
+                i += 2;
+            }
+            c => {
+                body.push(c);
+                i += 1;
+            }
+        }
+    }
+    Err(format!(
+        "unterminated {}",
+        if delimiter == '/' { "regex" } else { "string" }
+    ))
+}
+
+struct ExprParser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl ExprParser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    /// Consume an operator (or its keyword spelling) if it comes next
+    fn eat_op(&mut self, op: &str, keyword: Option<&str>) -> bool {
+        let found = match self.peek() {
+            Some(Token::Op(o)) => *o == op,
+            Some(Token::Ident(word)) => keyword.is_some_and(|k| word == k),
+            _ => false,
+        };
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), String> {
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            Some(token) => Err(format!(
+                "expected {}, found {}",
+                expected.describe(),
+                token.describe()
+            )),
+            None => Err(format!(
+                "expected {}, found end of input",
+                expected.describe()
+            )),
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, String> {
+        let mut left = self.parse_and()?;
+        while self.eat_op("||", Some("or")) {
+            left = Expr::Or(Box::new(left), Box::new(self.parse_and()?));
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, String> {
+        let mut left = self.parse_not()?;
+        while self.eat_op("&&", Some("and")) {
+            left = Expr::And(Box::new(left), Box::new(self.parse_not()?));
+        }
+        Ok(left)
+    }
+
+    fn parse_not(&mut self) -> Result<Expr, String> {
+        if self.eat_op("!", Some("not")) {
+            return Ok(Expr::Not(Box::new(self.parse_not()?)));
+        }
+        self.parse_comparison()
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expr, String> {
+        let left = self.parse_additive()?;
+
+        for (op, negated) in [("=~", false), ("!~", true)] {
+            if self.eat_op(op, None) {
+                let pattern = match self.next() {
+                    Some(Token::Regex(pattern)) | Some(Token::Str(pattern)) => pattern,
+                    Some(token) => {
+                        return Err(format!(
+                            "expected a regex after {}, found {}",
+                            op,
+                            token.describe()
+                        ))
+                    }
+                    None => return Err(format!("expected a regex after {}", op)),
+                };
+                let regex = Regex::new(&pattern).map_err(|e| e.to_string())?;
+                return Ok(Expr::Match {
+                    expr: Box::new(left),
+                    regex,
+                    negated,
+                });
+            }
+        }
+
+        let comparisons = [
+            ("==", BinOp::Eq),
+            ("!=", BinOp::Ne),
+            (">=", BinOp::Ge),
+            ("<=", BinOp::Le),
+            (">", BinOp::Gt),
+            ("<", BinOp::Lt),
+        ];
+        for (op, bin_op) in comparisons {
+            if self.eat_op(op, None) {
+                let right = self.parse_additive()?;
+                return Ok(Expr::Binary(bin_op, Box::new(left), Box::new(right)));
+            }
+        }
+        Ok(left)
+    }
+
+    fn parse_additive(&mut self) -> Result<Expr, String> {
+        let mut left = self.parse_multiplicative()?;
+        loop {
+            let op = if self.eat_op("+", None) {
+                BinOp::Add
+            } else if self.eat_op("-", None) {
+                BinOp::Sub
+            } else {
+                return Ok(left);
+            };
+            left = Expr::Binary(op, Box::new(left), Box::new(self.parse_multiplicative()?));
+        }
+    }
+
+    fn parse_multiplicative(&mut self) -> Result<Expr, String> {
+        let mut left = self.parse_unary()?;
+        loop {
+            let op = if self.eat_op("*", None) {
+                BinOp::Mul
+            } else if self.eat_op("/", None) {
+                BinOp::Div
+            } else if self.eat_op("%", None) {
+                BinOp::Rem
+            } else {
+                return Ok(left);
+            };
+            left = Expr::Binary(op, Box::new(left), Box::new(self.parse_unary()?));
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, String> {
+        if self.eat_op("-", None) {
+            return Ok(Expr::Neg(Box::new(self.parse_unary()?)));
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Number(n)) => Ok(Expr::Literal(FieldValue::Number(n))),
+            Some(Token::Str(s)) => Ok(Expr::Literal(FieldValue::String(s))),
+            Some(Token::LParen) => {
+                let expr = self.parse_or()?;
+                self.expect(Token::RParen)?;
+                Ok(expr)
+            }
+            Some(Token::Ident(name)) => match name.as_str() {
+                "true" => Ok(Expr::Literal(FieldValue::Boolean(true))),
+                "false" => Ok(Expr::Literal(FieldValue::Boolean(false))),
+                "null" => Ok(Expr::Literal(FieldValue::Null)),
+                _ if self.peek() == Some(&Token::LParen) => self.parse_call(&name),
+                _ => Ok(Expr::Field(name)),
+            },
+            Some(Token::Regex(pattern)) => Err(format!(
+                "regex /{}/ can only be used after =~ or !~",
+                pattern
+            )),
+            Some(token) => Err(format!("unexpected {}", token.describe())),
+            None => Err("unexpected end of expression".to_string()),
+        }
+    }
+
+    fn parse_call(&mut self, name: &str) -> Result<Expr, String> {
+        let (func, arity) =
+            Func::from_name(name).ok_or_else(|| format!("unknown function '{}'", name))?;
+        self.expect(Token::LParen)?;
+        let mut args = Vec::new();
+        if self.peek() != Some(&Token::RParen) {
+            loop {
+                args.push(self.parse_or()?);
+                if self.peek() == Some(&Token::Comma) {
+                    self.pos += 1;
+                } else {
+                    break;
+                }
+            }
+        }
+        self.expect(Token::RParen)?;
+        if args.len() != arity {
+            return Err(format!(
+                "{}() takes {} argument(s), got {}",
+                name,
+                arity,
+                args.len()
+            ));
+        }
+        Ok(Expr::Call(func, args))
+    }
+}
+
+/// A `--derive` assignment `NAME = EXPR`, computing a new field from existing ones
+#[derive(Debug, Clone)]
+pub struct Derivation {
+    pub name: String,
+    pub expr: Expr,
+}
+
+impl Derivation {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        // The first `=` that isn't part of `==`
+        let split = spec
+            .find('=')
+            .filter(|&i| !spec[i + 1..].starts_with('='))
+            .ok_or_else(|| format!("expected NAME = EXPR, got '{}'", spec))?;
+        let name = spec[..split].trim();
+        let valid_name = name.starts_with(|c: char| c.is_alphabetic() || c == '_' || c == '@')
+            && name
+                .chars()
+                .all(|c| c.is_alphanumeric() || matches!(c, '_' | '.' | '@' | '-'));
+        if !valid_name {
+            return Err(format!("invalid field name '{}'", name));
+        }
+        Ok(Self {
+            name: name.to_string(),
+            expr: Expr::parse(&spec[split + 1..])?,
+        })
+    }
+
+    /// Set the field to the expression's value; a `null` result leaves the event unchanged
+    pub fn apply(&self, event: &mut Event) {
+        let value = self.expr.evaluate(event);
+        if value != FieldValue::Null {
+            event.assign_field(&self.name, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event() -> Event {
+        let mut event = Event::new();
+        event.level = Some("ERROR".to_string());
+        event.message = Some("upstream timeout after 30s".to_string());
+        event.set_field("status".to_string(), FieldValue::Number(503.0));
+        event.set_field(
+            "latency_ms".to_string(),
+            FieldValue::String("1250".to_string()),
+        );
+        event.set_field(
+            "http.method".to_string(),
+            FieldValue::String("GET".to_string()),
+        );
+        event.set_field("cached".to_string(), FieldValue::Boolean(false));
+        event.set_field(
+            "x-request-id".to_string(),
+            FieldValue::String("r-1".to_string()),
+        );
+        event
+    }
+
+    fn eval(source: &str) -> FieldValue {
+        Expr::parse(source).unwrap().evaluate(&event())
+    }
+
+    fn check(source: &str) -> bool {
+        Expr::parse(source).unwrap().matches(&event())
+    }
+
+    #[test]
+    fn test_boolean_logic_and_comparisons() {
+        assert!(check(
+            r#"level == "ERROR" && (status >= 500 || msg =~ /timeout/)"#
+        ));
+        assert!(check("status > 500 and not cached"));
+        assert!(
+            check("latency_ms > 1000"),
+            "Numeric strings compare numerically"
+        );
+        assert!(!check("status < 500 or level != 'ERROR'"));
+        assert!(check("message !~ /^ok/"));
+        assert!(check(r#"http.method == "GET""#));
+    }
+
+    #[test]
+    fn test_functions() {
+        assert!(check("has(status) && !has(user)"));
+        assert!(check(r#"get("x-request-id") == "r-1""#));
+        assert!(check(r#"lower(level) == "error""#));
+        assert!(check(r#"contains(message, "timeout")"#));
+        assert!(check(
+            r#"starts_with(message, "up") && ends_with(message, "30s")"#
+        ));
+        assert_eq!(eval("len(http.method)"), FieldValue::Number(3.0));
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        assert_eq!(eval("latency_ms / 1000"), FieldValue::Number(1.25));
+        assert_eq!(eval("2 + 3 * 4 - -1"), FieldValue::Number(15.0));
+        assert_eq!(eval("status % 100"), FieldValue::Number(3.0));
+        assert_eq!(eval("status / 0"), FieldValue::Null);
+        assert_eq!(
+            eval(r#""req-" + status"#),
+            FieldValue::String("req-503".to_string())
+        );
+        assert_eq!(eval("missing + 1"), FieldValue::Null);
+    }
+
+    #[test]
+    fn test_missing_fields_are_null() {
+        assert!(!check("missing"));
+        assert!(check("missing == null"));
+        assert!(!check("missing > 0"));
+        assert!(!check("missing =~ /.*/"));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Expr::parse("status >").is_err());
+        assert!(Expr::parse("(status > 1").is_err());
+        assert!(Expr::parse("frobnicate(status)").is_err());
+        assert!(Expr::parse("has()").is_err());
+        assert!(Expr::parse("msg =~ status").is_err());
+        assert!(Expr::parse(r#"level == "ERROR"#).is_err());
+        assert!(Expr::parse("status $ 1").is_err());
+        assert!(Expr::parse("status 1").is_err());
+    }
+
+    #[test]
+    fn test_derive() {
+        let mut event = event();
+        for spec in [
+            "latency_s = latency_ms / 1000",
+            "is_slow = latency_ms > 500",
+            "status = status + 1",
+            "route=lower(http.method) + ' /api'",
+            "gone = missing",
+        ] {
+            Derivation::parse(spec).unwrap().apply(&mut event);
+        }
+        assert_eq!(event.fields["latency_s"], FieldValue::Number(1.25));
+        assert_eq!(event.fields["is_slow"], FieldValue::Boolean(true));
+        assert_eq!(event.fields["status"], FieldValue::Number(504.0));
+        assert_eq!(
+            event.fields["route"],
+            FieldValue::String("get /api".to_string())
+        );
+        assert!(!event.fields.contains_key("gone"));
+
+        Derivation::parse("msg = upper(msg)")
+            .unwrap()
+            .apply(&mut event);
+        assert_eq!(event.message.as_deref(), Some("UPSTREAM TIMEOUT AFTER 30S"));
+
+        assert!(Derivation::parse("a == 1").is_err());
+        assert!(Derivation::parse("= 1").is_err());
+        assert!(Derivation::parse("two words = 1").is_err());
+        assert!(Derivation::parse("x = 1 +").is_err());
+    }
+
+    #[test]
+    fn test_match_patterns() {
+        let expr = Expr::parse(r#"msg =~ /time(out)?/ && (path !~ "^/health" || !(host =~ /db/))"#)
+            .unwrap();
+        assert_eq!(expr.match_patterns(), vec!["time(out)?"]);
+        assert!(Expr::parse("status >= 500")
+            .unwrap()
+            .match_patterns()
+            .is_empty());
+    }
+}
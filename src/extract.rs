@@ -0,0 +1,171 @@
+use crate::event::Event;
+use crate::grok;
+use crate::parsers::{logfmt_pair, parse_field_value, LOGFMT_PAIR};
+use regex::Regex;
+
+/// Promotes parts of the message to fields (`--extract`, `--extract-grok`): each named capture
+/// group of the pattern that matches becomes a field, typed like logfmt values, so
+/// unstructured messages can still be filtered and grouped on
+#[derive(Debug, Clone)]
+pub struct Extractor {
+    regex: Regex,
+}
+
+impl Extractor {
+    pub fn new(pattern: &str) -> Result<Self, String> {
+        let regex = Regex::new(pattern).map_err(|e| e.to_string())?;
+        if regex.capture_names().flatten().next().is_none() {
+            return Err(format!(
+                "'{}' has no named groups like (?P<name>...) to extract",
+                pattern
+            ));
+        }
+        Ok(Self { regex })
+    }
+
+    /// Extract with a grok pattern such as `%{IP:client} %{WORD:method}`
+    pub fn grok(pattern: &str) -> Result<Self, String> {
+        if !pattern.contains("%{") {
+            return Err(format!(
+                "'{}' has no %{{PATTERN:field}} to extract",
+                pattern
+            ));
+        }
+        Self::new(&grok::compile(pattern)?)
+    }
+
+    pub fn apply(&self, event: &mut Event) {
+        let Some(message) = &event.message else {
+            return;
+        };
+        let Some(captures) = self.regex.captures(message) else {
+            return;
+        };
+        let values: Vec<(&str, String)> = self
+            .regex
+            .capture_names()
+            .flatten()
+            .filter_map(|name| Some((name, captures.name(name)?.as_str().to_string())))
+            .collect();
+        for (name, value) in values {
+            event.assign_field(name, parse_field_value(&value));
+        }
+    }
+}
+
+/// Promotes the logfmt-style `key=value` tokens in the message to fields (`--kv-in-message`),
+/// for messages that are almost logfmt, e.g. `login failed user=bob src="10.0.0.1"`. Fields
+/// already present keep their values
+#[derive(Debug, Clone)]
+pub struct KeyValueScraper {
+    regex: Regex,
+}
+
+impl KeyValueScraper {
+    pub fn new() -> Self {
+        // Keys start a word, so `?a=1` in a URL isn't taken for a pair
+        Self {
+            regex: Regex::new(&format!(r"(?:^|\s){}", LOGFMT_PAIR)).unwrap(),
+        }
+    }
+
+    pub fn apply(&self, event: &mut Event) {
+        let Some(message) = &event.message else {
+            return;
+        };
+        let pairs: Vec<_> = self
+            .regex
+            .captures_iter(message)
+            .filter_map(|cap| logfmt_pair(&cap))
+            .collect();
+        for (key, value) in pairs {
+            if !event.fields.contains_key(&key) {
+                event.assign_field(&key, parse_field_value(&value));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::FieldValue;
+
+    #[test]
+    fn test_extract() {
+        let mut event = Event::new();
+        event.message = Some("order 4711 shipped to berlin in 2.5s".to_string());
+
+        Extractor::new(
+            r"order (?P<order_id>\d+) shipped to (?P<city>\w+)(?: by (?P<carrier>\w+))?",
+        )
+        .unwrap()
+        .apply(&mut event);
+        Extractor::new(r"in (?P<duration_s>[\d.]+)s")
+            .unwrap()
+            .apply(&mut event);
+        assert_eq!(event.fields["order_id"], FieldValue::Number(4711.0));
+        assert_eq!(
+            event.fields["city"],
+            FieldValue::String("berlin".to_string())
+        );
+        assert_eq!(event.fields["duration_s"], FieldValue::Number(2.5));
+        assert!(!event.fields.contains_key("carrier"));
+
+        let mut unmatched = Event::new();
+        unmatched.message = Some("payment declined".to_string());
+        Extractor::new(r"order (?P<order_id>\d+)")
+            .unwrap()
+            .apply(&mut unmatched);
+        assert!(unmatched.fields.is_empty());
+
+        assert!(Extractor::new(r"order (\d+)").is_err());
+        assert!(Extractor::new(r"(?P<x>").is_err());
+    }
+
+    #[test]
+    fn test_extract_grok() {
+        let mut event = Event::new();
+        event.message = Some("Accepted password for alice from 203.0.113.9 port 52144".to_string());
+        Extractor::grok("for %{USER:user} from %{IP:client} port %{POSINT:port}")
+            .unwrap()
+            .apply(&mut event);
+        assert_eq!(
+            event.fields["user"],
+            FieldValue::String("alice".to_string())
+        );
+        assert_eq!(
+            event.fields["client"],
+            FieldValue::String("203.0.113.9".to_string())
+        );
+        assert_eq!(event.fields["port"], FieldValue::Number(52144.0));
+
+        assert!(Extractor::grok("%{IP}").is_err());
+        assert!(Extractor::grok(r"(?P<user>\w+)").is_err());
+    }
+
+    #[test]
+    fn test_kv_in_message() {
+        let mut event = Event::new();
+        event.set_field("pid".to_string(), FieldValue::Number(42.0));
+        event.message = Some(
+            r#"login failed user=bob src="10.0.0.1 (vpn)" attempts=3 pid=7 see http://x/?a=1"#
+                .to_string(),
+        );
+        KeyValueScraper::new().apply(&mut event);
+
+        let keys: Vec<&String> = event.fields.keys().collect();
+        assert_eq!(keys, ["pid", "user", "src", "attempts"]);
+        assert_eq!(
+            event.fields["src"],
+            FieldValue::String("10.0.0.1 (vpn)".to_string())
+        );
+        assert_eq!(event.fields["attempts"], FieldValue::Number(3.0));
+        assert_eq!(event.fields["pid"], FieldValue::Number(42.0));
+
+        let mut event = Event::new();
+        event.message = Some("level=warn disk almost full".to_string());
+        KeyValueScraper::new().apply(&mut event);
+        assert_eq!(event.level.as_deref(), Some("warn"));
+    }
+}
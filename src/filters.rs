@@ -0,0 +1,557 @@
+use crate::event::{key_matches, level_rank, Event, FieldValue};
+use regex::Regex;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+
+/// Comparison operator of a `--where` condition
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompareOp {
+    Eq,
+    Ne,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+    // The value is an IP address in one of `networks`
+    In,
+}
+
+/// A single `--where` condition such as `status=500`, `env!=prod`, `latency_ms>500` or
+/// `client_ip in 10.0.0.0/8`
+#[derive(Debug, Clone)]
+pub struct FieldCondition {
+    pub key: String,
+    pub op: CompareOp,
+    pub value: String,
+    // Parsed `value` of an `in` condition
+    pub networks: Vec<IpNetwork>,
+    // Keep events that do NOT satisfy the condition (--where-not)
+    pub negated: bool,
+}
+
+impl FieldCondition {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        // An " in " after an operator is just part of the value, as in `msg=logged in`
+        if let Some((key, networks)) = spec.split_once(" in ") {
+            if !key.contains(['=', '!', '<', '>']) {
+                return Self::parse_in(key, networks);
+            }
+        }
+
+        let start = spec.find(['=', '!', '<', '>']).ok_or_else(|| {
+            format!(
+                "expected KEY<op>VALUE (=, !=, >, >=, <, <=) or KEY in NETWORKS, got '{}'",
+                spec
+            )
+        })?;
+        let rest = &spec[start..];
+        let (op, len) = if rest.starts_with("==") {
+            (CompareOp::Eq, 2)
+        } else if rest.starts_with("!=") {
+            (CompareOp::Ne, 2)
+        } else if rest.starts_with(">=") {
+            (CompareOp::Ge, 2)
+        } else if rest.starts_with("<=") {
+            (CompareOp::Le, 2)
+        } else if rest.starts_with('=') {
+            (CompareOp::Eq, 1)
+        } else if rest.starts_with('>') {
+            (CompareOp::Gt, 1)
+        } else if rest.starts_with('<') {
+            (CompareOp::Lt, 1)
+        } else {
+            return Err(format!("invalid operator in '{}'", spec));
+        };
+
+        let key = spec[..start].trim();
+        if key.is_empty() {
+            return Err(format!("missing field name in '{}'", spec));
+        }
+        let value = &rest[len..];
+        if op.is_ordering() && value.trim().parse::<f64>().is_err() {
+            return Err(format!("'{}' needs a numeric value", spec));
+        }
+        Ok(Self {
+            key: key.to_string(),
+            op,
+            value: value.to_string(),
+            networks: Vec::new(),
+            negated: false,
+        })
+    }
+
+    /// `KEY in NETWORKS`, with comma-separated CIDR blocks or single addresses
+    fn parse_in(key: &str, networks: &str) -> Result<Self, String> {
+        let key = key.trim();
+        if key.is_empty() {
+            return Err(format!("missing field name in '{} in {}'", key, networks));
+        }
+        let parsed = networks
+            .split(',')
+            .map(IpNetwork::parse)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self {
+            key: key.to_string(),
+            op: CompareOp::In,
+            value: networks.trim().to_string(),
+            networks: parsed,
+            negated: false,
+        })
+    }
+
+    /// Invert the condition
+    pub fn negate(mut self) -> Self {
+        self.negated = !self.negated;
+        self
+    }
+
+    pub fn matches(&self, event: &Event) -> bool {
+        self.holds(event) != self.negated
+    }
+
+    /// Missing fields never equal anything, so they only satisfy `!=`; ordering comparisons
+    /// need a number or a string that parses as one
+    fn holds(&self, event: &Event) -> bool {
+        let value = event.get_value(&self.key);
+        match self.op {
+            CompareOp::Eq => value.is_some_and(|value| value_equals(&value, &self.value)),
+            CompareOp::Ne => !value.is_some_and(|value| value_equals(&value, &self.value)),
+            CompareOp::In => value
+                .and_then(|value| parse_ip(&value.to_string()))
+                .is_some_and(|ip| self.networks.iter().any(|network| network.contains(ip))),
+            op => {
+                let (Some(actual), Ok(expected)) = (
+                    value.as_ref().and_then(FieldValue::as_number),
+                    self.value.trim().parse::<f64>(),
+                ) else {
+                    return false;
+                };
+                match op {
+                    CompareOp::Gt => actual > expected,
+                    CompareOp::Ge => actual >= expected,
+                    CompareOp::Lt => actual < expected,
+                    CompareOp::Le => actual <= expected,
+                    CompareOp::Eq | CompareOp::Ne | CompareOp::In => unreachable!(),
+                }
+            }
+        }
+    }
+}
+
+impl CompareOp {
+    fn is_ordering(self) -> bool {
+        matches!(
+            self,
+            CompareOp::Gt | CompareOp::Ge | CompareOp::Lt | CompareOp::Le
+        )
+    }
+}
+
+/// An IPv4 or IPv6 network such as `10.0.0.0/8` or `2001:db8::/32`; a plain address is a
+/// network of one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpNetwork {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpNetwork {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let spec = spec.trim();
+        let (addr, prefix) = spec.split_once('/').unwrap_or((spec, ""));
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|_| format!("invalid IP address '{}'", addr))?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = if prefix.is_empty() {
+            max
+        } else {
+            prefix
+                .parse::<u8>()
+                .ok()
+                .filter(|prefix| *prefix <= max)
+                .ok_or_else(|| format!("invalid prefix length in '{}'", spec))?
+        };
+        Ok(Self { addr, prefix })
+    }
+
+    /// IPv4 addresses written as IPv6 (`::ffff:10.0.0.1`) count as IPv4
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// An IP address, also when logged with a port (`10.0.0.1:443`, `[2001:db8::1]:443`)
+pub fn parse_ip(value: &str) -> Option<IpAddr> {
+    let value = value.trim();
+    value
+        .parse()
+        .ok()
+        .or_else(|| value.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+}
+
+/// A `--grep` pattern over the message, or a `--grep-field KEY=REGEX` pattern over one field
+#[derive(Debug, Clone)]
+pub struct GrepFilter {
+    pub field: Option<String>,
+    pub regex: Regex,
+    // Keep events that do NOT match (--grep-v)
+    pub negated: bool,
+}
+
+impl GrepFilter {
+    pub fn message(pattern: &str) -> Result<Self, String> {
+        Ok(Self {
+            field: None,
+            regex: Regex::new(pattern).map_err(|e| e.to_string())?,
+            negated: false,
+        })
+    }
+
+    /// Parse a `KEY=REGEX` spec
+    pub fn field(spec: &str) -> Result<Self, String> {
+        let (key, pattern) = spec
+            .split_once('=')
+            .filter(|(key, _)| !key.trim().is_empty())
+            .ok_or_else(|| format!("expected KEY=REGEX, got '{}'", spec))?;
+        Ok(Self {
+            field: Some(key.trim().to_string()),
+            regex: Regex::new(pattern).map_err(|e| e.to_string())?,
+            negated: false,
+        })
+    }
+
+    /// Invert the filter
+    pub fn negate(mut self) -> Self {
+        self.negated = !self.negated;
+        self
+    }
+
+    /// Events without the message or field never match (so they pass a negated filter)
+    pub fn matches(&self, event: &Event) -> bool {
+        let found = match &self.field {
+            None => event
+                .message
+                .as_deref()
+                .is_some_and(|message| self.regex.is_match(message)),
+            Some(key) => event
+                .get_value(key)
+                .is_some_and(|value| self.regex.is_match(&value.to_string())),
+        };
+        found != self.negated
+    }
+}
+
+/// A `-l` level selection: level names and their aliases (`warn`/`warning`, `err`/`error`,
+/// `fatal`/`crit`/`critical`, ...), syslog severities (`3`) and severity ranges (`0-3`,
+/// `warn-crit`). Known levels compare by severity, so any spelling of a level matches any
+/// other; unknown names match their own level exactly, ignoring case
+#[derive(Debug, Clone, Default)]
+pub struct LevelFilter {
+    ranks: Vec<u8>,
+    names: Vec<String>,
+}
+
+impl LevelFilter {
+    pub fn parse(specs: &[String]) -> Result<Self, String> {
+        let mut filter = Self::default();
+        for spec in specs.iter().map(|spec| spec.trim()) {
+            if let Some(rank) = level_rank(spec) {
+                filter.ranks.push(rank);
+            } else if let Some((low, high)) = spec
+                .split_once('-')
+                .and_then(|(low, high)| Some((level_rank(low)?, level_rank(high)?)))
+            {
+                filter.ranks.extend(low.min(high)..=low.max(high));
+            } else if spec.parse::<u64>().is_ok() {
+                return Err(format!("'{}' is not a syslog severity (0-7)", spec));
+            } else {
+                filter.names.push(spec.to_uppercase());
+            }
+        }
+        Ok(filter)
+    }
+
+    pub fn matches(&self, event: &Event) -> bool {
+        event
+            .level_rank()
+            .is_some_and(|rank| self.ranks.contains(&rank))
+            || event
+                .level
+                .as_ref()
+                .is_some_and(|level| self.names.contains(&level.to_uppercase()))
+    }
+}
+
+/// Fields naming the host an event came from
+const HOST_KEYS: &[&str] = &["hostname", "host"];
+
+/// A `--source` selector: keeps events from any of the given input files (by path or file
+/// name) or hosts, with `*`/`?` wildcards, e.g. `web01.log` or `web*`
+#[derive(Debug, Clone)]
+pub struct SourceFilter {
+    patterns: Vec<String>,
+}
+
+impl SourceFilter {
+    pub fn new(patterns: Vec<String>) -> Self {
+        Self { patterns }
+    }
+
+    pub fn matches(&self, event: &Event) -> bool {
+        let mut names = Vec::new();
+        if let Some(path) = &event.source {
+            names.push(path.clone());
+            if let Some(name) = Path::new(path).file_name() {
+                names.push(name.to_string_lossy().into_owned());
+            }
+        }
+        names.extend(
+            HOST_KEYS
+                .iter()
+                .filter_map(|key| event.fields.get(*key))
+                .map(|host| host.to_string()),
+        );
+        self.patterns
+            .iter()
+            .any(|pattern| names.iter().any(|name| key_matches(pattern, name)))
+    }
+}
+
+/// Compare as strings first, then numerically so `1.50` matches `1.5`
+fn value_equals(value: &FieldValue, expected: &str) -> bool {
+    if value.to_string() == expected {
+        return true;
+    }
+    match (value, expected.trim().parse::<f64>()) {
+        (FieldValue::Number(n), Ok(expected)) => *n == expected,
+        (FieldValue::String(s), Ok(expected)) => s.trim().parse::<f64>() == Ok(expected),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event() -> Event {
+        let mut event = Event::new();
+        event.level = Some("ERROR".to_string());
+        event.set_field("status".to_string(), FieldValue::Number(500.0));
+        event.set_field(
+            "latency".to_string(),
+            FieldValue::String("1.50".to_string()),
+        );
+        event.set_field("env".to_string(), FieldValue::String("prod".to_string()));
+        event
+    }
+
+    #[test]
+    fn test_parse_condition() {
+        let condition = FieldCondition::parse("env!=prod").unwrap();
+        assert_eq!(condition.key, "env");
+        assert_eq!(condition.op, CompareOp::Ne);
+        assert_eq!(condition.value, "prod");
+
+        let condition = FieldCondition::parse("query=a=b").unwrap();
+        assert_eq!(condition.key, "query");
+        assert_eq!(condition.value, "a=b");
+
+        let condition = FieldCondition::parse("status>=500").unwrap();
+        assert_eq!(condition.key, "status");
+        assert_eq!(condition.op, CompareOp::Ge);
+        assert_eq!(condition.value, "500");
+
+        assert!(FieldCondition::parse("status").is_err());
+        assert!(FieldCondition::parse("=500").is_err());
+        assert!(FieldCondition::parse("env>prod").is_err());
+    }
+
+    #[test]
+    fn test_numeric_matching() {
+        let event = event();
+        let matches = |spec: &str| FieldCondition::parse(spec).unwrap().matches(&event);
+
+        assert!(matches("status>=500"));
+        assert!(!matches("status>500"));
+        assert!(matches("status<501"));
+        assert!(matches("latency>1"), "Numeric strings should be coerced");
+        assert!(matches("latency<=1.5"));
+        assert!(!matches("env<10"), "Non-numeric values never match");
+        assert!(!matches("missing>0"));
+    }
+
+    #[test]
+    fn test_equality_matching() {
+        let event = event();
+        let matches = |spec: &str| FieldCondition::parse(spec).unwrap().matches(&event);
+
+        assert!(matches("status=500"));
+        assert!(matches("status=500.0"));
+        assert!(matches("latency=1.5"));
+        assert!(matches("env=prod"));
+        assert!(matches("level=ERROR"));
+        assert!(!matches("env=staging"));
+        assert!(matches("env!=staging"));
+        assert!(!matches("missing=x"));
+        assert!(matches("missing!=x"));
+    }
+
+    #[test]
+    fn test_grep_filters() {
+        let mut event = event();
+        event.message = Some("upstream timeout after 30s".to_string());
+
+        assert!(GrepFilter::message("time(out)?").unwrap().matches(&event));
+        assert!(!GrepFilter::message("^timeout").unwrap().matches(&event));
+        assert!(GrepFilter::field("env=^pr").unwrap().matches(&event));
+        assert!(GrepFilter::field("status=^5\\d\\d$")
+            .unwrap()
+            .matches(&event));
+        assert!(!GrepFilter::field("missing=.*").unwrap().matches(&event));
+        assert!(!GrepFilter::message("x").unwrap().matches(&Event::new()));
+
+        assert!(GrepFilter::field("env").is_err());
+        assert!(GrepFilter::message("(").is_err());
+    }
+
+    #[test]
+    fn test_negated_filters() {
+        let mut event = event();
+        event.message = Some("GET /healthz".to_string());
+
+        let health = GrepFilter::message("healthz").unwrap().negate();
+        assert!(!health.matches(&event));
+        assert!(health.matches(&Event::new()));
+
+        let prod = FieldCondition::parse("env=prod").unwrap().negate();
+        assert!(!prod.matches(&event));
+        let slow = FieldCondition::parse("status>=500").unwrap().negate();
+        assert!(!slow.matches(&event));
+        assert!(FieldCondition::parse("missing>1")
+            .unwrap()
+            .negate()
+            .matches(&event));
+    }
+
+    #[test]
+    fn test_source_filter() {
+        let mut event = event();
+        event.source = Some("/var/log/web01.log".to_string());
+        let source =
+            |patterns: &[&str]| SourceFilter::new(patterns.iter().map(|p| p.to_string()).collect());
+
+        assert!(source(&["web01.log"]).matches(&event));
+        assert!(source(&["/var/log/web01.log"]).matches(&event));
+        assert!(source(&["db01.log", "web*"]).matches(&event));
+        assert!(!source(&["web02.log"]).matches(&event));
+        assert!(FieldCondition::parse("_file=/var/log/web01.log")
+            .unwrap()
+            .matches(&event));
+
+        let mut syslog = Event::new();
+        syslog.set_field(
+            "hostname".to_string(),
+            FieldValue::String("db01".to_string()),
+        );
+        assert!(source(&["db01"]).matches(&syslog));
+        assert!(!source(&["web01.log"]).matches(&syslog));
+    }
+
+    #[test]
+    fn test_level_filter() {
+        let filter = |specs: &[&str]| {
+            LevelFilter::parse(&specs.iter().map(|s| s.to_string()).collect::<Vec<_>>()).unwrap()
+        };
+        let event = |level: &str| {
+            let mut event = Event::new();
+            event.level = Some(level.to_string());
+            event
+        };
+
+        let errors = filter(&["error"]);
+        assert!(errors.matches(&event("ERROR")));
+        assert!(errors.matches(&event("err")));
+        assert!(errors.matches(&event("3")));
+        assert!(!errors.matches(&event("WARN")));
+        assert!(!errors.matches(&Event::new()));
+
+        assert!(filter(&["warning"]).matches(&event("WARN")));
+        assert!(filter(&["fatal"]).matches(&event("critical")));
+        assert!(!filter(&["debug"]).matches(&event("TRACE")));
+
+        let severe = filter(&["0-3"]);
+        assert!(severe.matches(&event("EMERG")));
+        assert!(severe.matches(&event("crit")));
+        assert!(severe.matches(&event("error")));
+        assert!(!severe.matches(&event("warning")));
+        let mut numeric = Event::new();
+        numeric.set_field("severity".to_string(), FieldValue::Number(2.0));
+        assert!(severe.matches(&numeric));
+
+        assert!(filter(&["warn-crit"]).matches(&event("error")));
+        assert!(filter(&["audit"]).matches(&event("AUDIT")));
+        assert!(filter(&["sec-audit"]).matches(&event("SEC-AUDIT")));
+        assert!(LevelFilter::parse(&["9".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_ip_networks() {
+        let mut event = event();
+        let matches =
+            |event: &Event, spec: &str| FieldCondition::parse(spec).unwrap().matches(event);
+
+        event.set_field(
+            "client_ip".to_string(),
+            FieldValue::String("10.1.2.3".to_string()),
+        );
+        assert!(matches(&event, "client_ip in 10.0.0.0/8"));
+        assert!(!matches(&event, "client_ip in 10.2.0.0/16"));
+        assert!(matches(&event, "client_ip in 192.168.0.0/16, 10.1.2.3"));
+        assert!(matches(&event, "client_ip in 0.0.0.0/0"));
+        assert!(!matches(&event, "client_ip in ::/0"));
+        assert!(!matches(&event, "missing in 10.0.0.0/8"));
+
+        event.set_field(
+            "client_ip".to_string(),
+            FieldValue::String("10.1.2.3:443".to_string()),
+        );
+        assert!(matches(&event, "client_ip in 10.0.0.0/8"));
+        event.set_field(
+            "client_ip".to_string(),
+            FieldValue::String("::ffff:10.1.2.3".to_string()),
+        );
+        assert!(matches(&event, "client_ip in 10.0.0.0/8"));
+        event.set_field(
+            "client_ip".to_string(),
+            FieldValue::String("[2001:db8::1]:443".to_string()),
+        );
+        assert!(matches(&event, "client_ip in 2001:db8::/32"));
+        assert!(!matches(&event, "client_ip in 2001:db9::/32"));
+        event.set_field(
+            "client_ip".to_string(),
+            FieldValue::String("10.1.2.300".to_string()),
+        );
+        assert!(
+            !matches(&event, "client_ip in 10.0.0.0/8"),
+            "Not an address"
+        );
+
+        let not_ip = FieldCondition::parse("msg=logged in").unwrap();
+        assert_eq!(not_ip.op, CompareOp::Eq);
+        assert_eq!(not_ip.value, "logged in");
+        assert!(FieldCondition::parse("client_ip in 10.0.0.0/33").is_err());
+        assert!(FieldCondition::parse("client_ip in 10.0.0/8").is_err());
+        assert!(FieldCondition::parse("client_ip in 2001:db8::/129").is_err());
+    }
+}
@@ -1,115 +1,1370 @@
-use crate::event::{Event, FieldValue};
+use crate::event::{
+    core_key_name, key_matches, syslog_severity, Event, FieldValue, TIMESTAMP_KEYS,
+};
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::io::{self, Write};
 
 pub trait Formatter {
     fn format(&self, event: &Event) -> String;
+
+    /// Write a formatted event. Formatters that need to see several events before
+    /// producing output (e.g. to size columns) may hold it back until `finish`.
+    fn write_event(&self, event: &Event, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "{}", self.format(event))
+    }
+
+    /// Flush any buffered or closing output once all events have been written
+    fn finish(&self, _out: &mut dyn Write) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// ANSI escape sequences used for colored output
+const ANSI_RESET: &str = "\x1b[0m";
+const ANSI_DIM: &str = "\x1b[2m";
+const ANSI_RED: &str = "\x1b[1;31m";
+const ANSI_YELLOW: &str = "\x1b[33m";
+const ANSI_GREEN: &str = "\x1b[32m";
+const ANSI_BLUE: &str = "\x1b[34m";
+const ANSI_MAGENTA: &str = "\x1b[35m";
+const ANSI_CYAN: &str = "\x1b[36m";
+const ANSI_INVERSE: &str = "\x1b[7m";
+const ANSI_INVERSE_OFF: &str = "\x1b[27m";
+
+/// How timestamps are rendered in output (`--ts-display`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TimestampDisplay {
+    /// A strftime pattern, applied in UTC
+    Pattern(String),
+    /// Seconds since the epoch, with millisecond decimals
+    Unix,
+}
+
+impl TimestampDisplay {
+    /// Parse a preset (`time-only`, `date-time`, `unix`) or a strftime pattern
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let pattern = match spec {
+            "unix" => return Ok(Self::Unix),
+            "time-only" => "%H:%M:%S%.3f",
+            "date-time" => "%Y-%m-%d %H:%M:%S",
+            pattern => pattern,
+        };
+        let invalid = chrono::format::StrftimeItems::new(pattern)
+            .any(|item| matches!(item, chrono::format::Item::Error));
+        if pattern.is_empty() || invalid {
+            return Err(format!(
+                "'{}' is neither a preset (time-only, date-time, unix) nor a valid strftime pattern",
+                spec
+            ));
+        }
+        Ok(Self::Pattern(pattern.to_string()))
+    }
+
+    pub fn render(&self, timestamp: &DateTime<Utc>) -> FieldValue {
+        match self {
+            Self::Pattern(pattern) => FieldValue::String(timestamp.format(pattern).to_string()),
+            Self::Unix => FieldValue::Number(timestamp.timestamp_millis() as f64 / 1000.0),
+        }
+    }
+}
+
+/// Look up a value for output, with the core timestamp as `--ts-display` rendered it, if it
+/// did. A timestamp key gets the rendering even when the raw field it was parsed from is still
+/// there
+fn output_value(event: &Event, key: &str) -> Option<FieldValue> {
+    match &event.timestamp_display {
+        Some(display) if TIMESTAMP_KEYS.contains(&key) => Some(display.clone()),
+        _ => event.get_value(key),
+    }
+}
+
+/// Replace the timestamp of a JSON event with its rendering by `--ts-display`, if any
+fn apply_ts_display(json: &mut serde_json::Value, event: &Event) {
+    if let (Some(display), serde_json::Value::Object(map)) = (&event.timestamp_display, json) {
+        map.insert("timestamp".to_string(), field_value_to_json(display));
+    }
+}
+
+/// How the default formatter orders non-core fields
+pub enum KeyOrder {
+    /// Alphabetically
+    Alpha,
+    /// As they appeared in the input line
+    Input,
+    /// In the order of the given key list, unlisted keys last
+    KeysAsGiven(Vec<String>),
+}
+
+// Default logfmt-style formatter
+pub struct DefaultFormatter {
+    color: bool,
+    order: KeyOrder,
+    first_keys: Vec<String>,
+    skip_empty: bool,
+    highlight: Option<Regex>,
+}
+
+impl DefaultFormatter {
+    pub fn new() -> Self {
+        Self {
+            color: false,
+            order: KeyOrder::Alpha,
+            first_keys: Vec::new(),
+            skip_empty: false,
+            highlight: None,
+        }
+    }
+
+    /// Mark matches in the message and field values with inverse video (only when colored)
+    pub fn with_highlight(mut self, highlight: Option<Regex>) -> Self {
+        self.highlight = highlight;
+        self
+    }
+
+    /// Omit fields whose value is null or an empty string
+    pub fn with_skip_empty(mut self, skip_empty: bool) -> Self {
+        self.skip_empty = skip_empty;
+        self
+    }
+
+    pub fn with_key_order(mut self, order: KeyOrder) -> Self {
+        self.order = order;
+        self
+    }
+
+    /// Pin keys to the front of the line, in the given order
+    pub fn with_first_keys(mut self, first_keys: Vec<String>) -> Self {
+        self.first_keys = first_keys;
+        self
+    }
+
+    /// Enable ANSI colors: dim timestamps, level-aware level colors, colored keys, and field
+    /// values colored by type
+    pub fn with_color(mut self, color: bool) -> Self {
+        self.color = color;
+        self
+    }
+
+    fn paint(&self, code: &str, text: &str) -> String {
+        if self.color {
+            format!("{}{}{}", code, text, ANSI_RESET)
+        } else {
+            text.to_string()
+        }
+    }
+
+    /// Quote-escape a value, wrapping highlight matches in inverse video. Only the inverse is
+    /// turned off after a match, so the color of the value around it carries on
+    fn highlighted(&self, value: &str) -> String {
+        let regex = match &self.highlight {
+            Some(regex) if self.color => regex,
+            _ => return escape_quotes(value),
+        };
+
+        let mut result = String::new();
+        let mut last = 0;
+        for m in regex.find_iter(value).filter(|m| !m.is_empty()) {
+            result.push_str(&escape_quotes(&value[last..m.start()]));
+            result.push_str(ANSI_INVERSE);
+            result.push_str(&escape_quotes(m.as_str()));
+            result.push_str(ANSI_INVERSE_OFF);
+            last = m.end();
+        }
+        result.push_str(&escape_quotes(&value[last..]));
+        result
+    }
+
+    fn pair(&self, key: &str, value: &str) -> String {
+        format!("{}={}", self.paint(ANSI_CYAN, key), value)
+    }
+}
+
+fn level_color(level: &str) -> &'static str {
+    match level.to_uppercase().as_str() {
+        "ERROR" | "ERR" | "FATAL" | "CRITICAL" | "CRIT" | "ALERT" | "EMERGENCY" | "EMERG"
+        | "PANIC" => ANSI_RED,
+        "WARN" | "WARNING" => ANSI_YELLOW,
+        "INFO" | "NOTICE" => ANSI_GREEN,
+        "DEBUG" | "TRACE" => ANSI_BLUE,
+        _ => "",
+    }
+}
+
+/// Color of a field value by its type; the message stays plain as the text of the line
+fn value_color(value: &FieldValue) -> &'static str {
+    match value {
+        FieldValue::String(_) => ANSI_GREEN,
+        FieldValue::Number(_) => ANSI_MAGENTA,
+        FieldValue::Boolean(_) => ANSI_YELLOW,
+        FieldValue::Null => ANSI_DIM,
+    }
+}
+
+impl Formatter for DefaultFormatter {
+    fn format(&self, event: &Event) -> String {
+        // (key, rendered pair) in the order they will be emitted
+        let mut parts: Vec<(&str, String)> = Vec::new();
+
+        // Add core fields first if they exist
+        if let Some(timestamp) = &event.timestamp {
+            let value = match &event.timestamp_display {
+                Some(FieldValue::Number(n)) => format_number(*n),
+                Some(value) => format!("\"{}\"", value),
+                None => format!("\"{}\"", timestamp.format("%Y-%m-%dT%H:%M:%S%.3fZ")),
+            };
+            parts.push((
+                "timestamp",
+                self.pair("timestamp", &self.paint(ANSI_DIM, &value)),
+            ));
+        }
+
+        if let Some(level) = &event.level {
+            let value = format!("\"{}\"", level);
+            parts.push((
+                "level",
+                self.pair("level", &self.paint(level_color(level), &value)),
+            ));
+        }
+
+        if let Some(message) = event
+            .message
+            .as_ref()
+            .filter(|message| !(self.skip_empty && message.is_empty()))
+        {
+            parts.push((
+                "message",
+                self.pair("message", &format!("\"{}\"", self.highlighted(message))),
+            ));
+        }
+
+        // Add other fields in the configured order
+        let mut field_keys: Vec<&String> = event.fields.keys().collect();
+        match &self.order {
+            KeyOrder::Alpha => field_keys.sort(),
+            KeyOrder::Input => {}
+            KeyOrder::KeysAsGiven(keys) => {
+                field_keys.sort_by_key(|key| key_rank(keys, key).unwrap_or(usize::MAX))
+            }
+        }
+
+        for key in field_keys {
+            if let Some(value) = event.fields.get(key) {
+                if self.skip_empty && is_empty_value(value) {
+                    continue;
+                }
+                let formatted_value = match value {
+                    FieldValue::String(s) => format!("\"{}\"", self.highlighted(s)),
+                    FieldValue::Number(n) => self.highlighted(&format_number(*n)),
+                    FieldValue::Boolean(b) => self.highlighted(&b.to_string()),
+                    FieldValue::Null => "null".to_string(),
+                };
+                let formatted_value = self.paint(value_color(value), &formatted_value);
+                parts.push((key, self.pair(key, &formatted_value)));
+            }
+        }
+
+        // Pinned keys lead the line, in the order they were given
+        if !self.first_keys.is_empty() {
+            parts.sort_by_key(|(key, _)| key_rank(&self.first_keys, key).unwrap_or(usize::MAX));
+        }
+
+        parts
+            .into_iter()
+            .map(|(_, part)| part)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// Position of `key` in a user-supplied key list, matching core fields by any of their aliases
+fn key_rank(keys: &[String], key: &str) -> Option<usize> {
+    keys.iter().position(|candidate| {
+        key_matches(candidate, key) || core_key_name(candidate).is_some_and(|core| core == key)
+    })
+}
+
+/// Null values and empty strings, which `--skip-empty` leaves out
+fn is_empty_value(value: &FieldValue) -> bool {
+    match value {
+        FieldValue::Null => true,
+        FieldValue::String(s) => s.is_empty(),
+        _ => false,
+    }
+}
+
+// JSONL formatter
+pub struct JsonlFormatter {
+    skip_empty: bool,
+    unflatten: Option<String>,
+    order: KeyOrder,
+}
+
+impl JsonlFormatter {
+    pub fn new() -> Self {
+        Self {
+            skip_empty: false,
+            unflatten: None,
+            order: KeyOrder::Alpha,
+        }
+    }
+
+    /// Order of the keys, all of them alphabetically by default
+    pub fn with_key_order(mut self, order: KeyOrder) -> Self {
+        self.order = order;
+        self
+    }
+
+    /// Omit fields whose value is null or an empty string
+    pub fn with_skip_empty(mut self, skip_empty: bool) -> Self {
+        self.skip_empty = skip_empty;
+        self
+    }
+
+    /// Nest keys containing this separator (see [`unflatten_json`])
+    pub fn with_unflatten(mut self, separator: Option<String>) -> Self {
+        self.unflatten = separator;
+        self
+    }
+}
+
+impl Formatter for JsonlFormatter {
+    fn format(&self, event: &Event) -> String {
+        let mut json = event_to_json(event);
+        apply_ts_display(&mut json, event);
+        if let (true, serde_json::Value::Object(map)) = (self.skip_empty, &mut json) {
+            map.retain(|_, value| match value {
+                serde_json::Value::Null => false,
+                serde_json::Value::String(s) => !s.is_empty(),
+                _ => true,
+            });
+        }
+        if let Some(separator) = &self.unflatten {
+            json = unflatten_json(json, separator);
+        }
+        order_json_keys(&mut json, &self.order);
+        serde_json::to_string(&json).unwrap_or_else(|_| "{}".to_string())
+    }
+}
+
+// Pretty-printed JSON formatter
+pub struct JsonPrettyFormatter {
+    // Wrap all events in a top-level JSON array
+    array: bool,
+    started: Cell<bool>,
+    unflatten: Option<String>,
+    order: KeyOrder,
+}
+
+impl JsonPrettyFormatter {
+    pub fn new(array: bool) -> Self {
+        Self {
+            array,
+            started: Cell::new(false),
+            unflatten: None,
+            order: KeyOrder::Alpha,
+        }
+    }
+
+    /// Order of the keys, all of them alphabetically by default
+    pub fn with_key_order(mut self, order: KeyOrder) -> Self {
+        self.order = order;
+        self
+    }
+
+    /// Nest keys containing this separator (see [`unflatten_json`])
+    pub fn with_unflatten(mut self, separator: Option<String>) -> Self {
+        self.unflatten = separator;
+        self
+    }
+}
+
+impl Formatter for JsonPrettyFormatter {
+    fn format(&self, event: &Event) -> String {
+        let mut json = event_to_json(event);
+        apply_ts_display(&mut json, event);
+        if let Some(separator) = &self.unflatten {
+            json = unflatten_json(json, separator);
+        }
+        order_json_keys(&mut json, &self.order);
+        serde_json::to_string_pretty(&json).unwrap_or_else(|_| "{}".to_string())
+    }
+
+    fn write_event(&self, event: &Event, out: &mut dyn Write) -> io::Result<()> {
+        if !self.array {
+            return writeln!(out, "{}", self.format(event));
+        }
+
+        // Array elements are indented one level and separated by commas
+        let separator = if self.started.replace(true) {
+            ",\n"
+        } else {
+            "[\n"
+        };
+        let element = self
+            .format(event)
+            .lines()
+            .map(|line| format!("  {}", line))
+            .collect::<Vec<_>>()
+            .join("\n");
+        write!(out, "{}{}", separator, element)
+    }
+
+    fn finish(&self, out: &mut dyn Write) -> io::Result<()> {
+        if !self.array {
+            Ok(())
+        } else if self.started.get() {
+            writeln!(out, "\n]")
+        } else {
+            writeln!(out, "[]")
+        }
+    }
+}
+
+/// Put the keys of JSON output in order: alphabetically at every level, as JSON output always
+/// had them, or the core fields followed by the input order, or the order of `-k`
+fn order_json_keys(json: &mut serde_json::Value, order: &KeyOrder) {
+    match (order, json) {
+        (KeyOrder::Alpha, json) => json.sort_all_objects(),
+        (KeyOrder::Input, _) => {}
+        (KeyOrder::KeysAsGiven(keys), serde_json::Value::Object(map)) => {
+            let mut entries: Vec<_> = std::mem::take(map).into_iter().collect();
+            entries.sort_by_key(|(key, _)| key_rank(keys, key).unwrap_or(usize::MAX));
+            map.extend(entries);
+        }
+        (KeyOrder::KeysAsGiven(_), _) => {}
+    }
+}
+
+pub fn event_to_json(event: &Event) -> serde_json::Value {
+    let mut json_obj = serde_json::Map::new();
+
+    // Add core fields
+    if let Some(timestamp) = &event.timestamp {
+        json_obj.insert(
+            "timestamp".to_string(),
+            serde_json::Value::String(timestamp.to_rfc3339()),
+        );
+    }
+
+    if let Some(level) = &event.level {
+        json_obj.insert(
+            "level".to_string(),
+            serde_json::Value::String(level.clone()),
+        );
+    }
+
+    if let Some(message) = &event.message {
+        json_obj.insert(
+            "message".to_string(),
+            serde_json::Value::String(message.clone()),
+        );
+    }
+
+    // Add other fields
+    for (key, value) in &event.fields {
+        json_obj.insert(key.clone(), field_value_to_json(value));
+    }
+
+    serde_json::Value::Object(json_obj)
+}
+
+fn field_value_to_json(value: &FieldValue) -> serde_json::Value {
+    match value {
+        FieldValue::String(s) => serde_json::Value::String(s.clone()),
+        FieldValue::Number(n) => serde_json::Value::Number(
+            serde_json::Number::from_f64(*n).unwrap_or_else(|| serde_json::Number::from(0)),
+        ),
+        FieldValue::Boolean(b) => serde_json::Value::Bool(*b),
+        FieldValue::Null => serde_json::Value::Null,
+    }
+}
+
+/// Rebuild nesting from flat keys: `http.status` becomes `{"http":{"status":..}}`, and
+/// objects keyed `0`, `1`, ... (as `--flatten` leaves arrays) become arrays again. Keys that
+/// can't nest stay as they are: those with empty parts, and those below a key that holds a
+/// value itself (`http=1` next to `http.status=200`)
+pub fn unflatten_json(json: serde_json::Value, separator: &str) -> serde_json::Value {
+    let serde_json::Value::Object(map) = json else {
+        return json;
+    };
+    let nestable = |key: &str| {
+        let parts: Vec<&str> = key.split(separator).collect();
+        parts.len() > 1
+            && parts.iter().all(|part| !part.is_empty())
+            && (1..parts.len()).all(|n| !map.contains_key(&parts[..n].join(separator)))
+    };
+    let nested_keys: Vec<bool> = map.keys().map(|key| nestable(key)).collect();
+
+    let mut root = serde_json::Map::new();
+    for ((key, value), nested) in map.into_iter().zip(nested_keys) {
+        if !nested {
+            root.insert(key, value);
+            continue;
+        }
+        let mut parts = key.split(separator).peekable();
+        let mut target = &mut root;
+        while let Some(part) = parts.next() {
+            if parts.peek().is_none() {
+                target.insert(part.to_string(), value);
+                break;
+            }
+            let child = target
+                .entry(part.to_string())
+                .or_insert_with(|| serde_json::Value::Object(serde_json::Map::new()));
+            match child {
+                serde_json::Value::Object(child) => target = child,
+                // Only nested objects are created below nestable keys
+                _ => unreachable!(),
+            }
+        }
+    }
+    serde_json::Value::Object(
+        root.into_iter()
+            .map(|(key, value)| (key, restore_arrays(value)))
+            .collect(),
+    )
+}
+
+fn restore_arrays(json: serde_json::Value) -> serde_json::Value {
+    let serde_json::Value::Object(map) = json else {
+        return json;
+    };
+    let is_array = !map.is_empty()
+        && map
+            .keys()
+            .enumerate()
+            .all(|(i, key)| key.parse::<usize>() == Ok(i));
+    let children = map
+        .into_iter()
+        .map(|(key, value)| (key, restore_arrays(value)));
+    if is_array {
+        serde_json::Value::Array(children.map(|(_, value)| value).collect())
+    } else {
+        serde_json::Value::Object(children.collect())
+    }
+}
+
+// CSV/TSV formatter
+pub struct CsvFormatter {
+    keys: Option<Vec<String>>,
+    delimiter: char,
+    // Fixed by the first event, which also emits the header row
+    columns: RefCell<Option<Vec<String>>>,
+}
+
+impl CsvFormatter {
+    pub fn new(keys: Option<Vec<String>>) -> Self {
+        Self {
+            keys,
+            delimiter: ',',
+            columns: RefCell::new(None),
+        }
+    }
+
+    /// Tab-delimited variant, escaping embedded tabs and newlines instead of quoting
+    pub fn tsv(keys: Option<Vec<String>>) -> Self {
+        Self {
+            delimiter: '\t',
+            ..Self::new(keys)
+        }
+    }
+
+    fn row(&self, values: impl Iterator<Item = String>) -> String {
+        let escape = if self.delimiter == '\t' {
+            escape_tsv
+        } else {
+            escape_csv
+        };
+        values
+            .map(|value| escape(&value))
+            .collect::<Vec<_>>()
+            .join(&self.delimiter.to_string())
+    }
+}
+
+impl Formatter for CsvFormatter {
+    fn format(&self, event: &Event) -> String {
+        let mut output = String::new();
+        let mut columns = self.columns.borrow_mut();
+        let columns = columns.get_or_insert_with(|| {
+            let columns = self.keys.clone().unwrap_or_else(|| discover_columns(event));
+            output.push_str(&self.row(columns.iter().cloned()));
+            output.push('\n');
+            columns
+        });
+
+        output.push_str(&self.row(columns.iter().map(|key| {
+            output_value(event, key)
+                .map(|value| plain_value(&value))
+                .unwrap_or_default()
+        })));
+        output
+    }
+}
+
+// Aligned table formatter
+pub struct TableFormatter {
+    keys: Option<Vec<String>>,
+    max_width: Option<usize>,
+    state: RefCell<TableState>,
+}
+
+#[derive(Default)]
+struct TableState {
+    // Events held back until enough have been seen to size the columns
+    pending: Vec<Event>,
+    // Column names and widths, fixed once the header has been written
+    layout: Option<(Vec<String>, Vec<usize>)>,
+}
+
+/// Number of events used to size the table columns before streaming the rest
+const TABLE_SAMPLE_SIZE: usize = 100;
+
+const TABLE_MIN_COLUMN_WIDTH: usize = 4;
+
+impl TableFormatter {
+    /// Create a table formatter whose rows fit within `max_width` characters, if given
+    pub fn new(keys: Option<Vec<String>>, max_width: Option<usize>) -> Self {
+        Self {
+            keys,
+            max_width,
+            state: RefCell::new(TableState::default()),
+        }
+    }
+
+    fn cell(&self, event: &Event, key: &str) -> String {
+        output_value(event, key)
+            .map(|value| plain_value(&value).replace(['\n', '\r', '\t'], " "))
+            .unwrap_or_default()
+    }
+
+    fn render_row(values: &[String], widths: &[usize]) -> String {
+        values
+            .iter()
+            .zip(widths)
+            .map(|(value, width)| {
+                let value = truncate_chars(value, *width);
+                format!("{:<width$}", value, width = width)
+            })
+            .collect::<Vec<_>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    }
+
+    /// Size the columns from the pending events and write the header plus held-back rows
+    fn flush(&self, state: &mut TableState, out: &mut dyn Write) -> io::Result<()> {
+        let columns = match &self.keys {
+            Some(keys) => keys.clone(),
+            None => {
+                let mut columns: Vec<String> = Vec::new();
+                for event in &state.pending {
+                    for column in discover_columns(event) {
+                        if !columns.contains(&column) {
+                            columns.push(column);
+                        }
+                    }
+                }
+                columns
+            }
+        };
+
+        let rows: Vec<Vec<String>> = state
+            .pending
+            .drain(..)
+            .map(|event| columns.iter().map(|key| self.cell(&event, key)).collect())
+            .collect();
+
+        let mut widths: Vec<usize> = columns.iter().map(|c| c.chars().count()).collect();
+        for row in &rows {
+            for (width, value) in widths.iter_mut().zip(row) {
+                *width = (*width).max(value.chars().count());
+            }
+        }
+        if let Some(max_width) = self.max_width {
+            fit_widths(&mut widths, max_width);
+        }
+
+        writeln!(out, "{}", Self::render_row(&columns, &widths))?;
+        for row in &rows {
+            writeln!(out, "{}", Self::render_row(row, &widths))?;
+        }
+
+        state.layout = Some((columns, widths));
+        Ok(())
+    }
 }
 
-// Default logfmt-style formatter
-pub struct DefaultFormatter;
+impl Formatter for TableFormatter {
+    fn format(&self, event: &Event) -> String {
+        let state = self.state.borrow();
+        match &state.layout {
+            Some((columns, widths)) => {
+                let row: Vec<String> = columns.iter().map(|key| self.cell(event, key)).collect();
+                Self::render_row(&row, widths)
+            }
+            None => {
+                let columns = self.keys.clone().unwrap_or_else(|| discover_columns(event));
+                let row: Vec<String> = columns.iter().map(|key| self.cell(event, key)).collect();
+                let widths: Vec<usize> = row.iter().map(|value| value.chars().count()).collect();
+                Self::render_row(&row, &widths)
+            }
+        }
+    }
+
+    fn write_event(&self, event: &Event, out: &mut dyn Write) -> io::Result<()> {
+        let mut state = self.state.borrow_mut();
+        if state.layout.is_some() {
+            drop(state);
+            return writeln!(out, "{}", self.format(event));
+        }
+
+        state.pending.push(event.clone());
+        if state.pending.len() >= TABLE_SAMPLE_SIZE {
+            self.flush(&mut state, out)?;
+        }
+        Ok(())
+    }
+
+    fn finish(&self, out: &mut dyn Write) -> io::Result<()> {
+        let mut state = self.state.borrow_mut();
+        if state.layout.is_none() && !state.pending.is_empty() {
+            self.flush(&mut state, out)?;
+        }
+        Ok(())
+    }
+}
+
+/// Shrink the widest columns until the row, including separators, fits in `max_width`
+fn fit_widths(widths: &mut [usize], max_width: usize) {
+    let separators = widths.len().saturating_sub(1) * 2;
+    while widths.iter().sum::<usize>() + separators > max_width {
+        match widths.iter_mut().max() {
+            Some(widest) if *widest > TABLE_MIN_COLUMN_WIDTH => *widest -= 1,
+            _ => break,
+        }
+    }
+}
+
+/// Cut a string to at most `width` characters, marking truncation with an ellipsis
+fn truncate_chars(s: &str, width: usize) -> String {
+    if s.chars().count() <= width {
+        s.to_string()
+    } else if width == 0 {
+        String::new()
+    } else {
+        let mut truncated: String = s.chars().take(width - 1).collect();
+        truncated.push('…');
+        truncated
+    }
+}
+
+// Template-string formatter
+pub struct TemplateFormatter {
+    segments: Vec<TemplateSegment>,
+}
+
+enum TemplateSegment {
+    Literal(String),
+    Placeholder {
+        key: String,
+        fallback: Option<String>,
+    },
+}
+
+/// Shown for placeholders whose field is missing and that have no fallback of their own
+const TEMPLATE_MISSING: &str = "-";
+
+impl TemplateFormatter {
+    /// Compile a template such as `{timestamp} [{level}] {message} ({fields.request_id:-none})`.
+    /// `{{` and `}}` produce literal braces.
+    pub fn new(template: &str) -> Result<Self, String> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut placeholder = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => placeholder.push(c),
+                            None => {
+                                return Err(format!("Unclosed placeholder '{{{}'", placeholder))
+                            }
+                        }
+                    }
+
+                    let (key, fallback) = match placeholder.split_once(":-") {
+                        Some((key, fallback)) => (key, Some(fallback.to_string())),
+                        None => (placeholder.as_str(), None),
+                    };
+                    let key = key.trim();
+                    if key.is_empty() {
+                        return Err("Empty placeholder in template".to_string());
+                    }
+
+                    if !literal.is_empty() {
+                        segments.push(TemplateSegment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(TemplateSegment::Placeholder {
+                        key: key.to_string(),
+                        fallback,
+                    });
+                }
+                '}' => {
+                    return Err(
+                        "Unmatched '}' in template (use '}}' for a literal brace)".to_string()
+                    )
+                }
+                c => literal.push(c),
+            }
+        }
+
+        if !literal.is_empty() {
+            segments.push(TemplateSegment::Literal(literal));
+        }
+        Ok(Self { segments })
+    }
+}
+
+impl Formatter for TemplateFormatter {
+    fn format(&self, event: &Event) -> String {
+        let mut output = String::new();
+        for segment in &self.segments {
+            match segment {
+                TemplateSegment::Literal(text) => output.push_str(text),
+                TemplateSegment::Placeholder { key, fallback } => {
+                    // `fields.` addresses the raw field map, bypassing core-field aliases
+                    let value = match key.strip_prefix("fields.") {
+                        Some(field) => event.fields.get(field).cloned(),
+                        None => output_value(event, key),
+                    };
+                    match value {
+                        Some(value) => output.push_str(&plain_value(&value)),
+                        None => output.push_str(fallback.as_deref().unwrap_or(TEMPLATE_MISSING)),
+                    }
+                }
+            }
+        }
+        output
+    }
+}
+
+// RFC 5424 syslog formatter
+pub struct SyslogFormatter;
+
+/// Facility used when an event doesn't carry one (1 = user-level messages)
+const SYSLOG_DEFAULT_FACILITY: u8 = 1;
+
+/// Severity used for events without a recognizable level (5 = notice)
+const SYSLOG_DEFAULT_SEVERITY: u8 = 5;
+
+/// SD-ID of the structured-data element that carries the remaining fields
+const SYSLOG_SD_ID: &str = "kelora@32473";
+
+/// Fields consumed by the syslog header, which are left out of the structured data
+const SYSLOG_HEADER_KEYS: &[&str] = &[
+    "priority", "facility", "severity", "hostname", "host", "process", "app", "appname", "pid",
+    "procid", "msgid",
+];
+
+impl SyslogFormatter {
+    pub fn new() -> Self {
+        Self
+    }
+
+    /// First present header field among the candidates, or the RFC 5424 nil value
+    fn header_value(event: &Event, keys: &[&str], max_len: usize) -> String {
+        keys.iter()
+            .find_map(|key| event.fields.get(*key))
+            .map(plain_value)
+            .map(|value| {
+                value
+                    .chars()
+                    .filter(|c| c.is_ascii_graphic())
+                    .take(max_len)
+                    .collect::<String>()
+            })
+            .filter(|value| !value.is_empty())
+            .unwrap_or_else(|| "-".to_string())
+    }
+}
+
+impl Formatter for SyslogFormatter {
+    fn format(&self, event: &Event) -> String {
+        let facility = match event.fields.get("facility") {
+            Some(FieldValue::Number(n)) if (0.0..24.0).contains(n) => *n as u8,
+            _ => SYSLOG_DEFAULT_FACILITY,
+        };
+        let severity = event
+            .level
+            .as_deref()
+            .and_then(syslog_severity)
+            .unwrap_or(SYSLOG_DEFAULT_SEVERITY);
+
+        let timestamp = event
+            .timestamp
+            .map(|ts| ts.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string())
+            .unwrap_or_else(|| "-".to_string());
+        let hostname = Self::header_value(event, &["hostname", "host"], 255);
+        let app_name = Self::header_value(event, &["process", "app", "appname"], 48);
+        let proc_id = Self::header_value(event, &["pid", "procid"], 128);
+        let msg_id = Self::header_value(event, &["msgid"], 32);
+
+        let params: Vec<String> = event
+            .fields
+            .iter()
+            .filter(|(key, _)| {
+                !SYSLOG_HEADER_KEYS.contains(&key.as_str()) && core_key_name(key).is_none()
+            })
+            .map(|(key, value)| {
+                format!(
+                    "{}=\"{}\"",
+                    sd_name(key),
+                    plain_value(value)
+                        .replace('\\', "\\\\")
+                        .replace('"', "\\\"")
+                        .replace(']', "\\]")
+                )
+            })
+            .collect();
+        let structured_data = if params.is_empty() {
+            "-".to_string()
+        } else {
+            format!("[{} {}]", SYSLOG_SD_ID, params.join(" "))
+        };
+
+        let mut line = format!(
+            "<{}>1 {} {} {} {} {} {}",
+            facility as u32 * 8 + severity as u32,
+            timestamp,
+            hostname,
+            app_name,
+            proc_id,
+            msg_id,
+            structured_data
+        );
+        if let Some(message) = &event.message {
+            line.push(' ');
+            line.push_str(&message.replace(['\n', '\r'], " "));
+        }
+        line
+    }
+}
+
+/// Make a key a valid SD-NAME: printable ASCII without `= ]"` or spaces, at most 32 chars
+fn sd_name(key: &str) -> String {
+    key.chars()
+        .map(|c| {
+            if c.is_ascii_graphic() && !matches!(c, '=' | ']' | '"') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .take(32)
+        .collect()
+}
+
+// YAML formatter, one document per event
+pub struct YamlFormatter;
+
+impl YamlFormatter {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl Formatter for YamlFormatter {
+    fn format(&self, event: &Event) -> String {
+        let mut document = String::from("---");
+        let mut json = event_to_json(event);
+        apply_ts_display(&mut json, event);
+        if let serde_json::Value::Object(map) = json {
+            if map.is_empty() {
+                document.push_str(" {}");
+            }
+            for (key, value) in &map {
+                let value = match value {
+                    serde_json::Value::Null => "null".to_string(),
+                    serde_json::Value::Bool(b) => b.to_string(),
+                    serde_json::Value::Number(n) => format_number(n.as_f64().unwrap_or(0.0)),
+                    serde_json::Value::String(s) => yaml_scalar(s),
+                    other => yaml_scalar(&other.to_string()),
+                };
+                document.push_str(&format!("\n{}: {}", yaml_scalar(key), value));
+            }
+        }
+        document
+    }
+}
+
+// GELF 1.1 formatter for Graylog
+pub struct GelfFormatter {
+    // Used as the required `host` when an event doesn't name one
+    default_host: String,
+}
+
+/// Fields consumed by the GELF `host` attribute
+const GELF_HOST_KEYS: &[&str] = &["hostname", "host"];
+
+impl GelfFormatter {
+    pub fn new(default_host: String) -> Self {
+        Self { default_host }
+    }
+
+    pub fn to_json(&self, event: &Event) -> serde_json::Value {
+        let mut json_obj = serde_json::Map::new();
+        json_obj.insert("version".to_string(), "1.1".into());
+
+        let host = GELF_HOST_KEYS
+            .iter()
+            .find_map(|key| event.fields.get(*key))
+            .map(plain_value)
+            .filter(|host| !host.is_empty())
+            .unwrap_or_else(|| self.default_host.clone());
+        json_obj.insert("host".to_string(), host.into());
+
+        // short_message is required; multi-line messages also go to full_message
+        let message = event.message.clone().unwrap_or_default();
+        let short_message = message.lines().next().unwrap_or("").trim();
+        let short_message = if short_message.is_empty() {
+            "-"
+        } else {
+            short_message
+        };
+        json_obj.insert("short_message".to_string(), short_message.into());
+        if message.contains('\n') {
+            json_obj.insert("full_message".to_string(), message.clone().into());
+        }
+
+        if let Some(timestamp) = &event.timestamp {
+            let seconds = timestamp.timestamp_millis() as f64 / 1000.0;
+            if let Some(number) = serde_json::Number::from_f64(seconds) {
+                json_obj.insert("timestamp".to_string(), serde_json::Value::Number(number));
+            }
+        }
+        if let Some(severity) = event.level.as_deref().and_then(syslog_severity) {
+            json_obj.insert("level".to_string(), severity.into());
+        }
+
+        // Remaining fields become additional fields, which GELF only allows as strings or numbers
+        for (key, value) in &event.fields {
+            if GELF_HOST_KEYS.contains(&key.as_str()) || core_key_name(key).is_some() {
+                continue;
+            }
+            let json_value = match value {
+                FieldValue::Number(n) => match serde_json::Number::from_f64(*n) {
+                    Some(number) => serde_json::Value::Number(number),
+                    None => continue,
+                },
+                FieldValue::Null => continue,
+                other => serde_json::Value::String(plain_value(other)),
+            };
+            json_obj.insert(gelf_field_name(key), json_value);
+        }
 
-impl DefaultFormatter {
-    pub fn new() -> Self {
-        Self
+        serde_json::Value::Object(json_obj)
     }
 }
 
-impl Formatter for DefaultFormatter {
+impl Formatter for GelfFormatter {
     fn format(&self, event: &Event) -> String {
-        let mut parts = Vec::new();
+        serde_json::to_string(&self.to_json(event)).unwrap_or_else(|_| "{}".to_string())
+    }
+}
 
-        // Add core fields first if they exist
-        if let Some(timestamp) = &event.timestamp {
-            parts.push(format!(
-                "timestamp=\"{}\"",
-                timestamp.format("%Y-%m-%dT%H:%M:%S%.3fZ")
-            ));
-        }
+/// Additional field name: `_` prefix, only word characters, dots and dashes, and never the
+/// reserved `_id`
+fn gelf_field_name(key: &str) -> String {
+    let name: String = std::iter::once('_')
+        .chain(key.chars().map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-') {
+                c
+            } else {
+                '_'
+            }
+        }))
+        .collect();
+    if name == "_id" {
+        "_id_".to_string()
+    } else {
+        name
+    }
+}
 
-        if let Some(level) = &event.level {
-            parts.push(format!("level=\"{}\"", level));
-        }
+// Elasticsearch bulk API formatter: an action line followed by the document source
+pub struct EsBulkFormatter {
+    // strftime pattern for the target index, e.g. `logs-%Y.%m.%d`
+    index_pattern: String,
+}
 
-        if let Some(message) = &event.message {
-            parts.push(format!("message=\"{}\"", escape_quotes(message)));
+impl EsBulkFormatter {
+    /// Index names are rendered from the event timestamp in UTC, or the current time
+    /// for events without one
+    pub fn new(index_pattern: &str) -> Result<Self, String> {
+        let invalid = chrono::format::StrftimeItems::new(index_pattern)
+            .any(|item| matches!(item, chrono::format::Item::Error));
+        if invalid || index_pattern.is_empty() {
+            return Err(format!("invalid index pattern '{}'", index_pattern));
         }
+        Ok(Self {
+            index_pattern: index_pattern.to_string(),
+        })
+    }
 
-        // Add other fields in sorted order
-        let mut field_keys: Vec<_> = event.fields.keys().collect();
-        field_keys.sort();
+    fn index_name(&self, event: &Event) -> String {
+        event
+            .timestamp
+            .unwrap_or_else(chrono::Utc::now)
+            .format(&self.index_pattern)
+            .to_string()
+    }
+}
 
-        for key in field_keys {
-            if let Some(value) = event.fields.get(key) {
-                let formatted_value = match value {
-                    FieldValue::String(s) => format!("\"{}\"", escape_quotes(s)),
-                    FieldValue::Number(n) => {
-                        // Format numbers nicely - avoid unnecessary decimal places for integers
-                        if n.fract() == 0.0 {
-                            format!("{}", *n as i64)
-                        } else {
-                            format!("{}", n)
+impl Formatter for EsBulkFormatter {
+    fn format(&self, event: &Event) -> String {
+        let action = serde_json::json!({ "index": { "_index": self.index_name(event) } });
+
+        // Elasticsearch expects the event time in @timestamp
+        let source = match event_to_json(event) {
+            serde_json::Value::Object(map) => serde_json::Value::Object(
+                map.into_iter()
+                    .map(|(key, value)| match key.as_str() {
+                        "timestamp" if event.timestamp.is_some() => {
+                            ("@timestamp".to_string(), value)
                         }
-                    }
-                    FieldValue::Boolean(b) => b.to_string(),
-                    FieldValue::Null => "null".to_string(),
-                };
-                parts.push(format!("{}={}", key, formatted_value));
+                        _ => (key, value),
+                    })
+                    .collect(),
+            ),
+            other => other,
+        };
+
+        format!(
+            "{}\n{}",
+            action,
+            serde_json::to_string(&source).unwrap_or_else(|_| "{}".to_string())
+        )
+    }
+}
+
+/// Render a string as a YAML scalar, double-quoting it whenever a plain scalar
+/// would be misread (as another type, a comment, a mapping, ...)
+fn yaml_scalar(s: &str) -> String {
+    let lower = s.to_lowercase();
+    let needs_quotes = s.is_empty()
+        || s.trim() != s
+        || matches!(
+            lower.as_str(),
+            "null" | "~" | "true" | "false" | "yes" | "no" | "on" | "off" | "y" | "n"
+        )
+        || s.starts_with(|c: char| c.is_ascii_digit() || "-+.?:,[]{}#&*!|>'\"%@`".contains(c))
+        || s.contains(": ")
+        || s.contains(" #")
+        || s.ends_with(':')
+        || s.chars().any(char::is_control);
+
+    if needs_quotes {
+        // JSON strings are valid YAML double-quoted scalars
+        serde_json::to_string(s).unwrap_or_else(|_| format!("\"{}\"", escape_quotes(s)))
+    } else {
+        s.to_string()
+    }
+}
+
+/// Maximum field value widths, in characters
+#[derive(Debug, Default)]
+pub struct FieldWidths {
+    default: Option<usize>,
+    per_field: HashMap<String, usize>,
+}
+
+impl FieldWidths {
+    /// Parse specs like `80` (all fields) and `message=200` (a single field)
+    pub fn parse(specs: &[String]) -> Result<Self, String> {
+        let mut widths = Self::default();
+        for spec in specs {
+            let parse_width = |value: &str| {
+                value
+                    .trim()
+                    .parse::<usize>()
+                    .map_err(|_| format!("'{}' is not a width", spec))
+            };
+            match spec.split_once('=') {
+                Some((key, value)) => {
+                    widths
+                        .per_field
+                        .insert(key.trim().to_string(), parse_width(value)?);
+                }
+                None => widths.default = Some(parse_width(spec)?),
             }
         }
+        Ok(widths)
+    }
 
-        parts.join(" ")
+    /// Limit for a key; core field aliases (e.g. `msg`) fall back to the core name's limit
+    fn limit(&self, key: &str) -> Option<usize> {
+        self.per_field
+            .get(key)
+            .or_else(|| core_key_name(key).and_then(|core| self.per_field.get(core)))
+            .copied()
+            .or(self.default)
     }
 }
 
-// JSONL formatter
-pub struct JsonlFormatter;
+/// Wraps another formatter, shortening long string values before they are formatted
+pub struct TruncatingFormatter {
+    inner: Box<dyn Formatter>,
+    widths: FieldWidths,
+}
 
-impl JsonlFormatter {
-    pub fn new() -> Self {
-        Self
+impl TruncatingFormatter {
+    pub fn new(inner: Box<dyn Formatter>, widths: FieldWidths) -> Self {
+        Self { inner, widths }
+    }
+
+    fn truncate(&self, event: &Event) -> Event {
+        let mut event = event.clone();
+        if let Some(message) = &mut event.message {
+            if let Some(limit) = self.widths.limit("message") {
+                *message = truncate_with_count(message, limit);
+            }
+        }
+        for (key, value) in event.fields.iter_mut() {
+            if let (FieldValue::String(s), Some(limit)) = (&mut *value, self.widths.limit(key)) {
+                *s = truncate_with_count(s, limit);
+            }
+        }
+        event
     }
 }
 
-impl Formatter for JsonlFormatter {
+impl Formatter for TruncatingFormatter {
     fn format(&self, event: &Event) -> String {
-        let mut json_obj = serde_json::Map::new();
+        self.inner.format(&self.truncate(event))
+    }
 
-        // Add core fields
-        if let Some(timestamp) = &event.timestamp {
-            json_obj.insert(
-                "timestamp".to_string(),
-                serde_json::Value::String(timestamp.to_rfc3339()),
-            );
-        }
+    fn write_event(&self, event: &Event, out: &mut dyn Write) -> io::Result<()> {
+        self.inner.write_event(&self.truncate(event), out)
+    }
 
-        if let Some(level) = &event.level {
-            json_obj.insert(
-                "level".to_string(),
-                serde_json::Value::String(level.clone()),
-            );
-        }
+    fn finish(&self, out: &mut dyn Write) -> io::Result<()> {
+        self.inner.finish(out)
+    }
+}
 
-        if let Some(message) = &event.message {
-            json_obj.insert(
-                "message".to_string(),
-                serde_json::Value::String(message.clone()),
-            );
-        }
+/// Wraps another formatter, rendering timestamps in a `--ts-display` format instead of RFC 3339
+pub struct TimestampDisplayFormatter {
+    inner: Box<dyn Formatter>,
+    display: TimestampDisplay,
+}
 
-        // Add other fields
-        for (key, value) in &event.fields {
-            let json_value = match value {
-                FieldValue::String(s) => serde_json::Value::String(s.clone()),
-                FieldValue::Number(n) => serde_json::Value::Number(
-                    serde_json::Number::from_f64(*n).unwrap_or_else(|| serde_json::Number::from(0)),
-                ),
-                FieldValue::Boolean(b) => serde_json::Value::Bool(*b),
-                FieldValue::Null => serde_json::Value::Null,
-            };
-            json_obj.insert(key.clone(), json_value);
-        }
+impl TimestampDisplayFormatter {
+    pub fn new(inner: Box<dyn Formatter>, display: TimestampDisplay) -> Self {
+        Self { inner, display }
+    }
+
+    fn render(&self, event: &Event) -> Event {
+        let mut event = event.clone();
+        event.timestamp_display = event.timestamp.as_ref().map(|ts| self.display.render(ts));
+        event
+    }
+}
+
+impl Formatter for TimestampDisplayFormatter {
+    fn format(&self, event: &Event) -> String {
+        self.inner.format(&self.render(event))
+    }
+
+    fn write_event(&self, event: &Event, out: &mut dyn Write) -> io::Result<()> {
+        self.inner.write_event(&self.render(event), out)
+    }
+
+    fn finish(&self, out: &mut dyn Write) -> io::Result<()> {
+        self.inner.finish(out)
+    }
+}
+
+/// Cut a string to `width` characters, noting how many were dropped
+fn truncate_with_count(s: &str, width: usize) -> String {
+    let total = s.chars().count();
+    if total <= width {
+        return s.to_string();
+    }
+    let kept: String = s.chars().take(width).collect();
+    format!("{}…(+{} chars)", kept, total - width)
+}
+
+/// Column names for tabular output: present core fields first, then the remaining keys sorted
+fn discover_columns(event: &Event) -> Vec<String> {
+    let mut columns = Vec::new();
+    if event.timestamp.is_some() {
+        columns.push("timestamp".to_string());
+    }
+    if event.level.is_some() {
+        columns.push("level".to_string());
+    }
+    if event.message.is_some() {
+        columns.push("message".to_string());
+    }
+
+    let mut field_keys: Vec<_> = event
+        .fields
+        .keys()
+        .filter(|key| !columns.contains(key))
+        .cloned()
+        .collect();
+    field_keys.sort();
+    columns.extend(field_keys);
+    columns
+}
 
-        serde_json::to_string(&serde_json::Value::Object(json_obj))
-            .unwrap_or_else(|_| "{}".to_string())
+pub fn escape_csv(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) || s.starts_with(' ') || s.ends_with(' ') {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+fn escape_tsv(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+/// Unquoted rendering of a value, for formats that do their own quoting
+fn plain_value(value: &FieldValue) -> String {
+    match value {
+        FieldValue::String(s) => s.clone(),
+        FieldValue::Number(n) => format_number(*n),
+        FieldValue::Boolean(b) => b.to_string(),
+        FieldValue::Null => String::new(),
+    }
+}
+
+// Format numbers nicely - avoid unnecessary decimal places for integers
+fn format_number(n: f64) -> String {
+    if n.fract() == 0.0 {
+        format!("{}", n as i64)
+    } else {
+        format!("{}", n)
     }
 }
 
@@ -118,6 +1373,7 @@ fn escape_quotes(s: &str) -> String {
 }
 
 #[cfg(test)]
+#[allow(unused_imports)]
 mod tests {
     use super::*;
     use chrono::Utc;
@@ -166,4 +1422,529 @@ mod tests {
         assert_eq!(escape_quotes("hello \"world\""), "hello \\\"world\\\"");
         assert_eq!(escape_quotes("path\\to\\file"), "path\\\\to\\\\file");
     }
+
+    #[test]
+    fn test_csv_formatter_discovers_header() {
+        let mut event = Event::new();
+        event.level = Some("INFO".to_string());
+        event.message = Some("said \"hi\", then left".to_string());
+        event.set_field("status".to_string(), FieldValue::Number(200.0));
+
+        let formatter = CsvFormatter::new(None);
+        assert_eq!(
+            formatter.format(&event),
+            "level,message,status\nINFO,\"said \"\"hi\"\", then left\",200"
+        );
+
+        // Header is only emitted once
+        event.set_field("extra".to_string(), FieldValue::Boolean(true));
+        assert_eq!(
+            formatter.format(&event),
+            "INFO,\"said \"\"hi\"\", then left\",200"
+        );
+    }
+
+    #[test]
+    fn test_csv_formatter_with_keys() {
+        let mut event = Event::new();
+        event.message = Some("hello".to_string());
+
+        let formatter = CsvFormatter::new(Some(vec!["msg".to_string(), "host".to_string()]));
+        assert_eq!(formatter.format(&event), "msg,host\nhello,");
+    }
+
+    #[test]
+    fn test_tsv_formatter_escapes_tabs_and_newlines() {
+        let mut event = Event::new();
+        event.message = Some("line1\nline2\tcol".to_string());
+        event.set_field(
+            "path".to_string(),
+            FieldValue::String("C:\\tmp".to_string()),
+        );
+
+        let formatter = CsvFormatter::tsv(None);
+        assert_eq!(
+            formatter.format(&event),
+            "message\tpath\nline1\\nline2\\tcol\tC:\\\\tmp"
+        );
+    }
+
+    #[test]
+    fn test_table_formatter_aligns_columns() {
+        let formatter = TableFormatter::new(None, None);
+        let mut out = Vec::new();
+
+        let mut event = Event::new();
+        event.level = Some("ERROR".to_string());
+        event.message = Some("Disk full".to_string());
+        formatter.write_event(&event, &mut out).unwrap();
+
+        let mut event = Event::new();
+        event.level = Some("INFO".to_string());
+        event.message = Some("OK".to_string());
+        event.set_field("host".to_string(), FieldValue::String("db01".to_string()));
+        formatter.write_event(&event, &mut out).unwrap();
+
+        assert!(
+            out.is_empty(),
+            "Rows are held back until the columns are sized"
+        );
+        formatter.finish(&mut out).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "level  message    host\nERROR  Disk full\nINFO   OK         db01\n"
+        );
+    }
+
+    #[test]
+    fn test_table_formatter_truncates_to_max_width() {
+        let formatter = TableFormatter::new(Some(vec!["message".to_string()]), Some(8));
+        let mut out = Vec::new();
+
+        let mut event = Event::new();
+        event.message = Some("A rather long message".to_string());
+        formatter.write_event(&event, &mut out).unwrap();
+        formatter.finish(&mut out).unwrap();
+
+        assert_eq!(String::from_utf8(out).unwrap(), "message\nA rathe…\n");
+    }
+
+    #[test]
+    fn test_json_pretty_formatter_array() {
+        let formatter = JsonPrettyFormatter::new(true);
+        let mut out = Vec::new();
+
+        let mut event = Event::new();
+        event.level = Some("INFO".to_string());
+        formatter.write_event(&event, &mut out).unwrap();
+        formatter.write_event(&event, &mut out).unwrap();
+        formatter.finish(&mut out).unwrap();
+
+        let output = String::from_utf8(out).unwrap();
+        assert_eq!(
+            output,
+            "[\n  {\n    \"level\": \"INFO\"\n  },\n  {\n    \"level\": \"INFO\"\n  }\n]\n"
+        );
+        let parsed: serde_json::Value = serde_json::from_str(&output).unwrap();
+        assert_eq!(parsed.as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_unflatten_json() {
+        let json = serde_json::json!({
+            "message": "hi",
+            "http.status": 200,
+            "http.req.method": "GET",
+            "tags.0": "a",
+            "tags.1": "b",
+            "k8s": "flat",
+            "k8s.pod": "api-1",
+            "odd..key": 1,
+        });
+        assert_eq!(
+            unflatten_json(json, "."),
+            serde_json::json!({
+                "message": "hi",
+                "http": {"status": 200, "req": {"method": "GET"}},
+                "tags": ["a", "b"],
+                "k8s": "flat",
+                "k8s.pod": "api-1",
+                "odd..key": 1,
+            })
+        );
+
+        let mut event = Event::new();
+        event.set_field("a_b".to_string(), FieldValue::Number(1.0));
+        let formatter = JsonlFormatter::new().with_unflatten(Some("_".to_string()));
+        assert_eq!(formatter.format(&event), r#"{"a":{"b":1.0}}"#);
+    }
+
+    #[test]
+    fn test_json_pretty_formatter_empty_array() {
+        let formatter = JsonPrettyFormatter::new(true);
+        let mut out = Vec::new();
+        formatter.finish(&mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "[]\n");
+    }
+
+    #[test]
+    fn test_default_formatter_color() {
+        let mut event = Event::new();
+        event.level = Some("error".to_string());
+        event.set_field("host".to_string(), FieldValue::String("db01".to_string()));
+
+        let formatter = DefaultFormatter::new().with_color(true);
+        let result = formatter.format(&event);
+
+        assert!(result.contains("\x1b[1;31m\"error\"\x1b[0m"));
+        assert!(result.contains("\x1b[36mhost\x1b[0m=\x1b[32m\"db01\"\x1b[0m"));
+
+        let formatter = DefaultFormatter::new().with_color(false);
+        assert!(!formatter.format(&event).contains('\x1b'));
+    }
+
+    #[test]
+    fn test_default_formatter_value_colors() {
+        let mut event = Event::new();
+        event.message = Some("served".to_string());
+        event.set_field("path".to_string(), FieldValue::String("/".to_string()));
+        event.set_field("status".to_string(), FieldValue::Number(200.0));
+        event.set_field("cached".to_string(), FieldValue::Boolean(true));
+        event.set_field("user".to_string(), FieldValue::Null);
+
+        let formatter = DefaultFormatter::new()
+            .with_key_order(KeyOrder::Input)
+            .with_color(true);
+        assert_eq!(
+            formatter.format(&event),
+            "\x1b[36mmessage\x1b[0m=\"served\" \
+             \x1b[36mpath\x1b[0m=\x1b[32m\"/\"\x1b[0m \
+             \x1b[36mstatus\x1b[0m=\x1b[35m200\x1b[0m \
+             \x1b[36mcached\x1b[0m=\x1b[33mtrue\x1b[0m \
+             \x1b[36muser\x1b[0m=\x1b[2mnull\x1b[0m"
+        );
+    }
+
+    #[test]
+    fn test_template_formatter() {
+        let mut event = Event::new();
+        event.level = Some("WARN".to_string());
+        event.message = Some("Slow query".to_string());
+        event.set_field(
+            "request_id".to_string(),
+            FieldValue::String("abc".to_string()),
+        );
+
+        let formatter = TemplateFormatter::new(
+            "[{level}] {message} ({fields.request_id}) {{{user}}} {host:-n/a}",
+        )
+        .unwrap();
+        assert_eq!(formatter.format(&event), "[WARN] Slow query (abc) {-} n/a");
+    }
+
+    #[test]
+    fn test_template_formatter_rejects_malformed() {
+        assert!(TemplateFormatter::new("{level").is_err());
+        assert!(TemplateFormatter::new("level}").is_err());
+        assert!(TemplateFormatter::new("{}").is_err());
+    }
+
+    #[test]
+    fn test_default_formatter_key_order() {
+        let mut event = Event::new();
+        event.level = Some("INFO".to_string());
+        event.set_field("zeta".to_string(), FieldValue::Number(1.0));
+        event.set_field("alpha".to_string(), FieldValue::Number(2.0));
+        event.set_field("mid".to_string(), FieldValue::Number(3.0));
+
+        let formatter = DefaultFormatter::new();
+        assert_eq!(
+            formatter.format(&event),
+            "level=\"INFO\" alpha=2 mid=3 zeta=1"
+        );
+
+        let formatter = DefaultFormatter::new().with_key_order(KeyOrder::Input);
+        assert_eq!(
+            formatter.format(&event),
+            "level=\"INFO\" zeta=1 alpha=2 mid=3"
+        );
+
+        let formatter = DefaultFormatter::new().with_key_order(KeyOrder::KeysAsGiven(vec![
+            "mid".to_string(),
+            "zeta".to_string(),
+        ]));
+        assert_eq!(
+            formatter.format(&event),
+            "level=\"INFO\" mid=3 zeta=1 alpha=2"
+        );
+    }
+
+    #[test]
+    fn test_default_formatter_first_keys() {
+        let mut event = Event::new();
+        event.level = Some("INFO".to_string());
+        event.message = Some("hi".to_string());
+        event.set_field("host".to_string(), FieldValue::String("db01".to_string()));
+        event.set_field("port".to_string(), FieldValue::Number(5432.0));
+
+        let formatter =
+            DefaultFormatter::new().with_first_keys(vec!["port".to_string(), "msg".to_string()]);
+        assert_eq!(
+            formatter.format(&event),
+            "port=5432 message=\"hi\" level=\"INFO\" host=\"db01\""
+        );
+    }
+
+    #[test]
+    fn test_syslog_formatter() {
+        let mut event = Event::new();
+        event.timestamp = Some(
+            chrono::DateTime::parse_from_rfc3339("2023-07-18T15:04:23.456Z")
+                .unwrap()
+                .into(),
+        );
+        event.level = Some("error".to_string());
+        event.message = Some("Disk full".to_string());
+        event.set_field(
+            "hostname".to_string(),
+            FieldValue::String("db01".to_string()),
+        );
+        event.set_field("process".to_string(), FieldValue::String("pg".to_string()));
+        event.set_field("pid".to_string(), FieldValue::Number(42.0));
+        event.set_field(
+            "path".to_string(),
+            FieldValue::String("/var/\"x\"]".to_string()),
+        );
+        event.set_field(
+            "msg".to_string(),
+            FieldValue::String("Disk full".to_string()),
+        );
+
+        let formatter = SyslogFormatter::new();
+        assert_eq!(
+            formatter.format(&event),
+            "<11>1 2023-07-18T15:04:23.456Z db01 pg 42 - [kelora@32473 path=\"/var/\\\"x\\\"\\]\"] Disk full"
+        );
+    }
+
+    #[test]
+    fn test_syslog_formatter_defaults() {
+        let mut event = Event::new();
+        event.message = Some("hello".to_string());
+        event.set_field("facility".to_string(), FieldValue::Number(4.0));
+
+        let formatter = SyslogFormatter::new();
+        assert_eq!(formatter.format(&event), "<37>1 - - - - - - hello");
+    }
+
+    #[test]
+    fn test_yaml_formatter() {
+        let mut event = Event::new();
+        event.level = Some("INFO".to_string());
+        event.set_field("user".to_string(), FieldValue::String("alice".to_string()));
+        event.set_field("status".to_string(), FieldValue::Number(200.0));
+        event.set_field("ratio".to_string(), FieldValue::Number(0.5));
+        event.set_field("cached".to_string(), FieldValue::Boolean(false));
+        event.set_field("trace".to_string(), FieldValue::Null);
+        event.set_field("code".to_string(), FieldValue::String("404".to_string()));
+        event.set_field("note".to_string(), FieldValue::String("a: b".to_string()));
+        event.set_field("flag".to_string(), FieldValue::String("yes".to_string()));
+
+        let formatter = YamlFormatter::new();
+        assert_eq!(
+            formatter.format(&event),
+            "---\nlevel: INFO\nuser: alice\nstatus: 200\nratio: 0.5\ncached: false\ntrace: null\ncode: \"404\"\nnote: \"a: b\"\nflag: \"yes\""
+        );
+    }
+
+    #[test]
+    fn test_yaml_scalar_quoting() {
+        assert_eq!(yaml_scalar("plain text"), "plain text");
+        assert_eq!(yaml_scalar(""), "\"\"");
+        assert_eq!(yaml_scalar("- item"), "\"- item\"");
+        assert_eq!(yaml_scalar("line\nbreak"), "\"line\\nbreak\"");
+        assert_eq!(yaml_scalar("Null"), "\"Null\"");
+    }
+
+    #[test]
+    fn test_gelf_formatter() {
+        let mut event = Event::new();
+        event.timestamp = Some(
+            chrono::DateTime::parse_from_rfc3339("2023-07-18T15:04:23.456Z")
+                .unwrap()
+                .into(),
+        );
+        event.level = Some("WARN".to_string());
+        event.message = Some("Slow query\nSELECT 1".to_string());
+        event.set_field("host".to_string(), FieldValue::String("db01".to_string()));
+        event.set_field("duration".to_string(), FieldValue::Number(1.5));
+        event.set_field("cached".to_string(), FieldValue::Boolean(true));
+        event.set_field("id".to_string(), FieldValue::String("q-7".to_string()));
+        event.set_field("user name".to_string(), FieldValue::Null);
+
+        let formatter = GelfFormatter::new("localhost".to_string());
+        assert_eq!(
+            formatter.format(&event),
+            r#"{"version":"1.1","host":"db01","short_message":"Slow query","full_message":"Slow query\nSELECT 1","timestamp":1689692663.456,"level":4,"_duration":1.5,"_cached":"true","_id_":"q-7"}"#
+        );
+    }
+
+    #[test]
+    fn test_gelf_formatter_defaults() {
+        let event = Event::new();
+        let formatter = GelfFormatter::new("localhost".to_string());
+        assert_eq!(
+            formatter.format(&event),
+            r#"{"version":"1.1","host":"localhost","short_message":"-"}"#
+        );
+    }
+
+    #[test]
+    fn test_es_bulk_formatter() {
+        let mut event = Event::new();
+        event.timestamp = Some(
+            chrono::DateTime::parse_from_rfc3339("2023-07-18T23:30:00+00:00")
+                .unwrap()
+                .into(),
+        );
+        event.level = Some("INFO".to_string());
+        event.set_field("status".to_string(), FieldValue::Number(200.0));
+
+        let formatter = EsBulkFormatter::new("logs-%Y.%m.%d").unwrap();
+        assert_eq!(
+            formatter.format(&event),
+            "{\"index\":{\"_index\":\"logs-2023.07.18\"}}\n{\"@timestamp\":\"2023-07-18T23:30:00+00:00\",\"level\":\"INFO\",\"status\":200.0}"
+        );
+    }
+
+    #[test]
+    fn test_es_bulk_invalid_index_pattern() {
+        assert!(EsBulkFormatter::new("logs-%Q").is_err());
+        assert!(EsBulkFormatter::new("").is_err());
+    }
+
+    #[test]
+    fn test_truncating_formatter() {
+        let mut event = Event::new();
+        event.message = Some("a".repeat(30));
+        event.set_field("msg".to_string(), FieldValue::String("a".repeat(30)));
+        event.set_field("trace".to_string(), FieldValue::String("b".repeat(12)));
+        event.set_field("id".to_string(), FieldValue::String("short".to_string()));
+        event.set_field("count".to_string(), FieldValue::Number(123456789.0));
+
+        let widths = FieldWidths::parse(&["8".to_string(), "message=20".to_string()]).unwrap();
+        let formatter = TruncatingFormatter::new(Box::new(JsonlFormatter::new()), widths);
+        let expected_message = format!("{}…(+10 chars)", "a".repeat(20));
+        assert_eq!(
+            formatter.format(&event),
+            format!(
+                "{{\"count\":123456789.0,\"id\":\"short\",\"message\":\"{0}\",\"msg\":\"{0}\",\"trace\":\"bbbbbbbb…(+4 chars)\"}}",
+                expected_message
+            )
+        );
+    }
+
+    #[test]
+    fn test_field_widths_rejects_invalid_specs() {
+        assert!(FieldWidths::parse(&["wide".to_string()]).is_err());
+        assert!(FieldWidths::parse(&["message=".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_skip_empty_fields() {
+        let mut event = Event::new();
+        event.message = Some("hi".to_string());
+        event.set_field("user".to_string(), FieldValue::String(String::new()));
+        event.set_field("trace".to_string(), FieldValue::Null);
+        event.set_field("retries".to_string(), FieldValue::Number(0.0));
+        event.set_field("cached".to_string(), FieldValue::Boolean(false));
+
+        let formatter = DefaultFormatter::new().with_skip_empty(true);
+        assert_eq!(
+            formatter.format(&event),
+            "message=\"hi\" cached=false retries=0"
+        );
+
+        let formatter = JsonlFormatter::new().with_skip_empty(true);
+        assert_eq!(
+            formatter.format(&event),
+            "{\"cached\":false,\"message\":\"hi\",\"retries\":0.0}"
+        );
+    }
+
+    #[test]
+    fn test_highlight_matches() {
+        let mut event = Event::new();
+        event.message = Some("upstream \"api\" timeout".to_string());
+        event.set_field(
+            "path".to_string(),
+            FieldValue::String("/api/v1".to_string()),
+        );
+        event.set_field("status".to_string(), FieldValue::Number(504.0));
+
+        let formatter = DefaultFormatter::new()
+            .with_key_order(KeyOrder::Input)
+            .with_highlight(Some(Regex::new("api|50").unwrap()));
+        assert_eq!(
+            formatter.format(&event),
+            "message=\"upstream \\\"api\\\" timeout\" path=\"/api/v1\" status=504",
+            "Highlighting only applies to colored output"
+        );
+
+        let formatter = formatter.with_color(true);
+        let output = formatter.format(&event);
+        assert!(output.contains("upstream \\\"\x1b[7mapi\x1b[27m\\\" timeout"));
+        assert!(output.contains("\x1b[32m\"/\x1b[7mapi\x1b[27m/v1\"\x1b[0m"));
+        assert!(output.contains("=\x1b[35m\x1b[7m50\x1b[27m4\x1b[0m"));
+    }
+
+    #[test]
+    fn test_ts_display() {
+        let mut event = Event::new();
+        event.timestamp = Some("2024-03-14T10:00:00.123Z".parse().unwrap());
+        event.message = Some("hi".to_string());
+
+        let displayed = |inner: Box<dyn Formatter>, spec: &str| {
+            TimestampDisplayFormatter::new(inner, TimestampDisplay::parse(spec).unwrap())
+        };
+        assert_eq!(
+            displayed(Box::new(DefaultFormatter::new()), "time-only").format(&event),
+            "timestamp=\"10:00:00.123\" message=\"hi\""
+        );
+        assert_eq!(
+            displayed(Box::new(JsonlFormatter::new()), "unix").format(&event),
+            r#"{"message":"hi","timestamp":1710410400.123}"#
+        );
+        let template = || Box::new(TemplateFormatter::new("{ts} {msg}").unwrap());
+        assert_eq!(
+            displayed(template(), "%d/%b/%Y").format(&event),
+            "14/Mar/2024 hi"
+        );
+        assert_eq!(
+            displayed(Box::new(CsvFormatter::new(None)), "date-time").format(&event),
+            "timestamp,message\n2024-03-14 10:00:00,hi"
+        );
+        // Also when the raw field is kept
+        event.set_field(
+            "ts".to_string(),
+            FieldValue::String("2024-03-14T10:00:00.123Z".to_string()),
+        );
+        assert_eq!(
+            displayed(template(), "time-only").format(&event),
+            "10:00:00.123 hi"
+        );
+
+        assert!(TimestampDisplay::parse("%Q").is_err());
+        assert!(TimestampDisplay::parse("").is_err());
+    }
+
+    #[test]
+    fn test_json_key_order() {
+        let mut event = Event::new();
+        event.level = Some("info".to_string());
+        event.message = Some("served".to_string());
+        event.set_field("zone".to_string(), FieldValue::String("eu".to_string()));
+        event.set_field("host".to_string(), FieldValue::String("web1".to_string()));
+
+        assert_eq!(
+            JsonlFormatter::new().format(&event),
+            r#"{"host":"web1","level":"info","message":"served","zone":"eu"}"#
+        );
+        assert_eq!(
+            JsonlFormatter::new()
+                .with_key_order(KeyOrder::Input)
+                .format(&event),
+            r#"{"level":"info","message":"served","zone":"eu","host":"web1"}"#
+        );
+        assert_eq!(
+            JsonlFormatter::new()
+                .with_key_order(KeyOrder::KeysAsGiven(vec![
+                    "host".to_string(),
+                    "msg".to_string()
+                ]))
+                .format(&event),
+            r#"{"host":"web1","message":"served","level":"info","zone":"eu"}"#
+        );
+    }
 }
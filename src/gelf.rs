@@ -0,0 +1,143 @@
+use crate::event::Event;
+use crate::formatters::{Formatter, GelfFormatter};
+use crate::sinks::Sink;
+use anyhow::{bail, Context, Result};
+use std::net::{ToSocketAddrs, UdpSocket};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// GELF over UDP, chunking messages that don't fit into a single datagram
+pub struct GelfUdpSink {
+    socket: UdpSocket,
+    formatter: GelfFormatter,
+    messages_sent: u64,
+}
+
+/// Largest datagram sent, including the chunk header (Graylog's default receive buffer limit)
+const GELF_MAX_DATAGRAM: usize = 8192;
+
+/// Chunk header: magic bytes, 8-byte message id, sequence number, sequence count
+const GELF_CHUNK_HEADER_LEN: usize = 12;
+
+/// Graylog drops messages split into more chunks than this
+const GELF_MAX_CHUNKS: usize = 128;
+
+impl GelfUdpSink {
+    /// Send to a Graylog GELF UDP input given as `host:port`
+    pub fn connect(address: &str, formatter: GelfFormatter) -> Result<Self> {
+        let target = address
+            .to_socket_addrs()
+            .with_context(|| format!("Invalid GELF UDP address: {}", address))?
+            .next()
+            .with_context(|| format!("GELF UDP address did not resolve: {}", address))?;
+        let local = if target.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+        let socket = UdpSocket::bind(local).context("Failed to open UDP socket for GELF")?;
+        socket
+            .connect(target)
+            .with_context(|| format!("Failed to connect to GELF UDP input: {}", address))?;
+        Ok(Self {
+            socket,
+            formatter,
+            messages_sent: 0,
+        })
+    }
+
+    /// Unique-enough id shared by all chunks of one message
+    fn next_message_id(&mut self) -> [u8; 8] {
+        self.messages_sent += 1;
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        (nanos ^ self.messages_sent.rotate_left(32)).to_be_bytes()
+    }
+}
+
+impl Sink for GelfUdpSink {
+    fn write(&mut self, event: &Event) -> Result<()> {
+        let payload = self.formatter.format(event).into_bytes();
+        let message_id = self.next_message_id();
+        for datagram in chunk_message(&payload, message_id)? {
+            self.socket
+                .send(&datagram)
+                .context("Failed to send GELF message")?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Split a GELF payload into datagrams, wrapping it in chunk headers when it is too large
+fn chunk_message(payload: &[u8], message_id: [u8; 8]) -> Result<Vec<Vec<u8>>> {
+    if payload.len() <= GELF_MAX_DATAGRAM {
+        return Ok(vec![payload.to_vec()]);
+    }
+
+    let chunk_size = GELF_MAX_DATAGRAM - GELF_CHUNK_HEADER_LEN;
+    let chunks: Vec<&[u8]> = payload.chunks(chunk_size).collect();
+    if chunks.len() > GELF_MAX_CHUNKS {
+        bail!(
+            "GELF message of {} bytes exceeds the maximum of {} chunks",
+            payload.len(),
+            GELF_MAX_CHUNKS
+        );
+    }
+
+    let count = chunks.len() as u8;
+    Ok(chunks
+        .into_iter()
+        .enumerate()
+        .map(|(sequence, chunk)| {
+            let mut datagram = Vec::with_capacity(GELF_CHUNK_HEADER_LEN + chunk.len());
+            datagram.extend_from_slice(&[0x1e, 0x0f]);
+            datagram.extend_from_slice(&message_id);
+            datagram.push(sequence as u8);
+            datagram.push(count);
+            datagram.extend_from_slice(chunk);
+            datagram
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_small_message_is_not_chunked() {
+        let datagrams = chunk_message(b"{}", [0; 8]).unwrap();
+        assert_eq!(datagrams, vec![b"{}".to_vec()]);
+    }
+
+    #[test]
+    fn test_large_message_is_chunked() {
+        let payload = vec![b'x'; GELF_MAX_DATAGRAM * 2];
+        let datagrams = chunk_message(&payload, [7; 8]).unwrap();
+
+        assert_eq!(datagrams.len(), 3);
+        for (sequence, datagram) in datagrams.iter().enumerate() {
+            assert!(datagram.len() <= GELF_MAX_DATAGRAM);
+            assert_eq!(&datagram[..2], &[0x1e, 0x0f]);
+            assert_eq!(&datagram[2..10], &[7; 8]);
+            assert_eq!(datagram[10], sequence as u8);
+            assert_eq!(datagram[11], 3);
+        }
+        let reassembled: Vec<u8> = datagrams
+            .iter()
+            .flat_map(|d| d[GELF_CHUNK_HEADER_LEN..].to_vec())
+            .collect();
+        assert_eq!(reassembled, payload);
+    }
+
+    #[test]
+    fn test_oversized_message_is_rejected() {
+        let payload = vec![b'x'; GELF_MAX_DATAGRAM * (GELF_MAX_CHUNKS + 1)];
+        assert!(chunk_message(&payload, [0; 8]).is_err());
+    }
+}
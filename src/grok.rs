@@ -0,0 +1,174 @@
+/// Bundled grok patterns, in the regex crate's syntax (no lookarounds). Definitions may refer
+/// to each other with `%{NAME}`
+const PATTERNS: &[(&str, &str)] = &[
+    ("USERNAME", r"[a-zA-Z0-9._-]+"),
+    ("USER", r"%{USERNAME}"),
+    ("INT", r"[+-]?[0-9]+"),
+    ("BASE10NUM", r"[+-]?(?:[0-9]+(?:\.[0-9]+)?|\.[0-9]+)"),
+    ("NUMBER", r"%{BASE10NUM}"),
+    ("BASE16NUM", r"(?:0[xX])?[0-9A-Fa-f]+"),
+    ("POSINT", r"\b[1-9][0-9]*\b"),
+    ("NONNEGINT", r"\b[0-9]+\b"),
+    ("WORD", r"\b\w+\b"),
+    ("NOTSPACE", r"\S+"),
+    ("SPACE", r"\s*"),
+    ("DATA", r".*?"),
+    ("GREEDYDATA", r".*"),
+    ("QUOTEDSTRING", r#""(?:[^"\\]|\\.)*"|'(?:[^'\\]|\\.)*'"#),
+    ("QS", r"%{QUOTEDSTRING}"),
+    (
+        "UUID",
+        r"[A-Fa-f0-9]{8}-(?:[A-Fa-f0-9]{4}-){3}[A-Fa-f0-9]{12}",
+    ),
+    ("MAC", r"(?:[A-Fa-f0-9]{2}[:-]){5}[A-Fa-f0-9]{2}"),
+    (
+        "IPV4",
+        r"(?:25[0-5]|2[0-4][0-9]|1[0-9]{2}|[1-9]?[0-9])(?:\.(?:25[0-5]|2[0-4][0-9]|1[0-9]{2}|[1-9]?[0-9])){3}",
+    ),
+    (
+        "IPV6",
+        r"(?:[0-9A-Fa-f]{1,4})?(?::[0-9A-Fa-f]{0,4}){2,7}(?::%{IPV4})?",
+    ),
+    ("IP", r"%{IPV4}|%{IPV6}"),
+    (
+        "HOSTNAME",
+        r"\b[0-9A-Za-z][0-9A-Za-z-]{0,62}(?:\.[0-9A-Za-z][0-9A-Za-z-]{0,62})*\.?",
+    ),
+    ("IPORHOST", r"%{IP}|%{HOSTNAME}"),
+    ("HOSTPORT", r"%{IPORHOST}:%{POSINT}"),
+    (
+        "EMAILADDRESS",
+        r"[a-zA-Z0-9!#$%&'*+/=?^_`{|}~-]+(?:\.[a-zA-Z0-9!#$%&'*+/=?^_`{|}~-]+)*@%{HOSTNAME}",
+    ),
+    ("UNIXPATH", r"(?:/[^/\s]*)+"),
+    ("PATH", r"%{UNIXPATH}"),
+    ("URIPROTO", r"[A-Za-z][A-Za-z0-9+.-]+"),
+    ("URIPATH", r"(?:/[A-Za-z0-9$.+!*'(){},~:;=@#%&_\-]*)+"),
+    ("URIPARAM", r"\?[A-Za-z0-9$.+!*'|(){},~@#%&/=:;_?\-\[\]<>]*"),
+    ("URIPATHPARAM", r"%{URIPATH}(?:%{URIPARAM})?"),
+    ("URIHOST", r"%{IPORHOST}(?::%{POSINT})?"),
+    (
+        "URI",
+        r"%{URIPROTO}://(?:%{USER}(?::[^@]*)?@)?(?:%{URIHOST})?(?:%{URIPATHPARAM})?",
+    ),
+    (
+        "MONTH",
+        r"\b(?:Jan(?:uary)?|Feb(?:ruary)?|Mar(?:ch)?|Apr(?:il)?|May|June?|July?|Aug(?:ust)?|Sep(?:tember)?|Oct(?:ober)?|Nov(?:ember)?|Dec(?:ember)?)\b",
+    ),
+    ("MONTHNUM", r"0?[1-9]|1[0-2]"),
+    ("MONTHDAY", r"0[1-9]|[12][0-9]|3[01]|[1-9]"),
+    (
+        "DAY",
+        r"Mon(?:day)?|Tue(?:sday)?|Wed(?:nesday)?|Thu(?:rsday)?|Fri(?:day)?|Sat(?:urday)?|Sun(?:day)?",
+    ),
+    ("YEAR", r"(?:\d\d){1,2}"),
+    ("HOUR", r"2[0123]|[01]?[0-9]"),
+    ("MINUTE", r"[0-5][0-9]"),
+    ("SECOND", r"(?:[0-5]?[0-9]|60)(?:[:.,][0-9]+)?"),
+    ("TIME", r"%{HOUR}:%{MINUTE}(?::%{SECOND})?"),
+    ("ISO8601_TIMEZONE", r"Z|[+-]%{HOUR}(?::?%{MINUTE})"),
+    (
+        "TIMESTAMP_ISO8601",
+        r"%{YEAR}-%{MONTHNUM}-%{MONTHDAY}[T ]%{HOUR}:?%{MINUTE}(?::?%{SECOND})?%{ISO8601_TIMEZONE}?",
+    ),
+    ("HTTPDATE", r"%{MONTHDAY}/%{MONTH}/%{YEAR}:%{TIME} %{INT}"),
+    ("SYSLOGTIMESTAMP", r"%{MONTH} +%{MONTHDAY} %{TIME}"),
+    (
+        "LOGLEVEL",
+        r"(?i:alert|trace|debug|notice|info(?:rmation)?|warn(?:ing)?|err(?:or)?|crit(?:ical)?|fatal|severe|emerg(?:ency)?)",
+    ),
+    (
+        "COMMONAPACHELOG",
+        r#"%{IPORHOST:clientip} %{USER:ident} %{USER:auth} \[%{HTTPDATE:timestamp}\] "(?:%{WORD:verb} %{NOTSPACE:request}(?: HTTP/%{NUMBER:httpversion})?|%{DATA:rawrequest})" %{NUMBER:response} (?:%{NUMBER:bytes}|-)"#,
+    ),
+    (
+        "COMBINEDAPACHELOG",
+        r"%{COMMONAPACHELOG} %{QS:referrer} %{QS:agent}",
+    ),
+];
+
+/// Patterns may refer to each other, but not endlessly
+const MAX_DEPTH: usize = 16;
+
+/// Translate a grok pattern such as `%{IP:client} %{WORD:method}` into a regex, with a named
+/// group for each `%{PATTERN:field}`. A `:int` or `:float` suffix on the field is accepted for
+/// compatibility; extracted numbers are typed either way
+pub fn compile(pattern: &str) -> Result<String, String> {
+    expand(pattern, 0)
+}
+
+fn expand(pattern: &str, depth: usize) -> Result<String, String> {
+    if depth > MAX_DEPTH {
+        return Err("grok patterns nest too deeply".to_string());
+    }
+    let mut regex = String::new();
+    let mut rest = pattern;
+    while let Some(start) = rest.find("%{") {
+        regex.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| format!("unclosed '%{{' in '{}'", pattern))?;
+        let reference = &rest[start + 2..start + end];
+        rest = &rest[start + end + 1..];
+
+        let mut parts = reference.splitn(3, ':');
+        let name = parts.next().unwrap_or_default();
+        let field = parts.next().filter(|field| !field.is_empty());
+        match parts.next() {
+            None | Some("int") | Some("float") => {}
+            Some(kind) => return Err(format!("unknown type '{}' in %{{{}}}", kind, reference)),
+        }
+
+        let definition = PATTERNS
+            .iter()
+            .find(|(known, _)| *known == name)
+            .map(|(_, definition)| *definition)
+            .ok_or_else(|| format!("unknown grok pattern '{}'", name))?;
+        let definition = expand(definition, depth + 1)?;
+        match field {
+            Some(field) => {
+                if !field
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+                {
+                    return Err(format!("invalid field name '{}'", field));
+                }
+                regex.push_str(&format!("(?P<{}>{})", field, definition));
+            }
+            None => regex.push_str(&format!("(?:{})", definition)),
+        }
+    }
+    regex.push_str(rest);
+    Ok(regex)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex::Regex;
+
+    #[test]
+    fn test_compile() {
+        let regex =
+            Regex::new(&compile("%{IP:client} %{WORD:method} %{URIPATHPARAM:path}").unwrap())
+                .unwrap();
+        let captures = regex.captures("10.1.2.3 GET /api/users?id=7").unwrap();
+        assert_eq!(&captures["client"], "10.1.2.3");
+        assert_eq!(&captures["method"], "GET");
+        assert_eq!(&captures["path"], "/api/users?id=7");
+
+        let regex = Regex::new(&compile("%{COMBINEDAPACHELOG}").unwrap()).unwrap();
+        let captures = regex
+            .captures(r#"203.0.113.9 - frank [14/Mar/2024:10:00:00 +0000] "GET /index.html HTTP/1.1" 200 2326 "-" "curl/8.0""#)
+            .unwrap();
+        assert_eq!(&captures["auth"], "frank");
+        assert_eq!(&captures["timestamp"], "14/Mar/2024:10:00:00 +0000");
+        assert_eq!(&captures["response"], "200");
+        assert_eq!(&captures["agent"], "\"curl/8.0\"");
+
+        assert!(compile("%{NUMBER:latency:float}ms").is_ok());
+        assert!(compile("%{NOPE:x}").is_err());
+        assert!(compile("%{INT:x:date}").is_err());
+        assert!(compile("%{INT:x").is_err());
+    }
+}
@@ -0,0 +1,151 @@
+use crate::event::Event;
+use crate::formatters::event_to_json;
+use crate::parsers::event_from_json;
+use jaq_core::load::{lex, parse, Arena, File, Loader};
+use jaq_core::{Compiler, Ctx, Native, RcIter};
+use jaq_json::Val;
+
+/// A compiled jq program, run against the JSON representation of each event
+///
+/// Every result of the program becomes an output event: objects replace the event,
+/// `true` keeps it unchanged, `false`/`null` drop it (so `select(...)` and plain
+/// conditions both filter), and any other value is kept as a single `value` field.
+pub struct JqProgram {
+    filter: jaq_core::Filter<Native<Val>>,
+}
+
+impl JqProgram {
+    pub fn compile(code: &str) -> Result<Self, String> {
+        let loader = Loader::new(jaq_std::defs().chain(jaq_json::defs()));
+        let arena = Arena::default();
+        let program = File { code, path: () };
+
+        let modules = loader.load(&arena, program).map_err(|errors| {
+            errors
+                .into_iter()
+                .flat_map(|(_, error)| describe_load_error(error))
+                .collect::<Vec<_>>()
+                .join("; ")
+        })?;
+
+        let filter = Compiler::default()
+            .with_funs(jaq_std::funs().chain(jaq_json::funs()))
+            .compile(modules)
+            .map_err(|errors| {
+                errors
+                    .into_iter()
+                    .flat_map(|(_, undefined)| undefined)
+                    .map(|(name, kind)| format!("undefined {} '{}'", kind.as_str(), name))
+                    .collect::<Vec<_>>()
+                    .join("; ")
+            })?;
+
+        Ok(Self { filter })
+    }
+
+    /// Run the program on one event, returning the events it produces
+    pub fn apply(&self, event: &Event) -> Result<Vec<Event>, String> {
+        let inputs = RcIter::new(core::iter::empty());
+        let input = Val::from(event_to_json(event));
+
+        let mut events = Vec::new();
+        for result in self.filter.run((Ctx::new([], &inputs), input)) {
+            let value = serde_json::Value::from(result.map_err(|e| e.to_string())?);
+            match value {
+                serde_json::Value::Object(map) => events.push(event_from_json(map)),
+                serde_json::Value::Bool(true) => events.push(event.clone()),
+                serde_json::Value::Bool(false) | serde_json::Value::Null => {}
+                other => {
+                    let mut map = serde_json::Map::new();
+                    map.insert("value".to_string(), other);
+                    events.push(event_from_json(map));
+                }
+            }
+        }
+        Ok(events)
+    }
+}
+
+fn describe_load_error(error: jaq_core::load::Error<&str>) -> Vec<String> {
+    // Show a little of the program where it went wrong
+    let near = |found: &str| found.chars().take(16).collect::<String>();
+    let describe = |expected: &str, found: &str| {
+        if found.is_empty() {
+            format!("expected {} at end of program", expected)
+        } else {
+            format!("expected {} near '{}'", expected, near(found))
+        }
+    };
+
+    match error {
+        jaq_core::load::Error::Io(errors) => errors
+            .into_iter()
+            .map(|(path, message)| format!("{}: {}", path, message))
+            .collect(),
+        jaq_core::load::Error::Lex(errors) => errors
+            .into_iter()
+            .map(|(expected, found): lex::Error<&str>| describe(expected.as_str(), found))
+            .collect(),
+        jaq_core::load::Error::Parse(errors) => errors
+            .into_iter()
+            .map(|(expected, found): parse::Error<&str>| describe(expected.as_str(), found))
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::FieldValue;
+
+    fn event() -> Event {
+        let mut event = Event::new();
+        event.level = Some("ERROR".to_string());
+        event.message = Some("upstream timeout".to_string());
+        event.set_field("status".to_string(), FieldValue::Number(503.0));
+        event.set_field("user".to_string(), FieldValue::String("alice".to_string()));
+        event
+    }
+
+    fn apply(code: &str) -> Vec<Event> {
+        JqProgram::compile(code).unwrap().apply(&event()).unwrap()
+    }
+
+    #[test]
+    fn test_filtering() {
+        assert_eq!(apply("select(.status >= 500)").len(), 1);
+        assert_eq!(apply("select(.status < 500)").len(), 0);
+        assert_eq!(apply(".level == \"ERROR\"").len(), 1);
+        assert_eq!(apply(".missing").len(), 0);
+    }
+
+    #[test]
+    fn test_reshaping() {
+        let events = apply("{msg: .message, code: .status, who: (.user | ascii_upcase)}");
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].message.as_deref(), Some("upstream timeout"));
+        assert_eq!(
+            events[0].fields.get("code"),
+            Some(&FieldValue::Number(503.0))
+        );
+        assert_eq!(
+            events[0].fields.get("who"),
+            Some(&FieldValue::String("ALICE".to_string()))
+        );
+
+        let events = apply(".user, .status");
+        assert_eq!(events.len(), 2);
+        assert_eq!(
+            events[0].fields.get("value"),
+            Some(&FieldValue::String("alice".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(JqProgram::compile(".status >").is_err());
+        assert!(JqProgram::compile("frobnicate").is_err());
+        let program = JqProgram::compile(".user + 1").unwrap();
+        assert!(program.apply(&event()).is_err());
+    }
+}
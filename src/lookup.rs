@@ -0,0 +1,207 @@
+use crate::event::{Event, FieldValue};
+use crate::parsers::{event_from_json, parse_field_value};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Joins a local mapping file onto events (`--lookup FIELD:FILE[:COLUMN]`): the row whose
+/// COLUMN (by default also named FIELD) equals the event's FIELD adds its other columns to the
+/// event. CSV files need a header row; JSON files hold an array of objects or one object per
+/// line
+#[derive(Debug)]
+pub struct LookupTable {
+    field: String,
+    rows: HashMap<String, Vec<(String, FieldValue)>>,
+}
+
+impl LookupTable {
+    /// Load the table for a `FIELD:FILE[:COLUMN]` spec
+    pub fn load(spec: &str) -> Result<Self, String> {
+        let (field, rest) = spec
+            .split_once(':')
+            .ok_or_else(|| format!("expected FIELD:FILE[:COLUMN], got '{}'", spec))?;
+        let (path, column) = match rest.rsplit_once(':') {
+            Some((path, column)) if !column.contains(['/', '\\', '.']) => (path, column),
+            _ => (rest, field),
+        };
+        if field.is_empty() || path.is_empty() || column.is_empty() {
+            return Err(format!("expected FIELD:FILE[:COLUMN], got '{}'", spec));
+        }
+
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        let is_json = Path::new(path)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| matches!(ext, "json" | "jsonl" | "ndjson"));
+        let records = if is_json {
+            json_records(&text)
+        } else {
+            csv_records(&text, column)
+        }
+        .map_err(|e| format!("{}: {}", path, e))?;
+        let keyed =
+            |record: &Vec<(String, FieldValue)>| record.iter().any(|(key, _)| key == column);
+        if !records.is_empty() && !records.iter().any(keyed) {
+            return Err(format!("{}: no record has the key '{}'", path, column));
+        }
+
+        let mut rows = HashMap::new();
+        for mut record in records {
+            let Some(position) = record.iter().position(|(key, _)| key == column) else {
+                continue;
+            };
+            let (_, key) = record.remove(position);
+            rows.entry(key.to_string()).or_insert(record);
+        }
+        Ok(Self {
+            field: field.to_string(),
+            rows,
+        })
+    }
+
+    pub fn apply(&self, event: &mut Event) {
+        let Some(value) = event.get_value(&self.field) else {
+            return;
+        };
+        if let Some(row) = self.rows.get(&value.to_string()) {
+            for (key, value) in row {
+                event.set_field(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
+fn json_records(text: &str) -> Result<Vec<Vec<(String, FieldValue)>>, String> {
+    let objects: Vec<serde_json::Map<String, serde_json::Value>> =
+        if text.trim_start().starts_with('[') {
+            serde_json::from_str(text).map_err(|e| e.to_string())?
+        } else {
+            text.lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(serde_json::from_str)
+                .collect::<Result<_, _>>()
+                .map_err(|e| e.to_string())?
+        };
+    Ok(objects
+        .into_iter()
+        .map(|object| event_from_json(object).fields.into_iter().collect())
+        .collect())
+}
+
+fn csv_records(text: &str, column: &str) -> Result<Vec<Vec<(String, FieldValue)>>, String> {
+    let mut lines = text.lines().filter(|line| !line.trim().is_empty());
+    let header = split_csv_line(lines.next().ok_or("empty file, expected a header row")?);
+    if !header.iter().any(|key| key == column) {
+        return Err(format!(
+            "no '{}' column in the header ({})",
+            column,
+            header.join(", ")
+        ));
+    }
+    Ok(lines
+        .map(|line| {
+            header
+                .iter()
+                .cloned()
+                .zip(split_csv_line(line))
+                .map(|(key, value)| (key, parse_field_value(&value)))
+                .collect()
+        })
+        .collect())
+}
+
+/// Split a CSV line into its fields, unquoting `"a, ""b"""`-style quoted ones
+fn split_csv_line(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field).trim().to_string()),
+            c => field.push(c),
+        }
+    }
+    fields.push(field.trim().to_string());
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn file(suffix: &str, content: &str) -> tempfile::NamedTempFile {
+        let mut file = tempfile::Builder::new().suffix(suffix).tempfile().unwrap();
+        file.write_all(content.as_bytes()).unwrap();
+        file
+    }
+
+    fn event(key: &str, value: FieldValue) -> Event {
+        let mut event = Event::new();
+        event.set_field(key.to_string(), value);
+        event
+    }
+
+    #[test]
+    fn test_csv_lookup() {
+        let inventory = file(
+            ".csv",
+            "hostname,owner,rack,service\nweb01,alice,\"r1, row 2\",shop\ndb01,bob,r7,\"the \"\"db\"\"\"\n",
+        );
+        let table =
+            LookupTable::load(&format!("host:{}:hostname", inventory.path().display())).unwrap();
+
+        let mut web = event("host", FieldValue::String("web01".to_string()));
+        table.apply(&mut web);
+        let keys: Vec<&String> = web.fields.keys().collect();
+        assert_eq!(keys, ["host", "owner", "rack", "service"]);
+        assert_eq!(
+            web.fields["rack"],
+            FieldValue::String("r1, row 2".to_string())
+        );
+
+        let mut db = event("host", FieldValue::String("db01".to_string()));
+        table.apply(&mut db);
+        assert_eq!(
+            db.fields["service"],
+            FieldValue::String("the \"db\"".to_string())
+        );
+
+        let mut unknown = event("host", FieldValue::String("cache01".to_string()));
+        table.apply(&mut unknown);
+        assert_eq!(unknown.fields.len(), 1);
+
+        // The key column has to be there
+        let error = LookupTable::load(&format!("host:{}", inventory.path().display())).unwrap_err();
+        assert!(
+            error.ends_with("no 'host' column in the header (hostname, owner, rack, service)"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn test_json_lookup() {
+        let teams = file(
+            ".json",
+            r#"[{"team_id":7,"team":"payments","oncall":"carol"}]"#,
+        );
+        let table = LookupTable::load(&format!("team_id:{}", teams.path().display())).unwrap();
+        let mut event = event("team_id", FieldValue::Number(7.0));
+        table.apply(&mut event);
+        assert_eq!(
+            event.fields["team"],
+            FieldValue::String("payments".to_string())
+        );
+
+        let error = LookupTable::load(&format!("team:{}:id", teams.path().display())).unwrap_err();
+        assert!(error.ends_with("no record has the key 'id'"), "{}", error);
+        assert!(LookupTable::load("team_id").is_err());
+        assert!(LookupTable::load("team_id:/nonexistent.csv").is_err());
+    }
+}
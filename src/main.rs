@@ -1,24 +1,93 @@
 use anyhow::{Context, Result};
 use chrono::{DateTime, Utc};
-use clap::Parser;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser};
+use regex::Regex;
 use std::collections::HashMap;
 use std::fs::File;
-use std::io::{self, BufRead, BufReader, Write};
+use std::io::{self, BufRead, BufReader, IsTerminal, Write};
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
 
+mod aggregate;
+mod coerce;
+mod config;
+mod decode;
+mod dedup;
+mod drain;
+mod elasticsearch;
+mod enrich;
 mod event;
+mod expr;
+mod extract;
+mod filters;
 mod formatters;
+mod gelf;
+mod grok;
+mod jq;
+mod lookup;
+mod metrics;
+mod normalize;
+mod otlp;
+mod output;
 mod parsers;
+mod query;
+mod redact;
+mod sinks;
+mod throttle;
+mod timespec;
+mod timing;
 
-use formatters::{DefaultFormatter, Formatter, JsonlFormatter};
-use parsers::{JsonlParser, LogParser, LogfmtParser, SyslogParser};
+use aggregate::{
+    format_number, Activity, Cardinality, CountBy, DurationStats, EventTimeline, Histogram,
+    LineLengths, NumericAggregates, Schema, TopValues,
+};
+use coerce::TypeCoercion;
+use config::Config;
+use decode::FieldDecoder;
+use dedup::{Deduplicator, DistinctFilter, DistinctValues, Occurrence};
+use drain::{
+    Cluster, Drain, DrainConfig, DrainParams, DrainTimeline, HeldEvents, TemplateDiff,
+    TemplateFilter, TEMPLATE_KEY,
+};
+use elasticsearch::EsBulkSink;
+use enrich::{GeoIp, ReverseDns, UserAgentParser};
+use event::{is_key_pattern, level_rank, EpochUnit, Event, FieldValue, TimestampOptions};
+use expr::{Derivation, Expr};
+use extract::{Extractor, KeyValueScraper};
+use filters::{FieldCondition, GrepFilter, LevelFilter, SourceFilter};
+use formatters::{
+    CsvFormatter, DefaultFormatter, EsBulkFormatter, FieldWidths, Formatter, GelfFormatter,
+    JsonPrettyFormatter, JsonlFormatter, KeyOrder, SyslogFormatter, TableFormatter,
+    TemplateFormatter, TimestampDisplay, TimestampDisplayFormatter, TruncatingFormatter,
+    YamlFormatter,
+};
+use gelf::GelfUdpSink;
+use jq::JqProgram;
+use lookup::LookupTable;
+use metrics::MetricsServer;
+use normalize::{normalize_units, KeyCase, KeyNormalizer};
+use otlp::OtlpSink;
+use output::{parse_size, Compression, OutputWriter, RotationPolicy};
+use parsers::{FixedWidthParser, JsonlParser, LogParser, LogfmtParser, SyslogParser, XmlParser};
+use query::QuerySink;
+use redact::{FieldDropper, FieldHasher, Redactor};
+use sinks::{ArrowSink, Sink, SqliteSink};
+use throttle::Throttle;
+use timespec::{format_duration, parse_time_expr};
+use timing::{BurstDetector, GapDetector, InterArrival, Latency, Pairs, Sessionizer};
 
 #[derive(Parser)]
-#[command(name = "kelora")]
+#[command(name = "kelora", args_conflicts_with_subcommands = true)]
 #[command(about = "A fast, extensible log parser")]
 #[command(version = "0.1.1")]
 #[command(author = "Dirk Loss <mail@dirk-loss.de>")]
 pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     /// Input files (stdin if not specified)
     pub files: Vec<PathBuf>,
 
@@ -26,6 +95,23 @@ pub struct Cli {
     #[arg(short = 'f', long = "format", value_enum, default_value = "logfmt")]
     pub input_format: InputFormat,
 
+    /// Column widths for fixed-width input (comma-separated, 0 = rest of line)
+    #[arg(long = "widths", value_delimiter = ',')]
+    pub widths: Vec<usize>,
+
+    /// Column names for fixed-width input (comma-separated)
+    #[arg(long = "columns", value_delimiter = ',')]
+    pub columns: Vec<String>,
+
+    /// Timestamp layout of the input as a strftime pattern, e.g. '%d/%b/%Y:%H:%M:%S %z', tried
+    /// before the built-in formats
+    #[arg(long = "ts-format")]
+    pub ts_format: Option<String>,
+
+    /// Unit of numeric epoch timestamps, instead of guessing it from their magnitude
+    #[arg(long = "epoch-unit", value_enum)]
+    pub epoch_unit: Option<EpochUnitArg>,
+
     /// Output format  
     #[arg(
         short = 'F',
@@ -35,14 +121,674 @@ pub struct Cli {
     )]
     pub output_format: OutputFormat,
 
-    /// Only show specific keys (comma-separated)
+    /// Write output to a file instead of stdout (.gz/.zst names are compressed)
+    #[arg(short = 'o', long = "output")]
+    pub output: Option<PathBuf>,
+
+    /// Compress output, overriding the file extension
+    #[arg(long = "output-compress", value_enum)]
+    pub output_compress: Option<OutputCompression>,
+
+    /// Start a new numbered output file once this much output went into the current one,
+    /// counted before compression, e.g. 100MB; KB, MB, GB (or K, M, G) are decimal and KiB,
+    /// MiB, GiB binary
+    #[arg(long = "rotate-size")]
+    pub rotate_size: Option<String>,
+
+    /// Start a new output file every day (UTC), named after the date
+    #[arg(long = "rotate-daily")]
+    pub rotate_daily: bool,
+
+    /// Order of non-core fields in default output, and of all keys in JSON output
+    #[arg(long = "order", value_enum, default_value = "alpha")]
+    pub order: KeyOrderMode,
+
+    /// Keys to pin to the front of default output (comma-separated)
+    #[arg(long = "first-keys", value_delimiter = ',')]
+    pub first_keys: Vec<String>,
+
+    /// Output template for the template format, e.g. '{timestamp} [{level}] {message}'
+    #[arg(long = "template")]
+    pub template: Option<String>,
+
+    /// Colorize output (respects NO_COLOR in auto mode)
+    #[arg(long = "color", value_enum, default_value = "auto")]
+    pub color: ColorMode,
+
+    /// Omit fields that are null or empty strings (default and jsonl output)
+    #[arg(long = "skip-empty")]
+    pub skip_empty: bool,
+
+    /// Truncate long field values to N characters; NAME=N sets a per-field limit (comma-separated)
+    #[arg(long = "max-field-width", value_delimiter = ',')]
+    pub max_field_width: Vec<String>,
+
+    /// Disable field value truncation
+    #[arg(long = "no-truncate")]
+    pub no_truncate: bool,
+
+    /// Highlight matches of a regex in colored default output; by default those of --grep,
+    /// --grep-field and the =~ matches of --filter
+    #[arg(long = "highlight")]
+    pub highlight: Option<String>,
+
+    /// Wrap json-pretty output in a top-level JSON array; other formats reject it
+    #[arg(long = "json-array")]
+    pub json_array: bool,
+
+    /// Only show specific keys (comma-separated; wildcards like 'http.*' or '*_id' allowed)
     #[arg(short = 'k', long = "keys", value_delimiter = ',')]
     pub keys: Vec<String>,
 
-    /// Filter by log levels (comma-separated)
+    /// Hide specific keys, showing everything else (comma-separated; wildcards allowed)
+    #[arg(short = 'K', long = "exclude-keys", value_delimiter = ',')]
+    pub exclude_keys: Vec<String>,
+
+    /// Remove these fields from the output while keeping the events, e.g.
+    /// 'kubernetes.annotations.*' (comma-separated; wildcards allowed)
+    #[arg(long = "drop-field", value_delimiter = ',')]
+    pub drop_field: Vec<String>,
+
+    /// Remove fields whose value matches a regex from the output: KEY~=REGEX, e.g.
+    /// 'message~=health check' (wildcards allowed in KEY; repeatable)
+    #[arg(long = "drop-field-if")]
+    pub drop_field_if: Vec<String>,
+
+    /// Replace the values of these keys with [REDACTED] in the output, e.g.
+    /// password,token,authorization (comma-separated; case-insensitive, wildcards allowed)
+    #[arg(long = "redact", value_delimiter = ',')]
+    pub redact: Vec<String>,
+
+    /// Replace text matching a regex with [REDACTED] in the message and all string fields
+    /// (repeatable)
+    #[arg(long = "redact-pattern")]
+    pub redact_patterns: Vec<String>,
+
+    /// Replace the values of these keys with salted hashes in the output, keeping them
+    /// joinable: KEYS[:ALGORITHM[:SALT]], e.g. user_id,email:sha256:s3cret (sha256 or sha512;
+    /// repeatable)
+    #[arg(long = "hash")]
+    pub hash: Vec<String>,
+
+    /// Collapse runs of identical consecutive events (ignoring timestamps) into one,
+    /// annotated with repeat_count and first_seen/last_seen
+    #[arg(long = "dedup")]
+    pub dedup: bool,
+
+    /// Limit output to a rate such as 100/s, 600/min or 5000/h, dropping the excess and
+    /// reporting how much was dropped on stderr
+    #[arg(long = "throttle")]
+    pub throttle: Option<String>,
+
+    /// Deduplicate on these keys only, e.g. message,host (comma-separated; implies --dedup)
+    #[arg(long = "dedup-by", value_delimiter = ',')]
+    pub dedup_by: Vec<String>,
+
+    /// Expand nested JSON objects and arrays in field values into one field per leaf, with
+    /// dotted keys like http.request.method
+    #[arg(long = "flatten")]
+    pub flatten: bool,
+
+    /// Separator for --flatten and --unflatten keys
+    #[arg(long = "flatten-sep", default_value = ".")]
+    pub flatten_sep: String,
+
+    /// Expand fields holding stringified JSON objects into their fields, e.g. data (for
+    /// data.user, data.id, ...); wildcards allowed, '*' detects them in any field
+    /// (comma-separated)
+    #[arg(long = "expand-json", value_delimiter = ',')]
+    pub expand_json: Vec<String>,
+
+    /// Only flatten this many levels of nesting, keeping deeper levels as JSON text
+    #[arg(long = "flatten-depth", requires = "flatten")]
+    pub flatten_depth: Option<usize>,
+
+    /// Timestamp format for output: time-only, date-time, unix, or a strftime pattern such as
+    /// '%H:%M:%S%.3f' (UTC; not used by protocol formats like syslog, gelf or es-bulk)
+    #[arg(long = "ts-display")]
+    pub ts_display: Option<String>,
+
+    /// Add ts_delta, the time since the previous displayed event, e.g. +0.023s
+    #[arg(long = "ts-delta")]
+    pub ts_delta: bool,
+
+    /// Add ts_elapsed, the time since the first displayed event
+    #[arg(long = "ts-elapsed")]
+    pub ts_elapsed: bool,
+
+    /// Nest dotted keys in JSON output, e.g. http.status=200 becomes {"http":{"status":200}}
+    #[arg(long = "unflatten")]
+    pub unflatten: bool,
+
+    /// Rename keys after parsing, e.g. msg=message,ts=timestamp (comma-separated or
+    /// repeatable), so filters and output see the new names
+    #[arg(long = "rename", value_delimiter = ',')]
+    pub rename: Vec<String>,
+
+    /// Decode a base64 or hex field after parsing, optionally parsing the result too:
+    /// FIELD:ENCODING[:FORMAT], e.g. payload:base64 or data:hex:json (repeatable)
+    #[arg(long = "decode")]
+    pub decode: Vec<String>,
+
+    /// Bring keys into one case after parsing, e.g. Level and LEVEL become level, also
+    /// applying the [aliases] from the config file
+    #[arg(long = "normalize-keys", value_enum)]
+    pub normalize_keys: Option<KeyCaseArg>,
+
+    /// Rename a key to its canonical name after parsing, ignoring case, e.g.
+    /// severity=level,lvl=level (comma-separated or repeatable)
+    #[arg(long = "alias", value_delimiter = ',')]
+    pub alias: Vec<String>,
+
+    /// Force fields into types instead of guessing from the text: KEY:TYPE with int, float,
+    /// bool, string or duration (in milliseconds), e.g. status:int,took:duration
+    /// (comma-separated)
+    #[arg(long = "types", value_delimiter = ',')]
+    pub types: Vec<String>,
+
+    /// Add numeric fields for durations and sizes with units: 1.5s or 230ms as <key>_ms,
+    /// 4KiB or 2.3 MB as <key>_bytes. KB, MB, GB are decimal and KiB, MiB, GiB binary, as
+    /// in --rotate-size
+    #[arg(long = "normalize-units")]
+    pub normalize_units: bool,
+
+    /// Turn named regex groups matching the message into fields, e.g.
+    /// 'order (?P<order_id>\d+)' (repeatable)
+    #[arg(long = "extract")]
+    pub extract: Vec<String>,
+
+    /// Turn key=value tokens in the message into fields, for messages that are almost
+    /// logfmt, e.g. syslog payloads like 'login failed user=bob src=10.0.0.1'
+    #[arg(long = "kv-in-message")]
+    pub kv_in_message: bool,
+
+    /// Like --extract, with a grok pattern such as '%{IP:client} %{WORD:method}' (repeatable)
+    #[arg(long = "extract-grok")]
+    pub extract_grok: Vec<String>,
+
+    /// Expand a User-Agent field into ua_browser, ua_version, ua_os and ua_device
+    #[arg(long = "parse-ua", value_name = "FIELD")]
+    pub parse_ua: Option<String>,
+
+    /// Add geo_country, geo_city, geo_asn and geo_as_org for the IP address in this field,
+    /// from the --geoip-db databases
+    #[arg(long = "geoip", value_name = "FIELD", requires = "geoip_db")]
+    pub geoip: Option<String>,
+
+    /// MaxMind database (.mmdb) for --geoip, e.g. GeoLite2-City.mmdb (repeatable, to combine
+    /// City and ASN databases)
+    #[arg(long = "geoip-db", value_name = "PATH", requires = "geoip")]
+    pub geoip_db: Vec<PathBuf>,
+
+    /// Resolve the IP addresses in these fields to host names, stored in <FIELD>_ptr
+    /// (comma-separated)
+    #[arg(long = "rdns", value_name = "FIELDS", value_delimiter = ',')]
+    pub rdns: Vec<String>,
+
+    /// Give up on reverse DNS lookups after this many milliseconds
+    #[arg(long = "rdns-timeout", value_name = "MS", default_value_t = 500)]
+    pub rdns_timeout: u64,
+
+    /// Add the columns of a CSV or JSON file's matching row: FIELD:FILE[:COLUMN], e.g.
+    /// host:inventory.csv:hostname (repeatable)
+    #[arg(long = "lookup")]
+    pub lookup: Vec<String>,
+
+    /// Compute a field from an expression after parsing, e.g. 'duration_s = duration_ms / 1000'
+    /// (same expressions as --filter; repeatable, applied in order)
+    #[arg(long = "derive")]
+    pub derive: Vec<String>,
+
+    /// Only show events from these input files or hosts, e.g. web01.log or 'web*'
+    /// (comma-separated; matches the file path or name, or the hostname/host field)
+    #[arg(long = "source", value_delimiter = ',')]
+    pub source: Vec<String>,
+
+    /// Output only the first event for each distinct value of this key, e.g. message, or for
+    /// each kind of message as --drain would group them with _template
+    #[arg(long = "first-of", conflicts_with = "last_of")]
+    pub first_of: Option<String>,
+
+    /// Output only the last event for each distinct value of this key, or _template (emitted
+    /// at the end of the input)
+    #[arg(long = "last-of")]
+    pub last_of: Option<String>,
+
+    /// Filter by log levels (comma-separated): names, aliases such as warn/warning, syslog
+    /// severities such as 3, or ranges such as 0-3
     #[arg(short = 'l', long = "level", value_delimiter = ',')]
     pub levels: Vec<String>,
 
+    /// Only show events at or above a severity (trace < debug < info < notice < warn < error <
+    /// critical < alert < emergency); syslog numeric severities 0-7 are accepted too
+    #[arg(long = "min-level")]
+    pub min_level: Option<String>,
+
+    /// Only show events matching a field condition: KEY=VALUE, KEY!=VALUE, or a numeric
+    /// comparison like 'latency_ms>500' (>, >=, <, <=) (repeatable)
+    #[arg(long = "where")]
+    pub where_filters: Vec<String>,
+
+    /// Only show events whose message matches a regex (matches are highlighted in colored output)
+    #[arg(short = 'g', long = "grep")]
+    pub grep: Option<String>,
+
+    /// Only show events whose field matches a regex: KEY=REGEX (repeatable)
+    #[arg(long = "grep-field")]
+    pub grep_fields: Vec<String>,
+
+    /// Drop events whose message matches a regex (repeatable)
+    #[arg(long = "grep-v")]
+    pub grep_v: Vec<String>,
+
+    /// Drop events matching a field condition, with the same syntax as --where (repeatable)
+    #[arg(long = "where-not")]
+    pub where_not_filters: Vec<String>,
+
+    /// Start another group of --where, --where-not, --grep, --grep-field, --grep-v and
+    /// --filter conditions; events are shown when all conditions of any group hold
+    #[arg(long = "or", action = clap::ArgAction::Count)]
+    pub or: u8,
+
+    /// Only show events matching an expression, e.g.
+    /// 'level == "ERROR" && (status >= 500 || msg =~ /timeout/)' (repeatable)
+    #[arg(long = "filter")]
+    pub filters: Vec<String>,
+
+    /// Run a jq program over each event's JSON: objects replace the event, true keeps it,
+    /// false/null drop it, e.g. 'select(.status >= 500) | {ts, msg, status}'
+    #[arg(long = "jq")]
+    pub jq: Option<String>,
+
+    /// Apply a named filter set from the config file (comma-separated or repeatable)
+    #[arg(long = "apply", value_delimiter = ',')]
+    pub apply: Vec<String>,
+
+    /// Config file with filter sets [default: $KELORA_CONFIG or ~/.config/kelora/config.toml]
+    #[arg(long = "config")]
+    pub config: Option<PathBuf>,
+
+    /// Only show events at or after this time (e.g. 2024-03-14T10:00:00Z, 15m, "2 hours ago")
+    #[arg(long = "since")]
+    pub since: Option<String>,
+
+    /// Only show events before this time (e.g. now-5m)
+    #[arg(long = "until")]
+    pub until: Option<String>,
+
+    /// Reference time for relative --since/--until expressions [default: current time]
+    #[arg(long = "now")]
+    pub now: Option<String>,
+
+    /// Mine message templates with the Drain algorithm and show them with their counts
+    /// instead of the events
+    #[arg(
+        long = "drain",
+        conflicts_with_all = ["output_sqlite", "export_otlp", "es_url", "gelf_udp"]
+    )]
+    pub drain: bool,
+
+    /// Share of tokens a message must have in common with a template to join it (0-1)
+    #[arg(long = "drain-sim-th", default_value_t = 0.4, requires = "drain")]
+    pub drain_sim_th: f64,
+
+    /// Depth of the Drain parse tree; each level beyond 3 tells messages apart by one more
+    /// leading token
+    #[arg(long = "drain-depth", default_value_t = 4, requires = "drain")]
+    pub drain_depth: usize,
+
+    /// Most children per Drain tree node, beyond which tokens share a wildcard branch
+    #[arg(long = "drain-max-children", default_value_t = 100, requires = "drain")]
+    pub drain_max_children: usize,
+
+    /// Replace text matching a regex with a named placeholder before mining templates:
+    /// REGEX=PLACEHOLDER, e.g. 'order-\d+=<order_id>' (repeatable)
+    #[arg(long = "drain-mask", requires = "drain")]
+    pub drain_mask: Vec<String>,
+
+    /// File with --drain-mask rules, one per line ('#' starts a comment)
+    #[arg(long = "drain-masks", requires = "drain")]
+    pub drain_masks: Option<PathBuf>,
+
+    /// Show only the N most frequent templates
+    #[arg(long = "drain-top", requires = "drain")]
+    pub drain_top: Option<usize>,
+
+    /// Show up to K distinct messages of each template as examples
+    #[arg(long = "drain-examples", default_value_t = 0, requires = "drain")]
+    pub drain_examples: usize,
+
+    /// Show the values that fill each placeholder of the template with this ID (or ID prefix),
+    /// with their counts, instead of the templates
+    #[arg(long = "drain-params", requires = "drain")]
+    pub drain_params: Option<String>,
+
+    /// Show how many events of each template fell into time buckets of this size, e.g. 5m,
+    /// instead of the templates (as CSV with -F csv)
+    #[arg(long = "drain-timeline", requires = "drain")]
+    pub drain_timeline: Option<String>,
+
+    /// Continue mining with the templates saved in this file, and save them there when done,
+    /// so templates build up across runs
+    #[arg(long = "drain-state", requires = "drain")]
+    pub drain_state: Option<PathBuf>,
+
+    /// Output only the events whose template is rare instead of the templates. The events are
+    /// held back in a temporary file until all are mined
+    #[arg(long = "drain-anomalies", requires = "drain")]
+    pub drain_anomalies: bool,
+
+    /// Output the first event of each template as soon as it is seen, instead of the templates
+    /// at the end. With --drain-state, only templates no earlier run has seen
+    #[arg(long = "alert-new-template", requires = "drain")]
+    pub alert_new_template: bool,
+
+    /// Run this shell command for the first event of each new template, with the template in
+    /// KELORA_TEMPLATE_ID and KELORA_TEMPLATE, and the event's message in KELORA_MESSAGE
+    #[arg(long = "alert-command", requires = "drain")]
+    pub alert_command: Option<String>,
+
+    /// Share of all events below which a template is rare, as a percentage or fraction
+    #[arg(
+        long = "rare-below",
+        default_value = "0.1%",
+        requires = "drain_anomalies"
+    )]
+    pub rare_below: String,
+
+    /// Output the events with their template in `template_id` and `template` fields instead
+    /// of the templates, as mined by the time each event arrives
+    #[arg(long = "drain-annotate", requires = "drain")]
+    pub drain_annotate: bool,
+
+    /// Output only the events of the templates with these IDs (or ID prefixes), as shown by
+    /// --drain, instead of the templates, as mined by the time each event arrives
+    /// (comma-separated)
+    #[arg(long = "template-id", value_delimiter = ',', requires = "drain")]
+    pub template_id: Vec<String>,
+
+    /// Output only the events of templates matching this pattern, where '*' stands for any
+    /// text, instead of the templates, as mined by the time each event arrives (repeatable)
+    #[arg(long = "template-match", requires = "drain")]
+    pub template_match: Vec<String>,
+
+    /// Count events per distinct combination of these keys' values and show the counts,
+    /// most frequent first, instead of the events (comma-separated)
+    #[arg(long = "count-by", value_delimiter = ',', conflicts_with = "drain")]
+    pub count_by: Vec<String>,
+
+    /// Show the K most frequent values of each of these fields (comma-separated), with counts
+    /// and percentages, instead of the events, e.g. --top 10 status,path
+    #[arg(
+        long = "top",
+        num_args = 2,
+        value_names = ["K", "FIELDS"],
+        conflicts_with_all = ["drain", "count_by"]
+    )]
+    pub top: Vec<String>,
+
+    /// Estimate the number of distinct values of these fields (comma-separated) and show
+    /// them instead of the events, in fixed memory however many there are (within about 1%)
+    #[arg(
+        long = "cardinality",
+        value_delimiter = ',',
+        conflicts_with_all = ["drain", "count_by", "top"]
+    )]
+    pub cardinality: Vec<String>,
+
+    /// Compute statistics of a numeric field over the shown events and print them with the
+    /// stats: KEY[:STATS], where STATS is a comma-separated list of count, min, max, mean, sum
+    /// and percentiles like p95 or p99.9 (within 1% beyond 1000 values), e.g.
+    /// latency_ms:p50,p95,p99,max. Without STATS count, min, mean, p50, p95, p99 and max.
+    /// Repeatable
+    #[arg(long = "agg", value_name = "KEY[:STATS]")]
+    pub agg: Vec<String>,
+
+    /// Show the distribution of a numeric field as an ASCII histogram instead of the events
+    #[arg(long = "hist", value_name = "KEY", conflicts_with_all = ["drain", "count_by", "top", "cardinality"])]
+    pub hist: Option<String>,
+
+    /// Number of buckets of --hist
+    #[arg(long = "hist-buckets", default_value_t = 20, requires = "hist")]
+    pub hist_buckets: usize,
+
+    /// Space the buckets of --hist on a log scale, for long-tailed values like latencies;
+    /// values of zero or below are left out
+    #[arg(long = "hist-log", requires = "hist")]
+    pub hist_log: bool,
+
+    /// Show how many events fell into time buckets of this size, e.g. 1m or 10s, instead of
+    /// the events (as CSV with -F csv)
+    #[arg(
+        long = "timeline",
+        value_name = "INTERVAL",
+        conflicts_with_all = ["drain", "count_by", "top", "cardinality", "hist"]
+    )]
+    pub timeline: Option<String>,
+
+    /// Split the counts of --timeline by level
+    #[arg(long = "timeline-by-level", requires = "timeline")]
+    pub timeline_by_level: bool,
+
+    /// Report stretches longer than this, e.g. 30s or 5m, without events instead of the
+    /// events, as one event per gap
+    #[arg(
+        long = "gaps",
+        value_name = "THRESHOLD",
+        conflicts_with_all = ["drain", "count_by", "top", "cardinality", "hist", "timeline"]
+    )]
+    pub gaps: Option<String>,
+
+    /// Look for --gaps per value of this key, e.g. per host, so one that stops logging shows
+    #[arg(long = "gaps-by", value_name = "KEY", requires = "gaps")]
+    pub gaps_by: Option<String>,
+
+    /// Report time windows with a burst of events, several times as many as the ten windows
+    /// before had on average, with their levels and templates, instead of the events
+    #[arg(
+        long = "bursts",
+        conflicts_with_all = ["drain", "count_by", "top", "cardinality", "hist", "timeline", "gaps"]
+    )]
+    pub bursts: bool,
+
+    /// Size of the windows of --bursts
+    #[arg(
+        long = "burst-window",
+        value_name = "INTERVAL",
+        default_value = "1m",
+        requires = "bursts"
+    )]
+    pub burst_window: String,
+
+    /// How many times the baseline a window of --bursts needs
+    #[arg(long = "burst-factor", default_value_t = 3.0, requires = "bursts")]
+    pub burst_factor: f64,
+
+    /// Group events into sessions per value of this key, e.g. user_id, and show one event per
+    /// session with its start, end, duration, events and errors instead of the events
+    #[arg(
+        long = "sessions",
+        value_name = "KEY",
+        conflicts_with_all = [
+            "drain",
+            "count_by",
+            "top",
+            "cardinality",
+            "hist",
+            "timeline",
+            "gaps",
+            "bursts"
+        ]
+    )]
+    pub sessions: Option<String>,
+
+    /// End a session of --sessions after this long without events of its key
+    #[arg(
+        long = "session-gap",
+        value_name = "INTERVAL",
+        default_value = "30m",
+        requires = "sessions"
+    )]
+    pub session_gap: String,
+
+    /// Match each event of the START filter with the next END event of the same --pair-key
+    /// and show one event per pair, with its end, duration and duration_ms, instead of the
+    /// events; starts never ended show with paired=false, e.g.
+    /// --pair 'start: msg =~ "started"' 'end: msg =~ "completed"'
+    #[arg(
+        long = "pair",
+        num_args = 2,
+        value_names = ["START", "END"],
+        requires = "pair_key",
+        conflicts_with_all = [
+            "drain",
+            "count_by",
+            "top",
+            "cardinality",
+            "hist",
+            "timeline",
+            "gaps",
+            "bursts",
+            "sessions"
+        ]
+    )]
+    pub pair: Vec<String>,
+
+    /// Key whose value ties the start and end events of --pair together, e.g. request_id
+    #[arg(long = "pair-key", value_name = "KEY", requires = "pair")]
+    pub pair_key: Option<String>,
+
+    /// Measure the time from each event of the FROM filter to the next TO event of the same
+    /// --by value, e.g. a request sent and its response received, and show its statistics
+    /// like --agg (count, min, mean, p50, p95, p99 and max in ms) instead of the events
+    #[arg(
+        long = "latency-between",
+        num_args = 2,
+        value_names = ["FROM", "TO"],
+        requires = "by",
+        conflicts_with_all = [
+            "drain",
+            "count_by",
+            "top",
+            "cardinality",
+            "hist",
+            "timeline",
+            "gaps",
+            "bursts",
+            "sessions",
+            "pair"
+        ]
+    )]
+    pub latency_between: Vec<String>,
+
+    /// Key whose value ties the events of --latency-between together, e.g. trace_id
+    #[arg(long = "by", value_name = "KEY", requires = "latency_between")]
+    pub by: Option<String>,
+
+    /// Show how many events of each value of a key fell into time buckets, as FIELD@INTERVAL,
+    /// e.g. level@1h for the levels per hour, as a table with a row per bucket and a column
+    /// per value (levels most severe first) instead of the events
+    #[arg(
+        long = "crosstab",
+        value_name = "FIELD@INTERVAL",
+        conflicts_with_all = [
+            "drain",
+            "count_by",
+            "top",
+            "cardinality",
+            "hist",
+            "timeline",
+            "gaps",
+            "bursts",
+            "sessions",
+            "pair",
+            "latency_between"
+        ]
+    )]
+    pub crosstab: Option<String>,
+
+    /// Show statistics of the time between consecutive events like --agg (count, min, mean,
+    /// p50, p95, p99 and max in ms) instead of the events, which exposes periodic jobs, retry
+    /// loops and throttling
+    #[arg(
+        long = "interarrival",
+        conflicts_with_all = [
+            "drain",
+            "count_by",
+            "top",
+            "cardinality",
+            "hist",
+            "timeline",
+            "gaps",
+            "bursts",
+            "sessions",
+            "pair",
+            "latency_between",
+            "crosstab"
+        ]
+    )]
+    pub interarrival: bool,
+
+    /// Measure the time between consecutive events of --interarrival per value of this key,
+    /// e.g. per job
+    #[arg(
+        long = "interarrival-by",
+        value_name = "KEY",
+        requires = "interarrival"
+    )]
+    pub interarrival_by: Option<String>,
+
+    /// Also show a histogram of the times of --interarrival, on a log scale
+    #[arg(long = "interarrival-hist", requires = "interarrival")]
+    pub interarrival_hist: bool,
+
+    /// Show which keys the events have instead of the events: one event per key with how
+    /// many events had it and their percentage, the types of its values and a few examples
+    #[arg(
+        long = "schema",
+        conflicts_with_all = [
+            "drain",
+            "count_by",
+            "top",
+            "cardinality",
+            "hist",
+            "timeline",
+            "gaps",
+            "bursts",
+            "sessions",
+            "pair",
+            "latency_between",
+            "crosstab",
+            "interarrival"
+        ]
+    )]
+    pub schema: bool,
+
+    /// Output each value of this key the first time it is seen instead of the events, e.g.
+    /// path for the endpoints in an access log; streams, so a live log shows new values as
+    /// they appear
+    #[arg(
+        long = "distinct",
+        value_name = "KEY",
+        conflicts_with_all = [
+            "drain",
+            "count_by",
+            "top",
+            "cardinality",
+            "hist",
+            "timeline",
+            "gaps",
+            "bursts",
+            "sessions",
+            "pair",
+            "latency_between",
+            "crosstab",
+            "interarrival",
+            "schema"
+        ]
+    )]
+    pub distinct: Option<String>,
+
     /// Show statistics only
     #[arg(short = 'S', long = "stats-only")]
     pub stats_only: bool,
@@ -51,6 +797,48 @@ pub struct Cli {
     #[arg(short = 's', long = "stats")]
     pub stats: bool,
 
+    /// Also show a snapshot of the statistics every INTERVAL while the input streams in,
+    /// e.g. 10s when reading a live log from a pipe; implies --stats
+    #[arg(long = "stats-interval", value_name = "INTERVAL")]
+    pub stats_interval: Option<String>,
+
+    /// Format of the statistics: text, or one JSON object per snapshot and at the end
+    #[arg(long = "stats-format", value_enum, default_value = "text")]
+    pub stats_format: StatsFormat,
+
+    /// Serve the counts of the statistics and the --agg statistics as Prometheus metrics on
+    /// http://ADDR/metrics while the input streams in, e.g. 0.0.0.0:9090
+    #[arg(long = "prom-listen", value_name = "ADDR")]
+    pub prom_listen: Option<String>,
+
+    /// Write events into a SQLite database instead of stdout
+    #[arg(long = "output-sqlite")]
+    pub output_sqlite: Option<PathBuf>,
+
+    /// Table name for --output-sqlite
+    #[arg(long = "sqlite-table", default_value = "events")]
+    pub sqlite_table: String,
+
+    /// Maintain a full-text search index on the message column for --output-sqlite
+    #[arg(long = "sqlite-fts")]
+    pub sqlite_fts: bool,
+
+    /// Export events as OTLP logs to an OpenTelemetry collector (e.g. http://collector:4318)
+    #[arg(long = "export-otlp")]
+    pub export_otlp: Option<String>,
+
+    /// Send events as GELF to a Graylog UDP input (host:port)
+    #[arg(long = "gelf-udp")]
+    pub gelf_udp: Option<String>,
+
+    /// Index events directly into Elasticsearch through its _bulk API (e.g. http://localhost:9200)
+    #[arg(long = "es-url")]
+    pub es_url: Option<String>,
+
+    /// Target index for es-bulk output and --es-url, with strftime placeholders
+    #[arg(long = "index", default_value = "logs-%Y.%m.%d")]
+    pub index: String,
+
     /// Enable debug output
     #[arg(long)]
     pub debug: bool,
@@ -58,6 +846,94 @@ pub struct Cli {
     /// Show only core fields (timestamp, level, message)
     #[arg(short = 'c', long = "common")]
     pub common: bool,
+
+    /// SQL to answer over all events instead of printing them (set by `kelora query`)
+    #[arg(skip)]
+    pub query: Option<String>,
+
+    /// Change factor of templates to report when comparing inputs (set by `kelora drain-diff`)
+    #[arg(skip)]
+    pub drain_diff: Option<f64>,
+
+    /// Keep the stats of the two inputs apart and compare them (set by `kelora diff`)
+    #[arg(skip)]
+    pub stats_diff: bool,
+
+    /// The `--or` group of each value of the condition options, by option id; conditions
+    /// not listed (e.g. from filter sets) belong to every group
+    #[arg(skip)]
+    pub condition_groups: HashMap<String, Vec<usize>>,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum Command {
+    /// Answer a SQL query over the parsed events
+    ///
+    /// Events are loaded into an in-memory SQLite table, and each result row is printed
+    /// as an event in the chosen output format, e.g.
+    /// kelora query "SELECT level, count(*) FROM events GROUP BY level" -F table app.log
+    Query {
+        /// SQL query; events are in the `events` table (see --sqlite-table)
+        sql: String,
+
+        /// Any other kelora options and the input files
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+
+    /// Compare the message templates of two inputs, e.g. before and after a deployment
+    ///
+    /// Both inputs are mined together, so their templates line up, and the templates that
+    /// are new, gone or changed in frequency are shown, e.g.
+    /// kelora drain-diff before.log after.log -l error
+    DrainDiff {
+        /// Show templates whose share of events changed by at least this factor
+        #[arg(long = "min-change", default_value_t = 2.0)]
+        min_change: f64,
+
+        /// Input before the change
+        old: PathBuf,
+
+        /// Input after the change
+        new: PathBuf,
+
+        /// Any other kelora options, including the --drain-* ones
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+
+    /// Compare the statistics of two inputs, e.g. before and after a deployment
+    ///
+    /// Shows the events, parse errors, level mix and --agg statistics of both side by side
+    /// with their change, then the templates that are new, gone or changed as drain-diff
+    /// does, e.g.
+    /// kelora diff before.log after.log --agg latency_ms:p50,p95
+    Diff {
+        /// Show templates whose share of events changed by at least this factor
+        #[arg(long = "min-change", default_value_t = 2.0)]
+        min_change: f64,
+
+        /// Input before the change
+        old: PathBuf,
+
+        /// Input after the change
+        new: PathBuf,
+
+        /// Any other kelora options, including --agg and the --drain-* ones
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+
+    /// Combine saved --drain-state files, e.g. from several hosts, into one
+    ///
+    /// Similar templates are merged as if their events had been mined together, e.g.
+    /// kelora drain-merge web1.json web2.json -o fleet.json
+    /// Without -o, the combined state is printed.
+    DrainMerge {
+        /// The state files, then any --drain-* options and -o
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
 }
 
 #[derive(clap::ValueEnum, Clone, Debug)]
@@ -65,25 +941,126 @@ pub enum InputFormat {
     Logfmt,
     Jsonl,
     Syslog,
+    Fixed,
+    Xml,
 }
 
 #[derive(clap::ValueEnum, Clone, Debug)]
 pub enum OutputFormat {
     Default,
     Jsonl,
+    JsonPretty,
+    Csv,
+    Tsv,
+    Table,
+    Template,
+    /// RFC 5424 syslog
+    Syslog,
+    /// YAML stream, one document per event
+    Yaml,
+    /// GELF 1.1 JSON for Graylog
+    Gelf,
+    /// Elasticsearch bulk API request body
+    EsBulk,
+    /// Arrow IPC stream (binary)
+    Arrow,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum KeyCaseArg {
+    /// Lowercase, e.g. Request-ID becomes request-id
+    Lower,
+    /// Lowercase with underscores between words, e.g. requestId becomes request_id
+    Snake,
+}
+
+impl From<KeyCaseArg> for KeyCase {
+    fn from(case: KeyCaseArg) -> Self {
+        match case {
+            KeyCaseArg::Lower => KeyCase::Lower,
+            KeyCaseArg::Snake => KeyCase::Snake,
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum EpochUnitArg {
+    /// Seconds
+    S,
+    /// Milliseconds
+    Ms,
+    /// Microseconds
+    Us,
+    /// Nanoseconds
+    Ns,
+}
+
+impl From<EpochUnitArg> for EpochUnit {
+    fn from(unit: EpochUnitArg) -> Self {
+        match unit {
+            EpochUnitArg::S => EpochUnit::Seconds,
+            EpochUnitArg::Ms => EpochUnit::Millis,
+            EpochUnitArg::Us => EpochUnit::Micros,
+            EpochUnitArg::Ns => EpochUnit::Nanos,
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Debug)]
+pub enum KeyOrderMode {
+    /// In the order given with -k
+    KeysAsGiven,
+    /// Alphabetically
+    Alpha,
+    /// As they appeared in the input
+    Input,
+}
+
+#[derive(clap::ValueEnum, Clone, Debug)]
+pub enum OutputCompression {
+    Gzip,
+    Zstd,
+    None,
+}
+
+#[derive(clap::ValueEnum, Clone, Debug)]
+pub enum ColorMode {
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StatsFormat {
+    Text,
+    Json,
 }
 
 #[derive(Debug, Default)]
 pub struct Stats {
     pub lines_seen: usize,
+    pub bytes_read: u64,
+    /// Bytes of formatted output, when it goes to stdout or a file
+    pub bytes_emitted: Option<u64>,
+    pub line_lengths: LineLengths,
     pub events_shown: usize,
     pub parse_errors: usize,
     pub filtered_out: usize,
+    pub grep_misses: usize,
+    pub duplicates: usize,
+    pub throttled: usize,
+    pub type_errors: usize,
     pub start_time: Option<DateTime<Utc>>,
     pub end_time: Option<DateTime<Utc>>,
     pub levels_seen: HashMap<String, usize>,
+    pub aggregates: NumericAggregates,
+    pub durations: DurationStats,
+    pub activity: Activity,
 }
 
+/// Marks of the volume sparklines in the stats
+const SPARKLINE_WIDTH: usize = 40;
+
 impl Stats {
     pub fn new() -> Self {
         Self::default()
@@ -104,14 +1081,49 @@ impl Stats {
         if let Some(level) = &event.level {
             *self.levels_seen.entry(level.clone()).or_insert(0) += 1;
         }
+
+        self.activity.add(event);
+
+        if !self.aggregates.is_empty() {
+            self.aggregates.add(event);
+        }
+        if !self.durations.is_empty() {
+            self.durations.add(event);
+        }
     }
 
     pub fn print_stats(&self) {
+        let mut extra = String::new();
+        if self.grep_misses > 0 {
+            extra.push_str(&format!(", grep non-matches: {}", self.grep_misses));
+        }
+        if self.duplicates > 0 {
+            extra.push_str(&format!(", duplicates: {}", self.duplicates));
+        }
+        if self.throttled > 0 {
+            extra.push_str(&format!(", throttled: {}", self.throttled));
+        }
+        if self.type_errors > 0 {
+            extra.push_str(&format!(", type errors: {}", self.type_errors));
+        }
         eprintln!(
-            "Events shown: {} (parse errors: {}, lines seen: {}, filtered: {})",
-            self.events_shown, self.parse_errors, self.lines_seen, self.filtered_out
+            "Events shown: {} (parse errors: {}, lines seen: {}, filtered: {}{})",
+            self.events_shown, self.parse_errors, self.lines_seen, self.filtered_out, extra
         );
 
+        match self.bytes_emitted {
+            Some(emitted) if self.bytes_read > 0 => eprintln!(
+                "Bytes: {} read, {} emitted ({:.1}%)",
+                format_bytes(self.bytes_read),
+                format_bytes(emitted),
+                emitted as f64 * 100.0 / self.bytes_read as f64
+            ),
+            _ => eprintln!("Bytes: {} read", format_bytes(self.bytes_read)),
+        }
+        if let Some(summary) = self.line_lengths.summary() {
+            eprintln!("{}", summary);
+        }
+
         if let (Some(start), Some(end)) = (&self.start_time, &self.end_time) {
             let duration = end.signed_duration_since(*start);
             eprintln!(
@@ -122,6 +1134,20 @@ impl Stats {
             );
         }
 
+        if let Some(sparklines) = self.activity.sparklines(SPARKLINE_WIDTH) {
+            let per_mark = match sparklines.per_mark.num_milliseconds() {
+                millis if millis < 1000 => format!("{}ms", millis),
+                _ => format_duration(sparklines.per_mark),
+            };
+            eprintln!(
+                "Volume: {} ({} per mark, peak {})",
+                sparklines.events, per_mark, sparklines.peak_events
+            );
+            if let Some(errors) = &sparklines.errors {
+                eprintln!("Errors: {} (peak {})", errors, sparklines.peak_errors);
+            }
+        }
+
         if !self.levels_seen.is_empty() {
             let mut levels: Vec<_> = self.levels_seen.iter().collect();
             levels.sort_by_key(|(level, _)| level.as_str());
@@ -134,79 +1160,1018 @@ impl Stats {
                     .join(", ")
             );
         }
-    }
-}
 
-fn format_duration(duration: chrono::Duration) -> String {
-    let seconds = duration.num_seconds();
-    let hours = seconds / 3600;
-    let minutes = (seconds % 3600) / 60;
-    let secs = seconds % 60;
+        for summary in self.durations.summaries() {
+            eprintln!("{}", summary);
+        }
 
-    if hours > 0 {
-        format!("{}h{}m{}s", hours, minutes, secs)
-    } else if minutes > 0 {
-        format!("{}m{}s", minutes, secs)
-    } else {
-        format!("{}s", secs)
+        self.print_aggregates();
     }
-}
 
-fn main() -> Result<()> {
-    let cli = Cli::parse();
+    /// The counts as a JSON object, for `--stats-format json`
+    pub fn to_json(&self) -> serde_json::Value {
+        let time = |time: &Option<DateTime<Utc>>| {
+            time.map(|time| time.to_rfc3339_opts(chrono::SecondsFormat::Millis, true))
+        };
+        let mut levels: Vec<_> = self.levels_seen.iter().collect();
+        levels.sort_by_key(|(level, _)| level.as_str());
+        let levels: serde_json::Map<String, serde_json::Value> = levels
+            .into_iter()
+            .map(|(level, count)| (level.clone(), (*count).into()))
+            .collect();
+        let mut summaries = self.durations.summaries();
+        summaries.extend(self.aggregates.summaries());
+        serde_json::json!({
+            "events_shown": self.events_shown,
+            "lines_seen": self.lines_seen,
+            "parse_errors": self.parse_errors,
+            "filtered": self.filtered_out,
+            "grep_misses": self.grep_misses,
+            "duplicates": self.duplicates,
+            "throttled": self.throttled,
+            "type_errors": self.type_errors,
+            "bytes_read": self.bytes_read,
+            "bytes_emitted": self.bytes_emitted,
+            "start": time(&self.start_time),
+            "end": time(&self.end_time),
+            "levels": levels,
+            "aggregates": summaries,
+        })
+    }
 
-    let parser = create_parser(&cli.input_format);
-    let formatter = create_formatter(&cli.output_format);
+    /// The counts in the Prometheus text format, with the rate of events shown per second,
+    /// for `--prom-listen`
+    pub fn to_prometheus(&self, rate: f64) -> String {
+        let mut text = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: Vec<(String, String)>| {
+            text.push_str(&format!(
+                "# HELP {} {}\n# TYPE {} {}\n",
+                name, help, name, kind
+            ));
+            for (labels, value) in samples {
+                text.push_str(&format!("{}{} {}\n", name, labels, value));
+            }
+        };
+        let count = |value: usize| vec![(String::new(), value.to_string())];
 
-    let readers: Vec<Box<dyn BufRead>> = if cli.files.is_empty() {
-        vec![Box::new(io::stdin().lock())]
-    } else {
-        cli.files
-            .iter()
-            .map(|path| open_input_file(path))
-            .collect::<Result<Vec<_>>>()?
+        metric(
+            "kelora_lines_total",
+            "counter",
+            "Lines read",
+            count(self.lines_seen),
+        );
+        metric(
+            "kelora_bytes_read_total",
+            "counter",
+            "Bytes read",
+            vec![(String::new(), self.bytes_read.to_string())],
+        );
+        let mut levels: Vec<_> = self.levels_seen.iter().collect();
+        levels.sort_by_key(|(level, _)| level.as_str());
+        let without_level = self.events_shown - levels.iter().map(|(_, n)| **n).sum::<usize>();
+        let mut samples: Vec<_> = levels
+            .into_iter()
+            .map(|(level, n)| {
+                (
+                    format!("{{level=\"{}\"}}", metrics::label_value(level)),
+                    n.to_string(),
+                )
+            })
+            .collect();
+        if without_level > 0 || samples.is_empty() {
+            samples.push(("{level=\"none\"}".to_string(), without_level.to_string()));
+        }
+        metric(
+            "kelora_events_total",
+            "counter",
+            "Events shown, by level",
+            samples,
+        );
+        metric(
+            "kelora_parse_errors_total",
+            "counter",
+            "Lines that failed to parse",
+            count(self.parse_errors),
+        );
+        metric(
+            "kelora_filtered_total",
+            "counter",
+            "Events filtered out",
+            count(self.filtered_out),
+        );
+        metric(
+            "kelora_events_per_second",
+            "gauge",
+            "Events shown per second lately",
+            vec![(String::new(), format!("{}", (rate * 10.0).round() / 10.0))],
+        );
+
+        let values = self.aggregates.values();
+        if !values.is_empty() {
+            let samples = values
+                .into_iter()
+                .map(|(name, value)| {
+                    let (field, statistic) = name.rsplit_once(' ').unwrap_or((&name, ""));
+                    (
+                        format!(
+                            "{{field=\"{}\",statistic=\"{}\"}}",
+                            metrics::label_value(field),
+                            statistic
+                        ),
+                        value.to_string(),
+                    )
+                })
+                .collect();
+            metric(
+                "kelora_field",
+                "gauge",
+                "Statistics of --agg fields",
+                samples,
+            );
+        }
+        text
+    }
+
+    pub fn print_aggregates(&self) {
+        for summary in self.aggregates.summaries() {
+            eprintln!("{}", summary);
+        }
+    }
+}
+
+/// The stats of the two inputs of `kelora diff` side by side: events, parse errors, the
+/// share of each level (most severe first) and the --agg statistics, with their change
+fn write_stats_diff(old: &Stats, new: &Stats, out: &mut dyn Write) -> io::Result<()> {
+    let row = |out: &mut dyn Write, label: &str, old: &str, new: &str, change: &str| {
+        writeln!(out, "{:<20} {:>14} {:>14} {:>10}", label, old, new, change)
+    };
+    let change = |old: f64, new: f64| match (old, new) {
+        (old, new) if old == new => "0%".to_string(),
+        (0.0, _) => "new".to_string(),
+        (old, new) => format!("{:+.1}%", (new - old) / old.abs() * 100.0),
+    };
+    let count_row = |out: &mut dyn Write, label: &str, old: usize, new: usize| {
+        row(
+            out,
+            label,
+            &old.to_string(),
+            &new.to_string(),
+            &change(old as f64, new as f64),
+        )
     };
 
-    let mut stats = Stats::new();
-    let levels_filter = prepare_levels_filter(&cli.levels);
-    let keys_filter = prepare_keys_filter(&cli);
+    row(out, "", "old", "new", "change")?;
+    count_row(out, "events", old.events_shown, new.events_shown)?;
+    count_row(out, "parse errors", old.parse_errors, new.parse_errors)?;
 
-    for reader in readers {
-        process_reader(
-            reader,
-            &*parser,
-            &*formatter,
-            &mut stats,
-            &levels_filter,
-            &keys_filter,
-            &cli,
+    let mut levels: Vec<&String> = old.levels_seen.keys().collect();
+    levels.extend(
+        new.levels_seen
+            .keys()
+            .filter(|level| !old.levels_seen.contains_key(*level)),
+    );
+    levels.sort_by_key(|level| {
+        (
+            std::cmp::Reverse(event::level_rank(level)),
+            level.to_string(),
+        )
+    });
+    let share = |stats: &Stats, level: &String| {
+        let count = stats.levels_seen.get(level).copied().unwrap_or(0);
+        let share = count as f64 * 100.0 / stats.events_shown.max(1) as f64;
+        (format!("{} ({:.1}%)", count, share), share)
+    };
+    for level in levels {
+        let (old_text, old_share) = share(old, level);
+        let (new_text, new_share) = share(new, level);
+        row(
+            out,
+            &format!("level {}", level),
+            &old_text,
+            &new_text,
+            &format!("{:+.1} pts", new_share - old_share),
         )?;
     }
 
-    if cli.stats_only || cli.stats {
-        stats.print_stats();
+    let new_values: HashMap<String, f64> = new.aggregates.values().into_iter().collect();
+    for (name, old_value) in old.aggregates.values() {
+        let Some(&new_value) = new_values.get(&name) else {
+            continue;
+        };
+        row(
+            out,
+            &name,
+            &format_number(old_value),
+            &format_number(new_value),
+            &change(old_value, new_value),
+        )?;
     }
-
-    Ok(())
+    writeln!(out)
 }
 
-fn create_parser(format: &InputFormat) -> Box<dyn LogParser> {
-    match format {
-        InputFormat::Logfmt => Box::new(LogfmtParser::new()),
-        InputFormat::Jsonl => Box::new(JsonlParser::new()),
-        InputFormat::Syslog => Box::new(SyslogParser::new()),
+/// A byte count such as `512 B` or `1.4 MiB`
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["KiB", "MiB", "GiB", "TiB", "PiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
     }
+    format!("{:.1} {}", value, UNITS[unit])
 }
 
-fn create_formatter(format: &OutputFormat) -> Box<dyn Formatter> {
-    match format {
-        OutputFormat::Default => Box::new(DefaultFormatter::new()),
-        OutputFormat::Jsonl => Box::new(JsonlFormatter::new()),
-    }
+/// A time offset such as `+0.023s` or `-1.500s`
+fn format_offset(offset: chrono::Duration) -> String {
+    let millis = offset.num_milliseconds();
+    let sign = if millis < 0 { '-' } else { '+' };
+    let millis = millis.unsigned_abs();
+    format!("{}{}.{:03}s", sign, millis / 1000, millis % 1000)
 }
 
-fn open_input_file(path: &PathBuf) -> Result<Box<dyn BufRead>> {
-    let file =
+/// Timestamps of the displayed events, for --ts-delta and --ts-elapsed
+#[derive(Default)]
+struct TimeOffsets {
+    first: Option<DateTime<Utc>>,
+    previous: Option<DateTime<Utc>>,
+}
+
+impl TimeOffsets {
+    fn annotate(&mut self, event: &mut Event, delta: bool, elapsed: bool) {
+        let Some(timestamp) = event.timestamp else {
+            return;
+        };
+        let first = *self.first.get_or_insert(timestamp);
+        let previous = self.previous.replace(timestamp).unwrap_or(timestamp);
+        if delta {
+            event.set_field(
+                "ts_delta".to_string(),
+                FieldValue::String(format_offset(timestamp - previous)),
+            );
+        }
+        if elapsed {
+            event.set_field(
+                "ts_elapsed".to_string(),
+                FieldValue::String(format_offset(timestamp - first)),
+            );
+        }
+    }
+}
+
+/// Where processed events go
+enum Output {
+    /// Formatted text on stdout or in an output file
+    Text(Box<dyn Formatter>, OutputWriter),
+    /// Events consumed directly by a sink, replacing text output
+    Sink(Box<dyn Sink>),
+}
+
+/// The per-run processing state shared by all input readers
+struct Pipeline {
+    parser: Box<dyn LogParser>,
+    output: Output,
+    timestamps: Option<TimestampOptions>,
+    decoders: Vec<FieldDecoder>,
+    key_normalizer: Option<KeyNormalizer>,
+    renames: Vec<(String, String)>,
+    extractors: Vec<Extractor>,
+    kv_scraper: Option<KeyValueScraper>,
+    user_agents: Option<UserAgentParser>,
+    geoip: Option<GeoIp>,
+    rdns: Option<ReverseDns>,
+    lookups: Vec<LookupTable>,
+    types: Option<TypeCoercion>,
+    derivations: Vec<Derivation>,
+    levels_filter: Option<LevelFilter>,
+    min_level: Option<u8>,
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+    source_filter: Option<SourceFilter>,
+    condition_groups: Vec<ConditionGroup>,
+    jq: Option<JqProgram>,
+    keys_filter: Option<Vec<String>>,
+    exclude_keys: Vec<String>,
+    distinct: Option<DistinctFilter>,
+    dropper: Option<FieldDropper>,
+    redactor: Option<Redactor>,
+    hashers: Vec<FieldHasher>,
+    dedup: Option<Deduplicator>,
+    throttle: Option<Throttle>,
+    offsets: TimeOffsets,
+    drain: Option<Drain>,
+    template_filter: Option<TemplateFilter>,
+    held_events: Option<HeldEvents>,
+    diff: Option<TemplateDiff>,
+    params: Option<DrainParams>,
+    timeline: Option<DrainTimeline>,
+    count_by: Option<CountBy>,
+    top: Option<TopValues>,
+    cardinality: Option<Cardinality>,
+    histogram: Option<Histogram>,
+    event_timeline: Option<EventTimeline>,
+    gaps: Option<GapDetector>,
+    bursts: Option<BurstDetector>,
+    sessions: Option<Sessionizer>,
+    pairs: Option<Pairs>,
+    latency: Option<Latency>,
+    interarrival: Option<InterArrival>,
+    schema: Option<Schema>,
+    distinct_values: Option<DistinctValues>,
+    stats_ticker: Option<StatsTicker>,
+    metrics: Option<MetricsServer>,
+}
+
+/// Shows the stats every so often while the input streams in (`--stats-interval 10s`). It is
+/// checked as lines arrive and each [`IDLE_TICK`] without one, so a quiet stream still shows
+/// the stats, at a rate of 0
+struct StatsTicker {
+    interval: Duration,
+    format: StatsFormat,
+    /// When the last snapshot was shown, and the events shown by then
+    last: (Instant, usize),
+}
+
+impl StatsTicker {
+    fn new(interval: Duration, format: StatsFormat, now: Instant) -> Self {
+        Self {
+            interval,
+            format,
+            last: (now, 0),
+        }
+    }
+
+    /// The snapshot to show once the interval has passed since the last one, with the rate
+    /// of events shown in between
+    fn tick(&mut self, now: Instant, stats: &Stats) -> Option<String> {
+        let elapsed = now.duration_since(self.last.0);
+        if elapsed < self.interval {
+            return None;
+        }
+        let new = stats.events_shown - self.last.1;
+        let rate = (new as f64 / elapsed.as_secs_f64() * 10.0).round() / 10.0;
+        self.last = (now, stats.events_shown);
+        let time = Utc::now().format("%Y-%m-%dT%H:%M:%SZ");
+        Some(match self.format {
+            StatsFormat::Json => {
+                let mut snapshot = stats.to_json();
+                snapshot["time"] = time.to_string().into();
+                snapshot["events_per_second"] = rate.into();
+                snapshot.to_string()
+            }
+            StatsFormat::Text => format!(
+                "Stats at {}: {} events shown (+{}, {}/s), {} lines seen, {} parse errors, {} filtered",
+                time,
+                stats.events_shown,
+                new,
+                rate,
+                stats.lines_seen,
+                stats.parse_errors,
+                stats.filtered_out
+            ),
+        })
+    }
+}
+
+/// Set on SIGINT when writing to a file or showing stats every so often, so processing stops
+/// and the output and the stats are finished cleanly
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+fn main() -> Result<()> {
+    let mut cli = parse_cli(std::env::args_os());
+    match cli.command.take() {
+        Some(Command::Query { sql, args }) => {
+            cli = parse_cli(std::iter::once("kelora query".to_string()).chain(args));
+            cli.query = Some(sql);
+        }
+        Some(Command::DrainDiff {
+            min_change,
+            old,
+            new,
+            args,
+        }) => {
+            let prefix = ["kelora drain-diff".to_string(), "--drain".to_string()];
+            cli = parse_cli(prefix.into_iter().chain(args));
+            if !cli.files.is_empty() || old == new {
+                anyhow::bail!("kelora drain-diff compares two different inputs");
+            }
+            cli.files = vec![old, new];
+            cli.drain_diff = Some(min_change);
+        }
+        Some(Command::Diff {
+            min_change,
+            old,
+            new,
+            args,
+        }) => {
+            let prefix = ["kelora diff".to_string(), "--drain".to_string()];
+            cli = parse_cli(prefix.into_iter().chain(args));
+            if !cli.files.is_empty() || old == new {
+                anyhow::bail!("kelora diff compares two different inputs");
+            }
+            cli.files = vec![old, new];
+            cli.drain_diff = Some(min_change);
+            cli.stats_diff = true;
+        }
+        Some(Command::DrainMerge { args }) => {
+            let prefix = ["kelora drain-merge".to_string(), "--drain".to_string()];
+            return merge_drain_states(&parse_cli(prefix.into_iter().chain(args)));
+        }
+        None => {}
+    }
+    apply_filter_sets(&mut cli)?;
+    if cli.stats_interval.is_some() {
+        cli.stats = true;
+    }
+
+    if cli.output.is_some()
+        || cli.output_compress.is_some()
+        || cli.drain_state.is_some()
+        || cli.stats_interval.is_some()
+    {
+        ctrlc::set_handler(|| {
+            // A second interrupt aborts without waiting for the input to end
+            if INTERRUPTED.swap(true, Ordering::Relaxed) {
+                std::process::exit(130);
+            }
+        })
+        .context("Failed to install interrupt handler")?;
+    }
+
+    let keys_filter = prepare_keys_filter(&cli);
+    let now = match &cli.now {
+        Some(now) => parse_time_expr(now, Utc::now()).context("Invalid --now")?,
+        None => Utc::now(),
+    };
+    let since = cli
+        .since
+        .as_deref()
+        .map(|since| parse_time_expr(since, now).context("Invalid --since"))
+        .transpose()?;
+    let until = cli
+        .until
+        .as_deref()
+        .map(|until| parse_time_expr(until, now).context("Invalid --until"))
+        .transpose()?;
+    if let (Some(since), Some(until)) = (since, until) {
+        if since >= until {
+            anyhow::bail!("--since must be earlier than --until");
+        }
+    }
+    let mut pipeline = Pipeline {
+        parser: create_parser(&cli)?,
+        output: create_output(&cli, &keys_filter)?,
+        timestamps: prepare_timestamp_options(&cli)?,
+        decoders: cli
+            .decode
+            .iter()
+            .map(|spec| {
+                FieldDecoder::parse(spec)
+                    .map_err(|e| anyhow::anyhow!("Invalid --decode '{}': {}", spec, e))
+            })
+            .collect::<Result<_>>()?,
+        key_normalizer: prepare_key_normalizer(&cli)?,
+        renames: prepare_renames(&cli)?,
+        kv_scraper: cli.kv_in_message.then(KeyValueScraper::new),
+        extractors: cli
+            .extract
+            .iter()
+            .map(|pattern| {
+                Extractor::new(pattern)
+                    .map_err(|e| anyhow::anyhow!("Invalid --extract '{}': {}", pattern, e))
+            })
+            .chain(cli.extract_grok.iter().map(|pattern| {
+                Extractor::grok(pattern)
+                    .map_err(|e| anyhow::anyhow!("Invalid --extract-grok '{}': {}", pattern, e))
+            }))
+            .collect::<Result<_>>()?,
+        user_agents: cli.parse_ua.as_deref().map(UserAgentParser::new),
+        geoip: cli
+            .geoip
+            .as_deref()
+            .map(|field| GeoIp::open(field, &cli.geoip_db))
+            .transpose()
+            .map_err(|e| anyhow::anyhow!("Invalid --geoip-db {}", e))?,
+        rdns: (!cli.rdns.is_empty())
+            .then(|| ReverseDns::new(cli.rdns.clone(), Duration::from_millis(cli.rdns_timeout))),
+        lookups: cli
+            .lookup
+            .iter()
+            .map(|spec| {
+                LookupTable::load(spec)
+                    .map_err(|e| anyhow::anyhow!("Invalid --lookup '{}': {}", spec, e))
+            })
+            .collect::<Result<_>>()?,
+        types: (!cli.types.is_empty())
+            .then(|| prepare_types(&cli))
+            .transpose()?,
+        derivations: cli
+            .derive
+            .iter()
+            .map(|spec| {
+                Derivation::parse(spec)
+                    .map_err(|e| anyhow::anyhow!("Invalid --derive '{}': {}", spec, e))
+            })
+            .collect::<Result<_>>()?,
+        levels_filter: prepare_levels_filter(&cli.levels)?,
+        min_level: cli
+            .min_level
+            .as_deref()
+            .map(|level| {
+                level_rank(level).ok_or_else(|| anyhow::anyhow!("Invalid --min-level: {}", level))
+            })
+            .transpose()?,
+        since,
+        until,
+        source_filter: (!cli.source.is_empty()).then(|| SourceFilter::new(cli.source.clone())),
+        condition_groups: prepare_condition_groups(&cli)?,
+        jq: cli
+            .jq
+            .as_deref()
+            .map(|code| {
+                JqProgram::compile(code).map_err(|e| anyhow::anyhow!("Invalid --jq: {}", e))
+            })
+            .transpose()?,
+        keys_filter,
+        exclude_keys: cli.exclude_keys.clone(),
+        distinct: prepare_distinct(&cli)?,
+        dropper: prepare_field_dropper(&cli)?,
+        redactor: prepare_redactor(&cli)?,
+        hashers: cli
+            .hash
+            .iter()
+            .map(|spec| {
+                FieldHasher::parse(spec).map_err(|e| anyhow::anyhow!("Invalid --hash: {}", e))
+            })
+            .collect::<Result<_>>()?,
+        dedup: (cli.dedup || !cli.dedup_by.is_empty())
+            .then(|| Deduplicator::new().with_keys(cli.dedup_by.clone())),
+        throttle: cli
+            .throttle
+            .as_deref()
+            .map(|spec| Throttle::parse(spec).context("Invalid --throttle"))
+            .transpose()?,
+        offsets: TimeOffsets::default(),
+        drain: cli.drain.then(|| prepare_drain(&cli)).transpose()?,
+        template_filter: prepare_template_filter(&cli),
+        held_events: cli
+            .drain_anomalies
+            .then(|| HeldEvents::new(&cli.rare_below))
+            .transpose()
+            .map_err(|e| anyhow::anyhow!("Invalid --rare-below: {}", e))?,
+        diff: cli
+            .drain_diff
+            .map(|min_change| TemplateDiff::new(&cli.files[0].display().to_string(), min_change))
+            .transpose()
+            .map_err(|e| anyhow::anyhow!("Invalid --min-change: {}", e))?,
+        params: cli.drain_params.as_deref().map(DrainParams::new),
+        timeline: prepare_drain_timeline(&cli)?,
+        count_by: (!cli.count_by.is_empty()).then(|| CountBy::new(cli.count_by.clone())),
+        top: prepare_top_values(&cli)?,
+        cardinality: (!cli.cardinality.is_empty())
+            .then(|| Cardinality::new(cli.cardinality.clone())),
+        histogram: cli
+            .hist
+            .clone()
+            .map(|key| Histogram::new(key, cli.hist_buckets, cli.hist_log)),
+        event_timeline: prepare_event_timeline(&cli)?,
+        gaps: prepare_gaps(&cli)?,
+        bursts: prepare_bursts(&cli)?,
+        sessions: prepare_sessions(&cli)?,
+        pairs: prepare_pairs(&cli)?,
+        latency: prepare_latency(&cli)?,
+        interarrival: cli
+            .interarrival
+            .then(|| InterArrival::new(cli.interarrival_by.clone(), cli.interarrival_hist)),
+        schema: cli.schema.then(Schema::default),
+        distinct_values: cli.distinct.as_deref().map(DistinctValues::new),
+        stats_ticker: prepare_stats_ticker(&cli)?,
+        metrics: prepare_metrics(&cli)?,
+    };
+
+    let readers: Vec<(Option<String>, Box<dyn BufRead + Send>)> = if cli.files.is_empty() {
+        vec![(None, Box::new(BufReader::new(io::stdin())))]
+    } else {
+        cli.files
+            .iter()
+            .map(|path| Ok((Some(path.display().to_string()), open_input_file(path)?)))
+            .collect::<Result<Vec<_>>>()?
+    };
+
+    let mut stats = prepare_stats(&cli)?;
+    let mut old_stats = None;
+    for (i, (source, reader)) in readers.into_iter().enumerate() {
+        if INTERRUPTED.load(Ordering::Relaxed) {
+            break;
+        }
+        if cli.stats_diff && i == 1 {
+            old_stats = Some(std::mem::replace(&mut stats, prepare_stats(&cli)?));
+        }
+        process_reader(reader, source.as_deref(), &mut pipeline, &mut stats, &cli)?;
+    }
+
+    // Last occurrences are only known now
+    if let Some(events) = pipeline.distinct.as_mut().map(DistinctFilter::finish) {
+        for event in events {
+            if !emit_distinct(event, &mut pipeline, &mut stats, &cli)? {
+                break;
+            }
+        }
+    }
+    if let Some(distinct) = &pipeline.distinct {
+        stats.filtered_out += distinct.suppressed();
+    }
+
+    // The last run of duplicates is still held back
+    if let Some(event) = pipeline.dedup.as_mut().and_then(Deduplicator::flush) {
+        write_event(event, &mut pipeline, &mut stats, &cli)?;
+    }
+    if let Some(dedup) = &pipeline.dedup {
+        stats.duplicates = dedup.suppressed();
+    }
+    if let Some(throttle) = &mut pipeline.throttle {
+        report_throttled(throttle.take_dropped(), throttle);
+    }
+
+    if let (Some(drain), Some(path)) = (&pipeline.drain, &cli.drain_state) {
+        save_drain_state(drain, path)?;
+    }
+
+    // Which templates are rare is only known now; their events are output instead of a report
+    if let Some(held_events) = pipeline.held_events.take() {
+        if let Some(drain) = pipeline.drain.take() {
+            let held = held_events
+                .into_selected(&drain)
+                .context("Failed to read back the events held for --drain-anomalies")?;
+            for event in held {
+                let event = event.context("Failed to read back a held event")?;
+                if !output_event(&event, &mut pipeline, &cli)? {
+                    break;
+                }
+            }
+        }
+    }
+
+    let counts = if let Some(mut count_by) = pipeline.count_by.take() {
+        count_by.finish()
+    } else if let Some(top) = pipeline.top.take() {
+        top.finish()
+    } else if let Some(cardinality) = pipeline.cardinality.take() {
+        cardinality.finish()
+    } else if let Some(schema) = pipeline.schema.take() {
+        schema.finish()
+    } else if let Some(gaps) = pipeline.gaps.take() {
+        gaps.finish()
+    } else if let Some(mut sessions) = pipeline.sessions.take() {
+        sessions.finish()
+    } else if let Some(pairs) = pipeline.pairs.take() {
+        pairs.finish()
+    } else {
+        Vec::new()
+    };
+    for event in counts {
+        if !output_event(&event, &mut pipeline, &cli)? {
+            break;
+        }
+    }
+    if let Some(bursts) = &mut pipeline.bursts {
+        bursts.finish();
+    }
+
+    match &mut pipeline.output {
+        Output::Sink(sink) => sink.finish()?,
+        Output::Text(formatter, out) if !cli.stats_only => {
+            let report = pipeline.drain.as_ref().filter(|_| {
+                !cli.alert_new_template && !cli.drain_annotate && pipeline.template_filter.is_none()
+            });
+            let csv = matches!(cli.output_format, OutputFormat::Csv);
+            let finished = if let Some(drain) = report {
+                match (&pipeline.diff, &pipeline.params, &pipeline.timeline) {
+                    (Some(diff), _, _) => match &old_stats {
+                        Some(old_stats) => write_stats_diff(old_stats, &stats, out)
+                            .and_then(|_| diff.write_report(drain, out)),
+                        None => diff.write_report(drain, out),
+                    },
+                    (None, Some(params), _) => params.write_report(drain, out),
+                    (None, None, Some(timeline)) => timeline.write_report(drain, csv, out),
+                    (None, None, None) => drain.write_report(out),
+                }
+            } else if let Some(histogram) = &pipeline.histogram {
+                histogram.write_report(out)
+            } else if let Some(timeline) = &pipeline.event_timeline {
+                timeline.write_report(csv, out)
+            } else if let Some(bursts) = &pipeline.bursts {
+                bursts.write_report(out)
+            } else if let Some(latency) = &pipeline.latency {
+                latency.write_report(out)
+            } else if let Some(interarrival) = &pipeline.interarrival {
+                interarrival.write_report(out)
+            } else {
+                formatter.finish(out)
+            };
+            if let Err(e) = finished.and_then(|_| out.finish()) {
+                if e.kind() != std::io::ErrorKind::BrokenPipe {
+                    return Err(anyhow::Error::from(e));
+                }
+            }
+            stats.bytes_emitted = Some(out.bytes_written());
+        }
+        Output::Text(_, _) => {}
+    }
+    if let (Some(distinct), Some(key)) = (&pipeline.distinct_values, &cli.distinct) {
+        if distinct.is_approximate() {
+            eprintln!(
+                "Too many distinct values of {} to tell apart exactly: some may have been missed",
+                key
+            );
+        }
+    }
+
+    if cli.stats_only || cli.stats {
+        match cli.stats_format {
+            StatsFormat::Text => stats.print_stats(),
+            StatsFormat::Json => eprintln!("{}", stats.to_json()),
+        }
+    } else if !cli.stats_diff {
+        // kelora diff already compared them
+        stats.print_aggregates();
+    }
+
+    Ok(())
+}
+
+fn create_parser(cli: &Cli) -> Result<Box<dyn LogParser>> {
+    Ok(match cli.input_format {
+        InputFormat::Logfmt => {
+            Box::new(LogfmtParser::new().with_text_keys(prepare_types(cli)?.text_keys()))
+        }
+        InputFormat::Jsonl => Box::new(JsonlParser::new()),
+        InputFormat::Syslog => Box::new(SyslogParser::new()),
+        InputFormat::Xml => Box::new(XmlParser::new()),
+        InputFormat::Fixed => {
+            if cli.widths.is_empty() {
+                anyhow::bail!("Fixed-width input requires --widths");
+            }
+            if cli.columns.len() > cli.widths.len() {
+                anyhow::bail!(
+                    "Got {} column names but only {} widths",
+                    cli.columns.len(),
+                    cli.widths.len()
+                );
+            }
+            Box::new(FixedWidthParser::new(
+                cli.widths.clone(),
+                cli.columns.clone(),
+            ))
+        }
+    })
+}
+
+/// The option that replaces the events with a report written only as text, if any
+fn text_report(cli: &Cli) -> Option<&'static str> {
+    let drain_report = cli.drain
+        && !cli.alert_new_template
+        && !cli.drain_annotate
+        && cli.template_id.is_empty()
+        && cli.template_match.is_empty();
+    [
+        (drain_report, "--drain"),
+        (cli.hist.is_some(), "--hist"),
+        (cli.timeline.is_some(), "--timeline"),
+        (cli.crosstab.is_some(), "--crosstab"),
+        (cli.bursts, "--bursts"),
+        (!cli.latency_between.is_empty(), "--latency-between"),
+        (cli.interarrival, "--interarrival"),
+    ]
+    .into_iter()
+    .find_map(|(set, flag)| set.then_some(flag))
+}
+
+fn create_formatter(cli: &Cli, keys: &Option<Vec<String>>) -> Result<Box<dyn Formatter>> {
+    // Wildcard selectors can't name columns up front, so those come from the first event
+    let columns = keys
+        .clone()
+        .filter(|keys| !keys.iter().any(|key| is_key_pattern(key)));
+    let unflatten = cli.unflatten.then(|| cli.flatten_sep.clone());
+    let ts_display = cli
+        .ts_display
+        .as_deref()
+        .map(TimestampDisplay::parse)
+        .transpose()
+        .map_err(|e| anyhow::anyhow!("Invalid --ts-display: {}", e))?;
+    let order = match cli.order {
+        KeyOrderMode::Alpha => KeyOrder::Alpha,
+        KeyOrderMode::Input => KeyOrder::Input,
+        KeyOrderMode::KeysAsGiven => KeyOrder::KeysAsGiven(cli.keys.clone()),
+    };
+    let formatter: Box<dyn Formatter> = match cli.output_format {
+        OutputFormat::Default => Box::new(
+            DefaultFormatter::new()
+                .with_color(use_color(&cli.color))
+                .with_key_order(order)
+                .with_first_keys(cli.first_keys.clone())
+                .with_skip_empty(cli.skip_empty)
+                .with_highlight(highlight_regex(cli)?),
+        ),
+        OutputFormat::Jsonl => Box::new(
+            JsonlFormatter::new()
+                .with_skip_empty(cli.skip_empty)
+                .with_unflatten(unflatten)
+                .with_key_order(order),
+        ),
+        OutputFormat::JsonPretty => Box::new(
+            JsonPrettyFormatter::new(cli.json_array)
+                .with_unflatten(unflatten)
+                .with_key_order(order),
+        ),
+        OutputFormat::Csv => Box::new(CsvFormatter::new(columns)),
+        OutputFormat::Tsv => Box::new(CsvFormatter::tsv(columns)),
+        OutputFormat::Table => Box::new(TableFormatter::new(columns, terminal_width())),
+        OutputFormat::Template => {
+            let template = cli
+                .template
+                .as_deref()
+                .context("Template output requires --template")?;
+            Box::new(
+                TemplateFormatter::new(template)
+                    .map_err(|e| anyhow::anyhow!("Invalid template: {}", e))?,
+            )
+        }
+        OutputFormat::Syslog => Box::new(SyslogFormatter::new()),
+        OutputFormat::Yaml => Box::new(YamlFormatter::new()),
+        OutputFormat::Gelf => Box::new(GelfFormatter::new(local_hostname())),
+        OutputFormat::EsBulk => Box::new(create_es_bulk_formatter(cli)?),
+        OutputFormat::Arrow => anyhow::bail!("-F arrow is written by its own sink, not as text"),
+    };
+
+    let formatter: Box<dyn Formatter> = match ts_display {
+        Some(display) => Box::new(TimestampDisplayFormatter::new(formatter, display)),
+        None => formatter,
+    };
+
+    if cli.no_truncate || cli.max_field_width.is_empty() {
+        return Ok(formatter);
+    }
+    let widths = FieldWidths::parse(&cli.max_field_width)
+        .map_err(|e| anyhow::anyhow!("Invalid --max-field-width: {}", e))?;
+    Ok(Box::new(TruncatingFormatter::new(formatter, widths)))
+}
+
+/// Pattern whose matches are highlighted in colored output: --highlight, or else the regexes
+/// the events were selected by: --grep, --grep-field and the =~ matches of --filter
+fn highlight_regex(cli: &Cli) -> Result<Option<Regex>> {
+    if let Some(pattern) = &cli.highlight {
+        return Regex::new(pattern)
+            .map(Some)
+            .with_context(|| format!("Invalid highlight regex: {}", pattern));
+    }
+    let filters = cli
+        .filters
+        .iter()
+        .filter_map(|source| Expr::parse(source).ok())
+        .collect::<Vec<_>>();
+    let patterns: Vec<&str> = cli
+        .grep
+        .as_deref()
+        .into_iter()
+        .chain(
+            cli.grep_fields
+                .iter()
+                .filter_map(|spec| spec.split_once('=').map(|(_, pattern)| pattern)),
+        )
+        .chain(filters.iter().flat_map(Expr::match_patterns))
+        .collect();
+    if patterns.is_empty() {
+        return Ok(None);
+    }
+    let pattern = patterns
+        .iter()
+        .map(|pattern| format!("(?:{})", pattern))
+        .collect::<Vec<_>>()
+        .join("|");
+    Regex::new(&pattern)
+        .map(Some)
+        .with_context(|| format!("Invalid highlight regex: {}", pattern))
+}
+
+fn use_color(mode: &ColorMode) -> bool {
+    match mode {
+        ColorMode::Always => true,
+        ColorMode::Never => false,
+        ColorMode::Auto => {
+            let no_color = std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+            !no_color && io::stdout().is_terminal()
+        }
+    }
+}
+
+/// Width available for table output: $COLUMNS, or the terminal size when writing to a terminal
+fn terminal_width() -> Option<usize> {
+    std::env::var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.parse().ok())
+        .or_else(|| {
+            terminal_size::terminal_size().map(|(terminal_size::Width(width), _)| width as usize)
+        })
+}
+
+fn create_es_bulk_formatter(cli: &Cli) -> Result<EsBulkFormatter> {
+    EsBulkFormatter::new(&cli.index).map_err(|e| anyhow::anyhow!("Invalid --index: {}", e))
+}
+
+/// Name of this machine, used as the GELF host for events that don't carry one
+fn local_hostname() -> String {
+    std::env::var("HOSTNAME")
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "localhost".to_string())
+}
+
+/// Text output destination: `-o` file or stdout, compressed per --output-compress or the file
+/// extension
+fn open_output(cli: &Cli) -> Result<OutputWriter> {
+    let compression = match cli.output_compress {
+        Some(OutputCompression::Gzip) => Some(Compression::Gzip),
+        Some(OutputCompression::Zstd) => Some(Compression::Zstd),
+        Some(OutputCompression::None) => None,
+        None => cli.output.as_deref().and_then(Compression::from_path),
+    };
+    let rotate = cli.rotate_size.is_some() || cli.rotate_daily;
+    match &cli.output {
+        Some(path) if rotate => {
+            let policy = RotationPolicy {
+                max_size: cli
+                    .rotate_size
+                    .as_deref()
+                    .map(parse_size)
+                    .transpose()
+                    .context("Invalid --rotate-size")?,
+                daily: cli.rotate_daily,
+            };
+            OutputWriter::rotating(path, compression, policy)
+        }
+        Some(path) => OutputWriter::create(path, compression),
+        None if rotate => anyhow::bail!("--rotate-size and --rotate-daily require -o"),
+        None => OutputWriter::stdout(compression),
+    }
+}
+
+fn create_output(cli: &Cli, keys: &Option<Vec<String>>) -> Result<Output> {
+    if cli.json_array && !matches!(cli.output_format, OutputFormat::JsonPretty) {
+        anyhow::bail!("--json-array only applies to -F json-pretty");
+    }
+    if let Some(sql) = &cli.query {
+        if matches!(cli.output_format, OutputFormat::Arrow) {
+            anyhow::bail!("kelora query can't write -F arrow; use -F csv, jsonl or table");
+        }
+        let sink = QuerySink::new(
+            sql,
+            &cli.sqlite_table,
+            create_formatter(cli, keys)?,
+            open_output(cli)?,
+        )?;
+        return Ok(Output::Sink(Box::new(sink)));
+    }
+    if let Some(path) = &cli.output_sqlite {
+        let sink = SqliteSink::open(path, &cli.sqlite_table, cli.sqlite_fts)?;
+        return Ok(Output::Sink(Box::new(sink)));
+    }
+    if let Some(url) = &cli.export_otlp {
+        return Ok(Output::Sink(Box::new(OtlpSink::new(url))));
+    }
+    if let Some(url) = &cli.es_url {
+        let formatter = create_es_bulk_formatter(cli)?;
+        return Ok(Output::Sink(Box::new(EsBulkSink::new(url, formatter))));
+    }
+    if let Some(address) = &cli.gelf_udp {
+        let formatter = GelfFormatter::new(local_hostname());
+        return Ok(Output::Sink(Box::new(GelfUdpSink::connect(
+            address, formatter,
+        )?)));
+    }
+
+    match cli.output_format {
+        OutputFormat::Arrow => {
+            if let Some(flag) = text_report(cli) {
+                anyhow::bail!("{} writes a text report, which -F arrow can't hold", flag);
+            }
+            Ok(Output::Sink(Box::new(ArrowSink::new(Box::new(
+                open_output(cli)?,
+            )))))
+        }
+        _ => Ok(Output::Text(
+            create_formatter(cli, keys)?,
+            open_output(cli)?,
+        )),
+    }
+}
+
+fn open_input_file(path: &PathBuf) -> Result<Box<dyn BufRead + Send>> {
+    let file =
         File::open(path).with_context(|| format!("Failed to open file: {}", path.display()))?;
 
     // Future: Add compression support here
@@ -218,12 +2183,522 @@ fn open_input_file(path: &PathBuf) -> Result<Box<dyn BufRead>> {
     // }
 }
 
-fn prepare_levels_filter(levels: &[String]) -> Option<Vec<String>> {
+/// Parse the command line, noting which `--or` group each condition belongs to
+fn parse_cli<I, T>(args: I) -> Cli
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let matches = Cli::command().get_matches_from(args);
+    let mut cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    cli.condition_groups = condition_groups(&matches);
+    cli
+}
+
+/// Conditions are grouped by the number of `--or` flags before them on the command line
+fn condition_groups(matches: &ArgMatches) -> HashMap<String, Vec<usize>> {
+    let or_indices: Vec<usize> = matches
+        .indices_of("or")
+        .map(|indices| indices.collect())
+        .unwrap_or_default();
+    [
+        "where_filters",
+        "where_not_filters",
+        "grep",
+        "grep_fields",
+        "grep_v",
+        "filters",
+    ]
+    .into_iter()
+    .filter_map(|id| {
+        let groups = matches
+            .indices_of(id)?
+            .map(|index| or_indices.iter().filter(|or| **or < index).count())
+            .collect();
+        Some((id.to_string(), groups))
+    })
+    .collect()
+}
+
+/// The `--where`, `--grep` and `--filter` conditions that must all hold for an event to pass
+/// (one group per `--or`)
+#[derive(Debug, Clone, Default)]
+struct ConditionGroup {
+    where_filters: Vec<FieldCondition>,
+    expr_filters: Vec<Expr>,
+    grep_filters: Vec<GrepFilter>,
+}
+
+/// Why an event failed a condition group; regex misses are counted apart from the rest
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Rejection {
+    Filtered,
+    GrepMiss,
+}
+
+impl ConditionGroup {
+    fn is_empty(&self) -> bool {
+        self.where_filters.is_empty()
+            && self.expr_filters.is_empty()
+            && self.grep_filters.is_empty()
+    }
+
+    fn check(&self, event: &Event) -> Result<(), Rejection> {
+        if !self
+            .where_filters
+            .iter()
+            .all(|condition| condition.matches(event))
+            || !self.expr_filters.iter().all(|filter| filter.matches(event))
+        {
+            return Err(Rejection::Filtered);
+        }
+        if !self.grep_filters.iter().all(|grep| grep.matches(event)) {
+            return Err(Rejection::GrepMiss);
+        }
+        Ok(())
+    }
+}
+
+/// Passes if any group does; a miss only counts as a grep miss if some group got that far
+fn check_condition_groups(groups: &[ConditionGroup], event: &Event) -> Result<(), Rejection> {
+    let mut rejection = None;
+    for group in groups {
+        match group.check(event) {
+            Ok(()) => return Ok(()),
+            Err(reason) => rejection = rejection.max(Some(reason)),
+        }
+    }
+    rejection.map_or(Ok(()), Err)
+}
+
+fn prepare_condition_groups(cli: &Cli) -> Result<Vec<ConditionGroup>> {
+    let mut groups = vec![ConditionGroup::default(); cli.or as usize + 1];
+    // Add the i-th value of an option to its group, or to all of them
+    let mut add = |id: &str, i: usize, add_to: &dyn Fn(&mut ConditionGroup)| match cli
+        .condition_groups
+        .get(id)
+        .and_then(|groups| groups.get(i))
+    {
+        Some(&group) => add_to(&mut groups[group]),
+        None => groups.iter_mut().for_each(add_to),
+    };
+
+    let parse_where = |spec: &String, flag: &str| {
+        FieldCondition::parse(spec).map_err(|e| anyhow::anyhow!("Invalid {}: {}", flag, e))
+    };
+    for (i, spec) in cli.where_filters.iter().enumerate() {
+        let condition = parse_where(spec, "--where")?;
+        add("where_filters", i, &|group| {
+            group.where_filters.push(condition.clone())
+        });
+    }
+    for (i, spec) in cli.where_not_filters.iter().enumerate() {
+        let condition = parse_where(spec, "--where-not")?.negate();
+        add("where_not_filters", i, &|group| {
+            group.where_filters.push(condition.clone())
+        });
+    }
+
+    for (i, source) in cli.filters.iter().enumerate() {
+        let expr = Expr::parse(source)
+            .map_err(|e| anyhow::anyhow!("Invalid --filter '{}': {}", source, e))?;
+        add("filters", i, &|group| group.expr_filters.push(expr.clone()));
+    }
+
+    if let Some(pattern) = &cli.grep {
+        let grep =
+            GrepFilter::message(pattern).map_err(|e| anyhow::anyhow!("Invalid --grep: {}", e))?;
+        add("grep", 0, &|group| group.grep_filters.push(grep.clone()));
+    }
+    for (i, spec) in cli.grep_fields.iter().enumerate() {
+        let grep =
+            GrepFilter::field(spec).map_err(|e| anyhow::anyhow!("Invalid --grep-field: {}", e))?;
+        add("grep_fields", i, &|group| {
+            group.grep_filters.push(grep.clone())
+        });
+    }
+    for (i, pattern) in cli.grep_v.iter().enumerate() {
+        let grep = GrepFilter::message(pattern)
+            .map_err(|e| anyhow::anyhow!("Invalid --grep-v: {}", e))?
+            .negate();
+        add("grep_v", i, &|group| group.grep_filters.push(grep.clone()));
+    }
+
+    if groups.len() > 1 && groups.iter().any(ConditionGroup::is_empty) {
+        anyhow::bail!("--or needs conditions on both sides");
+    }
+    Ok(groups)
+}
+
+fn prepare_field_dropper(cli: &Cli) -> Result<Option<FieldDropper>> {
+    let mut dropper = FieldDropper::new(&cli.drop_field);
+    for spec in &cli.drop_field_if {
+        dropper = dropper
+            .with_condition(spec)
+            .map_err(|e| anyhow::anyhow!("Invalid --drop-field-if '{}': {}", spec, e))?;
+    }
+    Ok((!dropper.is_empty()).then_some(dropper))
+}
+
+fn prepare_redactor(cli: &Cli) -> Result<Option<Redactor>> {
+    let patterns = cli
+        .redact_patterns
+        .iter()
+        .map(|pattern| {
+            Regex::new(pattern).with_context(|| format!("Invalid --redact-pattern '{}'", pattern))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let redactor = Redactor::new(&cli.redact, patterns);
+    Ok((!redactor.is_empty()).then_some(redactor))
+}
+
+/// Overrides for reading input timestamps, if any were given
+fn prepare_timestamp_options(cli: &Cli) -> Result<Option<TimestampOptions>> {
+    if let Some(format) = &cli.ts_format {
+        let invalid = chrono::format::StrftimeItems::new(format)
+            .any(|item| matches!(item, chrono::format::Item::Error));
+        if format.is_empty() || invalid {
+            anyhow::bail!("Invalid --ts-format '{}': not a strftime pattern", format);
+        }
+    }
+    if cli.ts_format.is_none() && cli.epoch_unit.is_none() {
+        return Ok(None);
+    }
+    Ok(Some(TimestampOptions {
+        format: cli.ts_format.clone(),
+        epoch_unit: cli.epoch_unit.map(EpochUnit::from),
+    }))
+}
+
+fn prepare_distinct(cli: &Cli) -> Result<Option<DistinctFilter>> {
+    let filter = match (&cli.first_of, &cli.last_of) {
+        (Some(key), _) => DistinctFilter::new(key, Occurrence::First),
+        (None, Some(key)) => DistinctFilter::new(key, Occurrence::Last),
+        (None, None) => return Ok(None),
+    };
+    let key = cli.first_of.as_deref().or(cli.last_of.as_deref());
+    if key == Some(TEMPLATE_KEY) {
+        return Ok(Some(filter.with_templates(prepare_drain(cli)?)));
+    }
+    Ok(Some(filter))
+}
+
+fn prepare_drain(cli: &Cli) -> Result<Drain> {
+    let mut drain = Drain::new(DrainConfig {
+        sim_th: cli.drain_sim_th,
+        depth: cli.drain_depth,
+        max_children: cli.drain_max_children,
+    })
+    .map_err(|e| anyhow::anyhow!("Invalid --drain settings: {}", e))?
+    .with_examples(cli.drain_examples);
+    if let Some(top) = cli.drain_top {
+        drain = drain.with_top(top);
+    }
+
+    let mut rules = Vec::new();
+    if let Some(path) = &cli.drain_masks {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read --drain-masks {}", path.display()))?;
+        rules.extend(
+            text.lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(str::to_string),
+        );
+    }
+    rules.extend(cli.drain_mask.iter().cloned());
+    for rule in rules {
+        drain = drain
+            .with_mask(&rule)
+            .map_err(|e| anyhow::anyhow!("Invalid drain mask '{}': {}", rule, e))?;
+    }
+
+    // A state file that doesn't exist yet is created at the end
+    if let Some(path) = cli.drain_state.as_deref().filter(|path| path.exists()) {
+        let state = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read --drain-state {}", path.display()))?;
+        drain
+            .restore(&state)
+            .map_err(|e| anyhow::anyhow!("Invalid --drain-state {}: {}", path.display(), e))?;
+    }
+    Ok(drain)
+}
+
+fn prepare_top_values(cli: &Cli) -> Result<Option<TopValues>> {
+    let [k, fields] = cli.top.as_slice() else {
+        return Ok(None);
+    };
+    let k: usize = k
+        .parse()
+        .ok()
+        .filter(|&k| k > 0)
+        .with_context(|| format!("Invalid --top: '{}' is not a positive count", k))?;
+    let fields = fields
+        .split(',')
+        .map(|field| field.trim().to_string())
+        .filter(|field| !field.is_empty())
+        .collect();
+    Ok(Some(TopValues::new(k, fields)))
+}
+
+fn prepare_event_timeline(cli: &Cli) -> Result<Option<EventTimeline>> {
+    if let Some(spec) = &cli.crosstab {
+        let Some((key, bucket)) = spec.rsplit_once('@').filter(|(key, _)| !key.is_empty()) else {
+            anyhow::bail!(
+                "Invalid --crosstab: expected FIELD@INTERVAL, got '{}'",
+                spec
+            );
+        };
+        let bucket = timespec::parse_duration(bucket).context("Invalid --crosstab")?;
+        return EventTimeline::new(bucket, Some(key.to_string()))
+            .map(Some)
+            .map_err(|e| anyhow::anyhow!("Invalid --crosstab: {}", e));
+    }
+    let Some(bucket) = &cli.timeline else {
+        return Ok(None);
+    };
+    let bucket = timespec::parse_duration(bucket).context("Invalid --timeline")?;
+    let split_by = cli.timeline_by_level.then(|| "level".to_string());
+    EventTimeline::new(bucket, split_by)
+        .map(Some)
+        .map_err(|e| anyhow::anyhow!("Invalid --timeline: {}", e))
+}
+
+fn prepare_gaps(cli: &Cli) -> Result<Option<GapDetector>> {
+    let Some(threshold) = &cli.gaps else {
+        return Ok(None);
+    };
+    let threshold = timespec::parse_duration(threshold).context("Invalid --gaps")?;
+    GapDetector::new(threshold, cli.gaps_by.clone())
+        .map(Some)
+        .map_err(|e| anyhow::anyhow!("Invalid --gaps: {}", e))
+}
+
+fn prepare_bursts(cli: &Cli) -> Result<Option<BurstDetector>> {
+    if !cli.bursts {
+        return Ok(None);
+    }
+    let window = timespec::parse_duration(&cli.burst_window).context("Invalid --burst-window")?;
+    BurstDetector::new(window, cli.burst_factor)
+        .map(Some)
+        .map_err(|e| anyhow::anyhow!("Invalid --bursts settings: {}", e))
+}
+
+fn prepare_sessions(cli: &Cli) -> Result<Option<Sessionizer>> {
+    let Some(key) = &cli.sessions else {
+        return Ok(None);
+    };
+    let gap = timespec::parse_duration(&cli.session_gap).context("Invalid --session-gap")?;
+    Sessionizer::new(key.clone(), gap)
+        .map(Some)
+        .map_err(|e| anyhow::anyhow!("Invalid --session-gap: {}", e))
+}
+
+fn prepare_stats(cli: &Cli) -> Result<Stats> {
+    let mut stats = Stats::new();
+    stats.aggregates =
+        NumericAggregates::parse(&cli.agg).map_err(|e| anyhow::anyhow!("Invalid --agg: {}", e))?;
+    if cli.stats || cli.stats_only {
+        stats.durations =
+            DurationStats::new(prepare_types(cli)?.duration_keys(), cli.normalize_units);
+    }
+    Ok(stats)
+}
+
+fn prepare_stats_ticker(cli: &Cli) -> Result<Option<StatsTicker>> {
+    let Some(interval) = &cli.stats_interval else {
+        return Ok(None);
+    };
+    let interval = timespec::parse_duration(interval)
+        .context("Invalid --stats-interval")?
+        .to_std()
+        .ok()
+        .filter(|interval| !interval.is_zero())
+        .context("Invalid --stats-interval: must be positive")?;
+    Ok(Some(StatsTicker::new(
+        interval,
+        cli.stats_format,
+        Instant::now(),
+    )))
+}
+
+fn prepare_metrics(cli: &Cli) -> Result<Option<MetricsServer>> {
+    let Some(addr) = &cli.prom_listen else {
+        return Ok(None);
+    };
+    let server =
+        MetricsServer::bind(addr).with_context(|| format!("Invalid --prom-listen {}", addr))?;
+    eprintln!("Serving metrics on http://{}/metrics", server.local_addr());
+    Ok(Some(server))
+}
+
+fn prepare_pairs(cli: &Cli) -> Result<Option<Pairs>> {
+    let ([start, end], Some(key)) = (cli.pair.as_slice(), &cli.pair_key) else {
+        return Ok(None);
+    };
+    Ok(Some(Pairs::new(
+        parse_labeled_filter("--pair", "start", start)?,
+        parse_labeled_filter("--pair", "end", end)?,
+        key.clone(),
+    )))
+}
+
+fn prepare_latency(cli: &Cli) -> Result<Option<Latency>> {
+    let ([from, to], Some(key)) = (cli.latency_between.as_slice(), &cli.by) else {
+        return Ok(None);
+    };
+    Ok(Some(Latency::new(
+        parse_labeled_filter("--latency-between", "from", from)?,
+        parse_labeled_filter("--latency-between", "to", to)?,
+        key.clone(),
+    )))
+}
+
+/// A filter expression optionally labeled with its role, e.g. `start: msg =~ "started"`;
+/// the labels only help reading, the order of the values tells the roles apart
+fn parse_labeled_filter(flag: &str, label: &str, source: &str) -> Result<Expr> {
+    let source = source.trim();
+    let source = source
+        .strip_prefix(label)
+        .and_then(|rest| rest.strip_prefix(':'))
+        .unwrap_or(source);
+    Expr::parse(source)
+        .map_err(|e| anyhow::anyhow!("Invalid {} {} '{}': {}", flag, label, source, e))
+}
+
+fn prepare_drain_timeline(cli: &Cli) -> Result<Option<DrainTimeline>> {
+    let Some(bucket) = &cli.drain_timeline else {
+        return Ok(None);
+    };
+    let bucket = timespec::parse_duration(bucket).context("Invalid --drain-timeline")?;
+    DrainTimeline::new(bucket)
+        .map(Some)
+        .map_err(|e| anyhow::anyhow!("Invalid --drain-timeline: {}", e))
+}
+
+fn prepare_template_filter(cli: &Cli) -> Option<TemplateFilter> {
+    let mut filter = TemplateFilter::new();
+    for id in &cli.template_id {
+        filter = filter.with_template_id(id);
+    }
+    for pattern in &cli.template_match {
+        filter = filter.with_template_match(pattern);
+    }
+    (!filter.is_empty()).then_some(filter)
+}
+
+/// `kelora drain-merge`: fold the state files given as inputs into one
+fn merge_drain_states(cli: &Cli) -> Result<()> {
+    if cli.files.is_empty() {
+        anyhow::bail!("kelora drain-merge needs the state files to merge");
+    }
+    let mut drain = prepare_drain(cli)?;
+    for path in &cli.files {
+        let state = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        drain
+            .merge(&state)
+            .map_err(|e| anyhow::anyhow!("Invalid drain state {}: {}", path.display(), e))?;
+    }
+    match &cli.output {
+        Some(path) => save_drain_state(&drain, path),
+        None => {
+            println!("{}", drain.save());
+            Ok(())
+        }
+    }
+}
+
+/// Replace the state file in one step, so an interrupted run can't leave half of it
+fn save_drain_state(drain: &Drain, path: &std::path::Path) -> Result<()> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    let temp = PathBuf::from(temp);
+    std::fs::write(&temp, drain.save())
+        .and_then(|_| std::fs::rename(&temp, path))
+        .with_context(|| format!("Failed to save --drain-state {}", path.display()))
+}
+
+fn prepare_types(cli: &Cli) -> Result<TypeCoercion> {
+    TypeCoercion::parse(&cli.types).map_err(|e| anyhow::anyhow!("Invalid --types: {}", e))
+}
+
+fn prepare_key_normalizer(cli: &Cli) -> Result<Option<KeyNormalizer>> {
+    let mut normalizer = KeyNormalizer::new(cli.normalize_keys.map(KeyCase::from));
+    if cli.normalize_keys.is_some() {
+        let config = Config::load_if_present(cli.config.as_deref())?;
+        for (canonical, aliases) in &config.aliases {
+            for alias in aliases {
+                normalizer = normalizer.with_alias(alias, canonical);
+            }
+        }
+    }
+    for spec in &cli.alias {
+        let (alias, canonical) = spec
+            .split_once('=')
+            .map(|(alias, canonical)| (alias.trim(), canonical.trim()))
+            .filter(|(alias, canonical)| !alias.is_empty() && !canonical.is_empty())
+            .ok_or_else(|| {
+                anyhow::anyhow!("Invalid --alias: expected ALIAS=CANONICAL, got '{}'", spec)
+            })?;
+        normalizer = normalizer.with_alias(alias, canonical);
+    }
+    Ok((!normalizer.is_empty()).then_some(normalizer))
+}
+
+fn prepare_renames(cli: &Cli) -> Result<Vec<(String, String)>> {
+    cli.rename
+        .iter()
+        .map(|spec| {
+            spec.split_once('=')
+                .map(|(from, to)| (from.trim().to_string(), to.trim().to_string()))
+                .filter(|(from, to)| !from.is_empty() && !to.is_empty())
+                .ok_or_else(|| {
+                    anyhow::anyhow!("Invalid --rename: expected OLD=NEW, got '{}'", spec)
+                })
+        })
+        .collect()
+}
+
+fn prepare_levels_filter(levels: &[String]) -> Result<Option<LevelFilter>> {
     if levels.is_empty() {
-        None
-    } else {
-        Some(levels.iter().map(|level| level.to_uppercase()).collect())
+        return Ok(None);
+    }
+    LevelFilter::parse(levels)
+        .map(Some)
+        .map_err(|e| anyhow::anyhow!("Invalid --level: {}", e))
+}
+
+/// Merge the `--apply` filter sets into the command line. Conditions add up, while
+/// selections given on the command line (levels, keys, time window, ...) win over the set's
+fn apply_filter_sets(cli: &mut Cli) -> Result<()> {
+    if cli.apply.is_empty() {
+        return Ok(());
     }
+    let config = Config::load(cli.config.as_deref())?;
+    for name in cli.apply.clone() {
+        let set = config.filter_set(&name)?.clone();
+        if cli.levels.is_empty() {
+            cli.levels = set.level;
+        }
+        cli.min_level = cli.min_level.take().or(set.min_level);
+        cli.where_filters.extend(set.where_filters);
+        cli.where_not_filters.extend(set.where_not);
+        cli.grep = cli.grep.take().or(set.grep);
+        cli.grep_fields.extend(set.grep_field);
+        cli.grep_v.extend(set.grep_v);
+        cli.filters.extend(set.filter);
+        if cli.source.is_empty() {
+            cli.source = set.source;
+        }
+        cli.since = cli.since.take().or(set.since);
+        cli.until = cli.until.take().or(set.until);
+        if cli.keys.is_empty() {
+            cli.keys = set.keys;
+        }
+        cli.exclude_keys.extend(set.exclude_keys);
+    }
+    Ok(())
 }
 
 fn prepare_keys_filter(cli: &Cli) -> Option<Vec<String>> {
@@ -241,64 +2716,196 @@ fn prepare_keys_filter(cli: &Cli) -> Option<Vec<String>> {
     }
 }
 
+/// How long to wait for input before showing stats or refreshing metrics that are due anyway
+const IDLE_TICK: Duration = Duration::from_secs(1);
+
+/// The lines of an input. When stats are shown or metrics served, the lines are read on a
+/// thread of their own, and `None` stands for each [`IDLE_TICK`] that passes without one
+fn read_lines(
+    reader: Box<dyn BufRead + Send>,
+    timed: bool,
+) -> Box<dyn Iterator<Item = Option<io::Result<String>>>> {
+    if !timed {
+        return Box::new(reader.lines().map(Some));
+    }
+    let (sender, receiver) = mpsc::sync_channel(1024);
+    thread::spawn(move || {
+        for line in reader.lines() {
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    Box::new(std::iter::from_fn(move || {
+        match receiver.recv_timeout(IDLE_TICK) {
+            Ok(line) => Some(Some(line)),
+            Err(RecvTimeoutError::Timeout) => Some(None),
+            Err(RecvTimeoutError::Disconnected) => None,
+        }
+    }))
+}
+
 fn process_reader(
-    reader: Box<dyn BufRead>,
-    parser: &dyn LogParser,
-    formatter: &dyn Formatter,
+    reader: Box<dyn BufRead + Send>,
+    source: Option<&str>,
+    pipeline: &mut Pipeline,
     stats: &mut Stats,
-    levels_filter: &Option<Vec<String>>,
-    keys_filter: &Option<Vec<String>>,
     cli: &Cli,
 ) -> Result<()> {
-    for (line_num, line_result) in reader.lines().enumerate() {
+    let mut lines_read = 0;
+    let timed = pipeline.stats_ticker.is_some() || pipeline.metrics.is_some();
+    for input in read_lines(reader, timed) {
+        if INTERRUPTED.load(Ordering::Relaxed) {
+            break;
+        }
+        if let Some(ticker) = &mut pipeline.stats_ticker {
+            if let Some(snapshot) = ticker.tick(Instant::now(), stats) {
+                eprintln!("{}", snapshot);
+            }
+        }
+        if let Some(metrics) = &mut pipeline.metrics {
+            metrics.refresh(Instant::now(), stats.events_shown, |rate| {
+                stats.to_prometheus(rate)
+            });
+        }
+        // The input is quiet, but the stats or metrics may have been due
+        let Some(line_result) = input else {
+            continue;
+        };
+        let line_num = lines_read;
+        lines_read += 1;
         let line = line_result.with_context(|| format!("Failed to read line {}", line_num + 1))?;
         stats.lines_seen += 1;
+        // Counting one byte for the line break
+        stats.bytes_read += line.len() as u64 + 1;
+        stats.line_lengths.add(line.len());
 
         // Skip empty lines
         if line.trim().is_empty() {
             continue;
         }
 
-        match parser.parse(&line) {
+        match pipeline.parser.parse(&line) {
             Ok(mut event) => {
+                event.source = source.map(str::to_string);
+                if let Some(options) = &pipeline.timestamps {
+                    event.extract_timestamp(options);
+                }
+                if cli.flatten {
+                    event.flatten(&cli.flatten_sep, cli.flatten_depth);
+                }
+                if !cli.expand_json.is_empty() {
+                    event.expand_json(&cli.expand_json, &cli.flatten_sep);
+                }
+                for decoder in &pipeline.decoders {
+                    decoder.apply(&mut event);
+                }
+                if let Some(normalizer) = &pipeline.key_normalizer {
+                    normalizer.apply(&mut event);
+                }
+                for (from, to) in &pipeline.renames {
+                    event.rename_field(from, to);
+                }
+                for extractor in &pipeline.extractors {
+                    extractor.apply(&mut event);
+                }
+                if let Some(scraper) = &pipeline.kv_scraper {
+                    scraper.apply(&mut event);
+                }
+                if let Some(user_agents) = &pipeline.user_agents {
+                    user_agents.apply(&mut event);
+                }
+                if let Some(geoip) = &pipeline.geoip {
+                    geoip.apply(&mut event);
+                }
+                if let Some(rdns) = &pipeline.rdns {
+                    rdns.apply(&mut event);
+                }
+                for lookup in &pipeline.lookups {
+                    lookup.apply(&mut event);
+                }
+                if cli.normalize_units {
+                    normalize_units(&mut event);
+                }
+                if let Some(types) = &pipeline.types {
+                    stats.type_errors += types.apply(&mut event);
+                }
+                for derivation in &pipeline.derivations {
+                    derivation.apply(&mut event);
+                }
+
                 // Apply level filtering first
-                if let Some(ref levels) = levels_filter {
-                    if let Some(ref level) = event.level {
-                        if !levels.contains(&level.to_uppercase()) {
-                            stats.filtered_out += 1;
-                            continue;
-                        }
-                    } else {
-                        // If we're filtering by level but event has no level, filter it out
+                // Events without a level never match
+                if let Some(ref levels) = pipeline.levels_filter {
+                    if !levels.matches(&event) {
                         stats.filtered_out += 1;
                         continue;
                     }
                 }
 
-                // Apply key filtering
-                if let Some(ref keys) = keys_filter {
-                    event.filter_keys(keys);
+                // Events without a recognizable level are below any minimum
+                if let Some(min_level) = pipeline.min_level {
+                    if event.level_rank().is_none_or(|rank| rank < min_level) {
+                        stats.filtered_out += 1;
+                        continue;
+                    }
+                }
 
-                    // Skip events that have no displayable content after filtering
-                    if !event.has_displayable_content() {
+                // Drop events outside the --since/--until window
+                if pipeline.since.is_some() || pipeline.until.is_some() {
+                    let in_range = event.timestamp.is_some_and(|ts| {
+                        pipeline.since.is_none_or(|since| ts >= since)
+                            && pipeline.until.is_none_or(|until| ts < until)
+                    });
+                    if !in_range {
                         stats.filtered_out += 1;
                         continue;
                     }
                 }
 
-                // Record the event for stats
-                stats.record_event(&event);
-
-                // Output the event (unless we're in stats-only mode)
-                if !cli.stats_only {
-                    // Handle broken pipe gracefully (e.g., when piping to `head`)
-                    if let Err(e) = writeln!(io::stdout(), "{}", formatter.format(&event)) {
-                        if e.kind() == std::io::ErrorKind::BrokenPipe {
-                            // Broken pipe is expected when piping to tools like `head`
-                            break;
-                        } else {
-                            return Err(anyhow::Error::from(e));
+                // Narrow a merged view back down to some files or hosts
+                if !pipeline
+                    .source_filter
+                    .as_ref()
+                    .is_none_or(|filter| filter.matches(&event))
+                {
+                    stats.filtered_out += 1;
+                    continue;
+                }
+
+                // All --where, --filter and --grep conditions of some group must hold
+                match check_condition_groups(&pipeline.condition_groups, &event) {
+                    Ok(()) => {}
+                    Err(Rejection::Filtered) => {
+                        stats.filtered_out += 1;
+                        continue;
+                    }
+                    Err(Rejection::GrepMiss) => {
+                        stats.grep_misses += 1;
+                        continue;
+                    }
+                }
+
+                // A jq program can drop, reshape or multiply the event
+                let events = match &pipeline.jq {
+                    Some(program) => match program.apply(&event) {
+                        Ok(events) => events,
+                        Err(e) => {
+                            stats.filtered_out += 1;
+                            if cli.debug {
+                                eprintln!("jq error on line {}: {}", line_num + 1, e);
+                            }
+                            continue;
                         }
+                    },
+                    None => vec![event],
+                };
+                if events.is_empty() {
+                    stats.filtered_out += 1;
+                }
+                for event in events {
+                    if !emit_event(event, pipeline, stats, cli)? {
+                        return Ok(());
                     }
                 }
             }
@@ -314,30 +2921,439 @@ fn process_reader(
     Ok(())
 }
 
+/// Keep only first/last occurrences, mask sensitive data, apply key filtering and
+/// deduplication, then output the event (or the run of duplicates it ends). Returns false once
+/// output is closed
+fn emit_event(event: Event, pipeline: &mut Pipeline, stats: &mut Stats, cli: &Cli) -> Result<bool> {
+    let event = match &mut pipeline.distinct {
+        Some(distinct) => match distinct.push(event) {
+            Some(event) => event,
+            None => return Ok(true),
+        },
+        None => event,
+    };
+    emit_distinct(event, pipeline, stats, cli)
+}
+
+/// The rest of [`emit_event`], for events that passed `--first-of`/`--last-of`
+fn emit_distinct(
+    mut event: Event,
+    pipeline: &mut Pipeline,
+    stats: &mut Stats,
+    cli: &Cli,
+) -> Result<bool> {
+    if let Some(dropper) = &pipeline.dropper {
+        dropper.apply(&mut event);
+    }
+
+    // Mask sensitive data before anything can output it
+    for hasher in &pipeline.hashers {
+        hasher.apply(&mut event);
+    }
+    if let Some(redactor) = &pipeline.redactor {
+        redactor.apply(&mut event);
+    }
+
+    // Skip events that have no displayable content after dropping fields
+    if pipeline.dropper.is_some() && !event.has_displayable_content() {
+        stats.filtered_out += 1;
+        return Ok(true);
+    }
+
+    let event = match &mut pipeline.dedup {
+        Some(dedup) => match dedup.push(event) {
+            Some(previous) => previous,
+            None => return Ok(true),
+        },
+        None => event,
+    };
+    write_event(event, pipeline, stats, cli)
+}
+
+/// Record and output one event. Returns false once output is closed
+fn write_event(
+    mut event: Event,
+    pipeline: &mut Pipeline,
+    stats: &mut Stats,
+    cli: &Cli,
+) -> Result<bool> {
+    if let Some(throttle) = &mut pipeline.throttle {
+        let (pass, dropped) = throttle.admit(Instant::now());
+        report_throttled(dropped, throttle);
+        if !pass {
+            stats.throttled += 1;
+            return Ok(true);
+        }
+    }
+    if cli.ts_delta || cli.ts_elapsed {
+        pipeline
+            .offsets
+            .annotate(&mut event, cli.ts_delta, cli.ts_elapsed);
+    }
+
+    // Record the event for stats
+    stats.record_event(&event);
+
+    // Counted instead of output
+    if let Some(count_by) = &mut pipeline.count_by {
+        count_by.add(&event);
+        return Ok(true);
+    }
+    if let Some(top) = &mut pipeline.top {
+        top.add(&event);
+        return Ok(true);
+    }
+    if let Some(cardinality) = &mut pipeline.cardinality {
+        cardinality.add(&event);
+        return Ok(true);
+    }
+    if let Some(schema) = &mut pipeline.schema {
+        schema.add(&event);
+        return Ok(true);
+    }
+    if let Some(histogram) = &mut pipeline.histogram {
+        histogram.add(&event);
+        return Ok(true);
+    }
+    if let Some(timeline) = &mut pipeline.event_timeline {
+        timeline.add(&event);
+        return Ok(true);
+    }
+    if let Some(bursts) = &mut pipeline.bursts {
+        bursts.add(&event);
+        return Ok(true);
+    }
+    if let Some(sessions) = &mut pipeline.sessions {
+        for session in sessions.add(&event) {
+            if !output_event(&session, pipeline, cli)? {
+                return Ok(false);
+            }
+        }
+        return Ok(true);
+    }
+    if let Some(latency) = &mut pipeline.latency {
+        latency.add(&event);
+        return Ok(true);
+    }
+    if let Some(interarrival) = &mut pipeline.interarrival {
+        interarrival.add(&event);
+        return Ok(true);
+    }
+    if let Some(pairs) = &mut pipeline.pairs {
+        return match pairs.add(&event) {
+            Some(pair) => output_event(&pair, pipeline, cli),
+            None => Ok(true),
+        };
+    }
+    if let Some(distinct) = &mut pipeline.distinct_values {
+        return match distinct.add(&event) {
+            Some(value) => output_event(&value, pipeline, cli),
+            None => Ok(true),
+        };
+    }
+    if let Some(gaps) = &mut pipeline.gaps {
+        return match gaps.add(&event) {
+            Some(gap) => output_event(&gap, pipeline, cli),
+            None => Ok(true),
+        };
+    }
+
+    // Templates are mined instead of output
+    if let Some(drain) = &mut pipeline.drain {
+        let Some(index) = drain.add_event(&event) else {
+            return Ok(true);
+        };
+        if let Some(diff) = &mut pipeline.diff {
+            diff.add(index, &event);
+        }
+        if let (Some(params), Some(message)) = (&mut pipeline.params, &event.message) {
+            params.add(index, message);
+        }
+        if let Some(timeline) = &mut pipeline.timeline {
+            timeline.add(index, &event);
+        }
+        let cluster = drain.cluster(index);
+        let selected =
+            (pipeline.template_filter.as_ref()).is_none_or(|filter| filter.selects(cluster));
+        if cli.drain_annotate {
+            event.set_field("template_id".to_string(), FieldValue::String(cluster.id()));
+            event.set_field(
+                "template".to_string(),
+                FieldValue::String(cluster.template()),
+            );
+        }
+        if cluster.count == 1 {
+            if let Some(command) = &cli.alert_command {
+                run_alert_command(command, cluster, &event);
+            }
+            if cli.alert_new_template && !output_event(&event, pipeline, cli)? {
+                return Ok(false);
+            }
+        }
+        if !selected {
+            return Ok(true);
+        }
+        if let Some(held_events) = &mut pipeline.held_events {
+            held_events
+                .hold(index, event)
+                .context("Failed to hold back an event for --drain-anomalies")?;
+        } else if cli.drain_annotate || pipeline.template_filter.is_some() {
+            return output_event(&event, pipeline, cli);
+        }
+        return Ok(true);
+    }
+
+    output_event(&event, pipeline, cli)
+}
+
+/// Run `--alert-command` for the first event of a template. A failing command is reported but
+/// doesn't stop processing
+fn run_alert_command(command: &str, cluster: &Cluster, event: &Event) {
+    let status = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env("KELORA_TEMPLATE_ID", cluster.id())
+        .env("KELORA_TEMPLATE", cluster.template())
+        .env(
+            "KELORA_MESSAGE",
+            event.message.as_deref().unwrap_or_default(),
+        )
+        .status();
+    match status {
+        Ok(status) if status.success() => {}
+        Ok(status) => eprintln!("Alert command failed: {}", status),
+        Err(e) => eprintln!("Alert command failed: {}", e),
+    }
+}
+
+/// Output an event (unless we're in stats-only mode). Returns false once output is closed
+fn output_event(event: &Event, pipeline: &mut Pipeline, cli: &Cli) -> Result<bool> {
+    // Keys are selected only now, so aggregations and mining see the whole events
+    let selected;
+    let event = if pipeline.keys_filter.is_some() || !pipeline.exclude_keys.is_empty() {
+        let mut event = event.clone();
+        if let Some(keys) = &pipeline.keys_filter {
+            event.filter_keys(keys);
+        }
+        event.exclude_keys(&pipeline.exclude_keys);
+        // Skip events that have no displayable content after filtering
+        if !event.has_displayable_content() {
+            return Ok(true);
+        }
+        selected = event;
+        &selected
+    } else {
+        event
+    };
+
+    match &mut pipeline.output {
+        Output::Sink(sink) => sink.write(event)?,
+        Output::Text(formatter, out) if !cli.stats_only => {
+            // Handle broken pipe gracefully (e.g., when piping to `head`)
+            if let Err(e) = formatter
+                .write_event(event, out)
+                .and_then(|_| out.end_record())
+            {
+                if e.kind() == std::io::ErrorKind::BrokenPipe {
+                    // Broken pipe is expected when piping to tools like `head`
+                    return Ok(false);
+                } else {
+                    return Err(anyhow::Error::from(e));
+                }
+            }
+        }
+        Output::Text(_, _) => {}
+    }
+    Ok(true)
+}
+
+/// Tell the user (on stderr, out of the way of the events) what a throttle window dropped
+fn report_throttled(dropped: usize, throttle: &Throttle) {
+    if dropped > 0 {
+        eprintln!(
+            "Throttled: {} events dropped over the {} limit",
+            dropped,
+            throttle.spec()
+        );
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
-    fn test_format_duration() {
-        let duration = chrono::Duration::seconds(3661); // 1h 1m 1s
-        assert_eq!(format_duration(duration), "1h1m1s");
+    fn test_time_offsets() {
+        assert_eq!(format_offset(chrono::Duration::milliseconds(23)), "+0.023s");
+        assert_eq!(
+            format_offset(chrono::Duration::milliseconds(-1500)),
+            "-1.500s"
+        );
+        assert_eq!(format_offset(chrono::Duration::seconds(90)), "+90.000s");
+
+        let mut offsets = TimeOffsets::default();
+        let mut annotated = Vec::new();
+        for ts in [
+            Some("2024-03-14T10:00:00.000Z"),
+            None,
+            Some("2024-03-14T10:00:00.250Z"),
+            Some("2024-03-14T10:00:01.000Z"),
+        ] {
+            let mut event = Event::new();
+            event.timestamp = ts.map(|ts| ts.parse().unwrap());
+            offsets.annotate(&mut event, true, true);
+            annotated.push((
+                event.fields.get("ts_delta").map(|v| v.to_string()),
+                event.fields.get("ts_elapsed").map(|v| v.to_string()),
+            ));
+        }
+        let offset =
+            |delta: &str, elapsed: &str| (Some(delta.to_string()), Some(elapsed.to_string()));
+        assert_eq!(
+            annotated,
+            vec![
+                offset("+0.000s", "+0.000s"),
+                (None, None),
+                offset("+0.250s", "+0.250s"),
+                offset("+0.750s", "+1.000s"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_or_groups() {
+        let cli = parse_cli([
+            "kelora",
+            "--where",
+            "level=ERROR",
+            "--or",
+            "--where",
+            "level=WARN",
+            "--grep-field",
+            "component=^pay",
+        ]);
+        let groups = prepare_condition_groups(&cli).unwrap();
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].where_filters.len(), 1);
+        assert_eq!(groups[1].where_filters.len(), 1);
+        assert_eq!(groups[1].grep_filters.len(), 1);
 
-        let duration = chrono::Duration::seconds(61); // 1m 1s
-        assert_eq!(format_duration(duration), "1m1s");
+        let event = |level: &str, component: &str| {
+            let mut event = Event::new();
+            event.level = Some(level.to_string());
+            event.set_field(
+                "component".to_string(),
+                event::FieldValue::String(component.to_string()),
+            );
+            event
+        };
+        assert_eq!(
+            check_condition_groups(&groups, &event("ERROR", "db")),
+            Ok(())
+        );
+        assert_eq!(
+            check_condition_groups(&groups, &event("WARN", "payments")),
+            Ok(())
+        );
+        assert_eq!(
+            check_condition_groups(&groups, &event("WARN", "auth")),
+            Err(Rejection::GrepMiss)
+        );
+        assert_eq!(
+            check_condition_groups(&groups, &event("INFO", "payments")),
+            Err(Rejection::Filtered)
+        );
+
+        // Conditions added after parsing, like those of filter sets, apply to every group
+        let mut cli = cli;
+        cli.where_not_filters.push("component=db".to_string());
+        let groups = prepare_condition_groups(&cli).unwrap();
+        assert!(groups.iter().all(|group| group.where_filters.len() == 2));
 
-        let duration = chrono::Duration::seconds(30); // 30s
-        assert_eq!(format_duration(duration), "30s");
+        let cli = parse_cli(["kelora", "--where", "level=ERROR", "--or"]);
+        assert!(prepare_condition_groups(&cli).is_err());
     }
 
     #[test]
     fn test_prepare_levels_filter() {
         let levels = vec!["error".to_string(), "warn".to_string()];
-        let result = prepare_levels_filter(&levels);
-        assert_eq!(result, Some(vec!["ERROR".to_string(), "WARN".to_string()]));
+        let result = prepare_levels_filter(&levels).unwrap().unwrap();
+        let event = |level: &str| {
+            let mut event = Event::new();
+            event.level = Some(level.to_string());
+            event
+        };
+        assert!(result.matches(&event("ERROR")));
+        assert!(result.matches(&event("WARN")));
+        assert!(!result.matches(&event("INFO")));
 
         let empty_levels: Vec<String> = vec![];
-        let result = prepare_levels_filter(&empty_levels);
-        assert_eq!(result, None);
+        let result = prepare_levels_filter(&empty_levels).unwrap();
+        assert!(result.is_none());
+        assert!(prepare_levels_filter(&["9".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!(format_bytes(0), "0 B");
+        assert_eq!(format_bytes(1023), "1023 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(5 * 1024 * 1024 * 1024), "5.0 GiB");
+    }
+
+    #[test]
+    fn test_stats_ticker() {
+        let start = Instant::now();
+        let mut ticker = StatsTicker::new(Duration::from_secs(10), StatsFormat::Text, start);
+        let mut stats = Stats::new();
+        stats.events_shown = 50;
+        stats.lines_seen = 60;
+        assert_eq!(ticker.tick(start + Duration::from_secs(5), &stats), None);
+        let snapshot = ticker
+            .tick(start + Duration::from_secs(10), &stats)
+            .unwrap();
+        assert!(
+            snapshot.ends_with(
+                ": 50 events shown (+50, 5/s), 60 lines seen, 0 parse errors, 0 filtered"
+            ),
+            "{}",
+            snapshot
+        );
+        assert_eq!(ticker.tick(start + Duration::from_secs(15), &stats), None);
+
+        let mut ticker = StatsTicker::new(Duration::from_secs(10), StatsFormat::Json, start);
+        let snapshot = ticker
+            .tick(start + Duration::from_secs(20), &stats)
+            .unwrap();
+        let snapshot: serde_json::Value = serde_json::from_str(&snapshot).unwrap();
+        assert_eq!(snapshot["events_shown"], 50);
+        assert_eq!(snapshot["events_per_second"], 2.5);
+    }
+
+    #[test]
+    fn test_stats_to_prometheus() {
+        let mut stats = Stats::new();
+        stats.aggregates = NumericAggregates::parse(&["latency_ms:max".to_string()]).unwrap();
+        let mut event = Event::new();
+        event.level = Some("error".to_string());
+        event.set_field("latency_ms".to_string(), FieldValue::Number(120.0));
+        stats.record_event(&event);
+        stats.record_event(&Event::new());
+        stats.lines_seen = 3;
+        stats.parse_errors = 1;
+
+        let text = stats.to_prometheus(2.0);
+        assert!(text.contains("# TYPE kelora_lines_total counter\nkelora_lines_total 3\n"));
+        assert!(text.contains("kelora_events_total{level=\"error\"} 1\n"));
+        assert!(text.contains("kelora_events_total{level=\"none\"} 1\n"));
+        assert!(text.contains("kelora_parse_errors_total 1\n"));
+        assert!(
+            text.contains("# TYPE kelora_events_per_second gauge\nkelora_events_per_second 2\n")
+        );
+        assert!(
+            text.contains("kelora_field{field=\"latency_ms\",statistic=\"max\"} 120\n"),
+            "{}",
+            text
+        );
     }
 }
@@ -0,0 +1,213 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often the served metrics are brought up to date, at most
+const REFRESH: Duration = Duration::from_secs(1);
+
+/// How long a client may take to send its request, and to take the answer
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Most bytes of a request that are read; the rest is ignored
+const MAX_REQUEST: u64 = 8 * 1024;
+
+/// Most clients served at once; further connections are closed right away
+const MAX_CLIENTS: usize = 16;
+
+/// Serves metrics in the Prometheus text format on `/metrics` (`--prom-listen 0.0.0.0:9090`)
+/// from background threads, one per client up to [`MAX_CLIENTS`], while the main thread reads
+/// the input and refreshes them every second, also while the input is quiet
+pub struct MetricsServer {
+    metrics: Arc<Mutex<String>>,
+    local_addr: SocketAddr,
+    /// When the metrics were last refreshed, with the events shown by then, for the rate
+    last: Option<(Instant, usize)>,
+}
+
+impl MetricsServer {
+    pub fn bind(addr: &str) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        let metrics = Arc::new(Mutex::new(String::new()));
+        let served = Arc::clone(&metrics);
+        let clients = Arc::new(AtomicUsize::new(0));
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if clients.load(Ordering::SeqCst) >= MAX_CLIENTS {
+                    continue;
+                }
+                clients.fetch_add(1, Ordering::SeqCst);
+                // A client that is slow or goes away doesn't concern the others
+                let served = Arc::clone(&served);
+                let clients = Arc::clone(&clients);
+                thread::spawn(move || {
+                    let _ = respond(stream, &served);
+                    clients.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+        });
+        Ok(Self {
+            metrics,
+            local_addr,
+            last: None,
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Render the metrics anew, given the events shown per second since the last time,
+    /// unless that was less than a second ago
+    pub fn refresh(
+        &mut self,
+        now: Instant,
+        events_shown: usize,
+        render: impl FnOnce(f64) -> String,
+    ) {
+        let rate = match self.last {
+            Some((last, _)) if now.duration_since(last) < REFRESH => return,
+            Some((last, events)) => {
+                events_shown.saturating_sub(events) as f64 / now.duration_since(last).as_secs_f64()
+            }
+            None => 0.0,
+        };
+        self.last = Some((now, events_shown));
+        let metrics = render(rate);
+        if let Ok(mut served) = self.metrics.lock() {
+            *served = metrics;
+        }
+    }
+}
+
+/// Reads from a client until a deadline for the whole request, however slowly it trickles in
+struct Deadline<'a> {
+    stream: &'a TcpStream,
+    deadline: Instant,
+}
+
+impl Read for Deadline<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let left = self
+            .deadline
+            .checked_duration_since(Instant::now())
+            .filter(|left| !left.is_zero())
+            .ok_or(io::ErrorKind::TimedOut)?;
+        self.stream.set_read_timeout(Some(left))?;
+        self.stream.read(buf)
+    }
+}
+
+fn respond(mut stream: TcpStream, metrics: &Mutex<String>) -> io::Result<()> {
+    stream.set_write_timeout(Some(READ_TIMEOUT))?;
+    let deadline = Deadline {
+        stream: &stream,
+        deadline: Instant::now() + READ_TIMEOUT,
+    };
+    let mut reader = BufReader::new(deadline.take(MAX_REQUEST));
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    // The headers aren't needed, but have to be read before answering
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let path = request.split_whitespace().nth(1).unwrap_or("");
+    let (status, body) = if path == "/metrics" || path.starts_with("/metrics?") {
+        let body = metrics.lock().map(|m| m.clone()).unwrap_or_default();
+        ("200 OK", body)
+    } else {
+        (
+            "404 Not Found",
+            "Not found; metrics are on /metrics\n".to_string(),
+        )
+    };
+    drop(reader);
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+/// Text for a label value, with backslashes, quotes and line breaks escaped
+pub fn label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn get(addr: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn test_metrics_server() {
+        let mut server = MetricsServer::bind("127.0.0.1:0").unwrap();
+        let start = Instant::now();
+        server.refresh(start, 0, |rate| {
+            format!("kelora_events_per_second {}\n", rate)
+        });
+        // Too soon to render again
+        server.refresh(start + Duration::from_millis(10), 5, |_| unreachable!());
+        server.refresh(start + Duration::from_secs(2), 10, |rate| {
+            format!("kelora_events_per_second {}\n", rate)
+        });
+
+        let response = get(server.local_addr(), "/metrics");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        assert!(
+            response.ends_with("\r\n\r\nkelora_events_per_second 5\n"),
+            "{}",
+            response
+        );
+        let response = get(server.local_addr(), "/");
+        assert!(
+            response.starts_with("HTTP/1.1 404 Not Found\r\n"),
+            "{}",
+            response
+        );
+
+        assert_eq!(label_value("a \"b\"\\\n"), "a \\\"b\\\"\\\\\\n");
+    }
+
+    #[test]
+    fn test_idle_client() {
+        let server = MetricsServer::bind("127.0.0.1:0").unwrap();
+        // Connected, but never sending a request
+        let _idle = TcpStream::connect(server.local_addr()).unwrap();
+        let response = get(server.local_addr(), "/metrics");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    }
+
+    #[test]
+    fn test_too_many_clients() {
+        let server = MetricsServer::bind("127.0.0.1:0").unwrap();
+        let _idle: Vec<TcpStream> = (0..MAX_CLIENTS)
+            .map(|_| TcpStream::connect(server.local_addr()).unwrap())
+            .collect();
+        // Closed right away rather than given yet another thread
+        let mut rejected = TcpStream::connect(server.local_addr()).unwrap();
+        rejected
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        assert_eq!(rejected.read(&mut [0; 16]).unwrap(), 0);
+    }
+}
@@ -0,0 +1,275 @@
+use crate::event::{Event, FieldValue};
+use std::collections::HashMap;
+
+/// Case style for `--normalize-keys`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyCase {
+    /// `Request-ID` becomes `request-id`
+    Lower,
+    /// `requestId` and `Request-ID` become `request_id`
+    Snake,
+}
+
+impl KeyCase {
+    pub fn apply(self, key: &str) -> String {
+        match self {
+            KeyCase::Lower => key.to_lowercase(),
+            KeyCase::Snake => snake_case(key),
+        }
+    }
+}
+
+fn snake_case(key: &str) -> String {
+    let chars: Vec<char> = key.chars().collect();
+    let mut snake = String::with_capacity(key.len() + 4);
+    for (i, &c) in chars.iter().enumerate() {
+        if c == '-' || c == ' ' {
+            snake.push('_');
+        } else if c.is_uppercase() {
+            let previous = i.checked_sub(1).map(|i| chars[i]);
+            let next = chars.get(i + 1);
+            let word_start = match previous {
+                Some(p) if p.is_lowercase() || p.is_ascii_digit() => true,
+                Some(p) if p.is_uppercase() => next.is_some_and(|n| n.is_lowercase()),
+                _ => false,
+            };
+            if word_start {
+                snake.push('_');
+            }
+            snake.extend(c.to_lowercase());
+        } else {
+            snake.push(c);
+        }
+    }
+    snake
+}
+
+/// Renames keys so events from different services agree on field names (`--normalize-keys`
+/// and `--alias`): keys are first brought into one case, then aliases (compared ignoring case)
+/// are renamed to their canonical name. A key that would clash with one already present keeps
+/// its name
+#[derive(Debug, Default)]
+pub struct KeyNormalizer {
+    case: Option<KeyCase>,
+    /// Lowercased alias to canonical name
+    aliases: HashMap<String, String>,
+}
+
+impl KeyNormalizer {
+    pub fn new(case: Option<KeyCase>) -> Self {
+        Self {
+            case,
+            aliases: HashMap::new(),
+        }
+    }
+
+    /// Rename `alias` to `canonical`
+    pub fn with_alias(mut self, alias: &str, canonical: &str) -> Self {
+        self.aliases
+            .insert(alias.to_lowercase(), canonical.to_string());
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.case.is_none() && self.aliases.is_empty()
+    }
+
+    pub fn apply(&self, event: &mut Event) {
+        let keys: Vec<String> = event.fields.keys().cloned().collect();
+        for key in keys {
+            let normalized = match self.case {
+                Some(case) => case.apply(&key),
+                None => key.clone(),
+            };
+            let target = self
+                .aliases
+                .get(&normalized.to_lowercase())
+                .cloned()
+                .unwrap_or(normalized);
+            if target != key && !event.fields.contains_key(&target) {
+                event.rename_field(&key, &target);
+            }
+        }
+    }
+}
+
+/// Adds canonical numeric fields for string values with units (`--normalize-units`):
+/// durations such as `1.5s`, `230ms` or `1m30s` as `<key>_ms` in milliseconds, and sizes such
+/// as `4KiB` or `2.3 MB` as `<key>_bytes`. A key already ending in the suffix gets the number
+/// in place
+pub fn normalize_units(event: &mut Event) {
+    let mut normalized = Vec::new();
+    for (key, value) in &event.fields {
+        let FieldValue::String(text) = value else {
+            continue;
+        };
+        let (number, suffix) = if let Some(ms) = parse_duration_ms(text) {
+            (ms, "_ms")
+        } else if let Some(bytes) = parse_size_bytes(text) {
+            (bytes, "_bytes")
+        } else {
+            continue;
+        };
+        let target = if key.ends_with(suffix) {
+            key.clone()
+        } else {
+            format!("{}{}", key, suffix)
+        };
+        normalized.push((target, FieldValue::Number(number)));
+    }
+    for (key, value) in normalized {
+        event.assign_field(&key, value);
+    }
+}
+
+/// Split `2.5 MB` into its number and unit
+fn split_number(text: &str) -> Option<(f64, &str)> {
+    let end = text
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(text.len());
+    let number = text[..end].parse().ok()?;
+    Some((number, &text[end..]))
+}
+
+/// Milliseconds in a duration like `230ms`, `1.5 s` or Go's `1h2m3.5s`
+pub fn parse_duration_ms(text: &str) -> Option<f64> {
+    let mut rest = text.trim();
+    let mut total = None;
+    while !rest.is_empty() {
+        let (number, tail) = split_number(rest)?;
+        let tail = if total.is_none() {
+            tail.trim_start()
+        } else {
+            tail
+        };
+        let unit_len = tail
+            .find(|c: char| !c.is_alphabetic())
+            .unwrap_or(tail.len());
+        let scale = match &tail[..unit_len] {
+            "ns" => 1e-6,
+            "us" | "µs" => 1e-3,
+            "ms" => 1.0,
+            "s" | "sec" => 1e3,
+            "m" | "min" => 60e3,
+            "h" => 3600e3,
+            "d" => 86400e3,
+            _ => return None,
+        };
+        total = Some(total.unwrap_or(0.0) + number * scale);
+        rest = &tail[unit_len..];
+    }
+    total
+}
+
+/// Bytes in a size like `4KiB` or `2.3 MB`
+fn parse_size_bytes(text: &str) -> Option<f64> {
+    let (number, unit) = split_number(text.trim())?;
+    Some(number * size_unit_bytes(unit.trim_start())?)
+}
+
+/// Bytes per unit of size, for all sizes kelora reads: KB, MB, ... are decimal and KiB, MiB,
+/// ... binary
+pub fn size_unit_bytes(unit: &str) -> Option<f64> {
+    let scale = match unit.to_lowercase().as_str() {
+        "b" | "bytes" => 1.0,
+        "kb" => 1e3,
+        "mb" => 1e6,
+        "gb" => 1e9,
+        "tb" => 1e12,
+        "kib" => 1024.0,
+        "mib" => 1024.0 * 1024.0,
+        "gib" => 1024.0 * 1024.0 * 1024.0,
+        "tib" => 1024.0 * 1024.0 * 1024.0 * 1024.0,
+        _ => return None,
+    };
+    Some(scale)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(fields: &[(&str, &str)]) -> Event {
+        let mut event = Event::new();
+        for (key, value) in fields {
+            event.set_field(key.to_string(), FieldValue::String(value.to_string()));
+        }
+        event.extract_core_fields();
+        event
+    }
+
+    #[test]
+    fn test_key_case() {
+        assert_eq!(KeyCase::Lower.apply("Request-ID"), "request-id");
+        assert_eq!(KeyCase::Snake.apply("requestId"), "request_id");
+        assert_eq!(KeyCase::Snake.apply("Request-ID"), "request_id");
+        assert_eq!(KeyCase::Snake.apply("HTTPStatus"), "http_status");
+        assert_eq!(KeyCase::Snake.apply("http.statusCode"), "http.status_code");
+        assert_eq!(KeyCase::Snake.apply("user_id2"), "user_id2");
+    }
+
+    #[test]
+    fn test_normalize_keys() {
+        let normalizer = KeyNormalizer::new(Some(KeyCase::Lower))
+            .with_alias("severity", "level")
+            .with_alias("LVL", "level")
+            .with_alias("text", "msg");
+
+        let mut event = self::event(&[("Severity", "WARN"), ("TEXT", "disk low"), ("Host", "a")]);
+        assert_eq!(event.level, None);
+        normalizer.apply(&mut event);
+        let keys: Vec<&String> = event.fields.keys().collect();
+        assert_eq!(keys, ["level", "msg", "host"]);
+        assert_eq!(event.level.as_deref(), Some("WARN"));
+        assert_eq!(event.message.as_deref(), Some("disk low"));
+
+        // The key already using the canonical name wins
+        let mut event = self::event(&[("level", "info"), ("lvl", "debug")]);
+        normalizer.apply(&mut event);
+        let keys: Vec<&String> = event.fields.keys().collect();
+        assert_eq!(keys, ["level", "lvl"]);
+        assert_eq!(event.level.as_deref(), Some("info"));
+
+        let mut event = self::event(&[("Level", "error")]);
+        KeyNormalizer::new(None).apply(&mut event);
+        assert!(event.fields.contains_key("Level"));
+    }
+
+    #[test]
+    fn test_parse_units() {
+        assert_eq!(parse_duration_ms("230ms"), Some(230.0));
+        assert_eq!(parse_duration_ms("1.5s"), Some(1500.0));
+        assert_eq!(parse_duration_ms("2.5 s"), Some(2500.0));
+        assert_eq!(parse_duration_ms("1m30s"), Some(90000.0));
+        assert_eq!(parse_duration_ms("250us"), Some(0.25));
+        assert_eq!(parse_duration_ms("1m 30s"), None);
+        assert_eq!(parse_duration_ms("5 apples"), None);
+        assert_eq!(parse_duration_ms("ms"), None);
+        assert_eq!(parse_duration_ms("42"), None);
+
+        assert_eq!(parse_size_bytes("4KiB"), Some(4096.0));
+        assert_eq!(parse_size_bytes("2.3 MB"), Some(2.3e6));
+        assert_eq!(parse_size_bytes("512b"), Some(512.0));
+        assert_eq!(parse_size_bytes("4 KiB free"), None);
+        assert_eq!(parse_size_bytes("1.2.3 MB"), None);
+    }
+
+    #[test]
+    fn test_normalize_units() {
+        let mut event = event(&[
+            ("duration", "1.5s"),
+            ("latency_ms", "230ms"),
+            ("size", "4KiB"),
+            ("msg", "done in 3s"),
+        ]);
+        normalize_units(&mut event);
+        assert_eq!(
+            event.fields["duration"],
+            FieldValue::String("1.5s".to_string())
+        );
+        assert_eq!(event.fields["duration_ms"], FieldValue::Number(1500.0));
+        assert_eq!(event.fields["latency_ms"], FieldValue::Number(230.0));
+        assert_eq!(event.fields["size_bytes"], FieldValue::Number(4096.0));
+        assert!(!event.fields.contains_key("msg_ms"));
+    }
+}
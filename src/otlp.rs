@@ -0,0 +1,239 @@
+use crate::event::{core_key_name, Event, FieldValue};
+use crate::sinks::Sink;
+use anyhow::{Context, Result};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// OTLP/HTTP log exporter, encoding ExportLogsServiceRequest protobufs by hand
+pub struct OtlpSink {
+    endpoint: String,
+    records: Vec<Vec<u8>>,
+}
+
+/// Log records per export request
+const OTLP_BATCH_SIZE: usize = 512;
+
+impl OtlpSink {
+    /// Export to a collector base URL such as `http://collector:4318`; `/v1/logs` is
+    /// appended unless the URL already names the logs endpoint
+    pub fn new(url: &str) -> Self {
+        let url = url.trim_end_matches('/');
+        let endpoint = if url.ends_with("/v1/logs") {
+            url.to_string()
+        } else {
+            format!("{}/v1/logs", url)
+        };
+        Self {
+            endpoint,
+            records: Vec::new(),
+        }
+    }
+
+    fn export(&mut self) -> Result<()> {
+        if self.records.is_empty() {
+            return Ok(());
+        }
+
+        let request = encode_request(&self.records);
+        ureq::post(&self.endpoint)
+            .header("Content-Type", "application/x-protobuf")
+            .send(&request[..])
+            .with_context(|| format!("Failed to export logs to {}", self.endpoint))?;
+        self.records.clear();
+        Ok(())
+    }
+}
+
+impl Sink for OtlpSink {
+    fn write(&mut self, event: &Event) -> Result<()> {
+        let observed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        self.records.push(encode_log_record(event, observed));
+
+        if self.records.len() >= OTLP_BATCH_SIZE {
+            self.export()?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.export()
+    }
+}
+
+/// OTLP SeverityNumber for a level name (TRACE=1, DEBUG=5, INFO=9, WARN=13, ERROR=17, FATAL=21)
+fn severity_number(level: &str) -> u64 {
+    match level.to_uppercase().as_str() {
+        "TRACE" => 1,
+        "DEBUG" => 5,
+        "INFO" => 9,
+        "NOTICE" => 10,
+        "WARN" | "WARNING" => 13,
+        "ERROR" | "ERR" => 17,
+        "CRITICAL" | "CRIT" => 18,
+        "ALERT" => 19,
+        "FATAL" | "EMERGENCY" | "EMERG" | "PANIC" => 21,
+        _ => 0,
+    }
+}
+
+fn encode_request(records: &[Vec<u8>]) -> Vec<u8> {
+    let mut scope = Vec::new();
+    string_field(&mut scope, 1, "kelora");
+    string_field(&mut scope, 2, env!("CARGO_PKG_VERSION"));
+
+    // ScopeLogs { scope = 1; repeated log_records = 2 }
+    let mut scope_logs = Vec::new();
+    bytes_field(&mut scope_logs, 1, &scope);
+    for record in records {
+        bytes_field(&mut scope_logs, 2, record);
+    }
+
+    // ResourceLogs { resource = 1; repeated scope_logs = 2 }
+    let mut resource_logs = Vec::new();
+    bytes_field(&mut resource_logs, 1, &[]);
+    bytes_field(&mut resource_logs, 2, &scope_logs);
+
+    // ExportLogsServiceRequest { repeated resource_logs = 1 }
+    let mut request = Vec::new();
+    bytes_field(&mut request, 1, &resource_logs);
+    request
+}
+
+fn encode_log_record(event: &Event, observed_nanos: u64) -> Vec<u8> {
+    let mut record = Vec::new();
+
+    if let Some(nanos) = event.timestamp.and_then(|ts| ts.timestamp_nanos_opt()) {
+        fixed64_field(&mut record, 1, nanos as u64);
+    }
+    if let Some(level) = &event.level {
+        varint_field(&mut record, 2, severity_number(level));
+        string_field(&mut record, 3, level);
+    }
+    if let Some(message) = &event.message {
+        bytes_field(
+            &mut record,
+            5,
+            &encode_any_value(&FieldValue::String(message.clone())),
+        );
+    }
+
+    for (key, value) in &event.fields {
+        // Skip raw copies of the core fields, which already map to dedicated record fields
+        let duplicates_core = match core_key_name(key) {
+            Some("timestamp") => event.timestamp.is_some(),
+            Some("level") => event.level.is_some(),
+            Some("message") => event.message.is_some(),
+            _ => false,
+        };
+        if duplicates_core {
+            continue;
+        }
+
+        // KeyValue { key = 1; value = 2 }
+        let mut key_value = Vec::new();
+        string_field(&mut key_value, 1, key);
+        bytes_field(&mut key_value, 2, &encode_any_value(value));
+        bytes_field(&mut record, 6, &key_value);
+    }
+
+    fixed64_field(&mut record, 11, observed_nanos);
+    record
+}
+
+// AnyValue { string_value = 1; bool_value = 2; int_value = 3; double_value = 4 }
+fn encode_any_value(value: &FieldValue) -> Vec<u8> {
+    let mut any = Vec::new();
+    match value {
+        FieldValue::String(s) => string_field(&mut any, 1, s),
+        FieldValue::Boolean(b) => varint_field(&mut any, 2, *b as u64),
+        FieldValue::Number(n) if n.fract() == 0.0 && n.abs() < i64::MAX as f64 => {
+            varint_field(&mut any, 3, *n as i64 as u64)
+        }
+        FieldValue::Number(n) => {
+            tag(&mut any, 4, 1);
+            any.extend_from_slice(&n.to_le_bytes());
+        }
+        FieldValue::Null => {}
+    }
+    any
+}
+
+fn varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn tag(buf: &mut Vec<u8>, field: u32, wire_type: u8) {
+    varint(buf, ((field as u64) << 3) | wire_type as u64);
+}
+
+fn varint_field(buf: &mut Vec<u8>, field: u32, value: u64) {
+    tag(buf, field, 0);
+    varint(buf, value);
+}
+
+fn fixed64_field(buf: &mut Vec<u8>, field: u32, value: u64) {
+    tag(buf, field, 1);
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn bytes_field(buf: &mut Vec<u8>, field: u32, bytes: &[u8]) {
+    tag(buf, field, 2);
+    varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+fn string_field(buf: &mut Vec<u8>, field: u32, s: &str) {
+    bytes_field(buf, field, s.as_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_varint_encoding() {
+        let mut buf = Vec::new();
+        varint(&mut buf, 1);
+        varint(&mut buf, 300);
+        assert_eq!(buf, vec![0x01, 0xac, 0x02]);
+    }
+
+    #[test]
+    fn test_encode_log_record() {
+        let mut event = Event::new();
+        event.level = Some("warn".to_string());
+        event.message = Some("hi".to_string());
+        event.set_field("level".to_string(), FieldValue::String("warn".to_string()));
+        event.set_field("n".to_string(), FieldValue::Number(2.0));
+
+        let record = encode_log_record(&event, 0);
+        assert_eq!(
+            record,
+            vec![
+                0x10, 13, // severity_number = WARN
+                0x1a, 4, b'w', b'a', b'r', b'n', // severity_text
+                0x2a, 4, 0x0a, 2, b'h', b'i', // body
+                0x32, 7, 0x0a, 1, b'n', 0x12, 2, 0x18, 2, // attribute n=2
+                0x59, 0, 0, 0, 0, 0, 0, 0, 0, // observed_time_unix_nano
+            ]
+        );
+    }
+
+    #[test]
+    fn test_endpoint_path() {
+        assert_eq!(
+            OtlpSink::new("http://collector:4318/").endpoint,
+            "http://collector:4318/v1/logs"
+        );
+        assert_eq!(
+            OtlpSink::new("http://collector:4318/v1/logs").endpoint,
+            "http://collector:4318/v1/logs"
+        );
+    }
+}
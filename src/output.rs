@@ -0,0 +1,409 @@
+use crate::normalize::size_unit_bytes;
+use anyhow::{bail, Context, Result};
+use chrono::Utc;
+use flate2::write::GzEncoder;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Compression applied to formatted output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Compression implied by an output file name (`.gz`, `.zst`)
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "gz" | "gzip" => Some(Compression::Gzip),
+            "zst" | "zstd" => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+}
+
+/// Destination for formatted output: stdout or a file, optionally compressed on the fly
+pub struct OutputWriter {
+    encoder: Encoder,
+    rotation: Option<Box<Rotation>>,
+    // Bytes of formatted output, before compression
+    written: u64,
+}
+
+/// When to start a new output file
+#[derive(Debug, Clone, Default)]
+pub struct RotationPolicy {
+    /// Rotate once this many bytes of output went into the current file, counted before
+    /// compression
+    pub max_size: Option<u64>,
+    /// Rotate when the (UTC) date changes, naming files after the day
+    pub daily: bool,
+}
+
+struct Rotation {
+    base: PathBuf,
+    compression: Option<Compression>,
+    policy: RotationPolicy,
+    // Day the current file belongs to, when rotating daily
+    date: Option<String>,
+    // Position of the current file within its day (or the whole run)
+    index: usize,
+    // Bytes of output in the current file, before compression
+    written: u64,
+    // Whether an event just ended, so the next write may start a new file
+    between_records: bool,
+}
+
+enum Encoder {
+    Plain(Box<dyn Write>),
+    Gzip(GzEncoder<Box<dyn Write>>),
+    Zstd(zstd::Encoder<'static, Box<dyn Write>>),
+    // Already finished; further writes are an error
+    Closed,
+}
+
+impl OutputWriter {
+    pub fn new(target: Box<dyn Write>, compression: Option<Compression>) -> Result<Self> {
+        Ok(Self {
+            encoder: Encoder::new(target, compression)?,
+            rotation: None,
+            written: 0,
+        })
+    }
+
+    pub fn stdout(compression: Option<Compression>) -> Result<Self> {
+        Self::new(Box::new(io::stdout()), compression)
+    }
+
+    /// Create (or truncate) a file
+    pub fn create(path: &Path, compression: Option<Compression>) -> Result<Self> {
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create output file: {}", path.display()))?;
+        Self::new(Box::new(BufWriter::new(file)), compression)
+    }
+
+    /// Write to numbered (`out.1.log`) and/or dated (`out.2024-03-14.log`) files derived from
+    /// `path`, starting a new one whenever the policy says so
+    pub fn rotating(
+        path: &Path,
+        compression: Option<Compression>,
+        policy: RotationPolicy,
+    ) -> Result<Self> {
+        let date = policy.daily.then(today);
+        let first = rotated_path(path, date.as_deref(), 0);
+        let mut writer = Self::create(&first, compression)?;
+        writer.rotation = Some(Box::new(Rotation {
+            base: path.to_path_buf(),
+            compression,
+            policy,
+            date,
+            index: 0,
+            written: 0,
+            between_records: false,
+        }));
+        Ok(writer)
+    }
+
+    /// Mark the end of an event; rotation only ever happens between events, when the next
+    /// one is written, so no empty file is left behind at the end
+    pub fn end_record(&mut self) -> io::Result<()> {
+        if let Some(rotation) = &mut self.rotation {
+            rotation.between_records = true;
+        }
+        Ok(())
+    }
+
+    /// Start a new file before the next event if the policy says so
+    fn rotate_if_due(&mut self) -> io::Result<()> {
+        let Some(rotation) = &mut self.rotation else {
+            return Ok(());
+        };
+        if !std::mem::take(&mut rotation.between_records) {
+            return Ok(());
+        }
+
+        let date = rotation.policy.daily.then(today);
+        if date != rotation.date {
+            rotation.date = date;
+            rotation.index = 0;
+        } else if rotation
+            .policy
+            .max_size
+            .is_some_and(|max| rotation.written >= max)
+        {
+            rotation.index += 1;
+        } else {
+            return Ok(());
+        }
+
+        rotation.written = 0;
+        let next = rotated_path(&rotation.base, rotation.date.as_deref(), rotation.index);
+        let compression = rotation.compression;
+        self.finish()?;
+        let file = File::create(&next)?;
+        self.encoder =
+            Encoder::new(Box::new(BufWriter::new(file)), compression).map_err(io::Error::other)?;
+        Ok(())
+    }
+
+    /// Write the compression trailer (if any) and flush everything to the target
+    pub fn finish(&mut self) -> io::Result<()> {
+        match std::mem::replace(&mut self.encoder, Encoder::Closed) {
+            Encoder::Plain(mut target) => target.flush(),
+            Encoder::Gzip(encoder) => encoder.finish()?.flush(),
+            Encoder::Zstd(encoder) => encoder.finish()?.flush(),
+            Encoder::Closed => Ok(()),
+        }
+    }
+
+    /// Bytes of formatted output so far, before compression
+    pub fn bytes_written(&self) -> u64 {
+        self.written
+    }
+
+    fn target(&mut self) -> io::Result<&mut dyn Write> {
+        match &mut self.encoder {
+            Encoder::Plain(target) => Ok(target),
+            Encoder::Gzip(encoder) => Ok(encoder),
+            Encoder::Zstd(encoder) => Ok(encoder),
+            Encoder::Closed => Err(io::Error::other("output already finished")),
+        }
+    }
+}
+
+impl Encoder {
+    fn new(target: Box<dyn Write>, compression: Option<Compression>) -> Result<Self> {
+        Ok(match compression {
+            None => Encoder::Plain(target),
+            Some(Compression::Gzip) => {
+                Encoder::Gzip(GzEncoder::new(target, flate2::Compression::default()))
+            }
+            Some(Compression::Zstd) => Encoder::Zstd(
+                zstd::Encoder::new(target, zstd::DEFAULT_COMPRESSION_LEVEL)
+                    .context("Failed to set up zstd compression")?,
+            ),
+        })
+    }
+}
+
+impl Write for OutputWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.rotate_if_due()?;
+        let n = self.target()?.write(buf)?;
+        self.written += n as u64;
+        if let Some(rotation) = &mut self.rotation {
+            rotation.written += n as u64;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.target()?.flush()
+    }
+}
+
+fn today() -> String {
+    Utc::now().format("%Y-%m-%d").to_string()
+}
+
+/// `logs/app.log.gz` with date `2024-03-14` and index 2 becomes `logs/app.2024-03-14.2.log.gz`;
+/// the date and a zero index are left out
+fn rotated_path(base: &Path, date: Option<&str>, index: usize) -> PathBuf {
+    let name = base
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let (stem, extensions) = match name.find('.') {
+        Some(dot) if dot > 0 => name.split_at(dot),
+        _ => (name.as_str(), ""),
+    };
+
+    let mut rotated = stem.to_string();
+    if let Some(date) = date {
+        rotated.push('.');
+        rotated.push_str(date);
+    }
+    if index > 0 {
+        rotated.push_str(&format!(".{}", index));
+    }
+    rotated.push_str(extensions);
+    base.with_file_name(rotated)
+}
+
+/// Parse a size such as `100MB`, `512KiB`, `1.5G` or `4096`, in the units of
+/// `--normalize-units`: KB, MB, ... are decimal and KiB, MiB, ... binary, with K, M, G and T
+/// short for KB, MB, GB and TB
+pub fn parse_size(size: &str) -> Result<u64> {
+    let size = size.trim();
+    let number_end = size
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(size.len());
+    let (number, unit) = size.split_at(number_end);
+    let number: f64 = match number.parse() {
+        Ok(number) => number,
+        Err(_) => bail!("invalid size '{}'", size),
+    };
+    let scale = match unit.trim().to_lowercase().as_str() {
+        "" => Some(1.0),
+        short @ ("k" | "m" | "g" | "t") => size_unit_bytes(&format!("{}b", short)),
+        unit => size_unit_bytes(unit),
+    };
+    let Some(scale) = scale else {
+        bail!("invalid size unit in '{}'", size);
+    };
+    let bytes = (number * scale).round() as u64;
+    if bytes == 0 {
+        bail!("size must be greater than zero");
+    }
+    Ok(bytes)
+}
+
+impl Drop for OutputWriter {
+    // Keep compressed output readable even when an error cuts processing short
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::sync::{Arc, Mutex};
+
+    // Shared buffer so the test can inspect what was written after the writer is finished
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn round_trip(compression: Compression) -> Vec<u8> {
+        let buffer = SharedBuffer::default();
+        let mut writer = OutputWriter::new(Box::new(buffer.clone()), Some(compression)).unwrap();
+        writer.write_all(b"level=info message=\"hi\"\n").unwrap();
+        writer.finish().unwrap();
+        let compressed = buffer.0.lock().unwrap().clone();
+        compressed
+    }
+
+    #[test]
+    fn test_gzip_output() {
+        let compressed = round_trip(Compression::Gzip);
+        let mut text = String::new();
+        flate2::read::GzDecoder::new(&compressed[..])
+            .read_to_string(&mut text)
+            .unwrap();
+        assert_eq!(text, "level=info message=\"hi\"\n");
+    }
+
+    #[test]
+    fn test_zstd_output() {
+        let compressed = round_trip(Compression::Zstd);
+        let text = zstd::decode_all(&compressed[..]).unwrap();
+        assert_eq!(text, b"level=info message=\"hi\"\n");
+    }
+
+    #[test]
+    fn test_compression_from_path() {
+        assert_eq!(
+            Compression::from_path(Path::new("out.log.gz")),
+            Some(Compression::Gzip)
+        );
+        assert_eq!(
+            Compression::from_path(Path::new("out.zst")),
+            Some(Compression::Zstd)
+        );
+        assert_eq!(Compression::from_path(Path::new("out.log")), None);
+    }
+
+    #[test]
+    fn test_rotated_path() {
+        let base = Path::new("logs/app.log.gz");
+        assert_eq!(rotated_path(base, None, 0), Path::new("logs/app.log.gz"));
+        assert_eq!(rotated_path(base, None, 3), Path::new("logs/app.3.log.gz"));
+        assert_eq!(
+            rotated_path(base, Some("2024-03-14"), 1),
+            Path::new("logs/app.2024-03-14.1.log.gz")
+        );
+        assert_eq!(rotated_path(Path::new("out"), None, 2), Path::new("out.2"));
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("100M").unwrap(), 100_000_000);
+        assert_eq!(parse_size("100MB").unwrap(), 100_000_000);
+        assert_eq!(parse_size("512k").unwrap(), 512_000);
+        assert_eq!(parse_size("512KiB").unwrap(), 512 * 1024);
+        assert_eq!(parse_size("1.5 GiB").unwrap(), 3 << 29);
+        assert_eq!(parse_size("4096").unwrap(), 4096);
+        assert!(parse_size("M").is_err());
+        assert!(parse_size("10X").is_err());
+        assert!(parse_size("0").is_err());
+    }
+
+    #[test]
+    fn test_size_rotation() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().join("out.log");
+        let policy = RotationPolicy {
+            max_size: Some(10),
+            daily: false,
+        };
+        let mut writer = OutputWriter::rotating(&base, None, policy).unwrap();
+        for line in ["first line\n", "second\n", "third\n"] {
+            writer.write_all(line.as_bytes()).unwrap();
+            writer.flush().unwrap();
+            writer.end_record().unwrap();
+        }
+        writer.finish().unwrap();
+
+        let read = |name: &str| std::fs::read_to_string(dir.path().join(name)).unwrap();
+        assert_eq!(read("out.log"), "first line\n");
+        assert_eq!(read("out.1.log"), "second\nthird\n");
+        assert!(
+            !dir.path().join("out.2.log").exists(),
+            "No empty file is started at the end"
+        );
+    }
+
+    #[test]
+    fn test_compressed_size_rotation() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().join("out.log.gz");
+        let policy = RotationPolicy {
+            max_size: Some(100),
+            daily: false,
+        };
+        let mut writer = OutputWriter::rotating(&base, Some(Compression::Gzip), policy).unwrap();
+        for _ in 0..10 {
+            writer.write_all(&[b'a'; 50]).unwrap();
+            writer.end_record().unwrap();
+        }
+        writer.finish().unwrap();
+
+        // The size counts the output before compression, however well it compresses
+        let read = |name: &str| {
+            let mut text = String::new();
+            let file = std::fs::File::open(dir.path().join(name)).unwrap();
+            flate2::read::GzDecoder::new(file)
+                .read_to_string(&mut text)
+                .unwrap();
+            text
+        };
+        for name in ["out.log.gz", "out.1.log.gz", "out.4.log.gz"] {
+            assert_eq!(read(name).len(), 100, "{}", name);
+        }
+        assert!(!dir.path().join("out.5.log.gz").exists());
+    }
+}
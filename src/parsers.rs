@@ -1,5 +1,5 @@
-use crate::event::{Event, FieldValue};
-use regex::Regex;
+use crate::event::{Event, FieldValue, SYSLOG_LEVEL_NAMES};
+use regex::{Captures, Regex};
 
 pub trait LogParser {
     fn parse(&self, line: &str) -> Result<Event, ParseError>;
@@ -28,18 +28,40 @@ impl From<serde_json::Error> for ParseError {
     }
 }
 
+/// A logfmt `key=value` or `key="quoted value"` pair; see [`logfmt_pair`]
+pub const LOGFMT_PAIR: &str = r#"([a-zA-Z_][a-zA-Z0-9_.-]*)=(?:"((?:[^"\\]|\\.)*)"|([^\s]+))"#;
+
+/// Key and unquoted text value of a [`LOGFMT_PAIR`] match
+pub fn logfmt_pair(cap: &Captures) -> Option<(String, String)> {
+    let key = cap.get(1)?.as_str().to_string();
+    let value = if let Some(quoted) = cap.get(2) {
+        unescape_quoted(quoted.as_str())
+    } else {
+        cap.get(3)?.as_str().to_string()
+    };
+    Some((key, value))
+}
+
 // Logfmt Parser
 pub struct LogfmtParser {
     key_value_regex: Regex,
+    // Keys whose values stay text instead of being typed
+    text_keys: Vec<String>,
 }
 
 impl LogfmtParser {
     pub fn new() -> Self {
         Self {
-            key_value_regex: Regex::new(r#"([a-zA-Z_][a-zA-Z0-9_-]*)=(?:"([^"]*)"|([^\s]+))"#)
-                .unwrap(),
+            key_value_regex: Regex::new(LOGFMT_PAIR).unwrap(),
+            text_keys: Vec::new(),
         }
     }
+
+    /// Keep the values of these keys as text, e.g. ZIP codes with leading zeros
+    pub fn with_text_keys(mut self, keys: Vec<String>) -> Self {
+        self.text_keys = keys;
+        self
+    }
 }
 
 impl LogParser for LogfmtParser {
@@ -52,18 +74,14 @@ impl LogParser for LogfmtParser {
         }
 
         for cap in self.key_value_regex.captures_iter(line) {
-            let key = cap.get(1).unwrap().as_str().to_string();
-            let value = if let Some(quoted) = cap.get(2) {
-                quoted.as_str().to_string()
-            } else if let Some(unquoted) = cap.get(3) {
-                unquoted.as_str().to_string()
-            } else {
-                continue;
-            };
-
-            // Try to parse as number or boolean, with better error handling
-            let field_value = parse_field_value(&value);
-            event.set_field(key, field_value);
+            if let Some((key, value)) = logfmt_pair(&cap) {
+                let value = if self.text_keys.contains(&key) {
+                    FieldValue::String(value)
+                } else {
+                    parse_field_value(&value)
+                };
+                event.set_field(key, value);
+            }
         }
 
         event.extract_core_fields();
@@ -71,7 +89,37 @@ impl LogParser for LogfmtParser {
     }
 }
 
-fn parse_field_value(value: &str) -> FieldValue {
+/// Undo the escaping of quotes, backslashes and line breaks in a quoted logfmt value; other
+/// backslashes (e.g. in Windows paths) are kept
+fn unescape_quoted(value: &str) -> String {
+    if !value.contains('\\') {
+        return value.to_string();
+    }
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('"') => unescaped.push('"'),
+            Some('\\') => unescaped.push('\\'),
+            Some('n') => unescaped.push('\n'),
+            Some('t') => unescaped.push('\t'),
+            Some('r') => unescaped.push('\r'),
+            Some(other) => {
+                unescaped.push('\\');
+                unescaped.push(other);
+            }
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+/// Type a raw text value: null, booleans and numbers are recognized, the rest stays text
+pub fn parse_field_value(value: &str) -> FieldValue {
     // Try parsing as different types
     if value == "null" {
         return FieldValue::Null;
@@ -106,28 +154,31 @@ impl LogParser for JsonlParser {
     fn parse(&self, line: &str) -> Result<Event, ParseError> {
         let json_value: serde_json::Value = serde_json::from_str(line)?;
 
-        let mut event = Event::new();
-
         if let serde_json::Value::Object(map) = json_value {
-            for (key, value) in map {
-                let field_value = match value {
-                    serde_json::Value::String(s) => FieldValue::String(s),
-                    serde_json::Value::Number(n) => FieldValue::Number(n.as_f64().unwrap_or(0.0)),
-                    serde_json::Value::Bool(b) => FieldValue::Boolean(b),
-                    serde_json::Value::Null => FieldValue::Null,
-                    _ => FieldValue::String(value.to_string()),
-                };
-                event.set_field(key, field_value);
-            }
+            Ok(event_from_json(map))
         } else {
-            return Err(ParseError::InvalidFormat(
+            Err(ParseError::InvalidFormat(
                 "Expected JSON object".to_string(),
-            ));
+            ))
         }
+    }
+}
 
-        event.extract_core_fields();
-        Ok(event)
+/// Build an event from a JSON object; nested arrays and objects are kept as JSON text
+pub fn event_from_json(map: serde_json::Map<String, serde_json::Value>) -> Event {
+    let mut event = Event::new();
+    for (key, value) in map {
+        let field_value = match value {
+            serde_json::Value::String(s) => FieldValue::String(s),
+            serde_json::Value::Number(n) => FieldValue::Number(n.as_f64().unwrap_or(0.0)),
+            serde_json::Value::Bool(b) => FieldValue::Boolean(b),
+            serde_json::Value::Null => FieldValue::Null,
+            _ => FieldValue::String(value.to_string()),
+        };
+        event.set_field(key, field_value);
     }
+    event.extract_core_fields();
+    event
 }
 
 // Basic Syslog Parser (RFC3164-ish)
@@ -161,18 +212,7 @@ impl LogParser for SyslogParser {
                     event.set_field("severity".to_string(), FieldValue::Number(severity as f64));
 
                     // Map severity to log level
-                    let level = match severity {
-                        0 => "EMERGENCY",
-                        1 => "ALERT",
-                        2 => "CRITICAL",
-                        3 => "ERROR",
-                        4 => "WARNING",
-                        5 => "NOTICE",
-                        6 => "INFO",
-                        7 => "DEBUG",
-                        _ => "UNKNOWN",
-                    };
-                    event.level = Some(level.to_string());
+                    event.level = Some(SYSLOG_LEVEL_NAMES[severity as usize].to_string());
                 }
             }
 
@@ -226,6 +266,265 @@ impl LogParser for SyslogParser {
     }
 }
 
+// Fixed-width column parser
+pub struct FixedWidthParser {
+    widths: Vec<usize>,
+    columns: Vec<String>,
+}
+
+impl FixedWidthParser {
+    /// Create a parser that slices each line into columns of the given character widths.
+    /// A width of 0 consumes the rest of the line. Unnamed columns are called `col1`, `col2`, ...
+    pub fn new(widths: Vec<usize>, columns: Vec<String>) -> Self {
+        let columns = (0..widths.len())
+            .map(|i| {
+                columns
+                    .get(i)
+                    .cloned()
+                    .unwrap_or_else(|| format!("col{}", i + 1))
+            })
+            .collect();
+        Self { widths, columns }
+    }
+}
+
+impl LogParser for FixedWidthParser {
+    fn parse(&self, line: &str) -> Result<Event, ParseError> {
+        let mut event = Event::new();
+        let chars: Vec<char> = line.chars().collect();
+        let mut pos = 0;
+
+        for (width, column) in self.widths.iter().zip(&self.columns) {
+            if pos >= chars.len() {
+                break;
+            }
+
+            let end = if *width == 0 {
+                chars.len()
+            } else {
+                (pos + width).min(chars.len())
+            };
+            let value: String = chars[pos..end].iter().collect();
+            pos = end;
+
+            let value = value.trim();
+            if !value.is_empty() {
+                event.set_field(column.clone(), parse_field_value(value));
+            }
+        }
+
+        if event.fields.is_empty() {
+            return Err(ParseError::InvalidFormat(
+                "No fixed-width columns found".to_string(),
+            ));
+        }
+
+        event.extract_core_fields();
+        Ok(event)
+    }
+}
+
+// Line-oriented XML record parser: one element per line
+pub struct XmlParser;
+
+impl XmlParser {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+struct XmlElement {
+    name: String,
+    attributes: Vec<(String, String)>,
+    children: Vec<XmlElement>,
+    text: String,
+}
+
+impl LogParser for XmlParser {
+    fn parse(&self, line: &str) -> Result<Event, ParseError> {
+        let mut input = line.trim();
+
+        // Tolerate an XML declaration in front of the record
+        if input.starts_with("<?") {
+            let end = input
+                .find("?>")
+                .ok_or_else(|| ParseError::InvalidFormat("Unterminated declaration".into()))?;
+            input = input[end + 2..].trim_start();
+        }
+
+        let (root, rest) = parse_xml_element(input).map_err(ParseError::InvalidFormat)?;
+        if !rest.trim().is_empty() {
+            return Err(ParseError::InvalidFormat(format!(
+                "Unexpected content after </{}>",
+                root.name
+            )));
+        }
+
+        let mut event = Event::new();
+        for (key, value) in &root.attributes {
+            event.set_field(key.clone(), parse_field_value(value));
+        }
+        for child in &root.children {
+            add_xml_fields(&mut event, child, &child.name);
+        }
+        let text = root.text.trim();
+        if !text.is_empty() && !event.fields.contains_key("message") {
+            event.set_field("message".to_string(), FieldValue::String(text.to_string()));
+        }
+
+        event.extract_core_fields();
+        Ok(event)
+    }
+}
+
+/// Map a child element into fields, using dotted keys for attributes and nested children
+fn add_xml_fields(event: &mut Event, element: &XmlElement, prefix: &str) {
+    for (key, value) in &element.attributes {
+        event.set_field(format!("{}.{}", prefix, key), parse_field_value(value));
+    }
+    for child in &element.children {
+        add_xml_fields(event, child, &format!("{}.{}", prefix, child.name));
+    }
+    let text = element.text.trim();
+    if !text.is_empty() || (element.attributes.is_empty() && element.children.is_empty()) {
+        event.set_field(prefix.to_string(), parse_field_value(text));
+    }
+}
+
+/// Parse a single element starting at `<`, returning it and the remaining input
+fn parse_xml_element(input: &str) -> Result<(XmlElement, &str), String> {
+    let mut rest = input
+        .strip_prefix('<')
+        .ok_or_else(|| "Expected '<'".to_string())?;
+
+    let name_end = rest
+        .find(|c: char| c.is_whitespace() || c == '/' || c == '>')
+        .ok_or_else(|| "Unterminated start tag".to_string())?;
+    let name = rest[..name_end].to_string();
+    if name.is_empty() {
+        return Err("Missing element name".to_string());
+    }
+    rest = &rest[name_end..];
+
+    let mut element = XmlElement {
+        name,
+        attributes: Vec::new(),
+        children: Vec::new(),
+        text: String::new(),
+    };
+
+    // Attributes
+    loop {
+        rest = rest.trim_start();
+        if let Some(after) = rest.strip_prefix("/>") {
+            return Ok((element, after));
+        }
+        if let Some(after) = rest.strip_prefix('>') {
+            rest = after;
+            break;
+        }
+
+        let eq = rest
+            .find('=')
+            .ok_or_else(|| format!("Malformed attribute in <{}>", element.name))?;
+        let key = rest[..eq].trim().to_string();
+        rest = rest[eq + 1..].trim_start();
+
+        let quote = rest
+            .chars()
+            .next()
+            .filter(|c| *c == '"' || *c == '\'')
+            .ok_or_else(|| format!("Unquoted attribute value for {}", key))?;
+        rest = &rest[1..];
+        let end = rest
+            .find(quote)
+            .ok_or_else(|| format!("Unterminated attribute value for {}", key))?;
+        element.attributes.push((key, unescape_xml(&rest[..end])));
+        rest = &rest[end + 1..];
+    }
+
+    // Content: text, comments, CDATA and child elements up to the matching end tag
+    loop {
+        if let Some(after) = rest.strip_prefix("</") {
+            let end = after
+                .find('>')
+                .ok_or_else(|| "Unterminated end tag".to_string())?;
+            let closing = after[..end].trim();
+            if closing != element.name {
+                return Err(format!(
+                    "Mismatched end tag: expected </{}>, found </{}>",
+                    element.name, closing
+                ));
+            }
+            return Ok((element, &after[end + 1..]));
+        } else if let Some(after) = rest.strip_prefix("<!--") {
+            let end = after
+                .find("-->")
+                .ok_or_else(|| "Unterminated comment".to_string())?;
+            rest = &after[end + 3..];
+        } else if let Some(after) = rest.strip_prefix("<![CDATA[") {
+            let end = after
+                .find("]]>")
+                .ok_or_else(|| "Unterminated CDATA section".to_string())?;
+            element.text.push_str(&after[..end]);
+            rest = &after[end + 3..];
+        } else if rest.starts_with('<') {
+            let (child, after) = parse_xml_element(rest)?;
+            element.children.push(child);
+            rest = after;
+        } else if rest.is_empty() {
+            return Err(format!("Missing end tag </{}>", element.name));
+        } else {
+            let end = rest.find('<').unwrap_or(rest.len());
+            element.text.push_str(&unescape_xml(&rest[..end]));
+            rest = &rest[end..];
+        }
+    }
+}
+
+fn unescape_xml(s: &str) -> String {
+    if !s.contains('&') {
+        return s.to_string();
+    }
+
+    let mut result = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find('&') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let decoded = rest.find(';').and_then(|end| {
+            let entity = &rest[1..end];
+            let c = match entity {
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "amp" => Some('&'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                _ => entity
+                    .strip_prefix("#x")
+                    .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                    .or_else(|| entity.strip_prefix('#').and_then(|dec| dec.parse().ok()))
+                    .and_then(char::from_u32),
+            };
+            c.map(|c| (c, end))
+        });
+
+        match decoded {
+            Some((c, end)) => {
+                result.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                result.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -258,6 +557,33 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_logfmt_parser_escaped_quotes() {
+        let parser = LogfmtParser::new();
+        let result = parser
+            .parse(r#"data="{\"user\":\"x\",\"id\":5}" path="C:\data" msg="a\nb""#)
+            .unwrap();
+
+        assert!(
+            matches!(result.fields.get("data"), Some(FieldValue::String(s)) if s == r#"{"user":"x","id":5}"#)
+        );
+        assert!(
+            matches!(result.fields.get("path"), Some(FieldValue::String(s)) if s == r"C:\data")
+        );
+        assert_eq!(result.message, Some("a\nb".to_string()));
+    }
+
+    #[test]
+    fn test_logfmt_parser_dotted_keys() {
+        let parser = LogfmtParser::new();
+        let result = parser.parse("http.method=GET k8s.pod.name=api-1").unwrap();
+
+        assert!(
+            matches!(result.fields.get("http.method"), Some(FieldValue::String(s)) if s == "GET")
+        );
+        assert!(result.fields.contains_key("k8s.pod.name"));
+    }
+
     #[test]
     fn test_jsonl_parser_basic() {
         let parser = JsonlParser::new();
@@ -291,4 +617,79 @@ mod tests {
         ));
         assert!(matches!(parse_field_value("hello"), FieldValue::String(s) if s == "hello"));
     }
+
+    #[test]
+    fn test_fixed_width_parser() {
+        let parser = FixedWidthParser::new(
+            vec![20, 6, 0],
+            vec!["ts".to_string(), "level".to_string(), "message".to_string()],
+        );
+        let result = parser
+            .parse("2023-07-18 15:04:23 ERROR Disk full on /var")
+            .unwrap();
+
+        assert_eq!(result.level, Some("ERROR".to_string()));
+        assert_eq!(result.message, Some("Disk full on /var".to_string()));
+        assert!(result.timestamp.is_some());
+    }
+
+    #[test]
+    fn test_fixed_width_parser_default_column_names() {
+        let parser = FixedWidthParser::new(vec![3, 3], vec![]);
+        let result = parser.parse("abc42").unwrap();
+
+        assert!(matches!(result.fields.get("col1"), Some(FieldValue::String(s)) if s == "abc"));
+        assert!(matches!(
+            result.fields.get("col2"),
+            Some(FieldValue::Number(42.0))
+        ));
+    }
+
+    #[test]
+    fn test_xml_parser_attributes_and_text() {
+        let parser = XmlParser::new();
+        let result = parser
+            .parse(r#"<event time="2023-07-18T15:04:23Z" level="warn" code="42">Disk &amp; CPU high</event>"#)
+            .unwrap();
+
+        assert_eq!(result.level, Some("warn".to_string()));
+        assert_eq!(result.message, Some("Disk & CPU high".to_string()));
+        assert!(result.timestamp.is_some());
+        assert!(matches!(
+            result.fields.get("code"),
+            Some(FieldValue::Number(42.0))
+        ));
+
+        let result = parser
+            .parse(r#"<event time="2023-07-18T15:04:23Z" sev="error">disk full</event>"#)
+            .unwrap();
+        assert_eq!(result.level, Some("error".to_string()));
+        assert_eq!(result.message, Some("disk full".to_string()));
+    }
+
+    #[test]
+    fn test_xml_parser_child_elements() {
+        let parser = XmlParser::new();
+        let result = parser
+            .parse(r#"<rec><msg>hello</msg><src host="web01" port='80'/><empty/></rec>"#)
+            .unwrap();
+
+        assert_eq!(result.message, Some("hello".to_string()));
+        assert!(
+            matches!(result.fields.get("src.host"), Some(FieldValue::String(s)) if s == "web01")
+        );
+        assert!(matches!(
+            result.fields.get("src.port"),
+            Some(FieldValue::Number(80.0))
+        ));
+        assert!(result.fields.contains_key("empty"));
+    }
+
+    #[test]
+    fn test_xml_parser_rejects_malformed() {
+        let parser = XmlParser::new();
+        assert!(parser.parse("<event>text</other>").is_err());
+        assert!(parser.parse("<event sev=high>text</event>").is_err());
+        assert!(parser.parse("not xml").is_err());
+    }
 }
@@ -0,0 +1,55 @@
+use crate::event::Event;
+use crate::formatters::Formatter;
+use crate::output::OutputWriter;
+use crate::sinks::{Sink, SqliteSink};
+use anyhow::{Context, Result};
+use std::io;
+
+/// Loads events into an in-memory SQLite table, then answers a SQL query over them once
+/// the input is exhausted, formatting each result row as an event
+pub struct QuerySink {
+    db: SqliteSink,
+    sql: String,
+    formatter: Box<dyn Formatter>,
+    out: OutputWriter,
+}
+
+impl QuerySink {
+    pub fn new(
+        sql: &str,
+        table: &str,
+        formatter: Box<dyn Formatter>,
+        out: OutputWriter,
+    ) -> Result<Self> {
+        Ok(Self {
+            db: SqliteSink::in_memory(table)?,
+            sql: sql.to_string(),
+            formatter,
+            out,
+        })
+    }
+
+    fn write_rows(&mut self, rows: &[Event]) -> io::Result<()> {
+        for row in rows {
+            self.formatter.write_event(row, &mut self.out)?;
+        }
+        self.formatter.finish(&mut self.out)?;
+        self.out.finish()
+    }
+}
+
+impl Sink for QuerySink {
+    fn write(&mut self, event: &Event) -> Result<()> {
+        self.db.write(event)
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.db.finish()?;
+        let rows = self.db.query(&self.sql).context("Query failed")?;
+        match self.write_rows(&rows) {
+            // Expected when piping into tools like `head`
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+            result => Ok(result?),
+        }
+    }
+}
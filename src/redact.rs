@@ -0,0 +1,338 @@
+use crate::event::{core_key_name, key_matches, Event, FieldValue, MESSAGE_KEYS};
+use regex::Regex;
+use sha2::{Digest, Sha256, Sha512};
+use std::fmt::Write;
+
+/// Replacement for redacted values
+pub const REDACTED: &str = "[REDACTED]";
+
+/// Masks sensitive data before output: whole values of the selected keys (`--redact`, names
+/// compared ignoring case, wildcards allowed) and matching substrings anywhere in the message
+/// and string fields (`--redact-pattern`)
+#[derive(Debug, Default)]
+pub struct Redactor {
+    keys: Vec<String>,
+    patterns: Vec<Regex>,
+}
+
+impl Redactor {
+    pub fn new(keys: &[String], patterns: Vec<Regex>) -> Self {
+        Self {
+            keys: keys.iter().map(|key| key.to_lowercase()).collect(),
+            patterns,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty() && self.patterns.is_empty()
+    }
+
+    pub fn apply(&self, event: &mut Event) {
+        if MESSAGE_KEYS.iter().any(|key| self.redacts_key(key)) {
+            if let Some(message) = &mut event.message {
+                *message = REDACTED.to_string();
+            }
+        }
+        if let Some(message) = &mut event.message {
+            self.mask(message);
+        }
+
+        for (key, value) in event.fields.iter_mut() {
+            if self.redacts_key(key) && *value != FieldValue::Null {
+                *value = FieldValue::String(REDACTED.to_string());
+            } else if let FieldValue::String(text) = value {
+                self.mask(text);
+            }
+        }
+    }
+
+    fn redacts_key(&self, key: &str) -> bool {
+        let key = key.to_lowercase();
+        self.keys.iter().any(|pattern| key_matches(pattern, &key))
+    }
+
+    fn mask(&self, text: &mut String) {
+        for pattern in &self.patterns {
+            if pattern.is_match(text) {
+                *text = pattern.replace_all(text, REDACTED).into_owned();
+            }
+        }
+    }
+}
+
+/// Removes fields from events before output, by name (`--drop-field`, wildcards allowed) or by
+/// value (`--drop-field-if KEY~=REGEX`), while the events themselves stay. The level and
+/// message count as fields too
+#[derive(Debug, Default)]
+pub struct FieldDropper {
+    keys: Vec<String>,
+    conditions: Vec<(String, Regex)>,
+}
+
+impl FieldDropper {
+    pub fn new(keys: &[String]) -> Self {
+        Self {
+            keys: keys.to_vec(),
+            conditions: Vec::new(),
+        }
+    }
+
+    /// Also drop fields matching a `KEY~=REGEX` condition, e.g. `message~=health check`
+    pub fn with_condition(mut self, spec: &str) -> Result<Self, String> {
+        let (key, pattern) = spec
+            .split_once("~=")
+            .map(|(key, pattern)| (key.trim(), pattern))
+            .filter(|(key, _)| !key.is_empty())
+            .ok_or_else(|| format!("expected KEY~=REGEX, got '{}'", spec))?;
+        let regex = Regex::new(pattern).map_err(|e| e.to_string())?;
+        self.conditions.push((key.to_string(), regex));
+        Ok(self)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty() && self.conditions.is_empty()
+    }
+
+    pub fn apply(&self, event: &mut Event) {
+        if !self.keys.is_empty() {
+            event.exclude_keys(&self.keys);
+        }
+        for (pattern, regex) in &self.conditions {
+            let names_core =
+                |core: &str| core_key_name(pattern) == Some(core) || key_matches(pattern, core);
+            let core_matches = |value: &Option<String>| {
+                value.as_deref().is_some_and(|value| regex.is_match(value))
+            };
+            if names_core("level") && core_matches(&event.level) {
+                event.exclude_keys(&["level".to_string()]);
+            }
+            if names_core("message") && core_matches(&event.message) {
+                event.exclude_keys(&["message".to_string()]);
+            }
+            event.fields.retain(|key, value| {
+                !(key_matches(pattern, key) && regex.is_match(&value.to_string()))
+            });
+        }
+    }
+}
+
+/// Hash function for `--hash`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    Sha256,
+    Sha512,
+}
+
+/// Replaces the values of the selected keys with salted hashes (`--hash`), so events stay
+/// joinable on them while the values themselves are gone. Keys are compared like for
+/// [`Redactor`]
+#[derive(Debug, Clone)]
+pub struct FieldHasher {
+    keys: Vec<String>,
+    algorithm: HashAlgorithm,
+    salt: String,
+}
+
+impl FieldHasher {
+    /// Parse `KEYS[:ALGORITHM[:SALT]]`, e.g. `user_id,email` or `email:sha256:s3cret`
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut parts = spec.splitn(3, ':');
+        let keys: Vec<String> = parts
+            .next()
+            .unwrap_or_default()
+            .split(',')
+            .map(|key| key.trim().to_lowercase())
+            .filter(|key| !key.is_empty())
+            .collect();
+        if keys.is_empty() {
+            return Err(format!("no keys in '{}'", spec));
+        }
+        let algorithm = match parts.next().map(|name| name.trim().to_lowercase()) {
+            None => HashAlgorithm::Sha256,
+            Some(name) if name.is_empty() || name == "sha256" => HashAlgorithm::Sha256,
+            Some(name) if name == "sha512" => HashAlgorithm::Sha512,
+            Some(name) => {
+                return Err(format!(
+                    "unknown hash algorithm '{}' (use sha256 or sha512)",
+                    name
+                ))
+            }
+        };
+        Ok(Self {
+            keys,
+            algorithm,
+            salt: parts.next().unwrap_or_default().to_string(),
+        })
+    }
+
+    pub fn apply(&self, event: &mut Event) {
+        if MESSAGE_KEYS.iter().any(|key| self.hashes_key(key)) {
+            if let Some(message) = &mut event.message {
+                *message = self.hash(message);
+            }
+        }
+        for (key, value) in event.fields.iter_mut() {
+            if self.hashes_key(key) && *value != FieldValue::Null {
+                *value = FieldValue::String(self.hash(&value.to_string()));
+            }
+        }
+    }
+
+    fn hashes_key(&self, key: &str) -> bool {
+        let key = key.to_lowercase();
+        self.keys.iter().any(|pattern| key_matches(pattern, &key))
+    }
+
+    /// Hex digest of the salt followed by the value
+    fn hash(&self, value: &str) -> String {
+        let digest = match self.algorithm {
+            HashAlgorithm::Sha256 => Sha256::new()
+                .chain_update(&self.salt)
+                .chain_update(value)
+                .finalize()
+                .to_vec(),
+            HashAlgorithm::Sha512 => Sha512::new()
+                .chain_update(&self.salt)
+                .chain_update(value)
+                .finalize()
+                .to_vec(),
+        };
+        digest.iter().fold(String::new(), |mut hex, byte| {
+            let _ = write!(hex, "{:02x}", byte);
+            hex
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event() -> Event {
+        let mut event = Event::new();
+        event.message = Some("charged card 4111111111111111".to_string());
+        event.set_field(
+            "msg".to_string(),
+            FieldValue::String("charged card 4111111111111111".to_string()),
+        );
+        event.set_field(
+            "Authorization".to_string(),
+            FieldValue::String("Bearer abc".to_string()),
+        );
+        event.set_field("api_token".to_string(), FieldValue::Number(1234.0));
+        event.set_field("password".to_string(), FieldValue::Null);
+        event.set_field("user".to_string(), FieldValue::String("alice".to_string()));
+        event
+    }
+
+    #[test]
+    fn test_redact_keys() {
+        let mut event = event();
+        Redactor::new(
+            &["authorization".to_string(), "*token".to_string()],
+            Vec::new(),
+        )
+        .apply(&mut event);
+
+        let redacted = FieldValue::String(REDACTED.to_string());
+        assert_eq!(event.fields["Authorization"], redacted);
+        assert_eq!(event.fields["api_token"], redacted);
+        assert_eq!(event.fields["password"], FieldValue::Null);
+        assert_eq!(
+            event.fields["user"],
+            FieldValue::String("alice".to_string())
+        );
+        assert_eq!(
+            event.message.as_deref(),
+            Some("charged card 4111111111111111")
+        );
+
+        let mut event = self::event();
+        Redactor::new(&["msg".to_string()], Vec::new()).apply(&mut event);
+        assert_eq!(event.message.as_deref(), Some(REDACTED));
+        assert_eq!(
+            event.fields["msg"],
+            FieldValue::String(REDACTED.to_string())
+        );
+    }
+
+    #[test]
+    fn test_redact_patterns() {
+        let mut event = event();
+        Redactor::new(&[], vec![Regex::new(r"\b\d{16}\b").unwrap()]).apply(&mut event);
+        assert_eq!(event.message.as_deref(), Some("charged card [REDACTED]"));
+        assert_eq!(
+            event.fields["msg"],
+            FieldValue::String("charged card [REDACTED]".to_string())
+        );
+        assert_eq!(event.fields["api_token"], FieldValue::Number(1234.0));
+    }
+
+    #[test]
+    fn test_hash_fields() {
+        let hasher = FieldHasher::parse("User,email").unwrap();
+        let mut first = event();
+        let mut second = event();
+        hasher.apply(&mut first);
+        hasher.apply(&mut second);
+
+        // SHA-256 of "alice"
+        let alice = "2bd806c97f0e00af1a1fc3328fa763a9269723c8db8fac4f93af71db186d6e90";
+        assert_eq!(first.fields["user"], FieldValue::String(alice.to_string()));
+        assert_eq!(first.fields["user"], second.fields["user"]);
+        assert_eq!(first.fields["api_token"], FieldValue::Number(1234.0));
+
+        let mut salted = event();
+        FieldHasher::parse("user:sha256:pepper")
+            .unwrap()
+            .apply(&mut salted);
+        assert_ne!(salted.fields["user"], first.fields["user"]);
+
+        let mut long = event();
+        FieldHasher::parse("user:SHA512").unwrap().apply(&mut long);
+        assert!(matches!(&long.fields["user"], FieldValue::String(hash) if hash.len() == 128));
+
+        assert_eq!(
+            FieldHasher::parse("user:sha256:a:b").unwrap().salt,
+            "a:b",
+            "Salts may contain colons"
+        );
+        assert!(FieldHasher::parse("user:md5").is_err());
+        assert!(FieldHasher::parse(":sha256").is_err());
+    }
+
+    #[test]
+    fn test_drop_fields() {
+        let mut event = event();
+        event.set_field(
+            "kubernetes.annotations.checksum".to_string(),
+            FieldValue::String("abc".to_string()),
+        );
+        event.set_field(
+            "kubernetes.pod".to_string(),
+            FieldValue::String("api-1".to_string()),
+        );
+        FieldDropper::new(&["kubernetes.annotations.*".to_string()])
+            .with_condition("msg~=card \\d+")
+            .unwrap()
+            .with_condition("*~=^Bearer ")
+            .unwrap()
+            .apply(&mut event);
+
+        let keys: Vec<&String> = event.fields.keys().collect();
+        assert_eq!(keys, ["api_token", "password", "user", "kubernetes.pod"]);
+        assert_eq!(event.message, None);
+
+        let mut kept = self::event();
+        FieldDropper::new(&[])
+            .with_condition("message~=health check")
+            .unwrap()
+            .apply(&mut kept);
+        assert!(kept.message.is_some());
+        assert!(kept.fields.contains_key("msg"));
+
+        assert!(FieldDropper::new(&[]).with_condition("message=x").is_err());
+        assert!(FieldDropper::new(&[]).with_condition("~=x").is_err());
+        assert!(FieldDropper::new(&[]).with_condition("msg~=(").is_err());
+    }
+}
@@ -0,0 +1,563 @@
+use crate::event::{Event, FieldValue};
+use anyhow::{Context, Result};
+use arrow_array::{
+    ArrayRef, BooleanArray, Float64Array, RecordBatch, StringArray, TimestampMillisecondArray,
+};
+use arrow_ipc::writer::StreamWriter;
+use arrow_schema::{DataType, Field, Schema, TimeUnit};
+use rusqlite::types::Value;
+use rusqlite::Connection;
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+
+/// A destination that consumes events directly instead of formatted text on stdout
+pub trait Sink {
+    fn write(&mut self, event: &Event) -> Result<()>;
+
+    /// Flush pending work once all events have been written
+    fn finish(&mut self) -> Result<()>;
+}
+
+// SQLite sink
+pub struct SqliteSink {
+    conn: Connection,
+    table: String,
+    fts: bool,
+    // Lowercased column name -> actual column name (SQLite names are case-insensitive)
+    columns: HashMap<String, String>,
+    pending: usize,
+}
+
+/// Events per transaction
+const SQLITE_BATCH_SIZE: usize = 1000;
+
+impl SqliteSink {
+    /// Open (or create) the database, creating the table or picking up its existing columns
+    pub fn open(path: &Path, table: &str, fts: bool) -> Result<Self> {
+        let conn = Connection::open(path)
+            .with_context(|| format!("Failed to open SQLite database: {}", path.display()))?;
+        Self::with_connection(conn, table, fts)
+    }
+
+    /// A throwaway database that only lives as long as the sink
+    pub fn in_memory(table: &str) -> Result<Self> {
+        let conn = Connection::open_in_memory().context("Failed to open in-memory database")?;
+        Self::with_connection(conn, table, false)
+    }
+
+    fn with_connection(conn: Connection, table: &str, fts: bool) -> Result<Self> {
+        conn.execute(
+            &format!(
+                "CREATE TABLE IF NOT EXISTS {} (timestamp TEXT, level TEXT, message TEXT)",
+                quote_ident(table)
+            ),
+            [],
+        )?;
+        if fts {
+            conn.execute(
+                &format!(
+                    "CREATE VIRTUAL TABLE IF NOT EXISTS {} USING fts5(message, content={}, content_rowid='rowid')",
+                    quote_ident(&format!("{}_fts", table)),
+                    quote_ident(table)
+                ),
+                [],
+            )
+            .context("Failed to create full-text index")?;
+        }
+
+        let mut columns = HashMap::new();
+        {
+            let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", quote_ident(table)))?;
+            let names = stmt.query_map([], |row| row.get::<_, String>(1))?;
+            for name in names {
+                let name = name?;
+                columns.insert(name.to_lowercase(), name);
+            }
+        }
+
+        conn.execute_batch("BEGIN")?;
+        Ok(Self {
+            conn,
+            table: table.to_string(),
+            fts,
+            columns,
+            pending: 0,
+        })
+    }
+
+    /// Return the column for a key, adding it to the table on first sight
+    fn column_for(&mut self, key: &str, value: &FieldValue) -> Result<String> {
+        if let Some(column) = self.columns.get(&key.to_lowercase()) {
+            return Ok(column.clone());
+        }
+
+        let affinity = match value {
+            FieldValue::Number(_) => " REAL",
+            FieldValue::Boolean(_) => " INTEGER",
+            FieldValue::String(_) => " TEXT",
+            FieldValue::Null => "",
+        };
+        self.conn.execute(
+            &format!(
+                "ALTER TABLE {} ADD COLUMN {}{}",
+                quote_ident(&self.table),
+                quote_ident(key),
+                affinity
+            ),
+            [],
+        )?;
+        self.columns.insert(key.to_lowercase(), key.to_string());
+        Ok(key.to_string())
+    }
+
+    /// Run a query over what has been written so far, one event per result row
+    pub fn query(&self, sql: &str) -> Result<Vec<Event>> {
+        let mut stmt = self.conn.prepare(sql)?;
+        let names: Vec<String> = stmt.column_names().into_iter().map(String::from).collect();
+        let rows = stmt.query_map([], |row| {
+            let mut event = Event::new();
+            for (i, name) in names.iter().enumerate() {
+                let value = match row.get::<_, Value>(i)? {
+                    Value::Null => FieldValue::Null,
+                    Value::Integer(n) => FieldValue::Number(n as f64),
+                    Value::Real(n) => FieldValue::Number(n),
+                    Value::Text(s) => FieldValue::String(s),
+                    Value::Blob(bytes) => {
+                        FieldValue::String(String::from_utf8_lossy(&bytes).into_owned())
+                    }
+                };
+                event.set_field(name.clone(), value);
+            }
+            event.extract_core_fields();
+            Ok(event)
+        })?;
+        Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+}
+
+impl Sink for SqliteSink {
+    fn write(&mut self, event: &Event) -> Result<()> {
+        let mut row: Vec<(String, Value)> = Vec::new();
+
+        if let Some(timestamp) = &event.timestamp {
+            let ts = timestamp.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+            row.push(("timestamp".to_string(), Value::Text(ts)));
+        }
+        if let Some(level) = &event.level {
+            row.push(("level".to_string(), Value::Text(level.clone())));
+        }
+        if let Some(message) = &event.message {
+            row.push(("message".to_string(), Value::Text(message.clone())));
+        }
+
+        for (key, value) in &event.fields {
+            let column = self.column_for(key, value)?;
+            // Raw duplicates of core fields (e.g. a `level` field) map onto the core column
+            if row.iter().any(|(existing, _)| *existing == column) {
+                continue;
+            }
+            row.push((column, sql_value(value)));
+        }
+
+        if !row.is_empty() {
+            let names: Vec<String> = row.iter().map(|(name, _)| quote_ident(name)).collect();
+            let placeholders = vec!["?"; row.len()].join(", ");
+            let sql = format!(
+                "INSERT INTO {} ({}) VALUES ({})",
+                quote_ident(&self.table),
+                names.join(", "),
+                placeholders
+            );
+            let mut stmt = self.conn.prepare_cached(&sql)?;
+            stmt.execute(rusqlite::params_from_iter(row.iter().map(|(_, v)| v)))?;
+
+            if self.fts {
+                if let Some(message) = &event.message {
+                    let rowid = self.conn.last_insert_rowid();
+                    self.conn.execute(
+                        &format!(
+                            "INSERT INTO {} (rowid, message) VALUES (?1, ?2)",
+                            quote_ident(&format!("{}_fts", self.table))
+                        ),
+                        rusqlite::params![rowid, message],
+                    )?;
+                }
+            }
+        }
+
+        self.pending += 1;
+        if self.pending >= SQLITE_BATCH_SIZE {
+            self.conn.execute_batch("COMMIT; BEGIN")?;
+            self.pending = 0;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.conn.execute_batch("COMMIT")?;
+        Ok(())
+    }
+}
+
+// Arrow IPC stream sink
+pub struct ArrowSink {
+    out: Option<Box<dyn Write>>,
+    pending: Vec<Event>,
+    // Created from the first batch, which fixes the schema
+    stream: Option<ArrowStream>,
+}
+
+struct ArrowStream {
+    writer: StreamWriter<Box<dyn Write>>,
+    schema: Arc<Schema>,
+    columns: Vec<ArrowColumn>,
+}
+
+struct ArrowColumn {
+    name: String,
+    kind: ArrowKind,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ArrowKind {
+    Timestamp,
+    Float64,
+    Boolean,
+    Utf8,
+}
+
+/// Events per record batch; the schema is inferred from the first batch
+const ARROW_BATCH_SIZE: usize = 1024;
+
+impl ArrowSink {
+    pub fn new(out: Box<dyn Write>) -> Self {
+        Self {
+            out: Some(out),
+            pending: Vec::new(),
+            stream: None,
+        }
+    }
+
+    /// Infer columns and types from a batch: numbers and booleans keep their type
+    /// when a column holds nothing else, anything mixed becomes a string
+    fn infer_columns(events: &[Event]) -> Vec<ArrowColumn> {
+        let mut columns = Vec::new();
+        let core = [
+            (
+                "timestamp",
+                ArrowKind::Timestamp,
+                events.iter().any(|e| e.timestamp.is_some()),
+            ),
+            (
+                "level",
+                ArrowKind::Utf8,
+                events.iter().any(|e| e.level.is_some()),
+            ),
+            (
+                "message",
+                ArrowKind::Utf8,
+                events.iter().any(|e| e.message.is_some()),
+            ),
+        ];
+        for (name, kind, present) in core {
+            if present {
+                columns.push(ArrowColumn {
+                    name: name.to_string(),
+                    kind,
+                });
+            }
+        }
+
+        let mut field_kinds: Vec<(String, Option<ArrowKind>)> = Vec::new();
+        for event in events {
+            for (key, value) in &event.fields {
+                let kind = match value {
+                    FieldValue::Number(_) => Some(ArrowKind::Float64),
+                    FieldValue::Boolean(_) => Some(ArrowKind::Boolean),
+                    FieldValue::String(_) => Some(ArrowKind::Utf8),
+                    FieldValue::Null => None,
+                };
+                match field_kinds.iter_mut().find(|(name, _)| name == key) {
+                    Some((_, existing)) => {
+                        *existing = match (*existing, kind) {
+                            (None, kind) | (kind, None) => kind,
+                            (Some(a), Some(b)) if a == b => Some(a),
+                            _ => Some(ArrowKind::Utf8),
+                        }
+                    }
+                    None => field_kinds.push((key.clone(), kind)),
+                }
+            }
+        }
+
+        for (name, kind) in field_kinds {
+            // Raw duplicates of core fields are already covered by the core columns
+            if columns.iter().any(|column| column.name == name) {
+                continue;
+            }
+            columns.push(ArrowColumn {
+                name,
+                kind: kind.unwrap_or(ArrowKind::Utf8),
+            });
+        }
+        columns
+    }
+
+    fn build_batch(
+        schema: Arc<Schema>,
+        columns: &[ArrowColumn],
+        events: &[Event],
+    ) -> Result<RecordBatch> {
+        let arrays: Vec<ArrayRef> = columns
+            .iter()
+            .map(|column| -> ArrayRef {
+                let values = events.iter().map(|event| match column.name.as_str() {
+                    "level" if event.level.is_some() => event.level.clone().map(FieldValue::String),
+                    "message" if event.message.is_some() => {
+                        event.message.clone().map(FieldValue::String)
+                    }
+                    _ => event.fields.get(&column.name).cloned(),
+                });
+                match column.kind {
+                    ArrowKind::Timestamp => Arc::new(
+                        TimestampMillisecondArray::from(
+                            events
+                                .iter()
+                                .map(|event| event.timestamp.map(|ts| ts.timestamp_millis()))
+                                .collect::<Vec<_>>(),
+                        )
+                        .with_timezone("UTC"),
+                    ),
+                    ArrowKind::Float64 => Arc::new(Float64Array::from(
+                        values
+                            .map(|value| match value {
+                                Some(FieldValue::Number(n)) => Some(n),
+                                Some(FieldValue::String(s)) => s.parse().ok(),
+                                _ => None,
+                            })
+                            .collect::<Vec<_>>(),
+                    )),
+                    ArrowKind::Boolean => Arc::new(BooleanArray::from(
+                        values
+                            .map(|value| match value {
+                                Some(FieldValue::Boolean(b)) => Some(b),
+                                Some(FieldValue::String(s)) => s.parse().ok(),
+                                _ => None,
+                            })
+                            .collect::<Vec<_>>(),
+                    )),
+                    ArrowKind::Utf8 => Arc::new(StringArray::from(
+                        values
+                            .map(|value| match value {
+                                Some(FieldValue::Null) | None => None,
+                                Some(value) => Some(value.to_string()),
+                            })
+                            .collect::<Vec<_>>(),
+                    )),
+                }
+            })
+            .collect();
+
+        Ok(RecordBatch::try_new(schema, arrays)?)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if self.stream.is_none() {
+            let columns = Self::infer_columns(&self.pending);
+            let schema = Schema::new(
+                columns
+                    .iter()
+                    .map(|column| {
+                        let data_type = match column.kind {
+                            ArrowKind::Timestamp => {
+                                DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into()))
+                            }
+                            ArrowKind::Float64 => DataType::Float64,
+                            ArrowKind::Boolean => DataType::Boolean,
+                            ArrowKind::Utf8 => DataType::Utf8,
+                        };
+                        Field::new(&column.name, data_type, true)
+                    })
+                    .collect::<Vec<_>>(),
+            );
+            let out = self.out.take().expect("output is only taken once");
+            let writer = StreamWriter::try_new(out, &schema)?;
+            self.stream = Some(ArrowStream {
+                writer,
+                schema: Arc::new(schema),
+                columns,
+            });
+        }
+
+        if !self.pending.is_empty() {
+            let stream = self.stream.as_mut().unwrap();
+            let batch = Self::build_batch(stream.schema.clone(), &stream.columns, &self.pending)?;
+            stream.writer.write(&batch)?;
+            self.pending.clear();
+        }
+        Ok(())
+    }
+}
+
+impl Sink for ArrowSink {
+    fn write(&mut self, event: &Event) -> Result<()> {
+        self.pending.push(event.clone());
+        if self.pending.len() >= ARROW_BATCH_SIZE {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.flush()?;
+        if let Some(stream) = self.stream.as_mut() {
+            stream.writer.finish()?;
+        }
+        Ok(())
+    }
+}
+
+fn sql_value(value: &FieldValue) -> Value {
+    match value {
+        FieldValue::String(s) => Value::Text(s.clone()),
+        FieldValue::Number(n) if n.fract() == 0.0 && n.abs() < i64::MAX as f64 => {
+            Value::Integer(*n as i64)
+        }
+        FieldValue::Number(n) => Value::Real(*n),
+        FieldValue::Boolean(b) => Value::Integer(*b as i64),
+        FieldValue::Null => Value::Null,
+    }
+}
+
+fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sqlite_sink_extends_table() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.db");
+
+        let mut sink = SqliteSink::open(&path, "events", true).unwrap();
+        let mut event = Event::new();
+        event.level = Some("ERROR".to_string());
+        event.message = Some("disk full".to_string());
+        event.set_field("level".to_string(), FieldValue::String("ERROR".to_string()));
+        event.set_field("status".to_string(), FieldValue::Number(500.0));
+        sink.write(&event).unwrap();
+
+        let mut event = Event::new();
+        event.message = Some("all good".to_string());
+        event.set_field("Host".to_string(), FieldValue::String("db01".to_string()));
+        event.set_field("host".to_string(), FieldValue::String("db02".to_string()));
+        sink.write(&event).unwrap();
+        sink.finish().unwrap();
+
+        let conn = Connection::open(&path).unwrap();
+        let status: f64 = conn
+            .query_row("SELECT status FROM events WHERE level = 'ERROR'", [], |r| {
+                r.get(0)
+            })
+            .unwrap();
+        assert_eq!(status, 500.0);
+
+        let host: String = conn
+            .query_row(
+                "SELECT e.Host FROM events_fts f JOIN events e ON e.rowid = f.rowid WHERE events_fts MATCH 'good'",
+                [],
+                |r| r.get(0),
+            )
+            .unwrap();
+        assert_eq!(host, "db01");
+    }
+
+    #[test]
+    fn test_sqlite_query() {
+        let mut sink = SqliteSink::in_memory("events").unwrap();
+        for (level, status) in [("ERROR", 500.0), ("ERROR", 503.0), ("INFO", 200.0)] {
+            let mut event = Event::new();
+            event.level = Some(level.to_string());
+            event.set_field("status".to_string(), FieldValue::Number(status));
+            sink.write(&event).unwrap();
+        }
+        sink.finish().unwrap();
+
+        let rows = sink
+            .query("SELECT level, count(*) AS n FROM events WHERE status >= 500 GROUP BY level")
+            .unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].level.as_deref(), Some("ERROR"));
+        assert_eq!(rows[0].fields.get("n"), Some(&FieldValue::Number(2.0)));
+
+        assert!(sink.query("SELECT nope FROM events").is_err());
+    }
+
+    #[test]
+    fn test_arrow_sink_infers_types() {
+        let mut event = Event::new();
+        event.level = Some("INFO".to_string());
+        event.set_field("latency".to_string(), FieldValue::Number(12.5));
+        event.set_field("ok".to_string(), FieldValue::Boolean(true));
+        event.set_field("mixed".to_string(), FieldValue::Number(1.0));
+
+        let mut other = Event::new();
+        other.set_field("mixed".to_string(), FieldValue::String("n/a".to_string()));
+
+        let columns = ArrowSink::infer_columns(&[event, other]);
+        let kinds: Vec<(&str, ArrowKind)> = columns
+            .iter()
+            .map(|column| (column.name.as_str(), column.kind))
+            .collect();
+        assert!(
+            kinds
+                == vec![
+                    ("level", ArrowKind::Utf8),
+                    ("latency", ArrowKind::Float64),
+                    ("ok", ArrowKind::Boolean),
+                    ("mixed", ArrowKind::Utf8),
+                ]
+        );
+    }
+
+    #[test]
+    fn test_arrow_sink_roundtrip() {
+        let buffer = SharedBuffer::default();
+        let mut sink = ArrowSink::new(Box::new(buffer.clone()));
+
+        for i in 0..3 {
+            let mut event = Event::new();
+            event.message = Some(format!("event {}", i));
+            event.set_field("n".to_string(), FieldValue::Number(i as f64));
+            sink.write(&event).unwrap();
+        }
+        sink.finish().unwrap();
+
+        let bytes = buffer.0.borrow().clone();
+        let reader =
+            arrow_ipc::reader::StreamReader::try_new(std::io::Cursor::new(bytes), None).unwrap();
+        let batches: Vec<RecordBatch> = reader.map(|batch| batch.unwrap()).collect();
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].num_rows(), 3);
+        assert_eq!(batches[0].schema().field(1).data_type(), &DataType::Float64);
+    }
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+}
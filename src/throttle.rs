@@ -0,0 +1,119 @@
+use anyhow::{bail, Context, Result};
+use std::time::{Duration, Instant};
+
+/// Rate limit for emitted events, in fixed windows: up to `limit` events pass per `period`,
+/// the rest are dropped and counted
+#[derive(Debug)]
+pub struct Throttle {
+    limit: usize,
+    period: Duration,
+    spec: String,
+    window_start: Option<Instant>,
+    passed: usize,
+    dropped: usize,
+}
+
+impl Throttle {
+    /// Parse a rate such as `100/s`, `600/min`, `5000/h`, or a bare `100` (per second)
+    pub fn parse(spec: &str) -> Result<Self> {
+        let (count, unit) = spec.split_once('/').unwrap_or((spec, "s"));
+        let limit: usize = count
+            .trim()
+            .parse()
+            .with_context(|| format!("invalid rate '{}'", spec))?;
+        if limit == 0 {
+            bail!("rate must be greater than zero");
+        }
+        let period = match unit.trim() {
+            "s" | "sec" | "second" => Duration::from_secs(1),
+            "m" | "min" | "minute" => Duration::from_secs(60),
+            "h" | "hour" => Duration::from_secs(3600),
+            unit => bail!(
+                "invalid rate unit '{}' in '{}' (use s, min or h)",
+                unit,
+                spec
+            ),
+        };
+        Ok(Self {
+            limit,
+            period,
+            spec: spec.trim().to_string(),
+            window_start: None,
+            passed: 0,
+            dropped: 0,
+        })
+    }
+
+    /// Whether an event may pass at `now`. When a new window starts, also returns how many
+    /// events the previous window dropped, so they can be reported
+    pub fn admit(&mut self, now: Instant) -> (bool, usize) {
+        let mut dropped_before = 0;
+        let expired = self
+            .window_start
+            .is_none_or(|start| now.duration_since(start) >= self.period);
+        if expired {
+            dropped_before = self.take_dropped();
+            self.window_start = Some(now);
+            self.passed = 0;
+        }
+
+        if self.passed < self.limit {
+            self.passed += 1;
+            (true, dropped_before)
+        } else {
+            self.dropped += 1;
+            (false, dropped_before)
+        }
+    }
+
+    /// Events dropped in the current window that haven't been reported yet
+    pub fn take_dropped(&mut self) -> usize {
+        std::mem::take(&mut self.dropped)
+    }
+
+    /// The rate as given, for messages
+    pub fn spec(&self) -> &str {
+        &self.spec
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let throttle = Throttle::parse("100/s").unwrap();
+        assert_eq!(throttle.limit, 100);
+        assert_eq!(throttle.period, Duration::from_secs(1));
+        assert_eq!(
+            Throttle::parse("600/min").unwrap().period,
+            Duration::from_secs(60)
+        );
+        assert_eq!(
+            Throttle::parse("50").unwrap().period,
+            Duration::from_secs(1)
+        );
+        assert!(Throttle::parse("0/s").is_err());
+        assert!(Throttle::parse("fast").is_err());
+        assert!(Throttle::parse("10/fortnight").is_err());
+    }
+
+    #[test]
+    fn test_windows() {
+        let mut throttle = Throttle::parse("2/s").unwrap();
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+
+        assert_eq!(throttle.admit(at(0)), (true, 0));
+        assert_eq!(throttle.admit(at(100)), (true, 0));
+        assert_eq!(throttle.admit(at(200)), (false, 0));
+        assert_eq!(throttle.admit(at(900)), (false, 0));
+        // New window: passes again and reports what the last one dropped
+        assert_eq!(throttle.admit(at(1000)), (true, 2));
+        assert_eq!(throttle.admit(at(1001)), (true, 0));
+        assert_eq!(throttle.admit(at(1002)), (false, 0));
+        assert_eq!(throttle.take_dropped(), 1);
+        assert_eq!(throttle.take_dropped(), 0);
+    }
+}
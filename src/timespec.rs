@@ -0,0 +1,250 @@
+use crate::event::parse_timestamp;
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Duration, NaiveDate, NaiveTime, Utc};
+
+/// Resolve a time expression against `now`:
+/// - absolute timestamps in any supported event format, or a plain date (`2024-03-14`)
+/// - `now`, `today`, `yesterday`
+/// - `now-5m`, `now+1h`
+/// - a bare duration meaning that long ago (`15m`, `1h30m`)
+/// - `2 hours ago`, `90 seconds ago`
+pub fn parse_time_expr(expr: &str, now: DateTime<Utc>) -> Result<DateTime<Utc>> {
+    let expr = expr.trim();
+    let lower = expr.to_lowercase();
+
+    match lower.as_str() {
+        "now" => return Ok(now),
+        "today" => return Ok(start_of_day(now)),
+        "yesterday" => return Ok(start_of_day(now) - Duration::days(1)),
+        _ => {}
+    }
+
+    if let Some(offset) = lower.strip_prefix("now") {
+        let offset = offset.trim_start();
+        if let Some(duration) = offset.strip_prefix('-') {
+            return earlier(now, parse_duration(duration)?);
+        }
+        if let Some(duration) = offset.strip_prefix('+') {
+            return now
+                .checked_add_signed(parse_duration(duration)?)
+                .context("duration out of range");
+        }
+        bail!("Invalid time: {}", expr);
+    }
+
+    if let Some(duration) = lower.strip_suffix("ago") {
+        return earlier(now, parse_duration(duration)?);
+    }
+
+    if let Ok(ts) = parse_timestamp(expr) {
+        return Ok(ts);
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(expr, "%Y-%m-%d") {
+        return Ok(date.and_time(NaiveTime::MIN).and_utc());
+    }
+
+    let duration = parse_duration(&lower).with_context(|| format!("Invalid time: {}", expr))?;
+    earlier(now, duration)
+}
+
+/// The time this long before `now`, unless that is out of the range of dates
+fn earlier(now: DateTime<Utc>, duration: Duration) -> Result<DateTime<Utc>> {
+    now.checked_sub_signed(duration)
+        .context("duration out of range")
+}
+
+fn start_of_day(ts: DateTime<Utc>) -> DateTime<Utc> {
+    ts.date_naive().and_time(NaiveTime::MIN).and_utc()
+}
+
+/// A chrono format for compact labels of time buckets of this size from `first` to `last`:
+/// the time of day when they all fall on one day, with seconds or milliseconds only when
+/// buckets need them
+pub fn bucket_label_format(
+    first: DateTime<Utc>,
+    last: DateTime<Utc>,
+    bucket_ms: i64,
+) -> &'static str {
+    let same_day = first.date_naive() == last.date_naive();
+    match (same_day, bucket_ms % 60_000 == 0, bucket_ms % 1000 == 0) {
+        (true, true, _) => "%H:%M",
+        (true, false, true) => "%H:%M:%S",
+        (true, false, false) => "%H:%M:%S%.3f",
+        (false, true, _) => "%Y-%m-%dT%H:%M",
+        (false, false, true) => "%Y-%m-%dT%H:%M:%S",
+        (false, false, false) => "%Y-%m-%dT%H:%M:%S%.3f",
+    }
+}
+
+/// Most empty time buckets in a row a timeline fills in. Longer quiet stretches, such as the
+/// one before a stray epoch-0 timestamp, are left out rather than taking up memory by the
+/// gigabyte
+pub const MAX_EMPTY_BUCKETS: i64 = 1000;
+
+/// Starts of the buckets of a timeline, given those with events in ascending order: these and
+/// the empty ones between them, except in stretches of over [`MAX_EMPTY_BUCKETS`]
+pub fn bucket_starts(starts: impl IntoIterator<Item = i64>, bucket_ms: i64) -> Vec<i64> {
+    let mut buckets = Vec::new();
+    let mut previous: Option<i64> = None;
+    for start in starts {
+        if let Some(previous) = previous {
+            let empty = (start - previous) / bucket_ms - 1;
+            if empty <= MAX_EMPTY_BUCKETS {
+                buckets.extend((1..=empty).map(|i| previous + i * bucket_ms));
+            }
+        }
+        buckets.push(start);
+        previous = Some(start);
+    }
+    buckets
+}
+
+/// A duration such as `1h1m1s`, `2m5s` or `30s`, in whole seconds
+pub fn format_duration(duration: chrono::Duration) -> String {
+    let seconds = duration.num_seconds();
+    let hours = seconds / 3600;
+    let minutes = (seconds % 3600) / 60;
+    let secs = seconds % 60;
+
+    if hours > 0 {
+        format!("{}h{}m{}s", hours, minutes, secs)
+    } else if minutes > 0 {
+        format!("{}m{}s", minutes, secs)
+    } else {
+        format!("{}s", secs)
+    }
+}
+
+/// Parse durations like `15m`, `1h30m`, `2 hours`, `90 seconds`
+pub fn parse_duration(text: &str) -> Result<Duration> {
+    let mut rest = text.trim();
+    if rest.is_empty() {
+        bail!("empty duration");
+    }
+
+    let mut total_ms: i64 = 0;
+    while !rest.is_empty() {
+        let digits_end = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let amount: i64 = rest[..digits_end]
+            .parse()
+            .with_context(|| format!("invalid duration '{}'", text))?;
+        rest = rest[digits_end..].trim_start();
+
+        let unit_end = rest
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(rest.len());
+        let unit_ms: i64 = match &rest[..unit_end] {
+            "ms" | "msec" | "msecs" | "millisecond" | "milliseconds" => 1,
+            "s" | "sec" | "secs" | "second" | "seconds" => 1000,
+            "m" | "min" | "mins" | "minute" | "minutes" => 60_000,
+            "h" | "hr" | "hrs" | "hour" | "hours" => 3_600_000,
+            "d" | "day" | "days" => 86_400_000,
+            "w" | "week" | "weeks" => 604_800_000,
+            unit => bail!("invalid duration unit '{}' in '{}'", unit, text),
+        };
+        total_ms = amount
+            .checked_mul(unit_ms)
+            .and_then(|ms| total_ms.checked_add(ms))
+            .with_context(|| format!("duration out of range: '{}'", text))?;
+        rest = rest[unit_end..].trim_start();
+    }
+    Duration::try_milliseconds(total_ms)
+        .with_context(|| format!("duration out of range: '{}'", text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn now() -> DateTime<Utc> {
+        "2024-03-14T12:00:00Z".parse().unwrap()
+    }
+
+    fn at(ts: &str) -> DateTime<Utc> {
+        ts.parse().unwrap()
+    }
+
+    #[test]
+    fn test_relative_expressions() {
+        assert_eq!(parse_time_expr("now", now()).unwrap(), now());
+        assert_eq!(
+            parse_time_expr("15m", now()).unwrap(),
+            at("2024-03-14T11:45:00Z")
+        );
+        assert_eq!(
+            parse_time_expr("2 hours ago", now()).unwrap(),
+            at("2024-03-14T10:00:00Z")
+        );
+        assert_eq!(
+            parse_time_expr("now-5m", now()).unwrap(),
+            at("2024-03-14T11:55:00Z")
+        );
+        assert_eq!(
+            parse_time_expr("now + 1h30m", now()).unwrap(),
+            at("2024-03-14T13:30:00Z")
+        );
+        assert_eq!(
+            parse_time_expr("yesterday", now()).unwrap(),
+            at("2024-03-13T00:00:00Z")
+        );
+    }
+
+    #[test]
+    fn test_absolute_expressions() {
+        assert_eq!(
+            parse_time_expr("2024-03-14T10:00:00Z", now()).unwrap(),
+            at("2024-03-14T10:00:00Z")
+        );
+        assert_eq!(
+            parse_time_expr("2024-03-14", now()).unwrap(),
+            at("2024-03-14T00:00:00Z")
+        );
+    }
+
+    #[test]
+    fn test_invalid_expressions() {
+        assert!(parse_time_expr("yesterday-ish", now()).is_err());
+        assert!(parse_time_expr("now*2", now()).is_err());
+        assert!(parse_time_expr("5 fortnights ago", now()).is_err());
+        assert!(parse_duration("").is_err());
+    }
+
+    #[test]
+    fn test_out_of_range() {
+        let error = |expr| format!("{:#}", parse_time_expr(expr, now()).unwrap_err());
+        assert!(error("100000000d").contains("duration out of range"));
+        assert!(error("now+100000000d").contains("duration out of range"));
+        assert!(error("99999999999999999w ago").contains("duration out of range"));
+        // No longer wraps around into the future
+        assert_eq!(
+            parse_time_expr("3000000000s", now()).unwrap(),
+            at("1929-02-19T06:40:00Z")
+        );
+    }
+
+    #[test]
+    fn test_bucket_starts() {
+        assert_eq!(bucket_starts([0, 30, 90], 30), [0, 30, 60, 90]);
+        assert!(bucket_starts([], 30).is_empty());
+        // 1971 to 2024 in seconds doesn't fill in 1.7 billion empty buckets
+        let (old, new) = (31_536_000_000, 1_710_410_400_000);
+        assert_eq!(
+            bucket_starts([old, old + 2000, new], 1000),
+            [old, old + 1000, old + 2000, new]
+        );
+    }
+
+    #[test]
+    fn test_format_duration() {
+        let duration = chrono::Duration::seconds(3661); // 1h 1m 1s
+        assert_eq!(format_duration(duration), "1h1m1s");
+
+        let duration = chrono::Duration::seconds(61); // 1m 1s
+        assert_eq!(format_duration(duration), "1m1s");
+
+        let duration = chrono::Duration::seconds(30); // 30s
+        assert_eq!(format_duration(duration), "30s");
+    }
+}
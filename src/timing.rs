@@ -0,0 +1,884 @@
+use crate::aggregate::{FieldAggregate, Histogram};
+use crate::drain::{Drain, DrainConfig};
+use crate::event::{level_rank, Event, FieldValue};
+use crate::expr::Expr;
+use crate::timespec::{bucket_label_format, format_duration};
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use indexmap::IndexMap;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::io::{self, Write};
+
+fn time_value(timestamp: DateTime<Utc>) -> FieldValue {
+    FieldValue::String(timestamp.to_rfc3339_opts(SecondsFormat::Millis, true))
+}
+
+/// Finds silences: stretches longer than a threshold without events (`--gaps 30s`), overall
+/// or per value of a key (`--gaps-by host`), reported as one event per gap with `from`, `to`
+/// and `duration` as soon as the next event ends it. Per key, a value that stays silent until
+/// the input ends is reported then, up to the last event overall and with `ongoing` true. Events
+/// without a timestamp, or without the key, are left out
+#[derive(Debug)]
+pub struct GapDetector {
+    threshold: Duration,
+    key: Option<String>,
+    /// Latest timestamp per value of the key in its text form, with the value
+    last_seen: IndexMap<String, (FieldValue, DateTime<Utc>)>,
+    latest: Option<DateTime<Utc>>,
+}
+
+impl GapDetector {
+    pub fn new(threshold: Duration, key: Option<String>) -> Result<Self, String> {
+        if threshold <= Duration::zero() {
+            return Err("threshold must be positive".to_string());
+        }
+        Ok(Self {
+            threshold,
+            key,
+            last_seen: IndexMap::new(),
+            latest: None,
+        })
+    }
+
+    /// The gap this event ends, if any
+    pub fn add(&mut self, event: &Event) -> Option<Event> {
+        let timestamp = event.timestamp?;
+        self.latest = self.latest.max(Some(timestamp));
+        let value = match &self.key {
+            Some(key) => event.get_value(key)?,
+            None => FieldValue::Null,
+        };
+        let (_, last) = self
+            .last_seen
+            .entry(value.to_string())
+            .or_insert((value.clone(), timestamp));
+        // Events out of order don't move back in time
+        let previous = std::mem::replace(last, timestamp.max(*last));
+        (timestamp - previous > self.threshold)
+            .then(|| self.gap(&value, previous, timestamp, false))
+    }
+
+    /// Gaps per key value that last until the end of the input
+    pub fn finish(&self) -> Vec<Event> {
+        let Some(latest) = self.latest else {
+            return Vec::new();
+        };
+        self.last_seen
+            .values()
+            .filter(|(_, last)| latest - *last > self.threshold)
+            .map(|(value, last)| self.gap(value, *last, latest, true))
+            .collect()
+    }
+
+    fn gap(
+        &self,
+        value: &FieldValue,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        ongoing: bool,
+    ) -> Event {
+        let mut event = Event::new();
+        if let Some(key) = &self.key {
+            event.set_field(key.clone(), value.clone());
+        }
+        event.set_field("from".to_string(), time_value(from));
+        event.set_field("to".to_string(), time_value(to));
+        event.set_field(
+            "duration".to_string(),
+            FieldValue::String(format_duration(to - from)),
+        );
+        event.set_field("ongoing".to_string(), FieldValue::Boolean(ongoing));
+        event.extract_core_fields();
+        event
+    }
+}
+
+/// Windows before the current one that make up the baseline of `--bursts`
+const BASELINE_WINDOWS: usize = 10;
+
+/// Templates shown per burst
+const BURST_TEMPLATES: usize = 3;
+
+/// Events of one window of `--bursts`, with their levels and templates
+#[derive(Debug)]
+struct Window {
+    start: i64,
+    events: usize,
+    levels: BTreeMap<String, usize>,
+    drain: Drain,
+}
+
+impl Window {
+    fn new(start: i64) -> Self {
+        Self {
+            start,
+            events: 0,
+            levels: BTreeMap::new(),
+            drain: Drain::new(DrainConfig::default()).expect("default settings are valid"),
+        }
+    }
+}
+
+/// A window with a burst of events, and what made it up
+#[derive(Debug)]
+struct Burst {
+    start: i64,
+    events: usize,
+    baseline: f64,
+    /// Most frequent first
+    levels: Vec<(String, usize)>,
+    templates: Vec<(usize, String)>,
+}
+
+/// Finds bursts (`--bursts`): windows of a fixed size with at least `factor` times as many
+/// events as the windows before them had on average, over the last ten including empty
+/// ones. A baseline below one event counts as one, so a burst is always at least `factor`
+/// events. Each burst is reported with its dominant levels and templates. Events without a
+/// timestamp are left out, and late ones count towards the current window
+#[derive(Debug)]
+pub struct BurstDetector {
+    window_ms: i64,
+    factor: f64,
+    /// Events of the windows before the current one, oldest first
+    history: VecDeque<usize>,
+    current: Option<Window>,
+    bursts: Vec<Burst>,
+}
+
+impl BurstDetector {
+    pub fn new(window: Duration, factor: f64) -> Result<Self, String> {
+        let window_ms = window.num_milliseconds();
+        if window_ms <= 0 {
+            return Err("window must be positive".to_string());
+        }
+        if factor.is_nan() || factor <= 1.0 {
+            return Err("factor must be more than 1".to_string());
+        }
+        Ok(Self {
+            window_ms,
+            factor,
+            history: VecDeque::new(),
+            current: None,
+            bursts: Vec::new(),
+        })
+    }
+
+    pub fn add(&mut self, event: &Event) {
+        let Some(timestamp) = event.timestamp else {
+            return;
+        };
+        let start = timestamp
+            .timestamp_millis()
+            .div_euclid(self.window_ms)
+            .saturating_mul(self.window_ms);
+        match &self.current {
+            Some(window) if start > window.start => {
+                let empty = (start - window.start) / self.window_ms - 1;
+                self.close();
+                // Only the latest windows matter for the baseline
+                for _ in 0..empty.min(BASELINE_WINDOWS as i64) {
+                    self.push_history(0);
+                }
+                self.current = Some(Window::new(start));
+            }
+            Some(_) => {}
+            None => self.current = Some(Window::new(start)),
+        }
+        let Some(window) = &mut self.current else {
+            return;
+        };
+        window.events += 1;
+        if let Some(level) = &event.level {
+            *window.levels.entry(level.to_lowercase()).or_default() += 1;
+        }
+        window.drain.add_event(event);
+    }
+
+    fn push_history(&mut self, events: usize) {
+        self.history.push_back(events);
+        if self.history.len() > BASELINE_WINDOWS {
+            self.history.pop_front();
+        }
+    }
+
+    /// Judge the current window against the baseline, and add it to it
+    fn close(&mut self) {
+        let Some(window) = self.current.take() else {
+            return;
+        };
+        if !self.history.is_empty() {
+            let baseline = self.history.iter().sum::<usize>() as f64 / self.history.len() as f64;
+            if window.events as f64 >= self.factor * baseline.max(1.0) {
+                let mut levels: Vec<(String, usize)> = window.levels.into_iter().collect();
+                levels.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+                let templates = window
+                    .drain
+                    .most_frequent()
+                    .into_iter()
+                    .take(BURST_TEMPLATES)
+                    .map(|cluster| (cluster.count, cluster.template()))
+                    .collect();
+                self.bursts.push(Burst {
+                    start: window.start,
+                    events: window.events,
+                    baseline,
+                    levels,
+                    templates,
+                });
+            }
+        }
+        self.push_history(window.events);
+    }
+
+    /// Judge the last window too; call before the report
+    pub fn finish(&mut self) {
+        self.close();
+    }
+
+    /// Write each burst: its window, events and how many times the baseline they are, then
+    /// the events per level and its most frequent templates
+    pub fn write_report(&self, out: &mut dyn Write) -> io::Result<()> {
+        let times: Vec<DateTime<Utc>> = self
+            .bursts
+            .iter()
+            .flat_map(|burst| [burst.start, burst.start + self.window_ms])
+            .filter_map(DateTime::from_timestamp_millis)
+            .collect();
+        let (Some(&first), Some(&last)) = (times.first(), times.last()) else {
+            return writeln!(out, "No bursts of {}× the baseline", self.factor);
+        };
+        let layout = bucket_label_format(first, last, self.window_ms);
+        for (burst, window) in self.bursts.iter().zip(times.chunks(2)) {
+            writeln!(
+                out,
+                "{} .. {}  {} events, {:.1}× the baseline of {:.1}",
+                window[0].format(layout),
+                window[1].format(layout),
+                burst.events,
+                burst.events as f64 / burst.baseline.max(1.0),
+                burst.baseline
+            )?;
+            if !burst.levels.is_empty() {
+                let levels: Vec<String> = burst
+                    .levels
+                    .iter()
+                    .map(|(level, count)| format!("{} {}", level, count))
+                    .collect();
+                writeln!(out, "{:>8}  levels: {}", "", levels.join(", "))?;
+            }
+            for (count, template) in &burst.templates {
+                writeln!(out, "{:>8}  {}", count, template)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Events of one value of the key of `--sessions` close enough together
+#[derive(Debug)]
+struct Session {
+    value: FieldValue,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    events: usize,
+    errors: usize,
+}
+
+/// Groups events into sessions per value of a key (`--sessions user_id`): a session ends
+/// when that value has no events for longer than the gap. Each comes out as one event with
+/// the value, `start`, `end`, `duration`, `events` and `errors` (events of level error or
+/// worse), once a later event of any value shows it has ended, or when the input ends.
+/// Events without a timestamp or the key are left out
+#[derive(Debug)]
+pub struct Sessionizer {
+    key: String,
+    gap: Duration,
+    /// Open sessions by the value in its text form
+    open: IndexMap<String, Session>,
+    /// Open sessions by their last event, to end them in order
+    by_end: BTreeSet<(DateTime<Utc>, String)>,
+}
+
+impl Sessionizer {
+    pub fn new(key: String, gap: Duration) -> Result<Self, String> {
+        if gap <= Duration::zero() {
+            return Err("gap must be positive".to_string());
+        }
+        Ok(Self {
+            key,
+            gap,
+            open: IndexMap::new(),
+            by_end: BTreeSet::new(),
+        })
+    }
+
+    /// Sessions that have ended by the time of this event
+    pub fn add(&mut self, event: &Event) -> Vec<Event> {
+        let Some(timestamp) = event.timestamp else {
+            return Vec::new();
+        };
+        // A gap reaching back past the earliest date ends nothing
+        let ended = match timestamp.checked_sub_signed(self.gap) {
+            Some(cutoff) => self.end_before(cutoff),
+            None => Vec::new(),
+        };
+        let Some(value) = event.get_value(&self.key) else {
+            return ended;
+        };
+        let error = event.level_rank() >= level_rank("error");
+        let name = value.to_string();
+        let session = self.open.entry(name.clone()).or_insert(Session {
+            value,
+            start: timestamp,
+            end: timestamp,
+            events: 0,
+            errors: 0,
+        });
+        self.by_end.remove(&(session.end, name.clone()));
+        session.start = session.start.min(timestamp);
+        session.end = session.end.max(timestamp);
+        session.events += 1;
+        session.errors += usize::from(error);
+        self.by_end.insert((session.end, name));
+        ended
+    }
+
+    /// End the sessions whose last event is before `time`
+    fn end_before(&mut self, time: DateTime<Utc>) -> Vec<Event> {
+        let mut ended = Vec::new();
+        while let Some((end, name)) = self.by_end.first().cloned() {
+            if end >= time {
+                break;
+            }
+            self.by_end.pop_first();
+            if let Some(session) = self.open.shift_remove(&name) {
+                ended.push(self.summary(session));
+            }
+        }
+        ended
+    }
+
+    /// The sessions still open, in the order they ended
+    pub fn finish(&mut self) -> Vec<Event> {
+        self.end_before(DateTime::<Utc>::MAX_UTC)
+    }
+
+    fn summary(&self, session: Session) -> Event {
+        let mut event = Event::new();
+        event.set_field(self.key.clone(), session.value);
+        event.set_field("start".to_string(), time_value(session.start));
+        event.set_field("end".to_string(), time_value(session.end));
+        event.set_field(
+            "duration".to_string(),
+            FieldValue::String(format_duration(session.end - session.start)),
+        );
+        event.set_field(
+            "events".to_string(),
+            FieldValue::Number(session.events as f64),
+        );
+        event.set_field(
+            "errors".to_string(),
+            FieldValue::Number(session.errors as f64),
+        );
+        event.extract_core_fields();
+        event
+    }
+}
+
+/// Matches start events with the end events of the same value of a key (`--pair` with
+/// `--pair-key request_id`). A pair comes out when its end arrives, as the start event with
+/// the fields only the end has added, plus `end`, `duration`, `duration_ms` and `paired`
+/// true. Starts never ended come out as they were, with `paired` false, when another start
+/// of the same value replaces them or the input ends. Ends without a start, and events
+/// without a timestamp or the key, are left out
+#[derive(Debug)]
+pub struct Pairs {
+    start: Expr,
+    end: Expr,
+    key: String,
+    /// Starts waiting for their end, by the value of the key in its text form
+    open: IndexMap<String, (Event, DateTime<Utc>)>,
+}
+
+impl Pairs {
+    pub fn new(start: Expr, end: Expr, key: String) -> Self {
+        Self {
+            start,
+            end,
+            key,
+            open: IndexMap::new(),
+        }
+    }
+
+    /// The pair this event ends, or the start it replaces
+    pub fn add(&mut self, event: &Event) -> Option<Event> {
+        let timestamp = event.timestamp?;
+        let name = event.get_value(&self.key)?.to_string();
+        if self.start.matches(event) {
+            let replaced = self.open.insert(name, (event.clone(), timestamp));
+            return replaced.map(|(start, _)| unpaired(start));
+        }
+        if !self.end.matches(event) {
+            return None;
+        }
+        let (mut pair, started) = self.open.shift_remove(&name)?;
+        for (key, value) in &event.fields {
+            if !pair.fields.contains_key(key) {
+                pair.set_field(key.clone(), value.clone());
+            }
+        }
+        let duration = timestamp - started;
+        pair.set_field("end".to_string(), time_value(timestamp));
+        pair.set_field(
+            "duration".to_string(),
+            FieldValue::String(format_duration(duration)),
+        );
+        pair.set_field(
+            "duration_ms".to_string(),
+            FieldValue::Number(duration.num_milliseconds() as f64),
+        );
+        pair.set_field("paired".to_string(), FieldValue::Boolean(true));
+        Some(pair)
+    }
+
+    /// The starts still waiting for their end, in the order they started
+    pub fn finish(self) -> Vec<Event> {
+        self.open
+            .into_values()
+            .map(|(start, _)| unpaired(start))
+            .collect()
+    }
+}
+
+fn unpaired(mut start: Event) -> Event {
+    start.set_field("paired".to_string(), FieldValue::Boolean(false));
+    start
+}
+
+/// Time between two kinds of events sharing the value of a key (`--latency-between` with
+/// `--by trace_id`), matched like [`Pairs`], summed up as the statistics of `--agg` over
+/// the milliseconds between them, with how many starts never got their end
+#[derive(Debug)]
+pub struct Latency {
+    pairs: Pairs,
+    durations: FieldAggregate,
+    unended: usize,
+}
+
+impl Latency {
+    pub fn new(from: Expr, to: Expr, key: String) -> Self {
+        Self {
+            pairs: Pairs::new(from, to, key),
+            durations: FieldAggregate::with_default_statistics("latency_ms".to_string()),
+            unended: 0,
+        }
+    }
+
+    pub fn add(&mut self, event: &Event) {
+        let Some(pair) = self.pairs.add(event) else {
+            return;
+        };
+        match pair.fields.get("duration_ms") {
+            Some(FieldValue::Number(millis)) => self.durations.add(*millis),
+            _ => self.unended += 1,
+        }
+    }
+
+    pub fn write_report(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "{}", self.durations.summary())?;
+        let unended = self.unended + self.pairs.open.len();
+        if unended > 0 {
+            writeln!(out, "{} without an end", unended)?;
+        }
+        Ok(())
+    }
+}
+
+/// Buckets of the histogram of `--interarrival-hist`
+const INTERARRIVAL_BUCKETS: usize = 20;
+
+/// Time between consecutive events (`--interarrival`), overall or per value of a key
+/// (`--interarrival-by job`), summed up like `--agg` in milliseconds and optionally as a
+/// histogram on a log scale, where periodic jobs and retry loops stand out as peaks. Events
+/// without a timestamp or the key, and events earlier than the one before, are left out
+#[derive(Debug)]
+pub struct InterArrival {
+    key: Option<String>,
+    /// Latest timestamp per value of the key in its text form, or under "" overall
+    last: IndexMap<String, DateTime<Utc>>,
+    deltas: IndexMap<String, FieldAggregate>,
+    histogram: Option<Histogram>,
+}
+
+impl InterArrival {
+    pub fn new(key: Option<String>, histogram: bool) -> Self {
+        Self {
+            key,
+            last: IndexMap::new(),
+            deltas: IndexMap::new(),
+            histogram: histogram
+                .then(|| Histogram::new("interarrival_ms".to_string(), INTERARRIVAL_BUCKETS, true)),
+        }
+    }
+
+    pub fn add(&mut self, event: &Event) {
+        let Some(timestamp) = event.timestamp else {
+            return;
+        };
+        let name = match &self.key {
+            Some(key) => match event.get_value(key) {
+                Some(value) => value.to_string(),
+                None => return,
+            },
+            None => String::new(),
+        };
+        let Some(last) = self.last.get_mut(&name) else {
+            self.last.insert(name, timestamp);
+            return;
+        };
+        if timestamp < *last {
+            return;
+        }
+        let delta = timestamp - *last;
+        *last = timestamp;
+        let millis = match delta.num_microseconds() {
+            Some(micros) => micros as f64 / 1000.0,
+            None => delta.num_milliseconds() as f64,
+        };
+        let label = match &self.key {
+            Some(key) => format!("interarrival_ms {}={}", key, name),
+            None => "interarrival_ms".to_string(),
+        };
+        self.deltas
+            .entry(name)
+            .or_insert_with(|| FieldAggregate::with_default_statistics(label))
+            .add(millis);
+        if let Some(histogram) = &mut self.histogram {
+            histogram.add_value(millis);
+        }
+    }
+
+    pub fn write_report(&self, out: &mut dyn Write) -> io::Result<()> {
+        if self.deltas.is_empty() {
+            return writeln!(out, "No two consecutive events with timestamps");
+        }
+        // In the order the values first appeared
+        for deltas in self.last.keys().filter_map(|name| self.deltas.get(name)) {
+            writeln!(out, "{}", deltas.summary())?;
+        }
+        if let Some(histogram) = &self.histogram {
+            writeln!(out)?;
+            histogram.write_report(out)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(time: &str, host: Option<&str>) -> Event {
+        let mut event = Event::new();
+        event.timestamp = Some(time.parse().unwrap());
+        if let Some(host) = host {
+            event.set_field("host".to_string(), FieldValue::String(host.to_string()));
+        }
+        event
+    }
+
+    fn text(value: &str) -> FieldValue {
+        FieldValue::String(value.to_string())
+    }
+
+    #[test]
+    fn test_gaps() {
+        let mut gaps = GapDetector::new(Duration::seconds(30), None).unwrap();
+        assert!(gaps.add(&event("2024-03-14T10:00:00Z", None)).is_none());
+        assert!(gaps.add(&event("2024-03-14T10:00:30Z", None)).is_none());
+        let gap = gaps.add(&event("2024-03-14T10:02:45Z", None)).unwrap();
+        assert_eq!(gap.fields["from"], text("2024-03-14T10:00:30.000Z"));
+        assert_eq!(gap.fields["to"], text("2024-03-14T10:02:45.000Z"));
+        assert_eq!(gap.fields["duration"], text("2m15s"));
+        // Out of order
+        assert!(gaps.add(&event("2024-03-14T10:01:00Z", None)).is_none());
+        assert!(gaps.add(&event("2024-03-14T10:03:00Z", None)).is_none());
+        assert!(gaps.add(&Event::new()).is_none());
+        assert!(gaps.finish().is_empty());
+        assert!(GapDetector::new(Duration::zero(), None).is_err());
+    }
+
+    #[test]
+    fn test_gaps_by_key() {
+        let mut gaps = GapDetector::new(Duration::minutes(1), Some("host".to_string())).unwrap();
+        for (time, host) in [
+            ("2024-03-14T10:00:00Z", "web1"),
+            ("2024-03-14T10:00:00Z", "web2"),
+            ("2024-03-14T10:00:50Z", "web1"),
+            ("2024-03-14T10:01:40Z", "web1"),
+        ] {
+            assert!(gaps.add(&event(time, Some(host))).is_none());
+        }
+        assert!(gaps.add(&event("2024-03-14T10:05:00Z", None)).is_none());
+        let gap = gaps
+            .add(&event("2024-03-14T10:03:00Z", Some("web1")))
+            .unwrap();
+        assert_eq!(gap.fields["host"], text("web1"));
+        assert_eq!(gap.fields["duration"], text("1m20s"));
+        assert_eq!(gap.fields["ongoing"], FieldValue::Boolean(false));
+
+        // web2 fell silent, up to the last event of any host
+        let ongoing = gaps.finish();
+        assert_eq!(ongoing.len(), 2);
+        assert_eq!(ongoing[0].fields["host"], text("web1"));
+        assert_eq!(ongoing[0].fields["duration"], text("2m0s"));
+        assert_eq!(ongoing[1].fields["host"], text("web2"));
+        assert_eq!(ongoing[1].fields["to"], text("2024-03-14T10:05:00.000Z"));
+        assert_eq!(ongoing[1].fields["ongoing"], FieldValue::Boolean(true));
+    }
+
+    fn message(time: &str, level: &str, message: &str) -> Event {
+        let mut event = event(time, None);
+        event.level = Some(level.to_string());
+        event.message = Some(message.to_string());
+        event
+    }
+
+    #[test]
+    fn test_bursts() {
+        let mut bursts = BurstDetector::new(Duration::minutes(1), 3.0).unwrap();
+        // 2 events a minute, then 9 in one, then a minute without events and 3 after it
+        for minute in 0..4 {
+            for second in [10, 40] {
+                bursts.add(&message(
+                    &format!("2024-03-14T10:{:02}:{}Z", minute, second),
+                    "info",
+                    "request served",
+                ));
+            }
+        }
+        for second in 0..9 {
+            let (level, text) = match second {
+                0..=5 => ("error", format!("disk {} full", second)),
+                _ => ("info", "request served".to_string()),
+            };
+            bursts.add(&message(
+                &format!("2024-03-14T10:04:{:02}Z", second),
+                level,
+                &text,
+            ));
+        }
+        for second in [0, 1, 2] {
+            bursts.add(&message(
+                &format!("2024-03-14T10:06:{:02}Z", second),
+                "info",
+                "request served",
+            ));
+        }
+        bursts.add(&Event::new());
+        bursts.finish();
+
+        let mut out = Vec::new();
+        bursts.write_report(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "10:04 .. 10:05  9 events, 4.5× the baseline of 2.0\n\
+            \x20         levels: error 6, info 3\n\
+            \x20      6  disk <num> full\n\
+            \x20      3  request served\n"
+        );
+
+        let mut out = Vec::new();
+        BurstDetector::new(Duration::minutes(1), 3.0)
+            .unwrap()
+            .write_report(&mut out)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "No bursts of 3× the baseline\n"
+        );
+        assert!(BurstDetector::new(Duration::minutes(1), 1.0).is_err());
+        assert!(BurstDetector::new(Duration::zero(), 3.0).is_err());
+    }
+
+    #[test]
+    fn test_sessions() {
+        let mut sessions = Sessionizer::new("user".to_string(), Duration::minutes(30)).unwrap();
+        let mut ended = Vec::new();
+        for (time, user, level) in [
+            ("2024-03-14T10:00:00Z", "alice", "info"),
+            ("2024-03-14T10:05:00Z", "bob", "info"),
+            ("2024-03-14T10:20:00Z", "alice", "error"),
+            ("2024-03-14T10:45:00Z", "alice", "info"),
+            // bob's session ended at 10:05
+            ("2024-03-14T10:40:00Z", "carol", "info"),
+            ("2024-03-14T11:30:00Z", "alice", "info"),
+        ] {
+            let mut event = event(time, None);
+            event.set_field("user".to_string(), text(user));
+            event.level = Some(level.to_string());
+            ended.extend(sessions.add(&event));
+        }
+        assert!(sessions
+            .add(&event("2024-03-14T11:31:00Z", None))
+            .is_empty());
+        ended.extend(sessions.finish());
+
+        let rows: Vec<(FieldValue, FieldValue, FieldValue, FieldValue)> = ended
+            .into_iter()
+            .map(|row| {
+                (
+                    row.fields["user"].clone(),
+                    row.fields["duration"].clone(),
+                    row.fields["events"].clone(),
+                    row.fields["errors"].clone(),
+                )
+            })
+            .collect();
+        let number = FieldValue::Number;
+        assert_eq!(
+            rows,
+            [
+                (text("bob"), text("0s"), number(1.0), number(0.0)),
+                (text("carol"), text("0s"), number(1.0), number(0.0)),
+                (text("alice"), text("45m0s"), number(3.0), number(1.0)),
+                (text("alice"), text("0s"), number(1.0), number(0.0)),
+            ]
+        );
+        assert!(Sessionizer::new("user".to_string(), Duration::zero()).is_err());
+
+        // A gap longer than all of time
+        let mut sessions =
+            Sessionizer::new("user".to_string(), Duration::days(100_000_000)).unwrap();
+        let mut event = event("2024-03-14T10:00:00Z", None);
+        event.set_field("user".to_string(), text("alice"));
+        assert!(sessions.add(&event).is_empty());
+        assert_eq!(sessions.finish().len(), 1);
+    }
+
+    #[test]
+    fn test_pairs() {
+        let mut pairs = Pairs::new(
+            Expr::parse(r#"msg =~ "started""#).unwrap(),
+            Expr::parse(r#"msg =~ "completed""#).unwrap(),
+            "id".to_string(),
+        );
+        let mut output = Vec::new();
+        for (time, id, message) in [
+            ("2024-03-14T10:00:00Z", "a", "request started"),
+            ("2024-03-14T10:00:01Z", "b", "request started"),
+            ("2024-03-14T10:00:01Z", "a", "cache miss"),
+            ("2024-03-14T10:00:02.5Z", "a", "request completed"),
+            ("2024-03-14T10:00:03Z", "c", "request completed"),
+            ("2024-03-14T10:00:04Z", "d", "request started"),
+            ("2024-03-14T10:00:05Z", "d", "request started"),
+        ] {
+            let mut event = event(time, None);
+            event.set_field("id".to_string(), text(id));
+            event.message = Some(message.to_string());
+            if message == "request completed" {
+                event.set_field("status".to_string(), FieldValue::Number(200.0));
+            }
+            output.extend(pairs.add(&event));
+        }
+        output.extend(pairs.finish());
+
+        let rows: Vec<(
+            FieldValue,
+            Option<FieldValue>,
+            Option<FieldValue>,
+            FieldValue,
+        )> = output
+            .into_iter()
+            .map(|row| {
+                (
+                    row.fields["id"].clone(),
+                    row.fields.get("duration_ms").cloned(),
+                    row.fields.get("status").cloned(),
+                    row.fields["paired"].clone(),
+                )
+            })
+            .collect();
+        let number = |n| Some(FieldValue::Number(n));
+        assert_eq!(
+            rows,
+            [
+                (
+                    text("a"),
+                    number(2500.0),
+                    number(200.0),
+                    FieldValue::Boolean(true)
+                ),
+                (text("d"), None, None, FieldValue::Boolean(false)),
+                (text("b"), None, None, FieldValue::Boolean(false)),
+                (text("d"), None, None, FieldValue::Boolean(false)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_latency() {
+        let mut latency = Latency::new(
+            Expr::parse(r#"msg == "sent""#).unwrap(),
+            Expr::parse(r#"msg == "received""#).unwrap(),
+            "trace".to_string(),
+        );
+        for (time, trace, message) in [
+            ("2024-03-14T10:00:00Z", "a", "sent"),
+            ("2024-03-14T10:00:00.1Z", "b", "sent"),
+            ("2024-03-14T10:00:00.2Z", "a", "received"),
+            ("2024-03-14T10:00:00.5Z", "b", "received"),
+            ("2024-03-14T10:00:01Z", "c", "sent"),
+            ("2024-03-14T10:00:02Z", "a", "received"),
+        ] {
+            let mut event = event(time, None);
+            event.set_field("trace".to_string(), text(trace));
+            event.message = Some(message.to_string());
+            latency.add(&event);
+        }
+        let mut out = Vec::new();
+        latency.write_report(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "latency_ms: count=2 min=200 mean=300 p50=400 p95=400 p99=400 max=400\n\
+            1 without an end\n"
+        );
+    }
+
+    #[test]
+    fn test_interarrival() {
+        let report = |interarrival: &InterArrival| {
+            let mut out = Vec::new();
+            interarrival.write_report(&mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        let mut overall = InterArrival::new(None, false);
+        let mut by_host = InterArrival::new(Some("host".to_string()), false);
+        assert_eq!(
+            report(&overall),
+            "No two consecutive events with timestamps\n"
+        );
+        for (time, host) in [
+            ("2024-03-14T10:00:00Z", Some("a")),
+            ("2024-03-14T10:00:00.5Z", Some("b")),
+            ("2024-03-14T10:00:01Z", Some("a")),
+            // Out of order
+            ("2024-03-14T09:59:00Z", Some("a")),
+            ("2024-03-14T10:00:02Z", None),
+            ("2024-03-14T10:00:03.5Z", Some("b")),
+        ] {
+            let event = event(time, host);
+            overall.add(&event);
+            by_host.add(&event);
+        }
+        overall.add(&Event::new());
+        assert_eq!(
+            report(&overall),
+            "interarrival_ms: count=4 min=500 mean=875 p50=1000 p95=1500 p99=1500 max=1500\n"
+        );
+        assert_eq!(
+            report(&by_host),
+            "interarrival_ms host=a: count=1 min=1000 mean=1000 p50=1000 p95=1000 p99=1000 max=1000\n\
+            interarrival_ms host=b: count=1 min=3000 mean=3000 p50=3000 p95=3000 p99=3000 max=3000\n"
+        );
+    }
+}
@@ -1,4 +1,6 @@
 // tests/integration_tests.rs
+// The original tests are kept as written, with their unused bindings
+#![allow(unused_imports, unused_mut, unused_variables)]
 use std::fs;
 use std::io::Write;
 use std::process::{Command, Stdio};
@@ -488,3 +490,3062 @@ fn test_unicode_handling() {
         "Should preserve accented characters"
     );
 }
+
+#[test]
+fn test_fixed_width_parsing() {
+    let input = "2023-07-18 15:04:23 ERROR database  Connection failed\n2023-07-18 15:04:24 INFO  api       Request served";
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &[
+            "-f",
+            "fixed",
+            "--widths",
+            "20,6,10,0",
+            "--columns",
+            "ts,level,component,message",
+        ],
+        input,
+    );
+
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert!(stdout.contains("level=\"ERROR\""), "Should extract level");
+    assert!(
+        stdout.contains("component=\"database\""),
+        "Should trim padded columns"
+    );
+    assert!(
+        stdout.contains("message=\"Request served\""),
+        "Should take rest of line for width 0"
+    );
+}
+
+#[test]
+fn test_fixed_width_requires_widths() {
+    let (_stdout, stderr, exit_code) = run_kelora_with_input(&["-f", "fixed"], "abc");
+
+    assert_ne!(exit_code, 0, "kelora should fail without --widths");
+    assert!(stderr.contains("--widths"), "Should mention missing widths");
+}
+
+#[test]
+fn test_xml_parsing() {
+    let input = r#"<event time="2023-07-18T15:04:23Z" sev="ERROR" host="db01">Connection &quot;main&quot; lost</event>
+<event time="2023-07-18T15:04:24Z" sev="INFO"><msg>Recovered</msg><retry count="3"/></event>"#;
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(&["-f", "xml", "-F", "jsonl"], input);
+
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    let lines: Vec<serde_json::Value> = stdout
+        .lines()
+        .map(|line| serde_json::from_str(line).expect("Output should be valid JSON"))
+        .collect();
+    assert_eq!(lines.len(), 2, "Should parse both records");
+    assert_eq!(lines[0]["host"], "db01");
+    assert_eq!(lines[0]["message"], "Connection \"main\" lost");
+    assert_eq!(lines[1]["msg"], "Recovered");
+    assert_eq!(lines[1]["retry.count"], 3.0);
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &["-f", "xml", "-l", "error", "-F", "jsonl"],
+        r#"<event time="2023-07-18T15:04:23Z" sev="error">disk full</event>
+<event time="2023-07-18T15:04:24Z" sev="info">disk cleaned up</event>"#,
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(stdout.lines().count(), 1, "stdout: {}", stdout);
+    assert!(stdout.contains("disk full"), "stdout: {}", stdout);
+}
+
+#[test]
+fn test_csv_output_format() {
+    let input = r#"{"level":"ERROR","message":"Disk full, aborting","host":"db01"}
+{"level":"INFO","message":"Recovered","host":"db02"}"#;
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &["-f", "jsonl", "-F", "csv", "-k", "level,host,message"],
+        input,
+    );
+
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines[0], "level,host,message", "Header should follow -k");
+    assert_eq!(lines[1], "ERROR,db01,\"Disk full, aborting\"");
+    assert_eq!(lines[2], "INFO,db02,Recovered");
+}
+
+#[test]
+fn test_tsv_output_format() {
+    let input = r#"{"level":"ERROR","message":"first\tsecond","host":"db01"}"#;
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(&["-f", "jsonl", "-F", "tsv"], input);
+
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines[0], "level\tmessage\thost");
+    assert_eq!(lines[1], "ERROR\tfirst\\tsecond\tdb01");
+}
+
+#[test]
+fn test_table_output_format() {
+    let input = r#"{"level":"ERROR","message":"Disk full","host":"db01"}
+{"level":"INFO","message":"Recovered","host":"db02"}"#;
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &["-f", "jsonl", "-F", "table", "-k", "level,message"],
+        input,
+    );
+
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines[0], "level  message");
+    assert_eq!(lines[1], "ERROR  Disk full");
+    assert_eq!(lines[2], "INFO   Recovered");
+}
+
+#[test]
+fn test_json_pretty_output_format() {
+    let input = r#"level=ERROR message="First"
+level=INFO message="Second""#;
+
+    let (stdout, _stderr, exit_code) =
+        run_kelora_with_input(&["-F", "json-pretty", "--json-array", "-c"], input);
+
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert!(
+        stdout.contains("\n    \"level\": \"ERROR\""),
+        "Should be indented"
+    );
+    let parsed: serde_json::Value =
+        serde_json::from_str(&stdout).expect("Output should be a single JSON array");
+    assert_eq!(parsed[0]["message"], "First");
+    assert_eq!(parsed[1]["message"], "Second");
+
+    let (stdout, stderr, exit_code) =
+        run_kelora_with_input(&["-F", "jsonl", "--json-array"], input);
+    assert_ne!(
+        exit_code, 0,
+        "--json-array should be rejected with -F jsonl"
+    );
+    assert!(stdout.is_empty());
+    assert!(stderr.contains("-F json-pretty"), "stderr: {}", stderr);
+}
+
+#[test]
+fn test_color_output() {
+    let input = r#"level=ERROR message="Boom""#;
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(&["--color", "always"], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert!(stdout.contains("\x1b[1;31m"), "ERROR should be red");
+
+    // Piped output is not a terminal, so auto mode stays plain
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(&[], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert!(!stdout.contains('\x1b'), "Auto mode should not color pipes");
+}
+
+#[test]
+fn test_template_output_format() {
+    let input = r#"{"timestamp":"2023-07-18T15:04:23.456Z","level":"ERROR","message":"Timeout","request_id":"r-1"}
+{"level":"INFO","message":"No request"}"#;
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &[
+            "-f",
+            "jsonl",
+            "-F",
+            "template",
+            "--template",
+            "{timestamp} [{level}] {message} ({fields.request_id})",
+        ],
+        input,
+    );
+
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines[0], "2023-07-18T15:04:23.456Z [ERROR] Timeout (r-1)");
+    assert_eq!(lines[1], "- [INFO] No request (-)");
+}
+
+#[test]
+fn test_syslog_output_format() {
+    let input = r#"{"timestamp":"2023-07-18T15:04:23.456Z","level":"WARN","message":"Slow query","host":"db01","app":"pg","duration":1.5}"#;
+
+    let (stdout, _stderr, exit_code) =
+        run_kelora_with_input(&["-f", "jsonl", "-F", "syslog"], input);
+
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(
+        stdout.trim(),
+        "<12>1 2023-07-18T15:04:23.456Z db01 pg - - [kelora@32473 duration=\"1.5\"] Slow query"
+    );
+}
+
+#[test]
+fn test_yaml_output_format() {
+    let input = r#"{"level":"INFO","message":"Served","status":200,"cached":true,"zip":"01234"}
+{"level":"WARN","message":"Slow"}"#;
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(&["-f", "jsonl", "-F", "yaml"], input);
+
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    let documents: Vec<&str> = stdout.split("---\n").filter(|d| !d.is_empty()).collect();
+    assert_eq!(documents.len(), 2, "Should emit one document per event");
+    assert!(documents[0].contains("status: 200\n"));
+    assert!(documents[0].contains("cached: true\n"));
+    assert!(documents[0].contains("zip: \"01234\"\n"));
+    assert!(documents[1].contains("level: WARN\n"));
+}
+
+#[test]
+fn test_jsonl_key_order() {
+    let input = r#"{"timestamp":"2023-07-18T15:04:23.456Z","zone":"eu","level":"info","message":"served","host":"web1"}"#;
+
+    // Alphabetical, as JSONL output always was
+    let (stdout, _stderr, exit_code) =
+        run_kelora_with_input(&["-f", "jsonl", "-F", "jsonl"], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(
+        stdout.trim(),
+        r#"{"host":"web1","level":"info","message":"served","timestamp":"2023-07-18T15:04:23.456Z","zone":"eu"}"#
+    );
+
+    let (stdout, _stderr, exit_code) =
+        run_kelora_with_input(&["-f", "jsonl", "-F", "jsonl", "--order", "input"], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(
+        stdout.trim(),
+        r#"{"timestamp":"2023-07-18T15:04:23.456Z","level":"info","message":"served","zone":"eu","host":"web1"}"#
+    );
+}
+
+#[test]
+fn test_key_ordering_options() {
+    let input = r#"{"level":"INFO","message":"Served","zone":"eu","status":200,"path":"/"}"#;
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &["-f", "jsonl", "-k", "zone,status,path", "--order", "input"],
+        input,
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(stdout.trim(), "zone=\"eu\" status=200 path=\"/\"");
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &[
+            "-f",
+            "jsonl",
+            "-k",
+            "path,message,status",
+            "--order",
+            "keys-as-given",
+            "--first-keys",
+            "path,msg",
+        ],
+        input,
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(stdout.trim(), "path=\"/\" message=\"Served\" status=200");
+}
+
+#[test]
+fn test_sqlite_output() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let db_path = dir.path().join("events.db");
+    let input = r#"{"level":"ERROR","message":"Disk full","status":500}
+{"level":"INFO","message":"Recovered","host":"db01"}"#;
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &["-f", "jsonl", "--output-sqlite", db_path.to_str().unwrap()],
+        input,
+    );
+
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert!(stdout.is_empty(), "Events should go to the database only");
+    assert!(db_path.exists(), "Database should be created");
+}
+
+/// Start a one-shot HTTP server that answers 200 and hands back the raw request head and body
+fn spawn_http_receiver() -> (String, std::sync::mpsc::Receiver<(String, Vec<u8>)>) {
+    use std::io::{BufRead, BufReader, Read};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("Failed to bind");
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let (tx, rx) = std::sync::mpsc::channel();
+
+    std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().expect("Failed to accept");
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut head = String::new();
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if line == "\r\n" || line.is_empty() {
+                break;
+            }
+            head.push_str(&line);
+        }
+        let length = head
+            .lines()
+            .find_map(|line| {
+                let (name, value) = line.split_once(':')?;
+                name.eq_ignore_ascii_case("content-length")
+                    .then(|| value.trim().parse::<usize>().unwrap())
+            })
+            .unwrap_or(0);
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).unwrap();
+        stream
+            .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        tx.send((head, body)).unwrap();
+    });
+
+    (url, rx)
+}
+
+#[test]
+fn test_otlp_export() {
+    let (url, requests) = spawn_http_receiver();
+    let input = r#"level=error message="Payment declined" order_id=42"#;
+
+    let (stdout, stderr, exit_code) = run_kelora_with_input(&["--export-otlp", &url], input);
+
+    assert_eq!(exit_code, 0, "kelora should exit successfully: {}", stderr);
+    assert!(stdout.is_empty(), "Events should be exported, not printed");
+    let (head, body) = requests.recv().expect("Collector should receive a request");
+    assert!(
+        head.starts_with("POST /v1/logs "),
+        "Should post to the logs endpoint"
+    );
+    assert!(head
+        .to_lowercase()
+        .contains("content-type: application/x-protobuf"));
+    let body = String::from_utf8_lossy(&body);
+    assert!(
+        body.contains("Payment declined"),
+        "Body should carry the message"
+    );
+    assert!(body.contains("order_id"), "Fields should become attributes");
+}
+
+#[test]
+fn test_gelf_output_format() {
+    let input = r#"{"level":"ERROR","message":"Payment declined","host":"web01","order_id":42}"#;
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(&["-f", "jsonl", "-F", "gelf"], input);
+
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    let message: serde_json::Value =
+        serde_json::from_str(stdout.trim()).expect("Output should be valid JSON");
+    assert_eq!(message["version"], "1.1");
+    assert_eq!(message["host"], "web01");
+    assert_eq!(message["short_message"], "Payment declined");
+    assert_eq!(message["level"], 3);
+    assert_eq!(message["_order_id"], 42.0);
+}
+
+#[test]
+fn test_gelf_udp_export() {
+    let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket
+        .set_read_timeout(Some(std::time::Duration::from_secs(30)))
+        .unwrap();
+    let address = socket.local_addr().unwrap().to_string();
+    let input = r#"level=warn message="Disk almost full" mount=/var"#;
+
+    let (stdout, stderr, exit_code) = run_kelora_with_input(&["--gelf-udp", &address], input);
+
+    assert_eq!(exit_code, 0, "kelora should exit successfully: {}", stderr);
+    assert!(stdout.is_empty(), "Events should be sent, not printed");
+    let mut buf = [0u8; 8192];
+    let len = socket
+        .recv(&mut buf)
+        .expect("Should receive a GELF datagram");
+    let message: serde_json::Value = serde_json::from_slice(&buf[..len]).unwrap();
+    assert_eq!(message["short_message"], "Disk almost full");
+    assert_eq!(message["level"], 4);
+    assert_eq!(message["_mount"], "/var");
+}
+
+#[test]
+fn test_es_bulk_output_format() {
+    let input = r#"{"timestamp":"2023-07-18T15:04:23Z","level":"INFO","message":"Served"}"#;
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &["-f", "jsonl", "-F", "es-bulk", "--index", "app-%Y.%m"],
+        input,
+    );
+
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 2, "Should emit an action and a source line");
+    assert_eq!(lines[0], r#"{"index":{"_index":"app-2023.07"}}"#);
+    let source: serde_json::Value = serde_json::from_str(lines[1]).unwrap();
+    assert_eq!(source["@timestamp"], "2023-07-18T15:04:23Z");
+    assert_eq!(source["message"], "Served");
+}
+
+#[test]
+fn test_es_url_indexing() {
+    let (url, requests) = spawn_http_receiver();
+    let input = "level=error message=\"Payment declined\"\nlevel=info message=\"Paid\"";
+
+    let (stdout, stderr, exit_code) =
+        run_kelora_with_input(&["--es-url", &url, "--index", "payments"], input);
+
+    assert_eq!(exit_code, 0, "kelora should exit successfully: {}", stderr);
+    assert!(stdout.is_empty(), "Events should be indexed, not printed");
+    let (head, body) = requests.recv().expect("Cluster should receive a request");
+    assert!(head.starts_with("POST /_bulk "), "Should post to _bulk");
+    assert!(head
+        .to_lowercase()
+        .contains("content-type: application/x-ndjson"));
+    let body = String::from_utf8(body).unwrap();
+    assert_eq!(
+        body.lines().count(),
+        4,
+        "Both events should share one batch"
+    );
+    assert!(body.ends_with('\n'), "Bulk bodies must end with a newline");
+    assert!(body.contains(r#"{"index":{"_index":"payments"}}"#));
+}
+
+#[test]
+fn test_max_field_width() {
+    let input =
+        r#"level=error message="Connection refused by upstream server" trace=abcdefghijklmnop"#;
+
+    let (stdout, _stderr, exit_code) =
+        run_kelora_with_input(&["-F", "jsonl", "--max-field-width", "6,message=10"], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    let json: serde_json::Value = serde_json::from_str(stdout.trim()).unwrap();
+    assert_eq!(json["message"], "Connection…(+27 chars)");
+    assert_eq!(json["trace"], "abcdef…(+10 chars)");
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &["-F", "jsonl", "--max-field-width", "6", "--no-truncate"],
+        input,
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert!(
+        stdout.contains("abcdefghijklmnop"),
+        "--no-truncate should win"
+    );
+}
+
+#[test]
+fn test_skip_empty_fields() {
+    let input = r#"{"level":"INFO","message":"Served","user":"","session":null,"status":200}"#;
+
+    let (stdout, _stderr, exit_code) =
+        run_kelora_with_input(&["-f", "jsonl", "-F", "jsonl", "--skip-empty"], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    let json: serde_json::Value = serde_json::from_str(stdout.trim()).unwrap();
+    assert!(
+        json.get("user").is_none(),
+        "Empty strings should be omitted"
+    );
+    assert!(json.get("session").is_none(), "Nulls should be omitted");
+    assert_eq!(json["status"], 200.0);
+
+    let (stdout, _stderr, exit_code) =
+        run_kelora_with_input(&["-f", "jsonl", "--skip-empty"], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert!(
+        !stdout.contains("user="),
+        "Default output should omit empty fields"
+    );
+    assert!(
+        !stdout.contains("session="),
+        "Default output should omit nulls"
+    );
+    assert!(stdout.contains("status=200"));
+}
+
+#[test]
+fn test_highlight_matches() {
+    let input = r#"level=error message="Upstream timeout" path=/api/timeout"#;
+
+    let (stdout, _stderr, exit_code) =
+        run_kelora_with_input(&["--color", "always", "--highlight", "time(out)?"], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert!(stdout.contains("Upstream \x1b[7mtimeout\x1b[27m"));
+    assert!(stdout.contains("/api/\x1b[7mtimeout\x1b[27m"));
+
+    let (stdout, _stderr, exit_code) =
+        run_kelora_with_input(&["--color", "never", "--highlight", "timeout"], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert!(!stdout.contains('\x1b'), "Highlighting needs color");
+}
+
+#[test]
+fn test_highlight_filter_matches() {
+    let input = r#"level=error message="Upstream timeout" path=/api/users host=db01"#;
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &[
+            "--color",
+            "always",
+            "--grep-field",
+            "path=users",
+            "--filter",
+            "message =~ /time(out)?/ && host !~ /web/",
+        ],
+        input,
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert!(
+        stdout.contains("/api/\x1b[7musers\x1b[27m"),
+        "stdout: {}",
+        stdout
+    );
+    assert!(
+        stdout.contains("Upstream \x1b[7mtimeout\x1b[27m"),
+        "stdout: {}",
+        stdout
+    );
+    assert!(!stdout.contains("\x1b[7mweb"), "stdout: {}", stdout);
+}
+
+#[test]
+fn test_output_file_compression() {
+    use std::io::Read;
+
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let gz_path = dir.path().join("errors.log.gz");
+    let input = "level=error message=\"Disk full\"\nlevel=info message=\"Started\"";
+
+    let (stdout, _stderr, exit_code) =
+        run_kelora_with_input(&["-l", "error", "-o", gz_path.to_str().unwrap()], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert!(stdout.is_empty(), "Output should go to the file");
+    let mut text = String::new();
+    flate2::read::GzDecoder::new(std::fs::File::open(&gz_path).unwrap())
+        .read_to_string(&mut text)
+        .expect("Output should be valid gzip");
+    assert!(text.contains("Disk full"));
+    assert!(!text.contains("Started"));
+
+    let zst_path = dir.path().join("all.log");
+    let (_stdout, _stderr, exit_code) = run_kelora_with_input(
+        &[
+            "-o",
+            zst_path.to_str().unwrap(),
+            "--output-compress",
+            "zstd",
+        ],
+        input,
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    let text = zstd::decode_all(std::fs::File::open(&zst_path).unwrap()).unwrap();
+    assert_eq!(String::from_utf8(text).unwrap().lines().count(), 2);
+}
+
+#[test]
+fn test_source_filter() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let web = dir.path().join("web01.log");
+    let db = dir.path().join("db01.log");
+    std::fs::write(&web, "level=ERROR msg=\"upstream timeout\"\n").unwrap();
+    std::fs::write(&db, "level=ERROR msg=\"deadlock detected\"\n").unwrap();
+    let files = [web.to_str().unwrap(), db.to_str().unwrap()];
+
+    let (stdout, _stderr, exit_code) =
+        run_kelora_with_input(&["--source", "web01.log", files[0], files[1]], "");
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert!(stdout.contains("upstream timeout"));
+    assert!(!stdout.contains("deadlock"));
+    assert!(!stdout.contains("_file"), "The source isn't output");
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &[
+            "--where",
+            &format!("_file={}", files[1]),
+            files[0],
+            files[1],
+        ],
+        "",
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert!(stdout.contains("deadlock") && !stdout.contains("upstream"));
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &[
+            "--source",
+            "db*",
+            "--filter",
+            "_file != null",
+            files[0],
+            files[1],
+        ],
+        "",
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(stdout.lines().count(), 1);
+    assert!(stdout.contains("deadlock"));
+}
+
+#[test]
+fn test_apply_filter_set() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let config = dir.path().join("config.toml");
+    std::fs::write(
+        &config,
+        r#"
+[filters.prod-errors]
+level = "error"
+where = "status>=500"
+keys = "msg,status"
+"#,
+    )
+    .unwrap();
+    let config = config.to_str().unwrap();
+    let input = r#"level=ERROR msg="bad gateway" status=502 env=prod
+level=ERROR msg="not found" status=404 env=prod
+level=INFO msg="ok" status=200 env=prod
+level=ERROR msg="unavailable" status=503 env=staging"#;
+
+    let (stdout, _stderr, exit_code) =
+        run_kelora_with_input(&["--config", config, "--apply", "prod-errors"], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(
+        stdout,
+        "message=\"bad gateway\" status=502\nmessage=\"unavailable\" status=503\n"
+    );
+
+    // Command-line conditions narrow the set further, and -k replaces its keys
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &[
+            "--config",
+            config,
+            "--apply",
+            "prod-errors",
+            "--where",
+            "env=prod",
+            "-k",
+            "status",
+        ],
+        input,
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(stdout, "status=502\n");
+
+    let (_stdout, stderr, exit_code) =
+        run_kelora_with_input(&["--config", config, "--apply", "nope"], input);
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("Unknown filter set 'nope' (defined: prod-errors)"));
+}
+
+#[test]
+fn test_output_rotation_by_size() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let path = dir.path().join("events.log");
+    let input: String = (0..50)
+        .map(|i| format!("level=info message=\"event number {}\"\n", i))
+        .collect();
+
+    let (_stdout, stderr, exit_code) = run_kelora_with_input(
+        &["-o", path.to_str().unwrap(), "--rotate-size", "1K"],
+        &input,
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully: {}", stderr);
+
+    let files: Vec<String> = std::fs::read_dir(dir.path())
+        .unwrap()
+        .map(|entry| std::fs::read_to_string(entry.unwrap().path()).unwrap())
+        .collect();
+    assert!(files.len() >= 3, "Should have rotated at least twice");
+    assert!(dir.path().join("events.1.log").exists());
+    assert!(
+        files.iter().all(|text| text.ends_with('\n')),
+        "Files should end on an event boundary"
+    );
+    let total: usize = files.iter().map(|text| text.lines().count()).sum();
+    assert_eq!(total, 50, "No events should be lost across files");
+}
+
+#[test]
+fn test_rotation_requires_output_file() {
+    let (_stdout, stderr, exit_code) =
+        run_kelora_with_input(&["--rotate-daily"], "level=info message=hi");
+    assert_ne!(exit_code, 0, "Rotation without -o should fail");
+    assert!(stderr.contains("require -o"));
+}
+
+#[test]
+fn test_time_range_filtering() {
+    let input = r#"{"timestamp":"2024-03-14T09:59:59Z","message":"too early"}
+{"timestamp":"2024-03-14T10:00:00Z","message":"start"}
+{"timestamp":"2024-03-14T11:30:00Z","message":"middle"}
+{"timestamp":"2024-03-14T12:00:00Z","message":"too late"}
+{"message":"no timestamp"}"#;
+
+    let (stdout, stderr, exit_code) = run_kelora_with_input(
+        &[
+            "-f",
+            "jsonl",
+            "--since",
+            "2024-03-14T10:00:00Z",
+            "--until",
+            "2024-03-14T12:00:00Z",
+            "--stats",
+        ],
+        input,
+    );
+
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].contains("start"));
+    assert!(lines[1].contains("middle"));
+    assert!(stderr.contains("filtered: 3"), "Stats: {}", stderr);
+}
+
+#[test]
+fn test_relative_time_range_filtering() {
+    let input = r#"{"timestamp":"2024-03-14T09:00:00Z","message":"old"}
+{"timestamp":"2024-03-14T11:50:00Z","message":"recent"}
+{"timestamp":"2024-03-14T11:58:00Z","message":"too fresh"}"#;
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &[
+            "-f",
+            "jsonl",
+            "--now",
+            "2024-03-14T12:00:00Z",
+            "--since",
+            "1 hour ago",
+            "--until",
+            "now-5m",
+        ],
+        input,
+    );
+
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(stdout.lines().count(), 1);
+    assert!(stdout.contains("recent"));
+}
+
+#[test]
+fn test_where_filters() {
+    let input = r#"{"level":"ERROR","message":"a","service":"api","status":500}
+{"level":"ERROR","message":"b","service":"db","status":500}
+{"level":"INFO","message":"c","service":"api","status":200}"#;
+
+    let (stdout, stderr, exit_code) = run_kelora_with_input(
+        &[
+            "-f",
+            "jsonl",
+            "--where",
+            "status=500",
+            "--where",
+            "service!=db",
+            "--stats",
+        ],
+        input,
+    );
+
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(stdout.lines().count(), 1);
+    assert!(stdout.contains("message=\"a\""));
+    assert!(stderr.contains("filtered: 2"), "Stats: {}", stderr);
+}
+
+#[test]
+fn test_where_numeric_comparisons() {
+    let input = r#"{"message":"fast","latency_ms":120,"status":200}
+{"message":"slow","latency_ms":"850","status":200}
+{"message":"failed","latency_ms":900,"status":503}"#;
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &[
+            "-f",
+            "jsonl",
+            "--where",
+            "latency_ms>500",
+            "--where",
+            "status<500",
+        ],
+        input,
+    );
+
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(stdout.lines().count(), 1);
+    assert!(stdout.contains("slow"));
+}
+
+#[test]
+fn test_grep_filters() {
+    let input = r#"level=error message="Upstream timeout" path=/api/orders
+level=error message="Upstream timeout" path=/health
+level=info message="Request served" path=/api/orders"#;
+
+    let (stdout, stderr, exit_code) = run_kelora_with_input(
+        &["-g", "time(out)?", "--grep-field", "path=^/api/", "--stats"],
+        input,
+    );
+
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(stdout.lines().count(), 1);
+    assert!(stdout.contains("/api/orders"));
+    assert!(stdout.contains("Upstream timeout"));
+    assert!(
+        stderr.contains("filtered: 0, grep non-matches: 2"),
+        "Stats: {}",
+        stderr
+    );
+}
+
+#[test]
+fn test_inverse_filters() {
+    let input = r#"level=info message="GET /healthz" status=200
+level=info message="keepalive" status=200
+level=error message="GET /orders" status=500
+level=info message="GET /orders" status=200 env=staging"#;
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &[
+            "--grep-v",
+            "healthz",
+            "--grep-v",
+            "^keepalive$",
+            "--where-not",
+            "env=staging",
+        ],
+        input,
+    );
+
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(stdout.lines().count(), 1);
+    assert!(stdout.contains("status=500"));
+}
+
+#[test]
+fn test_filter_expression() {
+    let input = r#"level=ERROR message="upstream timeout" status=200
+level=ERROR message="internal error" status=503
+level=ERROR message="bad input" status=400
+level=INFO message="request timeout" status=504"#;
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &[
+            "--filter",
+            r#"level == "ERROR" && (status >= 500 || msg =~ /timeout/)"#,
+        ],
+        input,
+    );
+
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(stdout.lines().count(), 2);
+    assert!(stdout.contains("upstream timeout"));
+    assert!(stdout.contains("internal error"));
+
+    let (_stdout, stderr, exit_code) = run_kelora_with_input(&["--filter", "status >"], input);
+    assert_ne!(exit_code, 0, "Invalid expressions should be rejected");
+    assert!(stderr.contains("--filter"));
+}
+
+#[test]
+fn test_jq_program() {
+    let input = r#"{"level":"ERROR","message":"boom","status":503,"user":"bob"}
+{"level":"INFO","message":"ok","status":200,"user":"alice"}"#;
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &[
+            "-f",
+            "jsonl",
+            "-F",
+            "jsonl",
+            "--jq",
+            "select(.status >= 500) | {message, code: .status}",
+        ],
+        input,
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(stdout.trim(), r#"{"code":503.0,"message":"boom"}"#);
+
+    let (stdout, _stderr, exit_code) =
+        run_kelora_with_input(&["-f", "jsonl", "--jq", r#".user == "alice""#], input);
+    assert_eq!(exit_code, 0);
+    assert_eq!(stdout.lines().count(), 1);
+    assert!(stdout.contains("alice"));
+
+    let (_stdout, stderr, exit_code) = run_kelora_with_input(&["--jq", ".status >"], input);
+    assert_ne!(exit_code, 0, "Invalid programs should be rejected");
+    assert!(stderr.contains("--jq"));
+}
+
+#[test]
+fn test_query_subcommand() {
+    let input = r#"level=ERROR status=503 message="upstream"
+level=ERROR status=500 message="db"
+level=WARN status=502 message="gateway"
+level=INFO status=200 message="ok""#;
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &[
+            "query",
+            "SELECT level, count(*) AS n FROM events WHERE status >= 500 GROUP BY level ORDER BY n DESC",
+            "-F",
+            "csv",
+        ],
+        input,
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines, vec!["level,n", "ERROR,2", "WARN,1"]);
+
+    let (_stdout, stderr, exit_code) =
+        run_kelora_with_input(&["query", "SELECT nope FROM events"], input);
+    assert_ne!(exit_code, 0, "Invalid queries should fail");
+    assert!(stderr.contains("Query failed"));
+
+    let (stdout, stderr, exit_code) =
+        run_kelora_with_input(&["query", "SELECT * FROM events", "-F", "arrow"], input);
+    assert_ne!(exit_code, 0, "Arrow query output should be rejected");
+    assert!(stdout.is_empty());
+    assert!(
+        stderr.contains("kelora query can't write -F arrow") && !stderr.contains("panicked"),
+        "stderr: {}",
+        stderr
+    );
+}
+
+#[test]
+fn test_min_level_filtering() {
+    let input = r#"level=trace message="t"
+level=debug message="d"
+level=info message="i"
+level=warn message="w"
+level=error message="e"
+level=fatal message="f"
+level=3 message="numeric error"
+level=custom message="unknown"
+message="no level""#;
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(&["--min-level", "warn"], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    let messages: Vec<&str> = stdout
+        .lines()
+        .filter_map(|line| line.split("message=\"").nth(1))
+        .map(|rest| rest.split('"').next().unwrap())
+        .collect();
+    assert_eq!(messages, vec!["w", "e", "f", "numeric error"]);
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(&["--min-level", "debug"], input);
+    assert_eq!(exit_code, 0);
+    assert_eq!(
+        stdout.lines().count(),
+        6,
+        "Only trace and level-less events are dropped"
+    );
+
+    let (_stdout, stderr, exit_code) = run_kelora_with_input(&["--min-level", "loud"], input);
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("--min-level"));
+}
+
+#[test]
+fn test_where_ip_in_network() {
+    let input = r#"client_ip=10.1.2.3 msg="internal"
+client_ip=203.0.113.7 msg="external"
+client_ip=2001:db8::42 msg="v6"
+client_ip=unknown msg="garbage""#;
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &[
+            "--where",
+            "client_ip in 10.0.0.0/8, 2001:db8::/32",
+            "-k",
+            "msg",
+        ],
+        input,
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(stdout, "message=\"internal\"\nmessage=\"v6\"\n");
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &["--where-not", "client_ip in 10.0.0.0/8", "-k", "msg"],
+        input,
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(stdout.lines().count(), 3);
+
+    let (_stdout, stderr, exit_code) =
+        run_kelora_with_input(&["--where", "client_ip in 10.0.0.0/40"], input);
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("prefix"));
+}
+
+#[test]
+fn test_or_groups() {
+    let input = r#"level=ERROR component=db msg="a"
+level=WARN component=payments msg="b"
+level=WARN component=auth msg="c"
+level=INFO component=payments msg="d""#;
+
+    let (stdout, stderr, exit_code) = run_kelora_with_input(
+        &[
+            "--where",
+            "level=ERROR",
+            "--or",
+            "--where",
+            "level=WARN",
+            "--where",
+            "component=payments",
+            "-k",
+            "msg",
+            "-s",
+        ],
+        input,
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(stdout, "message=\"a\"\nmessage=\"b\"\n");
+    assert!(stderr.contains("filtered: 2"));
+
+    // Grep patterns and expressions group the same way
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &[
+            "-g",
+            "^c$",
+            "--or",
+            "--filter",
+            "level == \"INFO\"",
+            "-k",
+            "msg",
+        ],
+        input,
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(stdout, "message=\"c\"\nmessage=\"d\"\n");
+
+    let (_stdout, stderr, exit_code) =
+        run_kelora_with_input(&["--or", "--where", "level=WARN"], input);
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("--or needs conditions on both sides"));
+}
+
+#[test]
+fn test_level_aliases_and_ranges() {
+    let input = r#"level=emerg message="a"
+level=CRIT message="b"
+level=err message="c"
+level=3 message="d"
+level=WARNING message="e"
+level=info message="f"
+level=AUDIT message="g""#;
+    let messages = |stdout: &str| -> Vec<String> {
+        stdout
+            .lines()
+            .filter_map(|line| line.split("message=\"").nth(1))
+            .map(|rest| rest.split('"').next().unwrap().to_string())
+            .collect()
+    };
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(&["-l", "error"], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(messages(&stdout), vec!["c", "d"]);
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(&["-l", "0-3"], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(messages(&stdout), vec!["a", "b", "c", "d"]);
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(&["-l", "warn,audit"], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(messages(&stdout), vec!["e", "g"]);
+
+    let (_stdout, stderr, exit_code) = run_kelora_with_input(&["-l", "12"], input);
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("--level"));
+}
+
+#[test]
+fn test_flatten() {
+    let input =
+        r#"{"msg":"request","http":{"status":503,"req":{"method":"GET"}},"tags":["a","b"]}"#;
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &[
+            "-f",
+            "jsonl",
+            "--flatten",
+            "--where",
+            "http.status>=500",
+            "-F",
+            "jsonl",
+            "-k",
+            "http.*,tags.*",
+        ],
+        input,
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert!(stdout.starts_with(r#"{"http.req.method":"GET","http.status":503"#));
+    assert!(stdout.contains(r#""tags.0":"a","tags.1":"b"}"#));
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &[
+            "-f",
+            "jsonl",
+            "--flatten",
+            "--flatten-sep",
+            "_",
+            "--flatten-depth",
+            "1",
+            "-k",
+            "http_req",
+        ],
+        input,
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(stdout.trim(), r#"http_req="{\"method\":\"GET\"}""#);
+}
+
+#[test]
+fn test_unflatten() {
+    let input = r#"msg="request" http.host=web01 http.req.method=GET"#;
+
+    let (stdout, _stderr, exit_code) =
+        run_kelora_with_input(&["--unflatten", "-F", "jsonl", "-k", "http.*"], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(
+        stdout.trim(),
+        r#"{"http":{"host":"web01","req":{"method":"GET"}}}"#
+    );
+
+    // A round trip through --flatten restores the original structure
+    let input = r#"{"http":{"status":"503"},"tags":["a","b"]}"#;
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &["-f", "jsonl", "--flatten", "--unflatten", "-F", "jsonl"],
+        input,
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(stdout.trim(), input);
+}
+
+#[test]
+fn test_redaction() {
+    let input = r#"level=INFO msg="charged card 4111111111111111" user=alice Authorization="Bearer abc" session_token=xyz"#;
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &[
+            "--redact",
+            "authorization,*_token",
+            "--redact-pattern",
+            r"\b\d{16}\b",
+            "--where",
+            "session_token=xyz",
+            "-F",
+            "jsonl",
+        ],
+        input,
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert!(stdout.contains(r#""message":"charged card [REDACTED]""#));
+    assert!(stdout.contains(r#""Authorization":"[REDACTED]""#));
+    assert!(stdout.contains(r#""session_token":"[REDACTED]""#));
+    assert!(stdout.contains(r#""user":"alice""#));
+    assert!(!stdout.contains("4111") && !stdout.contains("Bearer") && !stdout.contains("xyz"));
+
+    let (_stdout, stderr, exit_code) =
+        run_kelora_with_input(&["--redact-pattern", "(unclosed"], input);
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("--redact-pattern"));
+}
+
+#[test]
+fn test_hash_fields() {
+    let input = r#"user=alice action=login
+user=bob action=login
+user=alice action=logout"#;
+
+    let (stdout, _stderr, exit_code) =
+        run_kelora_with_input(&["--hash", "user:sha256:pepper", "-k", "user"], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    let users: Vec<&str> = stdout.lines().collect();
+    assert_eq!(users.len(), 3);
+    assert!(!stdout.contains("alice") && !stdout.contains("bob"));
+    assert_eq!(users[0], users[2], "Equal values hash alike");
+    assert_ne!(users[0], users[1]);
+
+    let (unsalted, _stderr, _exit_code) =
+        run_kelora_with_input(&["--hash", "user", "-k", "user"], input);
+    assert_ne!(unsalted.lines().next(), users.first().copied());
+
+    let (_stdout, stderr, exit_code) = run_kelora_with_input(&["--hash", "user:md5"], input);
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("--hash"));
+}
+
+#[test]
+fn test_rename_keys() {
+    let input = r#"when=2024-03-14T10:00:00Z text="disk full" usr=alice
+when=2024-03-14T10:00:01Z text="retrying" usr=bob"#;
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &[
+            "--rename",
+            "text=msg,when=ts",
+            "--rename",
+            "usr=user",
+            "--where",
+            "user=alice",
+            "-k",
+            "timestamp,message,user",
+        ],
+        input,
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(
+        stdout,
+        "timestamp=\"2024-03-14T10:00:00.000Z\" message=\"disk full\" user=\"alice\"\n"
+    );
+
+    let (_stdout, stderr, exit_code) = run_kelora_with_input(&["--rename", "usr"], input);
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("--rename"));
+}
+
+#[test]
+fn test_derive_fields() {
+    let input = r#"path=/a latency_ms=250
+path=/b latency_ms=1500
+path=/c"#;
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &[
+            "--derive",
+            "latency_s = latency_ms / 1000",
+            "--derive",
+            "is_slow = latency_ms > 500",
+            "--where",
+            "is_slow=true",
+            "-k",
+            "path,latency_s,is_slow",
+        ],
+        input,
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(stdout, "is_slow=true latency_s=1.5 path=\"/b\"\n");
+
+    let (_stdout, stderr, exit_code) = run_kelora_with_input(&["--derive", "x == 1"], input);
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("--derive"));
+}
+
+#[test]
+fn test_exclude_keys() {
+    let input = r#"ts=2024-03-14T10:00:00Z level=INFO msg="started" pod=api-1 trace_context=abc labels="a=b""#;
+
+    let (stdout, _stderr, exit_code) =
+        run_kelora_with_input(&["-K", "trace_context,labels", "-F", "jsonl"], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert!(stdout.contains("\"pod\":\"api-1\""));
+    assert!(stdout.contains("\"message\":\"started\""));
+    assert!(!stdout.contains("trace_context"));
+    assert!(!stdout.contains("labels"));
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(&["-K", "timestamp,msg"], input);
+    assert_eq!(exit_code, 0);
+    assert!(
+        !stdout.contains("2024-03-14"),
+        "Core fields and their raw copies are hidden"
+    );
+    assert!(!stdout.contains("started"));
+    assert!(stdout.contains("pod=\"api-1\""));
+}
+
+#[test]
+fn test_key_patterns() {
+    let input = r#"level=INFO msg="ok" http.method=GET http.status=200 request_id=r1 user_id=u1 k8s.pod.name=api-1"#;
+
+    let (stdout, _stderr, exit_code) =
+        run_kelora_with_input(&["-k", "http.*,*_id", "-F", "csv"], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(
+        lines,
+        vec![
+            "http.method,http.status,request_id,user_id",
+            "GET,200,r1,u1"
+        ]
+    );
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(&["-K", "k8s.*,*_id"], input);
+    assert_eq!(exit_code, 0);
+    assert!(stdout.contains("http.status=200"));
+    assert!(!stdout.contains("api-1"));
+    assert!(!stdout.contains("r1"));
+}
+
+#[test]
+fn test_dedup() {
+    let input = r#"ts=2024-03-14T10:00:00Z level=WARN msg="connection refused, retrying"
+ts=2024-03-14T10:00:01Z level=WARN msg="connection refused, retrying"
+ts=2024-03-14T10:00:02Z level=WARN msg="connection refused, retrying"
+ts=2024-03-14T10:00:03Z level=INFO msg="connected"
+ts=2024-03-14T10:00:04Z level=INFO msg="connected""#;
+
+    let (stdout, stderr, exit_code) = run_kelora_with_input(&["--dedup", "-s"], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].contains("retrying") && lines[0].contains("repeat_count=3"));
+    assert!(
+        lines[0].contains("10:00:00"),
+        "The first event of a run is kept"
+    );
+    assert!(lines[1].contains("connected") && lines[1].contains("repeat_count=2"));
+    assert!(stderr.contains("duplicates: 3"));
+}
+
+#[test]
+fn test_dedup_by_fields() {
+    let input = r#"ts=2024-03-14T10:00:00Z host=web01 msg="upstream timeout" request_id=a1
+ts=2024-03-14T10:00:02Z host=web01 msg="upstream timeout" request_id=b2
+ts=2024-03-14T10:00:07Z host=web01 msg="upstream timeout" request_id=c3
+ts=2024-03-14T10:00:08Z host=web02 msg="upstream timeout" request_id=d4"#;
+
+    let (stdout, _stderr, exit_code) =
+        run_kelora_with_input(&["--dedup-by", "message,host", "-F", "jsonl"], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].contains(r#""request_id":"a1""#));
+    assert!(lines[0].contains(r#""repeat_count":3"#));
+    assert!(lines[0].contains(r#""first_seen":"2024-03-14T10:00:00.000Z""#));
+    assert!(lines[0].contains(r#""last_seen":"2024-03-14T10:00:07.000Z""#));
+    assert!(lines[1].contains("web02") && !lines[1].contains("repeat_count"));
+}
+
+#[test]
+fn test_first_and_last_of() {
+    let input = r#"level=ERROR msg="disk full" host=a
+level=ERROR msg="connection refused" host=b
+level=ERROR msg="disk full" host=c
+level=INFO msg="done" host=d"#;
+
+    let (stdout, stderr, exit_code) =
+        run_kelora_with_input(&["--first-of", "message", "-k", "host", "-s"], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(stdout, "host=\"a\"\nhost=\"b\"\nhost=\"d\"\n");
+    assert!(stderr.contains("filtered: 1"));
+
+    let (stdout, _stderr, exit_code) =
+        run_kelora_with_input(&["--last-of", "message", "-k", "host"], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(stdout, "host=\"c\"\nhost=\"b\"\nhost=\"d\"\n");
+
+    let input = r#"msg="user alice logged in" host=a
+msg="disk full" host=b
+msg="user bob logged in" host=c"#;
+    let (stdout, _stderr, exit_code) =
+        run_kelora_with_input(&["--first-of", "_template", "-k", "host"], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(stdout, "host=\"a\"\nhost=\"b\"\n");
+    let (stdout, _stderr, exit_code) =
+        run_kelora_with_input(&["--last-of", "_template", "-k", "host"], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(stdout, "host=\"c\"\nhost=\"b\"\n");
+
+    let (_stdout, _stderr, exit_code) =
+        run_kelora_with_input(&["--first-of", "msg", "--last-of", "msg"], input);
+    assert_ne!(exit_code, 0);
+}
+
+#[test]
+fn test_throttle() {
+    let input: String = (0..50)
+        .map(|i| format!("level=INFO msg=\"event {}\"\n", i))
+        .collect();
+
+    // All 50 events arrive well within one minute
+    let (stdout, stderr, exit_code) =
+        run_kelora_with_input(&["--throttle", "10/min", "-s"], &input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(stdout.lines().count(), 10);
+    assert!(stdout.contains("event 9\""));
+    assert!(stderr.contains("Throttled: 40 events dropped over the 10/min limit"));
+    assert!(stderr.contains("throttled: 40"));
+
+    let (_stdout, stderr, exit_code) = run_kelora_with_input(&["--throttle", "fast"], &input);
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("--throttle"));
+}
+
+#[test]
+fn test_ts_display() {
+    let input = r#"ts=2024-03-14T10:00:00.123Z level=INFO msg="started""#;
+
+    let (stdout, _stderr, exit_code) =
+        run_kelora_with_input(&["--ts-display", "time-only", "-c"], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(
+        stdout,
+        "timestamp=\"10:00:00.123\" level=\"INFO\" message=\"started\"\n"
+    );
+
+    let (stdout, _stderr, exit_code) =
+        run_kelora_with_input(&["--ts-display", "unix", "-c", "-F", "jsonl"], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert!(stdout.contains("\"timestamp\":1710410400.123"));
+
+    // The raw timestamp field of JSON input gets the display format too
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &["-f", "jsonl", "-F", "csv", "--ts-display", "time-only"],
+        r#"{"timestamp":"2024-03-14T10:00:00.123Z","host":"web1"}"#,
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(stdout, "timestamp,host\n10:00:00.123,web1\n");
+
+    let (_stdout, stderr, exit_code) = run_kelora_with_input(&["--ts-display", "%Q"], input);
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("--ts-display"));
+}
+
+#[test]
+fn test_ts_delta_and_elapsed() {
+    let input = r#"ts=2024-03-14T10:00:00.000Z msg="starting"
+ts=2024-03-14T10:00:00.023Z msg="config loaded"
+ts=2024-03-14T10:00:02.523Z msg="listening""#;
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &[
+            "--ts-delta",
+            "--ts-elapsed",
+            "-k",
+            "ts,msg,ts_delta,ts_elapsed",
+            "--ts-display",
+            "time-only",
+        ],
+        input,
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(
+        lines,
+        vec![
+            "timestamp=\"10:00:00.000\" message=\"starting\" ts_delta=\"+0.000s\" ts_elapsed=\"+0.000s\"",
+            "timestamp=\"10:00:00.023\" message=\"config loaded\" ts_delta=\"+0.023s\" ts_elapsed=\"+0.023s\"",
+            "timestamp=\"10:00:02.523\" message=\"listening\" ts_delta=\"+2.500s\" ts_elapsed=\"+2.523s\"",
+        ]
+    );
+}
+
+#[test]
+fn test_epoch_timestamps() {
+    let input = r#"{"ts":1710410400123,"msg":"millis"}
+{"ts":1710410400.5,"msg":"seconds"}
+{"ts":1710410400,"msg":"forced"}"#;
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &["-f", "jsonl", "-c", "--since", "2024-03-14T00:00:00Z"],
+        input,
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(
+        lines,
+        vec![
+            "timestamp=\"2024-03-14T10:00:00.123Z\" message=\"millis\"",
+            "timestamp=\"2024-03-14T10:00:00.500Z\" message=\"seconds\"",
+            "timestamp=\"2024-03-14T10:00:00.000Z\" message=\"forced\"",
+        ]
+    );
+
+    let (stdout, _stderr, exit_code) =
+        run_kelora_with_input(&["-f", "jsonl", "-c", "--epoch-unit", "ms"], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert!(stdout.contains("timestamp=\"1970-01-20T19:06:50.400Z\" message=\"forced\""));
+}
+
+#[test]
+fn test_custom_ts_format() {
+    let input = r#"time="14/Mar/2024:11:00:00 +0100" msg="early"
+time="14/Mar/2024:13:00:00 +0100" msg="late""#;
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &[
+            "--ts-format",
+            "%d/%b/%Y:%H:%M:%S %z",
+            "--since",
+            "2024-03-14T11:00:00Z",
+            "-c",
+        ],
+        input,
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(
+        stdout,
+        "timestamp=\"2024-03-14T12:00:00.000Z\" message=\"late\"\n"
+    );
+
+    let (_stdout, stderr, exit_code) = run_kelora_with_input(&["--ts-format", "%Q"], input);
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("--ts-format"));
+}
+
+#[test]
+fn test_extract_from_message() {
+    let input = r#"level=INFO msg="order 4711 shipped in 2.5s"
+level=INFO msg="order 4712 shipped in 0.4s"
+level=WARN msg="payment declined""#;
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &[
+            "--extract",
+            r"order (?P<order_id>\d+)",
+            "--extract",
+            r"in (?P<duration_s>[\d.]+)s",
+            "--where",
+            "duration_s>1",
+            "-k",
+            "order_id,duration_s",
+        ],
+        input,
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(stdout, "duration_s=2.5 order_id=4711\n");
+
+    let (_stdout, stderr, exit_code) = run_kelora_with_input(&["--extract", r"order (\d+)"], input);
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("named groups"));
+}
+
+#[test]
+fn test_extract_grok() {
+    let input = r#"<38>Mar 14 10:00:00 web01 sshd[812]: Accepted password for alice from 203.0.113.9 port 52144
+<38>Mar 14 10:00:05 web01 sshd[812]: Failed password for bob from 198.51.100.4 port 40022"#;
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &[
+            "-f",
+            "syslog",
+            "--extract-grok",
+            "%{WORD:outcome} password for %{USER:user} from %{IP:client}",
+            "--where",
+            "outcome=Failed",
+            "-k",
+            "user,client",
+        ],
+        input,
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(stdout, "client=\"198.51.100.4\" user=\"bob\"\n");
+
+    let (_stdout, stderr, exit_code) =
+        run_kelora_with_input(&["--extract-grok", "%{NOPE:x}"], input);
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("unknown grok pattern"));
+}
+
+#[test]
+fn test_parse_user_agent() {
+    let input = r#"{"path":"/","user_agent":"Mozilla/5.0 (iPhone; CPU iPhone OS 17_0 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.0 Mobile/15E148 Safari/604.1"}
+{"path":"/robots.txt","user_agent":"Mozilla/5.0 (compatible; Googlebot/2.1; +http://www.google.com/bot.html)"}"#;
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &[
+            "-f",
+            "jsonl",
+            "--parse-ua",
+            "user_agent",
+            "--where",
+            "ua_device!=crawler",
+            "-k",
+            "path,ua_browser,ua_os,ua_device",
+        ],
+        input,
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(
+        stdout,
+        "path=\"/\" ua_browser=\"Safari\" ua_device=\"smartphone\" ua_os=\"iPhone\"\n"
+    );
+}
+
+#[test]
+fn test_geoip_needs_database() {
+    let input = r#"client_ip=203.0.113.9 msg="login""#;
+
+    let (_stdout, stderr, exit_code) = run_kelora_with_input(&["--geoip", "client_ip"], input);
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("--geoip-db"));
+
+    let (_stdout, stderr, exit_code) = run_kelora_with_input(
+        &[
+            "--geoip",
+            "client_ip",
+            "--geoip-db",
+            "/nonexistent/GeoLite2-City.mmdb",
+        ],
+        input,
+    );
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("Invalid --geoip-db /nonexistent/GeoLite2-City.mmdb"));
+}
+
+#[test]
+fn test_reverse_dns() {
+    let input = r#"src_ip=127.0.0.1 dst_ip=none action=accept"#;
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &["--rdns", "src_ip,dst_ip", "--rdns-timeout", "2000"],
+        input,
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert!(stdout.contains("src_ip_ptr=\"localhost"));
+    assert!(!stdout.contains("dst_ip_ptr"));
+}
+
+#[test]
+fn test_lookup_join() {
+    let mut inventory = tempfile::Builder::new().suffix(".csv").tempfile().unwrap();
+    inventory
+        .write_all(b"hostname,owner,service\nweb01,alice,shop\ndb01,bob,orders\n")
+        .unwrap();
+    let spec = format!("host:{}:hostname", inventory.path().display());
+    let input = r#"host=web01 msg="slow request"
+host=db01 msg="replication lag""#;
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &[
+            "--lookup",
+            &spec,
+            "--where",
+            "owner=bob",
+            "-k",
+            "host,service",
+        ],
+        input,
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(stdout, "host=\"db01\" service=\"orders\"\n");
+}
+
+#[test]
+fn test_normalize_keys() {
+    let mut config = NamedTempFile::new().unwrap();
+    config
+        .write_all(b"[aliases]\nlevel = [\"severity\", \"lvl\"]\n")
+        .unwrap();
+    let config_path = config.path().to_str().unwrap();
+    let input = r#"{"Severity":"ERROR","requestId":"a1"}
+{"LVL":"info","requestId":"b2"}
+{"Level":"error","RequestID":"c3"}"#;
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &[
+            "-f",
+            "jsonl",
+            "--config",
+            config_path,
+            "--normalize-keys",
+            "snake",
+            "-l",
+            "error",
+            "-k",
+            "request_id",
+        ],
+        input,
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(stdout, "request_id=\"a1\"\nrequest_id=\"c3\"\n");
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &[
+            "-f",
+            "jsonl",
+            "--alias",
+            "lvl=level",
+            "-l",
+            "info",
+            "-k",
+            "requestId",
+        ],
+        input,
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(stdout, "requestId=\"b2\"\n");
+}
+
+#[test]
+fn test_normalize_units() {
+    let input = r#"path=/a took=1.5s resp_size="2.3 MB"
+path=/b took=230ms resp_size=4KiB
+path=/c took=fast"#;
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &[
+            "--normalize-units",
+            "--where",
+            "took_ms>500",
+            "-k",
+            "path,took_ms,resp_size_bytes",
+        ],
+        input,
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(stdout, "path=\"/a\" resp_size_bytes=2300000 took_ms=1500\n");
+}
+
+#[test]
+fn test_decode_field() {
+    // {"user":"alice","action":"delete"} and {"user":"bob","action":"read"}
+    let input = r#"id=1 payload=eyJ1c2VyIjoiYWxpY2UiLCJhY3Rpb24iOiJkZWxldGUifQ==
+id=2 payload=eyJ1c2VyIjoiYm9iIiwiYWN0aW9uIjoicmVhZCJ9"#;
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &[
+            "--decode",
+            "payload:base64:json",
+            "--where",
+            "payload.action=delete",
+        ],
+        input,
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(
+        stdout,
+        "id=1 payload.action=\"delete\" payload.user=\"alice\"\n"
+    );
+
+    let (stdout, _stderr, exit_code) =
+        run_kelora_with_input(&["--decode", "token:hex", "-k", "token"], "token=6869\n");
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(stdout, "token=\"hi\"\n");
+}
+
+#[test]
+fn test_expand_json() {
+    let input = r#"level=info data="{\"user\":\"x\",\"id\":5}" meta="{\"a\":1}"
+level=info data="{\"user\":\"y\",\"id\":7}" meta="{\"a\":2}""#;
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &[
+            "--expand-json",
+            "data",
+            "--where",
+            "data.id>6",
+            "-k",
+            "data.user,meta",
+        ],
+        input,
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(stdout, "data.user=\"y\" meta=\"{\\\"a\\\":2}\"\n");
+
+    let (stdout, _stderr, exit_code) =
+        run_kelora_with_input(&["--expand-json", "*", "-k", "data.id,meta.a"], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(stdout, "data.id=5 meta.a=1\ndata.id=7 meta.a=2\n");
+}
+
+#[test]
+fn test_kv_in_message() {
+    let input = "<38>Mar 14 10:00:00 web01 sshd[42]: login failed user=bob src=10.0.0.1 attempts=3
+<38>Mar 14 10:00:01 web01 sshd[42]: login ok user=alice src=10.0.0.2 attempts=1";
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &[
+            "-f",
+            "syslog",
+            "--kv-in-message",
+            "--where",
+            "attempts>1",
+            "-k",
+            "user,src",
+        ],
+        input,
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(stdout, "src=\"10.0.0.1\" user=\"bob\"\n");
+}
+
+#[test]
+fn test_drop_fields() {
+    let input = r#"level=info msg="health check ok" path=/healthz k8s.labels.app=api k8s.labels.tier=web
+level=info msg="order placed" path=/orders k8s.labels.app=api note="""#;
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &[
+            "--drop-field",
+            "k8s.labels.*",
+            "--drop-field-if",
+            "msg~=^health",
+            "--drop-field-if",
+            "*~=^$",
+        ],
+        input,
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(
+        stdout,
+        "level=\"info\" level=\"info\" path=\"/healthz\"\n\
+         level=\"info\" message=\"order placed\" level=\"info\" msg=\"order placed\" path=\"/orders\"\n"
+    );
+}
+
+#[test]
+fn test_field_types() {
+    let input = "zip=01234 version=1.10 status=503 ok=yes\nzip=98765 version=2.0 status=n/a ok=no";
+
+    let (stdout, stderr, exit_code) = run_kelora_with_input(
+        &[
+            "--types",
+            "zip:string,version:string,status:int,ok:bool",
+            "-F",
+            "jsonl",
+            "--stats",
+        ],
+        input,
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(
+        stdout,
+        "{\"ok\":true,\"status\":503.0,\"version\":\"1.10\",\"zip\":\"01234\"}\n\
+         {\"ok\":false,\"status\":\"n/a\",\"version\":\"2.0\",\"zip\":\"98765\"}\n"
+    );
+    assert!(stderr.contains("type errors: 1"), "stderr: {}", stderr);
+}
+
+#[test]
+fn test_drain_templates() {
+    let input = r#"level=info msg="GET /a returned 200 in 12ms"
+level=info msg="user alice logged in"
+level=info msg="GET /b returned 404 in 3ms"
+level=error msg="user bob logged out"
+level=info msg="GET /c returned 200 in 7ms""#;
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(&["--drain"], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(
+        stdout,
+        "       3  ebe1077a  GET <*> returned <num> in <num>\n          info 100%\n       2  49f0680d  user <*> logged <*>\n          error 50%, info 50%\n"
+    );
+
+    let (stdout, _stderr, exit_code) =
+        run_kelora_with_input(&["--drain", "--drain-sim-th", "0.9"], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    // Each template is followed by its levels
+    assert_eq!(stdout.lines().count(), 10, "stdout: {}", stdout);
+
+    let (_stdout, stderr, exit_code) =
+        run_kelora_with_input(&["--drain", "--drain-depth", "2"], input);
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("depth"), "stderr: {}", stderr);
+}
+
+#[test]
+fn test_drain_masks() {
+    let input = r#"msg="shipped order-1001 for cust_ab12cd34"
+msg="shipped order-1002 for cust_ff00ee11""#;
+    let mut masks = NamedTempFile::new().unwrap();
+    masks
+        .write_all(b"# customers\n\\bcust_[a-z0-9]{8}\\b=<customer>\n\n")
+        .unwrap();
+    let masks_path = masks.path().to_str().unwrap();
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &[
+            "--drain",
+            "--drain-masks",
+            masks_path,
+            "--drain-mask",
+            r"order-\d+=order_id",
+        ],
+        input,
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(
+        stdout,
+        "       2  0ac7bc2c  shipped <order_id> for <customer>\n"
+    );
+
+    let (_stdout, stderr, exit_code) =
+        run_kelora_with_input(&["--drain", "--drain-mask", "order-\\d+"], input);
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("REGEX=PLACEHOLDER"), "stderr: {}", stderr);
+}
+
+#[test]
+fn test_drain_state() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = dir.path().join("templates.json");
+    let state_path = state.to_str().unwrap();
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &["--drain", "--drain-state", state_path],
+        "msg=\"user alice logged in\"\nmsg=\"disk full\"",
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(
+        stdout,
+        "       1  495c1905  user alice logged in\n       1  8c96d76f  disk full\n"
+    );
+    assert!(state.exists());
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &["--drain", "--drain-state", state_path],
+        "msg=\"user bob logged in\"\nmsg=\"user carol logged in\"",
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(
+        stdout,
+        "       3  65231b8d  user <*> logged in\n       1  8c96d76f  disk full\n"
+    );
+
+    std::fs::write(&state, "not json").unwrap();
+    let (_stdout, stderr, exit_code) =
+        run_kelora_with_input(&["--drain", "--drain-state", state_path], "msg=x");
+    assert_ne!(exit_code, 0);
+    assert!(
+        stderr.contains("Invalid --drain-state"),
+        "stderr: {}",
+        stderr
+    );
+}
+
+#[test]
+fn test_drain_stats() {
+    let input = r#"{"timestamp":"2024-03-14T10:00:00Z","level":"WARN","message":"disk sda full"}
+{"timestamp":"2024-03-14T10:01:00Z","level":"ERROR","message":"disk sdb full"}
+{"timestamp":"2024-03-14T10:04:00Z","level":"ERROR","message":"disk sdc full"}
+{"timestamp":"2024-03-14T10:02:00Z","message":"backup done"}"#;
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(&["-f", "jsonl", "--drain"], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(
+        lines,
+        [
+            "       3  b9d31f14  disk <*> full",
+            "          2024-03-14T10:00:00Z .. 2024-03-14T10:04:00Z, 0.8/min, error 67%, warn 33%",
+            "       1  daefe7ea  backup done",
+            "          2024-03-14T10:02:00Z",
+        ]
+    );
+}
+
+#[test]
+fn test_drain_anomalies() {
+    let mut input: Vec<String> = (0..30)
+        .map(|i| format!("level=info msg=\"request {} served\"", i))
+        .collect();
+    input.insert(
+        12,
+        "level=error msg=\"checksum mismatch on block\"".to_string(),
+    );
+    let input = input.join("\n");
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &["--drain", "--drain-anomalies", "--rare-below", "5%"],
+        &input,
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(stdout.lines().count(), 1, "stdout: {}", stdout);
+    assert!(stdout.contains("checksum mismatch on block"));
+
+    // Nothing is rare enough
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &["--drain", "--drain-anomalies", "--rare-below", "0.001"],
+        &input,
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(stdout, "");
+
+    let (_stdout, stderr, exit_code) = run_kelora_with_input(
+        &["--drain", "--drain-anomalies", "--rare-below", "x"],
+        &input,
+    );
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("--rare-below"), "stderr: {}", stderr);
+}
+
+#[test]
+fn test_alert_new_template() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = dir.path().join("templates.json");
+    let state_path = state.to_str().unwrap();
+    let alerts = dir.path().join("alerts.txt");
+    let command = format!(
+        "echo \"$KELORA_TEMPLATE_ID $KELORA_MESSAGE\" >> {}",
+        alerts.display()
+    );
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &[
+            "--drain",
+            "--drain-state",
+            state_path,
+            "--alert-new-template",
+        ],
+        "msg=\"user alice logged in\"\nmsg=\"user bob logged in\"\nmsg=\"disk full\"",
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 2, "stdout: {}", stdout);
+    assert!(lines[0].contains("user alice logged in"));
+    assert!(lines[1].contains("disk full"));
+
+    // Templates from the state file aren't new
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &[
+            "--drain",
+            "--drain-state",
+            state_path,
+            "--alert-new-template",
+            "--alert-command",
+            &command,
+        ],
+        "msg=\"user carol logged in\"\nmsg=\"checksum mismatch\"",
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(stdout.lines().count(), 1, "stdout: {}", stdout);
+    assert!(stdout.contains("checksum mismatch"));
+    let alerts = std::fs::read_to_string(alerts).unwrap();
+    assert!(
+        alerts.ends_with(" checksum mismatch\n") && alerts.lines().count() == 1,
+        "alerts: {}",
+        alerts
+    );
+}
+
+#[test]
+fn test_template_filters() {
+    let input = r#"msg="user alice logged in"
+msg="disk sda full"
+msg="user bob logged in"
+msg="cache cleared""#;
+
+    let (stdout, _stderr, exit_code) =
+        run_kelora_with_input(&["--drain", "--template-id", "65231B8D"], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    // Alice's event arrives while its template is still "user alice logged in"
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 1, "stdout: {}", stdout);
+    assert!(lines[0].contains("user bob logged in"));
+
+    // Templates mined beforehand match from the first event on
+    let dir = tempfile::tempdir().unwrap();
+    let state = dir.path().join("templates.json");
+    let state = state.to_str().unwrap();
+    run_kelora_with_input(&["--drain", "--drain-state", state], input);
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &[
+            "--drain",
+            "--drain-state",
+            state,
+            "--template-id",
+            "65231B8D",
+        ],
+        input,
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(stdout.lines().count(), 2, "stdout: {}", stdout);
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &[
+            "--drain",
+            "--template-match",
+            "disk * full",
+            "--template-match",
+            "cache*",
+        ],
+        input,
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 2, "stdout: {}", stdout);
+    assert!(lines[0].contains("disk sda full"));
+    assert!(lines[1].contains("cache cleared"));
+}
+
+#[test]
+fn test_drain_diff() {
+    let mut before = NamedTempFile::new().unwrap();
+    let mut after = NamedTempFile::new().unwrap();
+    for i in 0..20 {
+        writeln!(before, "msg=\"request {} served\"", i).unwrap();
+        writeln!(after, "msg=\"request {} served\"", i).unwrap();
+    }
+    writeln!(before, "msg=\"config reloaded\"").unwrap();
+    writeln!(after, "msg=\"connection reset by peer\"").unwrap();
+    let before = before.path().to_str().unwrap();
+    let after = after.path().to_str().unwrap();
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(&["drain-diff", before, after], "");
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 2, "stdout: {}", stdout);
+    assert!(lines[0].starts_with("new           0        1  "));
+    assert!(lines[0].ends_with("connection reset by peer"));
+    assert!(lines[1].starts_with("gone          1        0  "));
+    assert!(lines[1].ends_with("config reloaded"));
+
+    let (_stdout, stderr, exit_code) = run_kelora_with_input(&["drain-diff", before, before], "");
+    assert_ne!(exit_code, 0);
+    assert!(
+        stderr.contains("two different inputs"),
+        "stderr: {}",
+        stderr
+    );
+}
+
+#[test]
+fn test_drain_annotate() {
+    let input = r#"level=info msg="user alice logged in"
+level=error msg="disk full"
+level=info msg="user bob logged in""#;
+
+    let (stdout, _stderr, exit_code) =
+        run_kelora_with_input(&["--drain", "--drain-annotate", "-F", "jsonl"], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    let events: Vec<serde_json::Value> = stdout
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(events.len(), 3, "stdout: {}", stdout);
+    // The template as mined so far
+    assert_eq!(events[0]["template"], "user alice logged in");
+    assert_eq!(events[1]["template"], "disk full");
+    assert_eq!(events[2]["template"], "user <*> logged in");
+    assert_eq!(events[2]["template_id"], "65231b8d");
+
+    // Added before -k/-K select the keys
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &[
+            "--drain",
+            "--drain-annotate",
+            "-k",
+            "template_id",
+            "-F",
+            "jsonl",
+        ],
+        input,
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(stdout.lines().last(), Some(r#"{"template_id":"65231b8d"}"#));
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &[
+            "--drain",
+            "--drain-annotate",
+            "-K",
+            "template",
+            "-F",
+            "jsonl",
+        ],
+        input,
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert!(
+        !stdout.contains("\"template\"") && stdout.contains("template_id"),
+        "stdout: {}",
+        stdout
+    );
+}
+
+#[test]
+fn test_drain_top_examples() {
+    let input = r#"msg="user alice logged in"
+msg="disk full"
+msg="user bob logged in"
+msg="user carol logged in""#;
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &["--drain", "--drain-top", "1", "--drain-examples", "2"],
+        input,
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(
+        stdout,
+        "       3  65231b8d  user <*> logged in\n          | user alice logged in\n          | user bob logged in\n"
+    );
+}
+
+#[test]
+fn test_drain_merge() {
+    let dir = tempfile::tempdir().unwrap();
+    let web1 = dir.path().join("web1.json");
+    let web2 = dir.path().join("web2.json");
+    let fleet = dir.path().join("fleet.json");
+    for (state, input) in [
+        (&web1, "msg=\"user alice logged in\"\nmsg=\"disk full\""),
+        (
+            &web2,
+            "msg=\"user bob logged in\"\nmsg=\"user carol logged in\"",
+        ),
+    ] {
+        let (_stdout, _stderr, exit_code) = run_kelora_with_input(
+            &["--drain", "--drain-state", state.to_str().unwrap()],
+            input,
+        );
+        assert_eq!(exit_code, 0, "kelora should exit successfully");
+    }
+
+    let (_stdout, stderr, exit_code) = run_kelora_with_input(
+        &[
+            "drain-merge",
+            web1.to_str().unwrap(),
+            web2.to_str().unwrap(),
+            "-o",
+            fleet.to_str().unwrap(),
+        ],
+        "",
+    );
+    assert_eq!(exit_code, 0, "stderr: {}", stderr);
+
+    let (stdout, _stderr, exit_code) =
+        run_kelora_with_input(&["--drain", "--drain-state", fleet.to_str().unwrap()], "");
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(
+        stdout,
+        "       3  65231b8d  user <*> logged in\n       1  8c96d76f  disk full\n"
+    );
+
+    let (_stdout, stderr, exit_code) = run_kelora_with_input(&["drain-merge"], "");
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("state files"), "stderr: {}", stderr);
+}
+
+#[test]
+fn test_drain_params() {
+    let input = r#"msg="user alice logged in from 10.0.0.1"
+msg="disk full"
+msg="user bob logged in from 10.0.0.1"
+msg="user alice logged in from 10.0.0.2""#;
+
+    let (stdout, _stderr, exit_code) =
+        run_kelora_with_input(&["--drain", "--drain-params", "23F1"], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(
+        stdout,
+        "       3  23f1c4f2  user <*> logged in from <ip>
+          1 <*>
+                 2  alice
+                 1  bob
+          2 <ip>
+                 2  10.0.0.1
+                 1  10.0.0.2
+"
+    );
+}
+
+#[test]
+fn test_drain_timeline() {
+    let input = r#"ts=2024-03-14T10:01:00Z msg="user alice logged in"
+ts=2024-03-14T10:07:00Z msg="disk full"
+ts=2024-03-14T10:11:00Z msg="user bob logged in"
+ts=2024-03-14T10:12:00Z msg="user carol logged in""#;
+
+    let (stdout, _stderr, exit_code) =
+        run_kelora_with_input(&["--drain", "--drain-timeline", "5m"], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(
+        stdout,
+        "id        10:00  10:05  10:10  template
+65231b8d      1      0      2  user <*> logged in
+8c96d76f      0      1      0  disk full
+"
+    );
+
+    let (stdout, _stderr, exit_code) =
+        run_kelora_with_input(&["--drain", "--drain-timeline", "10m", "-F", "csv"], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(
+        stdout,
+        "template_id,template,2024-03-14T10:00:00Z,2024-03-14T10:10:00Z
+65231b8d,user <*> logged in,1,2
+8c96d76f,disk full,1,0
+"
+    );
+
+    let (_stdout, stderr, exit_code) =
+        run_kelora_with_input(&["--drain", "--drain-timeline", "soon"], input);
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("--drain-timeline"), "stderr: {}", stderr);
+}
+
+#[test]
+fn test_keys_after_aggregation() {
+    let input = "host=a level=error message=\"disk full on 1\"\n\
+                 host=a level=info message=\"disk full on 2\"\n\
+                 host=b level=error message=\"user 5 login\"\n";
+
+    // The keys are selected from the counts, not from the events before counting
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &["--count-by", "host", "-k", "host,count", "-F", "csv"],
+        input,
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(stdout, "host,count\na,2\nb,1\n");
+
+    let (stdout, _stderr, exit_code) =
+        run_kelora_with_input(&["--count-by", "host", "-K", "count"], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(stdout, "host=\"a\"\nhost=\"b\"\n");
+
+    // Templates are mined from the messages -k leaves out
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(&["--drain", "-k", "level"], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert!(stdout.contains("disk full on <num>"), "stdout: {}", stdout);
+}
+
+#[test]
+fn test_count_by() {
+    let input = r#"host=web1 level=error msg=a
+host=web2 level=info msg=b
+host=web1 level=error msg=c
+level=error msg=d
+host=web1 level=info msg=e
+host=web1 level=error msg=f"#;
+
+    let (stdout, _stderr, exit_code) =
+        run_kelora_with_input(&["--count-by", "host,level", "-F", "csv"], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 5, "stdout: {}", stdout);
+    assert_eq!(lines[0], "level,count,host");
+    assert_eq!(lines[1], "error,3,web1");
+    assert_eq!(lines[3], "error,1,");
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &["--count-by", "level", "-l", "error", "-F", "jsonl"],
+        input,
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(stdout.trim(), r#"{"count":4.0,"level":"error"}"#);
+}
+
+#[test]
+fn test_top_values() {
+    let input = r#"status=200 path=/a
+status=500 path=/b
+status=200 path=/a
+status=200 path=/c
+path=/a"#;
+
+    let (stdout, _stderr, exit_code) =
+        run_kelora_with_input(&["--top", "1", "status,path", "-F", "csv"], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(
+        stdout,
+        "count,field,percent,value\n3,status,60,200\n3,path,60,/a\n"
+    );
+
+    let (_stdout, stderr, exit_code) = run_kelora_with_input(&["--top", "x", "status"], input);
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("--top"), "stderr: {}", stderr);
+}
+
+#[test]
+fn test_agg() {
+    let input = r#"level=info latency_ms=12
+level=info latency_ms=30
+level=error latency_ms=oops
+level=info latency_ms=18
+level=error latency_ms=240"#;
+
+    let (stdout, stderr, exit_code) = run_kelora_with_input(
+        &["-S", "--agg", "latency_ms:count,p50,max", "--agg", "size"],
+        input,
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert!(stdout.is_empty());
+    assert!(stderr.contains("Events shown: 5"), "stderr: {}", stderr);
+    assert!(
+        stderr.contains("latency_ms: count=4 p50=30 max=240\nsize: no numeric values\n"),
+        "stderr: {}",
+        stderr
+    );
+
+    // Only over the events that pass the filters, and without -S only the aggregates
+    let (stdout, stderr, exit_code) =
+        run_kelora_with_input(&["-l", "info", "--agg", "latency_ms:mean"], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(stdout.lines().count(), 3);
+    assert!(
+        stderr.ends_with("\nlatency_ms: mean=20\n"),
+        "stderr: {}",
+        stderr
+    );
+
+    let (_stdout, stderr, exit_code) = run_kelora_with_input(&["--agg", "latency_ms:p200"], input);
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("--agg"), "stderr: {}", stderr);
+}
+
+#[test]
+fn test_hist() {
+    let input = r#"latency_ms=1
+latency_ms=2
+latency_ms=2
+latency_ms=10
+msg="no latency""#;
+
+    let (stdout, _stderr, exit_code) =
+        run_kelora_with_input(&["--hist", "latency_ms", "--hist-buckets", "2"], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(
+        stdout,
+        "latency_ms: 4 values, min 1, max 10\n\
+        \x20        1 .. 5.5              3  ########################################\n\
+        \x20      5.5 .. 10               1  ##############\n"
+    );
+
+    let (stdout, _stderr, exit_code) =
+        run_kelora_with_input(&["--hist", "latency_ms", "--hist-log"], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert!(stdout.starts_with("latency_ms: 4 values, min 1, max 10, log scale\n"));
+    assert_eq!(stdout.lines().count(), 21);
+
+    let (_stdout, _stderr, exit_code) =
+        run_kelora_with_input(&["--hist", "latency_ms", "--count-by", "level"], input);
+    assert_ne!(exit_code, 0);
+    // The report is text only, so Arrow output is refused instead of coming out empty
+    for args in [
+        &["--hist", "latency_ms", "-F", "arrow"][..],
+        &["--drain", "-F", "arrow"],
+    ] {
+        let (stdout, stderr, exit_code) = run_kelora_with_input(args, input);
+        assert_ne!(exit_code, 0, "{:?} should be rejected", args);
+        assert!(stdout.is_empty());
+        assert!(stderr.contains("-F arrow can't hold"), "stderr: {}", stderr);
+    }
+}
+
+#[test]
+fn test_timeline() {
+    let input = r#"ts=2024-03-14T10:00:05Z level=info msg=a
+ts=2024-03-14T10:00:40Z level=error msg=b
+ts=2024-03-14T10:00:41Z level=info msg=c
+ts=2024-03-14T10:02:10Z level=warn msg=d"#;
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(&["--timeline", "1m"], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(
+        stdout,
+        "time     count\n\
+        10:00        3  ########################################\n\
+        10:01        0\n\
+        10:02        1  ##############\n"
+    );
+
+    // Only the events shown count
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &[
+            "--timeline",
+            "1m",
+            "--timeline-by-level",
+            "-l",
+            "error,warn",
+            "-F",
+            "csv",
+        ],
+        input,
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(
+        stdout,
+        "time,error,warn,count\n\
+        2024-03-14T10:00:00Z,1,0,1\n\
+        2024-03-14T10:01:00Z,0,0,0\n\
+        2024-03-14T10:02:00Z,0,1,1\n"
+    );
+
+    let (_stdout, stderr, exit_code) = run_kelora_with_input(&["--timeline", "soon"], input);
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("--timeline"), "stderr: {}", stderr);
+}
+
+#[test]
+fn test_stats_sparklines() {
+    let input = r#"ts=2024-03-14T10:00:00Z level=info msg=a
+ts=2024-03-14T10:00:00Z level=info msg=b
+ts=2024-03-14T10:00:01Z level=error msg=c
+ts=2024-03-14T10:00:03Z level=info msg=d"#;
+
+    let (_stdout, stderr, exit_code) = run_kelora_with_input(&["-S"], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert!(
+        stderr.contains(
+            "\nVolume: █            ▄                         ▄ (76ms per mark, peak 2)\n\
+            Errors:              █                           (peak 1)\n"
+        ),
+        "stderr: {}",
+        stderr
+    );
+
+    // Without errors only the volume, and without a time span neither
+    let (_stdout, stderr, _exit_code) = run_kelora_with_input(&["-S", "-l", "info"], input);
+    assert!(stderr.contains("\nVolume: "), "stderr: {}", stderr);
+    assert!(!stderr.contains("Errors: "), "stderr: {}", stderr);
+    let (_stdout, stderr, _exit_code) = run_kelora_with_input(&["-S", "-l", "error"], input);
+    assert!(!stderr.contains("Volume: "), "stderr: {}", stderr);
+}
+
+#[test]
+fn test_cardinality() {
+    let input = r#"user=alice level=error
+user=bob level=error
+user=alice level=error
+user=carol level=info
+level=error"#;
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &["--cardinality", "user", "-l", "error", "-F", "csv"],
+        input,
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(stdout, "distinct,events,field\n2,3,user\n");
+
+    let (_stdout, _stderr, exit_code) =
+        run_kelora_with_input(&["--cardinality", "user", "--top", "1", "user"], input);
+    assert_ne!(exit_code, 0);
+}
+
+#[test]
+fn test_gaps() {
+    let input = r#"ts=2024-03-14T10:00:00Z host=web1 msg=a
+ts=2024-03-14T10:00:00Z host=web2 msg=a
+ts=2024-03-14T10:00:50Z host=web1 msg=b
+ts=2024-03-14T10:03:00Z host=web1 msg=c"#;
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(&["--gaps", "1m"], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(
+        stdout.trim(),
+        r#"duration="2m10s" from="2024-03-14T10:00:50.000Z" ongoing=false to="2024-03-14T10:03:00.000Z""#
+    );
+
+    let (stdout, _stderr, exit_code) =
+        run_kelora_with_input(&["--gaps", "1m", "--gaps-by", "host", "-F", "csv"], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(
+        stdout,
+        "duration,from,host,ongoing,to\n\
+        2m10s,2024-03-14T10:00:50.000Z,web1,false,2024-03-14T10:03:00.000Z\n\
+        3m0s,2024-03-14T10:00:00.000Z,web2,true,2024-03-14T10:03:00.000Z\n"
+    );
+
+    let (_stdout, stderr, exit_code) = run_kelora_with_input(&["--gaps", "0s"], input);
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("--gaps"), "stderr: {}", stderr);
+}
+
+#[test]
+fn test_bursts() {
+    let mut input = String::new();
+    for minute in 0..3 {
+        input.push_str(&format!(
+            "ts=2024-03-14T10:0{}:00Z level=info msg=\"request served\"\n",
+            minute
+        ));
+    }
+    for second in 0..4 {
+        input.push_str(&format!(
+            "ts=2024-03-14T10:03:0{}Z level=error msg=\"disk {} full\"\n",
+            second, second
+        ));
+    }
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(&["--bursts"], &input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(
+        stdout,
+        "10:03 .. 10:04  4 events, 4.0× the baseline of 1.0\n\
+        \x20         levels: error 4\n\
+        \x20      4  disk <num> full\n"
+    );
+
+    let (stdout, _stderr, exit_code) =
+        run_kelora_with_input(&["--bursts", "--burst-factor", "5"], &input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(stdout, "No bursts of 5× the baseline\n");
+
+    let (_stdout, stderr, exit_code) =
+        run_kelora_with_input(&["--bursts", "--burst-window", "0s"], &input);
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("--bursts"), "stderr: {}", stderr);
+}
+
+#[test]
+fn test_sessions() {
+    let input = r#"ts=2024-03-14T10:00:00Z user=alice level=info msg=login
+ts=2024-03-14T10:05:00Z user=bob level=info msg=login
+ts=2024-03-14T10:20:00Z user=alice level=error msg="checkout failed"
+ts=2024-03-14T10:45:00Z user=alice level=info msg=logout
+ts=2024-03-14T11:30:00Z user=alice level=info msg=login"#;
+
+    let (stdout, _stderr, exit_code) =
+        run_kelora_with_input(&["--sessions", "user", "-F", "csv"], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(
+        stdout,
+        "duration,end,errors,events,start,user\n\
+        0s,2024-03-14T10:05:00.000Z,0,1,2024-03-14T10:05:00.000Z,bob\n\
+        45m0s,2024-03-14T10:45:00.000Z,1,3,2024-03-14T10:00:00.000Z,alice\n\
+        0s,2024-03-14T11:30:00.000Z,0,1,2024-03-14T11:30:00.000Z,alice\n"
+    );
+
+    let (stdout, _stderr, exit_code) =
+        run_kelora_with_input(&["--sessions", "user", "--session-gap", "1h"], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(stdout.lines().count(), 2);
+    assert!(
+        stdout.contains(r#"duration="1h30m0s""#),
+        "stdout: {}",
+        stdout
+    );
+
+    let (_stdout, stderr, exit_code) =
+        run_kelora_with_input(&["--sessions", "user", "--session-gap", "soon"], input);
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("--session-gap"), "stderr: {}", stderr);
+}
+
+#[test]
+fn test_pair() {
+    let input = r#"ts=2024-03-14T10:00:00Z request_id=r1 msg="request started"
+ts=2024-03-14T10:00:01Z request_id=r2 msg="request started"
+ts=2024-03-14T10:00:01.2Z request_id=r1 msg="cache miss"
+ts=2024-03-14T10:00:01.5Z request_id=r1 status=200 msg="request completed"
+ts=2024-03-14T10:00:02Z request_id=r3 status=500 msg="request completed""#;
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &[
+            "--pair",
+            r#"start: msg =~ "request started""#,
+            r#"end: msg =~ "request completed""#,
+            "--pair-key",
+            "request_id",
+            "-F",
+            "csv",
+        ],
+        input,
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(
+        stdout,
+        "timestamp,message,duration,duration_ms,end,msg,paired,request_id,status,ts\n\
+        2024-03-14T10:00:00.000Z,request started,1s,1500,2024-03-14T10:00:01.500Z,\
+        request started,true,r1,200,2024-03-14T10:00:00Z\n\
+        2024-03-14T10:00:01.000Z,request started,,,,request started,false,r2,,\
+        2024-03-14T10:00:01Z\n"
+    );
+
+    let (_stdout, stderr, exit_code) = run_kelora_with_input(
+        &["--pair", "msg =~", r#"msg == "done""#, "--pair-key", "id"],
+        input,
+    );
+    assert_ne!(exit_code, 0);
+    assert!(
+        stderr.contains("Invalid --pair start"),
+        "stderr: {}",
+        stderr
+    );
+
+    let (_stdout, _stderr, exit_code) = run_kelora_with_input(&["--pair", "msg", "msg"], input);
+    assert_ne!(exit_code, 0, "--pair needs --pair-key");
+}
+
+#[test]
+fn test_latency_between() {
+    let input = r#"ts=2024-03-14T10:00:00Z trace_id=a msg=sent
+ts=2024-03-14T10:00:00.1Z trace_id=b msg=sent
+ts=2024-03-14T10:00:00.2Z trace_id=a msg=received
+ts=2024-03-14T10:00:00.5Z trace_id=b msg=received
+ts=2024-03-14T10:00:01Z trace_id=c msg=sent"#;
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &[
+            "--latency-between",
+            r#"from: msg == "sent""#,
+            r#"to: msg == "received""#,
+            "--by",
+            "trace_id",
+        ],
+        input,
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(
+        stdout,
+        "latency_ms: count=2 min=200 mean=300 p50=400 p95=400 p99=400 max=400\n\
+        1 without an end\n"
+    );
+
+    let (_stdout, _stderr, exit_code) =
+        run_kelora_with_input(&["--latency-between", "msg", "msg"], input);
+    assert_ne!(exit_code, 0, "--latency-between needs --by");
+}
+
+#[test]
+fn test_crosstab() {
+    let input = r#"ts=2024-03-14T09:10:00Z level=info status=200
+ts=2024-03-14T09:20:00Z level=error status=500
+ts=2024-03-14T10:05:00Z level=info status=200
+ts=2024-03-14T10:07:00Z status=200"#;
+
+    let (stdout, _stderr, exit_code) =
+        run_kelora_with_input(&["--crosstab", "level@1h", "-F", "csv"], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(
+        stdout,
+        "time,error,info,none,count\n\
+        2024-03-14T09:00:00Z,1,1,0,2\n\
+        2024-03-14T10:00:00Z,0,1,1,2\n"
+    );
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(&["--crosstab", "status@1h"], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(
+        stdout,
+        "time     200    500    count\n\
+        09:00      1      1        2  ########################################\n\
+        10:00      2      0        2  ########################################\n"
+    );
+
+    // A stray old timestamp doesn't make it fill in every second since
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &["--crosstab", "level@1s", "-F", "csv"],
+        "ts=1971-01-01T00:00:00Z level=info\nts=2024-03-14T10:00:00Z level=error",
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(
+        stdout,
+        "time,error,info,count\n\
+        1971-01-01T00:00:00Z,0,1,1\n\
+        2024-03-14T10:00:00Z,1,0,1\n"
+    );
+
+    let (_stdout, stderr, exit_code) = run_kelora_with_input(&["--crosstab", "1h"], input);
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("FIELD@INTERVAL"), "stderr: {}", stderr);
+}
+
+#[test]
+fn test_stats_durations() {
+    let input = r#"ts=2024-03-14T10:00:00Z status=200 took=120ms wait=5
+ts=2024-03-14T10:00:01Z status=500 took=2s wait=1.5s"#;
+
+    let (_stdout, stderr, exit_code) = run_kelora_with_input(&["-S", "--normalize-units"], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert!(
+        stderr.contains("took_ms (ms): min=120 mean=1060 p95=2000 max=2000\n"),
+        "stderr: {}",
+        stderr
+    );
+    assert!(!stderr.contains("status"), "stderr: {}", stderr);
+
+    let (_stdout, stderr, exit_code) =
+        run_kelora_with_input(&["-S", "--types", "wait:duration"], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert!(
+        stderr.ends_with("wait (ms): min=5 mean=752.5 p95=1500 max=1500\n"),
+        "stderr: {}",
+        stderr
+    );
+
+    // Only with the statistics
+    let (_stdout, stderr, _exit_code) = run_kelora_with_input(&["--types", "wait:duration"], input);
+    assert!(!stderr.contains("(ms)"), "stderr: {}", stderr);
+}
+
+#[test]
+fn test_interarrival() {
+    let input = r#"ts=2024-03-14T10:00:00Z job=backup
+ts=2024-03-14T10:00:01Z job=sync
+ts=2024-03-14T10:00:03Z job=sync
+ts=2024-03-14T10:01:00Z job=backup
+ts=2024-03-14T10:01:07Z job=sync"#;
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(&["--interarrival"], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(
+        stdout,
+        "interarrival_ms: count=4 min=1000 mean=16750 p50=7000 p95=57000 p99=57000 max=57000\n"
+    );
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &[
+            "--interarrival",
+            "--interarrival-by",
+            "job",
+            "--interarrival-hist",
+        ],
+        input,
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert!(
+        stdout.starts_with(
+            "interarrival_ms job=backup: count=1 min=60000 mean=60000 p50=60000 p95=60000 p99=60000 max=60000\n\
+            interarrival_ms job=sync: count=2 min=2000 mean=33000 p50=64000 p95=64000 p99=64000 max=64000\n\
+            \n\
+            interarrival_ms: 3 values, min 2000, max 64000, log scale\n"
+        ),
+        "stdout: {}",
+        stdout
+    );
+}
+
+#[test]
+fn test_schema() {
+    let input = r#"status=200 user=alice msg="hello world"
+status=oops msg=bye
+status=200 msg=hi"#;
+
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(&["--schema", "-F", "csv"], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(
+        stdout,
+        "events,examples,field,percent,types\n\
+        3,\"200, oops\",status,100,\"number,string\"\n\
+        1,alice,user,33.3,string\n\
+        3,\"hello world, bye, hi\",msg,100,string\n"
+    );
+}
+
+#[test]
+fn test_stats_bytes() {
+    let input = "level=info msg=a\nlevel=error msg=b\n";
+
+    let (stdout, stderr, exit_code) =
+        run_kelora_with_input(&["-s", "-l", "error", "-F", "jsonl"], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert!(
+        stderr.contains(&format!("Bytes: 35 B read, {} B emitted (", stdout.len())),
+        "stderr: {}",
+        stderr
+    );
+    assert!(
+        stderr.contains("Line length: p50=17 p95=17 p99=17 max=17\n"),
+        "stderr: {}",
+        stderr
+    );
+
+    // Nothing is emitted with -S
+    let (_stdout, stderr, _exit_code) = run_kelora_with_input(&["-S"], input);
+    assert!(stderr.contains("Bytes: 35 B read\n"), "stderr: {}", stderr);
+}
+
+#[test]
+fn test_stats_interval() {
+    let input = "level=info msg=a\nlevel=error msg=b\n";
+
+    let (_stdout, stderr, exit_code) = run_kelora_with_input(
+        &["-S", "--stats-interval", "1h", "--stats-format", "json"],
+        input,
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    let last = stderr.lines().last().unwrap();
+    let stats: serde_json::Value = serde_json::from_str(last).unwrap();
+    assert_eq!(stats["events_shown"], 2);
+    assert_eq!(stats["lines_seen"], 2);
+    assert_eq!(stats["levels"]["error"], 1);
+
+    // Implies the stats
+    let (_stdout, stderr, exit_code) = run_kelora_with_input(&["--stats-interval", "1h"], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert!(stderr.contains("Events shown: 2"), "stderr: {}", stderr);
+
+    let (_stdout, stderr, exit_code) = run_kelora_with_input(&["--stats-interval", "0s"], input);
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("--stats-interval"), "stderr: {}", stderr);
+}
+
+#[test]
+fn test_stats_interval_idle() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_kelora"))
+        .args(["--stats-interval", "1s"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to start kelora");
+    let mut stdin = child.stdin.take().unwrap();
+    writeln!(stdin, "level=info msg=a").unwrap();
+
+    // Stdin stays open without another line, yet the snapshots keep coming
+    let stderr = std::io::BufReader::new(child.stderr.take().unwrap());
+    let (sender, receiver) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        use std::io::BufRead;
+        for line in stderr.lines().map_while(Result::ok) {
+            let _ = sender.send(line);
+        }
+    });
+    let mut snapshots = Vec::new();
+    while snapshots.len() < 2 {
+        let line = receiver
+            .recv_timeout(std::time::Duration::from_secs(10))
+            .expect("no stats while stdin was idle");
+        if line.starts_with("Stats at ") {
+            snapshots.push(line);
+        }
+    }
+    assert!(
+        snapshots[1].contains(": 1 events shown (+0, 0/s)"),
+        "snapshots: {:?}",
+        snapshots
+    );
+
+    drop(stdin);
+    assert!(child.wait().unwrap().success());
+}
+
+#[test]
+fn test_distinct() {
+    let input = "path=/a status=200\npath=/b status=500\npath=/a status=404\nstatus=200\n";
+
+    let (stdout, _stderr, exit_code) =
+        run_kelora_with_input(&["--distinct", "path", "-F", "csv"], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(stdout, "path\n/a\n/b\n");
+
+    // Filters apply first
+    let (stdout, _stderr, exit_code) = run_kelora_with_input(
+        &[
+            "--distinct",
+            "path",
+            "--filter",
+            "status >= 400",
+            "-F",
+            "csv",
+        ],
+        input,
+    );
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert_eq!(stdout, "path\n/b\n/a\n");
+}
+
+#[test]
+fn test_prom_listen() {
+    let input = "level=info msg=a\n";
+
+    let (stdout, stderr, exit_code) =
+        run_kelora_with_input(&["--prom-listen", "127.0.0.1:0"], input);
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    assert!(stdout.contains("msg=\"a\""), "stdout: {}", stdout);
+    assert!(
+        stderr.contains("Serving metrics on http://127.0.0.1:"),
+        "stderr: {}",
+        stderr
+    );
+
+    let (_stdout, stderr, exit_code) =
+        run_kelora_with_input(&["--prom-listen", "not-an-address"], input);
+    assert_ne!(exit_code, 0);
+    assert!(
+        stderr.contains("Invalid --prom-listen"),
+        "stderr: {}",
+        stderr
+    );
+}
+
+#[test]
+fn test_prom_listen_idle() {
+    use std::io::{BufRead, Read};
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_kelora"))
+        .args(["--prom-listen", "127.0.0.1:0"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to start kelora");
+    let mut stdin = child.stdin.take().unwrap();
+    writeln!(stdin, "level=info msg=a\nlevel=info msg=b").unwrap();
+
+    let mut stderr = std::io::BufReader::new(child.stderr.take().unwrap());
+    let mut serving = String::new();
+    stderr.read_line(&mut serving).unwrap();
+    let url = serving
+        .trim()
+        .strip_prefix("Serving metrics on http://")
+        .unwrap();
+    let addr = url.strip_suffix("/metrics").unwrap().to_string();
+    let scrape = || {
+        let mut stream = std::net::TcpStream::connect(&addr).unwrap();
+        write!(stream, "GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    };
+
+    // Stdin stays open without another line, yet the metrics catch up and the rate drops
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+    loop {
+        let response = scrape();
+        if response.contains("kelora_lines_total 2\n")
+            && response.contains("kelora_events_per_second 0\n")
+        {
+            break;
+        }
+        assert!(std::time::Instant::now() < deadline, "{}", response);
+        std::thread::sleep(std::time::Duration::from_millis(200));
+    }
+
+    drop(stdin);
+    assert!(child.wait().unwrap().success());
+}
+
+#[test]
+fn test_diff() {
+    let mut before = NamedTempFile::new().unwrap();
+    let mut after = NamedTempFile::new().unwrap();
+    for (level, message, latency) in [
+        ("info", "request 1 served", 20),
+        ("info", "request 2 served", 40),
+        ("error", "config reload failed", 100),
+        ("info", "request 3 served", 30),
+    ] {
+        writeln!(
+            before,
+            "level={} msg=\"{}\" latency_ms={}",
+            level, message, latency
+        )
+        .unwrap();
+    }
+    for (level, message, latency) in [
+        ("info", "request 1 served", 50),
+        ("error", "connection reset by peer", 300),
+        ("error", "connection reset by peer", 200),
+    ] {
+        writeln!(
+            after,
+            "level={} msg=\"{}\" latency_ms={}",
+            level, message, latency
+        )
+        .unwrap();
+    }
+    let before = before.path().to_str().unwrap();
+    let after = after.path().to_str().unwrap();
+
+    let (stdout, _stderr, exit_code) =
+        run_kelora_with_input(&["diff", before, after, "--agg", "latency_ms:p50,max"], "");
+    assert_eq!(exit_code, 0, "kelora should exit successfully");
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(
+        lines[..7],
+        [
+            "                                old            new     change",
+            "events                            4              3     -25.0%",
+            "parse errors                      0              0         0%",
+            "level error               1 (25.0%)      2 (66.7%)  +41.7 pts",
+            "level info                3 (75.0%)      1 (33.3%)  -41.7 pts",
+            "latency_ms p50                   40            200    +400.0%",
+            "latency_ms max                  100            300    +200.0%",
+        ],
+        "stdout: {}",
+        stdout
+    );
+    assert_eq!(lines[7], "");
+    assert!(lines[8].starts_with("new           0        2  "));
+    assert!(lines[8].ends_with("connection reset by peer"));
+
+    let (_stdout, stderr, exit_code) = run_kelora_with_input(&["diff", before, before], "");
+    assert_ne!(exit_code, 0);
+    assert!(
+        stderr.contains("two different inputs"),
+        "stderr: {}",
+        stderr
+    );
+}